@@ -10,14 +10,36 @@
 //!   Middleware functions can be registered to process incoming HTTP requests. They can modify
 //!   requests or short-circuit further processing by returning an immediate HTTP response.
 //!
+//! - **Layers:**  
+//!   [`Layer`]s wrap middleware and routing, seeing both the request and the response produced
+//!   for it.
+//!
 //! - **Routing:**  
 //!   Routes can be defined with parameterized URI patterns and HTTP method matching. The router
 //!   matches incoming requests to routes and invokes the corresponding asynchronous handler.
 //!
+//! - **Extractors:**  
+//!   Handlers registered with [`HoochAppBuilder::add_handler`] declare typed arguments such as
+//!   [`Path`](crate::Path), [`Query`](crate::Query) or [`Json`](crate::Json), which are extracted
+//!   from the request before the handler runs.
+//!
 //! - **Asynchronous I/O:**  
 //!   The server uses `HoochTcpListener` and `HoochTcpStream` to handle TCP connections asynchronously,
 //!   ensuring scalable and non-blocking I/O operations.
 //!
+//! - **Logging:**  
+//!   With the `tracing` feature enabled, every request runs inside a span recording its method,
+//!   matched route, status, response size and latency. The [`AccessLog`] layer additionally emits
+//!   one line per request in the combined log format.
+//!
+//! - **Shared State:**  
+//!   Values registered with [`HoochAppBuilder::with_state`] are reachable from every request via
+//!   [`HttpRequest::state`], so handlers and middleware need not capture shared resources themselves.
+//!
+//! - **Timeouts:**  
+//!   Reading the request head, reading the request body, running the handler and writing the
+//!   response can each be bounded by a timeout, so slow clients or handlers cannot hold a task forever.
+//!
 //! - **Static Lifetime Management:**  
 //!   Middleware and route handlers are required to have a `'static` lifetime. To satisfy this, the
 //!   middleware and route vectors are leaked during the build process.
@@ -58,18 +80,39 @@ use std::{
     future::Future,
     io,
     net::{SocketAddr, ToSocketAddrs},
-    pin::Pin,
+    pin::{pin, Pin},
+    sync::Arc,
+    time::Duration,
 };
 
-use futures::FutureExt;
+use futures::{
+    future::{select, Either},
+    FutureExt, StreamExt,
+};
 
 use hooch::{
     net::{HoochTcpListener, HoochTcpStream},
     spawner::Spawner,
+    time::sleep,
 };
 
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+use crate::decompression::RequestDecompression;
+#[cfg(feature = "http2")]
+use crate::http2;
 use crate::{
-    request::HttpRequest, response::HttpResponse, HttpMethod, HttpResponseBuilder, Params, Uri,
+    body::{encode_chunk, Body, LAST_CHUNK},
+    extensions::Extensions,
+    extract::FromRequest,
+    handler::Handler,
+    layer::{Layer, Next},
+    logging::RequestSpan,
+    proxy::{parse_proxy_header, ClientInfo, ProxyHeader, TrustedProxies},
+    request::HttpRequest,
+    request_body::{self, reframed_head, Decoder, Framing, CONTINUE},
+    response::{HttpResponse, HttpStatus, IntoResponse},
+    websocket::{has_token, WebSocket, WebSocketUpgrade},
+    HttpMethod, HttpResponseBuilder, HttpVersion, Params, Uri,
 };
 
 /// A future that will eventually resolve to a [`Middleware`] result.
@@ -86,7 +129,14 @@ type RouterFuture = Pin<Box<dyn Future<Output = HttpResponse> + Send>>;
 /// and returns a [`RouterFuture`] resolving to an [`HttpResponse`].
 type RouterFn = Box<dyn Fn(HttpRequest<'static>, Params<'static>) -> RouterFuture + Send + Sync>;
 
+/// Size of the per-connection buffer the request head and body are read into.
+pub(crate) const REQUEST_BUFFER_SIZE: usize = 1024 * 100;
+
+/// Marker for the end of the request head.
+const HEAD_TERMINATOR: &[u8; 4] = b"\r\n\r\n";
+
 /// Enum representing the outcome of middleware processing.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum Middleware {
     /// Continue processing the request, possibly with modifications.
@@ -103,6 +153,116 @@ pub struct Route {
     method: HttpMethod,
     /// The URI pattern against which requests are matched.
     path: &'static str,
+    /// Whether the handler receives the body as a [`RequestBody`](crate::RequestBody) stream instead of buffered.
+    stream_body: bool,
+}
+
+impl Route {
+    /// Returns `true` if this route handles the given request.
+    fn matches(&self, req: &HttpRequest<'_>) -> bool {
+        self.method == req.method() && req.uri().is_match(self.path).is_some()
+    }
+}
+
+/// Timeouts applied to the individual phases of handling a connection.
+///
+/// A `None` value disables the timeout for that phase.
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct Timeouts {
+    /// Time allowed to receive the request line and headers.
+    pub(crate) header_read: Option<Duration>,
+    /// Time allowed to receive the request body once the headers are in.
+    body_read: Option<Duration>,
+    /// Time allowed for the matched route handler to produce a response.
+    handler: Option<Duration>,
+    /// Time allowed to write the response back to the client.
+    pub(crate) write: Option<Duration>,
+}
+
+/// Reasons reading a request from a connection can stop early.
+#[derive(Debug)]
+enum ReadError {
+    /// The client closed the connection or the socket errored.
+    Closed,
+    /// The header read timeout elapsed before any byte of the request arrived.
+    Idle,
+    /// The configured read timeout elapsed.
+    TimedOut,
+    /// The request head does not fit in the request buffer.
+    HeadTooLarge,
+    /// The declared body does not fit in the request buffer.
+    BodyTooLarge,
+    /// The chunked encoding of the body is malformed.
+    MalformedBody,
+    /// The head does not say unambiguously where the body ends.
+    AmbiguousFraming,
+    /// The connection did not start with a valid PROXY protocol header.
+    InvalidProxyHeader,
+}
+
+/// Error returned by [`timeout`] when the deadline elapses before the future completes.
+#[derive(Debug)]
+pub(crate) struct TimedOut;
+
+/// The leaked, application-wide state every connection task shares.
+#[derive(Copy, Clone)]
+pub(crate) struct Pipeline {
+    pub(crate) layers: &'static [Box<dyn Layer>],
+    middleware: &'static [MiddlewareFn],
+    routes: &'static [Route],
+    pub(crate) state: &'static Extensions,
+    pub(crate) timeouts: Timeouts,
+    proxies: Option<&'static TrustedProxies>,
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    decompression: Option<RequestDecompression>,
+}
+
+impl Pipeline {
+    /// Attaches the [`ClientInfo`] of a request received from `peer`, and returns the client's
+    /// address.
+    pub(crate) fn identify_client(
+        &self,
+        req: &mut HttpRequest<'_>,
+        peer: SocketAddr,
+    ) -> SocketAddr {
+        let client = match self.proxies {
+            Some(proxies) => proxies.client_info(req, peer),
+            None => ClientInfo {
+                addr: peer,
+                scheme: None,
+                host: None,
+            },
+        };
+        let addr = client.addr;
+        req.extensions_mut().insert(client);
+        addr
+    }
+
+    /// Returns `true` if the request is routed to a handler taking its body as a stream.
+    pub(crate) fn streams_body(&self, req: &HttpRequest<'_>) -> bool {
+        self.routes
+            .iter()
+            .any(|route| route.stream_body && route.matches(req))
+    }
+
+    /// Decodes the body of a raw request if request decompression is enabled.
+    ///
+    /// Returns the rewritten request, or `None` if it is left as is.
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    pub(crate) fn decode_request(&self, request: &[u8]) -> Result<Option<Vec<u8>>, HttpResponse> {
+        match self.decompression {
+            Some(decompression) => decompression.decode(request),
+            None => Ok(None),
+        }
+    }
+
+    /// Decodes the body of a raw request if request decompression is enabled.
+    ///
+    /// Returns the rewritten request, or `None` if it is left as is.
+    #[cfg(not(any(feature = "gzip", feature = "deflate", feature = "brotli")))]
+    pub(crate) fn decode_request(&self, _request: &[u8]) -> Result<Option<Vec<u8>>, HttpResponse> {
+        Ok(None)
+    }
 }
 
 /// Builder for configuring and creating a [`HoochApp`] instance.
@@ -111,8 +271,14 @@ pub struct Route {
 /// of the application. Note that middleware and routes are leaked to achieve a `'static` lifetime.
 pub struct HoochAppBuilder {
     addr: SocketAddr,
+    layers: Vec<Box<dyn Layer>>,
     middleware: Vec<MiddlewareFn>,
     router: Vec<Route>,
+    state: Extensions,
+    timeouts: Timeouts,
+    proxies: Option<TrustedProxies>,
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    decompression: Option<RequestDecompression>,
 }
 
 impl HoochAppBuilder {
@@ -129,11 +295,95 @@ impl HoochAppBuilder {
 
         Ok(Self {
             addr,
+            layers: Vec::new(),
             middleware: Vec::new(),
             router: Vec::new(),
+            state: Extensions::new(),
+            timeouts: Timeouts::default(),
+            proxies: None,
+            #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+            decompression: None,
         })
     }
 
+    /// Registers shared application state.
+    ///
+    /// The state is available to middleware, layers and handlers through
+    /// [`HttpRequest::state`]. One value can be registered per type; registering another value
+    /// of the same type replaces the previous one.
+    ///
+    /// ```rust
+    /// use hooch_http::{HoochAppBuilder, HttpMethod, HttpResponseBuilder};
+    ///
+    /// struct Config {
+    ///     greeting: String,
+    /// }
+    ///
+    /// let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+    /// app.with_state(Config { greeting: "hello".into() });
+    /// app.add_route("/", HttpMethod::GET, |req, _params| async move {
+    ///     let config = req.state::<Config>().unwrap();
+    ///     HttpResponseBuilder::ok().body(config.greeting.clone()).build()
+    /// });
+    /// ```
+    pub fn with_state<T: Send + Sync + 'static>(&mut self, state: T) {
+        self.state.insert(state);
+    }
+
+    /// Sets the time allowed to receive the request line and headers.
+    ///
+    /// The timeout covers the whole head, not each individual read, so clients trickling in
+    /// bytes cannot keep the connection open indefinitely. When it elapses the server replies
+    /// with `408 Request Timeout` and closes the connection.
+    pub fn set_header_read_timeout(&mut self, timeout: Duration) {
+        self.timeouts.header_read = Some(timeout);
+    }
+
+    /// Sets the time allowed to receive the request body after the headers have been read.
+    ///
+    /// When it elapses the server replies with `408 Request Timeout` and closes the connection.
+    pub fn set_body_read_timeout(&mut self, timeout: Duration) {
+        self.timeouts.body_read = Some(timeout);
+    }
+
+    /// Sets the time allowed for a route handler to produce its response.
+    ///
+    /// When it elapses the handler future is dropped and the server replies with
+    /// `503 Service Unavailable`.
+    pub fn set_handler_timeout(&mut self, timeout: Duration) {
+        self.timeouts.handler = Some(timeout);
+    }
+
+    /// Sets the time allowed to write the response back to the client.
+    ///
+    /// When it elapses the connection is closed without sending the rest of the response.
+    pub fn set_write_timeout(&mut self, timeout: Duration) {
+        self.timeouts.write = Some(timeout);
+    }
+
+    /// Decodes compressed request bodies before they reach layers, middleware and handlers.
+    ///
+    /// See [`RequestDecompression`] for which requests are decoded or rejected.
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    pub fn set_request_decompression(&mut self, decompression: RequestDecompression) {
+        self.decompression = Some(decompression);
+    }
+
+    /// Derives the client of requests from proxy reports, for peers in the trusted networks.
+    ///
+    /// See [`TrustedProxies`] for which reports are used.
+    pub fn set_trusted_proxies(&mut self, proxies: TrustedProxies) {
+        self.proxies = Some(proxies);
+    }
+
+    /// Adds a layer wrapping middleware and routing.
+    ///
+    /// Layers run in the order they are added, so the first layer added sees the request first
+    /// and the response last.
+    pub fn add_layer(&mut self, layer: impl Layer) {
+        self.layers.push(Box::new(layer));
+    }
+
     /// Adds a middleware function to the application.
     ///
     /// The middleware is a function that receives an HTTP request and the client's socket address,
@@ -151,7 +401,8 @@ impl HoochAppBuilder {
     /// Adds a new route to the application.
     ///
     /// The route is specified by a URI pattern, an HTTP method, and a handler function.
-    /// The handler receives the request and extracted route parameters, and returns a [`RouterFuture`].
+    /// The handler receives the request and extracted route parameters, and resolves to any
+    /// type implementing [`IntoResponse`].
     pub fn add_route<FutRoute, FnRoute>(
         &mut self,
         path: &'static str,
@@ -159,27 +410,134 @@ impl HoochAppBuilder {
         route: FnRoute,
     ) where
         FnRoute: Fn(HttpRequest<'static>, Params<'static>) -> FutRoute + Sync + Send + 'static,
-        FutRoute: Future<Output = HttpResponse> + Send + 'static,
+        FutRoute: Future + Send + 'static,
+        FutRoute::Output: IntoResponse,
+    {
+        let fut: RouterFn = Box::new(move |req, params| {
+            route(req, params).map(IntoResponse::into_response).boxed()
+        });
+        self.push_route(path, method, fut, false);
+    }
+
+    /// Adds a new route whose handler receives the request body as a stream.
+    ///
+    /// The handler is called as soon as the request head has been read, and takes the body with
+    /// [`HttpRequest::take_body_stream`]. Bodies of streaming routes are not limited by the
+    /// request buffer, and the body read timeout applies to each read rather than the whole body.
+    /// See [`RequestBody`](crate::RequestBody) for details.
+    pub fn add_streaming_route<FutRoute, FnRoute>(
+        &mut self,
+        path: &'static str,
+        method: HttpMethod,
+        route: FnRoute,
+    ) where
+        FnRoute: Fn(HttpRequest<'static>, Params<'static>) -> FutRoute + Sync + Send + 'static,
+        FutRoute: Future + Send + 'static,
+        FutRoute::Output: IntoResponse,
+    {
+        let fut: RouterFn = Box::new(move |req, params| {
+            route(req, params).map(IntoResponse::into_response).boxed()
+        });
+        self.push_route(path, method, fut, true);
+    }
+
+    /// Adds a route accepting WebSocket connections.
+    ///
+    /// `GET` requests to `path` that carry a valid WebSocket handshake are answered with
+    /// `101 Switching Protocols`, after which the handler runs with the established
+    /// [`WebSocket`](crate::WebSocket), the handshake request and its route parameters. Other
+    /// requests are rejected as described on [`WebSocketUpgrade`].
+    pub fn add_websocket_route<FnSocket, FutSocket>(
+        &mut self,
+        path: &'static str,
+        handler: FnSocket,
+    ) where
+        FnSocket: Fn(WebSocket, HttpRequest<'static>, Params<'static>) -> FutSocket
+            + Send
+            + Sync
+            + 'static,
+        FutSocket: Future<Output = ()> + Send + 'static,
     {
-        let route = Route {
-            fut: Box::new(move |req, params| route(req, params).boxed()),
+        let handler = Arc::new(handler);
+        self.add_route(path, HttpMethod::GET, move |req, params| {
+            let upgrade = WebSocketUpgrade::from_request(&req, &params);
+            let handler = Arc::clone(&handler);
+            async move {
+                match upgrade {
+                    Ok(upgrade) => upgrade.on_upgrade(move |socket| handler(socket, req, params)),
+                    Err(rejection) => rejection,
+                }
+            }
+        });
+    }
+
+    /// Adds a new route whose handler takes [extractors](crate::FromRequest) as arguments.
+    ///
+    /// Each argument is extracted from the request before the handler runs, and the first
+    /// extractor that fails answers the request with its rejection. Closures must annotate their
+    /// argument types, since they cannot be inferred from the [`Handler`] bound.
+    pub fn add_handler<Args, H>(&mut self, path: &'static str, method: HttpMethod, handler: H)
+    where
+        H: Handler<Args>,
+    {
+        let fut: RouterFn = Box::new(move |req, params| handler.call(req, params));
+        self.push_route(path, method, fut, false);
+    }
+
+    /// Adds a new extractor route whose handler receives the request body as a stream.
+    ///
+    /// The handler takes the body through a [`RequestBody`](crate::RequestBody) argument, which must come last.
+    /// See [`HoochAppBuilder::add_streaming_route`] for how streaming routes differ.
+    pub fn add_streaming_handler<Args, H>(
+        &mut self,
+        path: &'static str,
+        method: HttpMethod,
+        handler: H,
+    ) where
+        H: Handler<Args>,
+    {
+        let fut: RouterFn = Box::new(move |req, params| handler.call(req, params));
+        self.push_route(path, method, fut, true);
+    }
+
+    fn push_route(
+        &mut self,
+        path: &'static str,
+        method: HttpMethod,
+        fut: RouterFn,
+        stream_body: bool,
+    ) {
+        self.router.push(Route {
+            fut,
             method,
             path,
-        };
-        self.router.push(route);
+            stream_body,
+        });
     }
 
     /// Consumes the builder and returns a [`HoochApp`] instance.
     ///
-    /// This function leaks the middleware and route vectors in order to provide them with a `'static` lifetime,
-    /// which is required by the async runtime.
+    /// This function leaks the layer, middleware and route vectors in order to provide them with a `'static`
+    /// lifetime, which is required by the async runtime.
     pub fn build(self) -> HoochApp {
+        let layer_ptr: &'static Vec<Box<dyn Layer>> = Box::leak(Box::new(self.layers));
         let middleware_ptr: &'static Vec<MiddlewareFn> = Box::leak(Box::new(self.middleware));
         let route_ptr: &'static Vec<Route> = Box::leak(Box::new(self.router));
+        let state_ptr: &'static Extensions = Box::leak(Box::new(self.state));
+        let proxies_ptr: Option<&'static TrustedProxies> =
+            self.proxies.map(|proxies| &*Box::leak(Box::new(proxies)));
         HoochApp {
             addr: self.addr,
-            middleware: middleware_ptr,
-            routes: route_ptr,
+            pipeline: Pipeline {
+                layers: layer_ptr,
+                middleware: middleware_ptr,
+                routes: route_ptr,
+                state: state_ptr,
+                timeouts: self.timeouts,
+                proxies: proxies_ptr,
+                #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+                decompression: self.decompression,
+            },
         }
     }
 }
@@ -190,8 +548,7 @@ impl HoochAppBuilder {
 /// matches requests to routes, and returns serialized HTTP responses.
 pub struct HoochApp {
     addr: SocketAddr,
-    middleware: &'static Vec<MiddlewareFn>,
-    routes: &'static Vec<Route>,
+    pipeline: Pipeline,
 }
 
 impl HoochApp {
@@ -201,73 +558,566 @@ impl HoochApp {
     /// it spawns an asynchronous task to handle the stream.
     pub async fn serve(&self) {
         let listener = HoochTcpListener::bind(self.addr).await.unwrap();
-        let middleware_ptr: &'static Vec<MiddlewareFn> = self.middleware;
-        let route_ptr: &'static Vec<Route> = self.routes;
+        let pipeline = self.pipeline;
 
         while let Ok((stream, socket)) = listener.accept().await {
-            println!("Received connection from {:?}", socket);
+            #[cfg(feature = "tracing")]
+            tracing::debug!(peer = %socket, "accepted connection");
             Spawner::spawn(async move {
-                Self::handle_stream(stream, socket, middleware_ptr, route_ptr).await;
+                Self::handle_stream(stream, socket, pipeline).await;
             });
         }
     }
 
+    /// Serves a connection that already negotiated HTTP/2, through the same pipeline as
+    /// [`serve`](Self::serve).
+    ///
+    /// This is the hook for HTTP/2 over TLS: accept the connection and complete the TLS handshake
+    /// with a TLS library, and pass the stream here when ALPN selected `h2`. The client's
+    /// connection preface must not have been read from `stream` yet. The returned future ends
+    /// once the connection closes and can be spawned on its own task.
+    #[cfg(feature = "http2")]
+    pub fn serve_h2(
+        &self,
+        stream: impl http2::Http2Transport,
+        socket_addr: SocketAddr,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        http2::serve(stream, socket_addr, self.pipeline, Vec::new())
+    }
+
     /// Handles a single TCP stream.
     ///
-    /// This method reads the HTTP request from the stream, applies all middleware in sequence,
-    /// and then routes the request to the appropriate handler based on HTTP method and URI matching.
-    /// If a middleware short-circuits the processing or no matching route is found, an appropriate
-    /// HTTP response is sent back immediately.
+    /// Requests are served one after another for as long as the connection stays persistent.
+    /// HTTP/1.1 connections persist unless either side sends `Connection: close`, while HTTP/1.0
+    /// connections close after each response unless the client asks for `Connection: keep-alive`.
     ///
     /// # Arguments
     ///
     /// * `stream` - The TCP stream representing the client connection.
     /// * `socket_addr` - The client's socket address.
-    /// * `middleware_fns` - A slice of middleware functions to process the request.
-    /// * `routes` - A slice of defined routes to match against the request.
+    /// * `pipeline` - The layers, middleware, routes and timeouts of the application.
     async fn handle_stream(
         mut stream: HoochTcpStream,
-        socket_addr: SocketAddr,
-        middleware_fns: &'static [MiddlewareFn],
-        routes: &'static [Route],
+        mut socket_addr: SocketAddr,
+        pipeline: Pipeline,
     ) {
-        let mut buffer = [0; 1024 * 100];
-        let bytes_read = stream.read(&mut buffer).await.unwrap();
+        let mut buffered = Vec::new();
+        if let Some(proxies) = pipeline.proxies
+            && proxies.expects_proxy_header(socket_addr)
+        {
+            match Self::read_proxy_header(&mut stream, pipeline.timeouts).await {
+                Ok((source, leftover)) => {
+                    socket_addr = source.unwrap_or(socket_addr);
+                    buffered = leftover;
+                }
+                Err(err) => {
+                    Self::handle_read_error::<()>(err, stream, pipeline.timeouts).await;
+                    return;
+                }
+            }
+        }
+        let mut reused = false;
+        while let Some((next_stream, leftover)) = Box::pin(Self::handle_request(
+            stream,
+            socket_addr,
+            pipeline,
+            buffered,
+            reused,
+        ))
+        .await
+        {
+            stream = next_stream;
+            buffered = leftover;
+            reused = true;
+        }
+    }
+
+    /// Reads a single request from the stream and answers it.
+    ///
+    /// The request is run through the layers, middleware and routes of the pipeline and the
+    /// resulting response is written back to the stream. Requests that cannot be read in full
+    /// are answered with an error response directly.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The TCP stream representing the client connection.
+    /// * `socket_addr` - The client's socket address.
+    /// * `pipeline` - The layers, middleware, routes and timeouts of the application.
+    /// * `buffered` - Bytes of this request the client already sent along with the previous one.
+    /// * `reused` - Whether a previous request was served on this connection.
+    ///
+    /// Returns the stream and any bytes read past the request if the connection stays open for
+    /// another request.
+    async fn handle_request(
+        mut stream: HoochTcpStream,
+        socket_addr: SocketAddr,
+        pipeline: Pipeline,
+        buffered: Vec<u8>,
+        reused: bool,
+    ) -> Option<(HoochTcpStream, Vec<u8>)> {
+        let timeouts = pipeline.timeouts;
+        let mut buffer = vec![0; REQUEST_BUFFER_SIZE];
+        buffer[..buffered.len()].copy_from_slice(&buffered);
+        let read = Self::read_head(&mut stream, &mut buffer, buffered.len(), timeouts);
+        let (head_end, bytes_read) = match read.await {
+            Ok(read) => read,
+            // A persistent connection the client stopped using is closed quietly.
+            Err(ReadError::Idle) if reused => return None,
+            Err(err) => return Self::handle_read_error(err, stream, timeouts).await,
+        };
+
+        #[cfg(feature = "http2")]
+        if buffer[..head_end] == *http2::PREFACE_HEAD {
+            buffer.truncate(bytes_read);
+            Box::pin(http2::serve(stream, socket_addr, pipeline, buffer)).await;
+            return None;
+        }
+
+        let head = match HttpRequest::from_bytes(&buffer[..head_end]) {
+            Ok(head) => head,
+            Err(err) => {
+                let response = err.into_response();
+                Self::write_final_response(response, &mut stream, timeouts).await;
+                return None;
+            }
+        };
+        let version = head.version();
+        // Only HTTP/1.0 clients may leave out the `Host` header.
+        if version != HttpVersion::OneZero && head.headers().get("Host").is_none() {
+            let response = HttpResponseBuilder::bad_request()
+                .body("Missing Host header".into())
+                .build();
+            Self::write_final_response(response, &mut stream, timeouts).await;
+            return None;
+        }
+
+        // Where the body of a request with ambiguous framing ends is unknown, so the connection
+        // is never kept alive after one.
+        let framing = match Framing::of(&head) {
+            Ok(framing) => framing,
+            Err(_) => {
+                return Self::handle_read_error(ReadError::AmbiguousFraming, stream, timeouts)
+                    .await;
+            }
+        };
+        let expect_continue = head
+            .headers()
+            .get("Expect")
+            .is_some_and(|expect| expect.trim().eq_ignore_ascii_case("100-continue"));
+        let connect = head.method() == HttpMethod::CONNECT;
+        let is_head = head.method() == HttpMethod::HEAD;
+        let stream_body = pipeline.streams_body(&head);
+        let connection = head.headers().get("Connection");
+        // Streamed bodies are not tracked to their end, so the next request cannot be found after
+        // them. HTTP/1.0 has no chunked encoding, and an intermediary may have framed such a body
+        // differently, so the connection closes after one as well.
+        let keep_alive = match version {
+            HttpVersion::OneZero => {
+                has_token(connection, "keep-alive") && framing != Framing::Chunked
+            }
+            _ => !has_token(connection, "close"),
+        } && !stream_body;
+
+        let ((request_end, bytes_read), pump) = if stream_body {
+            let (body, pump) = request_body::channel(
+                framing,
+                buffer[head_end..bytes_read].to_vec(),
+                expect_continue,
+            );
+            ((head_end, head_end), Some((body, pump)))
+        } else {
+            let read = Self::read_body(
+                &mut stream,
+                &mut buffer,
+                (head_end, bytes_read),
+                framing,
+                expect_continue,
+                timeouts,
+            );
+            match read.await {
+                Ok(read) => (read, None),
+                Err(err) => return Self::handle_read_error(err, stream, timeouts).await,
+            }
+        };
+
+        // Streaming routes receive the body as sent.
+        let decoded = if stream_body {
+            Ok(None)
+        } else {
+            pipeline.decode_request(&buffer[..request_end])
+        };
+        let decoded = match decoded {
+            Ok(decoded) => decoded,
+            Err(response) => {
+                Self::write_final_response(response, &mut stream, timeouts).await;
+                return None;
+            }
+        };
 
         // Parse the raw bytes into an HTTP request.
-        let http_request = HttpRequest::from_bytes(&buffer[..bytes_read]);
+        let http_request =
+            match HttpRequest::from_bytes(decoded.as_deref().unwrap_or(&buffer[..request_end])) {
+                Ok(http_request) => http_request,
+                Err(err) => {
+                    let response = err.into_response();
+                    Self::write_final_response(response, &mut stream, timeouts).await;
+                    return None;
+                }
+            };
+
+        #[cfg(feature = "http2")]
+        if !stream_body && let Some(upgrade) = http2::Upgrade::from_request(&http_request) {
+            let buffered = buffer[request_end..bytes_read].to_vec();
+            let connection =
+                http2::serve_upgraded(stream, socket_addr, pipeline, upgrade, buffered);
+            Box::pin(connection).await;
+            return None;
+        }
 
-        // SAFETY: Transmute the lifetime of the request to 'static since the buffer is no longer used.
+        // SAFETY: Transmute the lifetime of the request to 'static since neither the buffer nor the
+        // decoded request are used or dropped before the response has been written.
         let mut http_request: HttpRequest<'static> = unsafe { std::mem::transmute(http_request) };
+        http_request.set_state(pipeline.state);
+        let client_addr = pipeline.identify_client(&mut http_request, socket_addr);
+
+        let span = RequestSpan::new(&http_request);
+        let mut response = match pump {
+            None => {
+                span.instrument(Next::new(pipeline, client_addr).run(http_request))
+                    .await
+            }
+            Some((body, pump)) => {
+                http_request.extensions_mut().insert(body);
+                let response = span.instrument(Next::new(pipeline, client_addr).run(http_request));
+                // Feed the body while the handler runs; once it responds, stop reading.
+                match select(response, pump.run(&mut stream, timeouts.body_read).boxed()).await {
+                    Either::Left((response, _)) => response,
+                    Either::Right(((), response)) => response.await,
+                }
+            }
+        };
+        let route = response.route();
+        let status = response.status();
+        // Only a protocol switch or a successful `CONNECT` leaves the connection to the handler.
+        let upgrade = response.take_upgrade().filter(|_| {
+            status == HttpStatus::SwitchingProtocols
+                || (connect && (200..300).contains(&u16::from(status)))
+        });
+        // The handler frames whatever follows an upgrade itself.
+        let keep_alive = match upgrade {
+            None => Self::prepare_response(&mut response, version, is_head, keep_alive),
+            Some(_) => {
+                response.set_version(version);
+                false
+            }
+        };
+        let bytes_written = Self::handle_http_response(response, &mut stream, timeouts).await;
+        span.finish(route, status, bytes_written);
 
+        if bytes_written == 0 {
+            return None;
+        }
+        let buffered = buffer[request_end..bytes_read].to_vec();
+
+        // The connection now speaks another protocol; hand it over along with anything the client
+        // already sent past the request.
+        if let Some(upgrade) = upgrade {
+            upgrade.run(stream, buffered).await;
+            return None;
+        }
+
+        keep_alive.then_some((stream, buffered))
+    }
+
+    /// Adds the framing and connection headers of an HTTP/1 response.
+    ///
+    /// Bodies held in memory get a `Content-Length`. HTTP/1.1 clients receive streaming bodies
+    /// chunked, while HTTP/1.0 clients receive them as is and the end of the body is marked by
+    /// closing the connection. Responses to `HEAD` requests keep their headers but lose the body.
+    ///
+    /// Returns `true` if the connection stays open after the response.
+    fn prepare_response(
+        response: &mut HttpResponse,
+        version: HttpVersion,
+        is_head: bool,
+        keep_alive: bool,
+    ) -> bool {
+        response.set_version(version);
+        let has_body = !matches!(u16::from(response.status()), 100..=199 | 204 | 304);
+        if has_body && !response.is_streaming() && response.header("Content-Length").is_none() {
+            let length = response.body().map_or(0, <[u8]>::len);
+            response.insert_header("Content-Length", length.to_string());
+        }
+        if is_head {
+            response.take_body();
+        }
+
+        let delimited = !response.is_streaming() || version != HttpVersion::OneZero;
+        let keep_alive =
+            keep_alive && delimited && !has_token(response.header("Connection"), "close");
+        match (version, keep_alive) {
+            (HttpVersion::OneZero, true) => response.insert_header("Connection", "keep-alive"),
+            (HttpVersion::OneZero, false) => {}
+            (_, false) => response.insert_header("Connection", "close"),
+            (_, true) => {}
+        }
+        keep_alive
+    }
+
+    /// Answers a request that could not be read with the matching error response.
+    async fn handle_read_error<T>(
+        err: ReadError,
+        mut stream: HoochTcpStream,
+        timeouts: Timeouts,
+    ) -> Option<T> {
+        let response = match err {
+            ReadError::Closed => return None,
+            ReadError::Idle | ReadError::TimedOut => HttpResponseBuilder::request_timeout(),
+            ReadError::HeadTooLarge => HttpResponseBuilder::request_header_fields_too_large(),
+            ReadError::BodyTooLarge => HttpResponseBuilder::payload_too_large(),
+            ReadError::InvalidProxyHeader
+            | ReadError::MalformedBody
+            | ReadError::AmbiguousFraming => HttpResponseBuilder::bad_request(),
+        };
+        Self::write_final_response(response.build(), &mut stream, timeouts).await;
+        None
+    }
+
+    /// Writes a response after which the connection is closed.
+    async fn write_final_response(
+        mut response: HttpResponse,
+        stream: &mut HoochTcpStream,
+        timeouts: Timeouts,
+    ) {
+        Self::prepare_response(&mut response, HttpVersion::OnePointOne, false, false);
+        Self::handle_http_response(response, stream, timeouts).await;
+    }
+
+    /// Runs the request through the middleware and dispatches it to the matching route.
+    ///
+    /// If any middleware short-circuits, its response is returned without further processing.
+    /// Requests matching no route are answered with `404 Not Found`.
+    pub(crate) async fn dispatch(
+        mut http_request: HttpRequest<'static>,
+        socket_addr: SocketAddr,
+        pipeline: Pipeline,
+    ) -> HttpResponse {
         // Process middleware sequentially. If any middleware returns a ShortCircuit,
-        // send its response immediately without further processing.
-        for mid in middleware_fns.iter() {
+        // return its response immediately without further processing.
+        for mid in pipeline.middleware.iter() {
             let middleware = mid(http_request, socket_addr).await;
             match middleware {
                 Middleware::Continue(req) => {
                     http_request = req;
                 }
                 Middleware::ShortCircuit(response) => {
-                    return Self::handle_http_response(response, stream).await;
+                    return response;
                 }
             }
         }
 
         // Iterate through routes to find a match for the request's URI and HTTP method.
-        for route in routes.iter() {
+        for route in pipeline.routes.iter() {
             // SAFETY: Transmute the URI lifetime to 'static for matching within this async context.
             let uri: &Uri<'static> = unsafe { std::mem::transmute(http_request.uri()) };
-            if let Some(param) = uri.is_match(route.path) {
-                if route.method == http_request.method() {
-                    let response = (route.fut)(http_request, param).await;
-                    return Self::handle_http_response(response, stream).await;
-                }
+            if let Some(param) = uri.is_match(route.path)
+                && route.method == http_request.method()
+            {
+                let response = timeout((route.fut)(http_request, param), pipeline.timeouts.handler)
+                    .await
+                    .unwrap_or_else(|TimedOut| HttpResponseBuilder::service_unavailable().build());
+                return response.with_route(route.path);
             }
         }
 
         // If no matching route is found, respond with a 404 Not Found.
-        Self::handle_http_response(HttpResponseBuilder::not_found().build(), stream).await;
+        HttpResponseBuilder::not_found().build()
+    }
+
+    /// Reads the request head from the stream into `buffer`.
+    ///
+    /// The first `filled` bytes of `buffer` already hold the start of the request. The head is
+    /// read until the blank line terminating the headers, bounded by the header read timeout.
+    /// Bytes following the head may already have been read along with it.
+    ///
+    /// Returns the length of the head and the total number of bytes stored in `buffer`.
+    async fn read_head(
+        stream: &mut HoochTcpStream,
+        buffer: &mut [u8],
+        mut filled: usize,
+        timeouts: Timeouts,
+    ) -> Result<(usize, usize), ReadError> {
+        let head_end = timeout(
+            async {
+                loop {
+                    if let Some(idx) = buffer[..filled]
+                        .windows(HEAD_TERMINATOR.len())
+                        .position(|window| window == HEAD_TERMINATOR)
+                    {
+                        return Ok(idx + HEAD_TERMINATOR.len());
+                    }
+                    if filled == buffer.len() {
+                        return Err(ReadError::HeadTooLarge);
+                    }
+                    filled += Self::read_some(stream, &mut buffer[filled..]).await?;
+                }
+            },
+            timeouts.header_read,
+        )
+        .await
+        .map_err(|TimedOut| match filled {
+            0 => ReadError::Idle,
+            _ => ReadError::TimedOut,
+        })??;
+
+        Ok((head_end, filled))
+    }
+
+    /// Reads the rest of the body into `buffer`.
+    ///
+    /// Reading continues until the declared number of body bytes have arrived, or the chunked
+    /// body has ended, bounded by the body read timeout. If the client asked for it,
+    /// `100 Continue` is sent before waiting for the body.
+    ///
+    /// Returns the end of the request within `buffer` and the total number of bytes stored in it,
+    /// which exceeds the former if the client already sent more after the request.
+    async fn read_body(
+        stream: &mut HoochTcpStream,
+        buffer: &mut [u8],
+        (head_end, mut filled): (usize, usize),
+        framing: Framing,
+        expect_continue: bool,
+        timeouts: Timeouts,
+    ) -> Result<(usize, usize), ReadError> {
+        let content_length = match framing {
+            Framing::Length(length) => length as usize,
+            Framing::Chunked => {
+                let read = Self::read_chunked_body(
+                    stream,
+                    buffer,
+                    (head_end, filled),
+                    expect_continue,
+                    timeouts,
+                );
+                return read.await;
+            }
+        };
+        let request_end = head_end.saturating_add(content_length);
+        if request_end > buffer.len() {
+            return Err(ReadError::BodyTooLarge);
+        }
+
+        if expect_continue && filled < request_end {
+            write_all(stream, CONTINUE)
+                .await
+                .map_err(|_| ReadError::Closed)?;
+        }
+
+        timeout(
+            async {
+                while filled < request_end {
+                    filled += Self::read_some(stream, &mut buffer[filled..]).await?;
+                }
+                Ok(())
+            },
+            timeouts.body_read,
+        )
+        .await
+        .map_err(|TimedOut| ReadError::TimedOut)??;
+
+        Ok((request_end, filled))
+    }
+
+    /// Reads a chunked body into `buffer`, replacing its chunked encoding with the decoded body.
+    ///
+    /// The head is rewritten by [`reframed_head`] to frame the decoded body with a
+    /// `Content-Length`. The encoded body has to fit in the buffer after the head. Returns the
+    /// same as [`Self::read_body`], with the request ending after the decoded body.
+    async fn read_chunked_body(
+        stream: &mut HoochTcpStream,
+        buffer: &mut [u8],
+        (head_end, mut filled): (usize, usize),
+        mut expect_continue: bool,
+        timeouts: Timeouts,
+    ) -> Result<(usize, usize), ReadError> {
+        let mut decoder = Decoder::new(Framing::Chunked);
+        let mut body = Vec::new();
+        let mut body_end = head_end;
+        timeout(
+            async {
+                loop {
+                    body_end += decoder
+                        .decode(&buffer[body_end..filled], &mut body)
+                        .map_err(|_| ReadError::MalformedBody)?;
+                    if decoder.is_done() {
+                        return Ok(());
+                    }
+                    if filled == buffer.len() {
+                        return Err(ReadError::BodyTooLarge);
+                    }
+                    if expect_continue {
+                        write_all(stream, CONTINUE)
+                            .await
+                            .map_err(|_| ReadError::Closed)?;
+                        expect_continue = false;
+                    }
+                    filled += Self::read_some(stream, &mut buffer[filled..]).await?;
+                }
+            },
+            timeouts.body_read,
+        )
+        .await
+        .map_err(|TimedOut| ReadError::TimedOut)??;
+
+        // Dropping `Transfer-Encoding` and the chunk framing saves more than the `Content-Length`
+        // takes up, so the rewritten request is never longer and anything the client sent after
+        // the body moves forward to follow it.
+        let head = reframed_head(&buffer[..head_end], &[], body.len());
+        let request_end = head.len() + body.len();
+        if request_end > body_end {
+            return Err(ReadError::BodyTooLarge);
+        }
+        buffer.copy_within(body_end..filled, request_end);
+        buffer[..head.len()].copy_from_slice(&head);
+        buffer[head.len()..request_end].copy_from_slice(&body);
+        Ok((request_end, filled - (body_end - request_end)))
+    }
+
+    /// Reads the PROXY protocol header opening a connection.
+    ///
+    /// Returns the source address the header reports, if any, and the bytes read past it.
+    async fn read_proxy_header(
+        stream: &mut HoochTcpStream,
+        timeouts: Timeouts,
+    ) -> Result<(Option<SocketAddr>, Vec<u8>), ReadError> {
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 512];
+        timeout(
+            async {
+                loop {
+                    match parse_proxy_header(&buffer) {
+                        ProxyHeader::Incomplete => {}
+                        ProxyHeader::Invalid => return Err(ReadError::InvalidProxyHeader),
+                        ProxyHeader::Parsed { source, len } => {
+                            return Ok((source, buffer.split_off(len)));
+                        }
+                    }
+                    let bytes_read = Self::read_some(stream, &mut chunk).await?;
+                    buffer.extend_from_slice(&chunk[..bytes_read]);
+                }
+            },
+            timeouts.header_read,
+        )
+        .await
+        .map_err(|TimedOut| ReadError::TimedOut)?
+    }
+
+    /// Performs a single read, treating end-of-stream and socket errors as a closed connection.
+    async fn read_some(stream: &mut HoochTcpStream, buffer: &mut [u8]) -> Result<usize, ReadError> {
+        match stream.read(buffer).await {
+            Ok(0) | Err(_) => Err(ReadError::Closed),
+            Ok(bytes_read) => Ok(bytes_read),
+        }
     }
 
     /// Serializes an [`HttpResponse`] and writes it to the TCP stream.
@@ -279,9 +1129,1175 @@ impl HoochApp {
     ///
     /// * `http_response` - The response to serialize and send.
     /// * `stream` - The TCP stream to write the response to.
-    async fn handle_http_response(http_response: HttpResponse, mut stream: HoochTcpStream) {
-        let mut buffer = Vec::with_capacity(std::mem::size_of_val(&http_response));
-        buffer = http_response.serialize(buffer);
-        stream.write(&buffer).await.unwrap();
+    /// * `timeouts` - The timeouts applied to the connection; only the write timeout is used.
+    ///
+    /// Returns the number of bytes written, which is zero if the write failed or timed out.
+    async fn handle_http_response(
+        http_response: HttpResponse,
+        stream: &mut HoochTcpStream,
+        timeouts: Timeouts,
+    ) -> usize {
+        // HTTP/1.0 has no chunked encoding; the body runs until the connection closes.
+        let chunked = http_response.version() != HttpVersion::OneZero;
+        let (mut buffer, body) = http_response.serialize_head(Vec::with_capacity(1024));
+        let mut chunks = match body {
+            Body::Empty => None,
+            Body::Full(body) => {
+                buffer.extend_from_slice(&body);
+                None
+            }
+            Body::Stream(chunks) => Some(chunks),
+        };
+
+        // A failed or timed out write leaves nothing to recover; the connection is dropped either way.
+        let mut bytes_written = match timeout(write_all(stream, &buffer), timeouts.write).await {
+            Ok(Ok(())) => buffer.len(),
+            Ok(Err(_)) | Err(TimedOut) => return 0,
+        };
+
+        let Some(chunks) = chunks.as_mut() else {
+            return bytes_written;
+        };
+
+        // Only pull the next chunk once the previous one is on the wire, so a slow client applies
+        // backpressure to the producer. The write timeout applies to each chunk individually.
+        while let Some(chunk) = chunks.next().await {
+            buffer.clear();
+            match chunk {
+                Ok(chunk) if chunked => encode_chunk(&chunk, &mut buffer),
+                Ok(chunk) => buffer.extend_from_slice(&chunk),
+                Err(_err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(error = %_err, "response body stream failed");
+                    return bytes_written;
+                }
+            }
+            match timeout(write_all(stream, &buffer), timeouts.write).await {
+                Ok(Ok(())) => bytes_written += buffer.len(),
+                Ok(Err(_)) | Err(TimedOut) => return bytes_written,
+            }
+        }
+
+        if !chunked {
+            return bytes_written;
+        }
+        match timeout(write_all(stream, LAST_CHUNK), timeouts.write).await {
+            Ok(Ok(())) => bytes_written + LAST_CHUNK.len(),
+            Ok(Err(_)) | Err(TimedOut) => bytes_written,
+        }
+    }
+}
+
+/// Writes the whole buffer to the stream, retrying on partial writes.
+pub(crate) async fn write_all(stream: &mut HoochTcpStream, mut buffer: &[u8]) -> io::Result<()> {
+    while !buffer.is_empty() {
+        match stream.write(buffer).await? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            bytes_written => buffer = &buffer[bytes_written..],
+        }
+    }
+    Ok(())
+}
+
+/// Runs a future to completion, giving up once `duration` has elapsed.
+///
+/// A `None` duration waits for the future without a deadline.
+pub(crate) async fn timeout<F: Future>(
+    fut: F,
+    duration: Option<Duration>,
+) -> Result<F::Output, TimedOut> {
+    let Some(duration) = duration else {
+        return Ok(fut.await);
+    };
+
+    match select(pin!(fut), pin!(sleep(duration))).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(TimedOut),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        time::Instant,
+    };
+
+    use hooch::runtime::RuntimeBuilder;
+
+    use super::*;
+    use crate::layer_fn;
+
+    fn get_free_port() -> u16 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    /// Configures an app on a free local port and serves it from a background runtime.
+    pub(crate) fn serve(configure: impl FnOnce(&mut HoochAppBuilder)) -> SocketAddr {
+        let mut builder = HoochAppBuilder::new(("127.0.0.1", get_free_port())).unwrap();
+        configure(&mut builder);
+        let app = builder.build();
+        let addr = app.addr;
+
+        std::thread::spawn(move || {
+            let handle = RuntimeBuilder::default().build();
+            handle.run_blocking(async move { app.serve().await });
+        });
+
+        addr
+    }
+
+    /// Serves the routes `routes` adds behind `layer`.
+    pub(crate) fn serve_layer(
+        layer: impl Layer,
+        routes: impl FnOnce(&mut HoochAppBuilder),
+    ) -> SocketAddr {
+        serve(move |app| {
+            app.add_layer(layer);
+            routes(app);
+        })
+    }
+
+    /// Connects to the server, retrying while it is still binding.
+    pub(crate) fn connect(addr: SocketAddr) -> TcpStream {
+        let start = Instant::now();
+        loop {
+            match TcpStream::connect(addr) {
+                Ok(stream) => return stream,
+                Err(_) if start.elapsed() < Duration::from_secs(5) => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                Err(err) => panic!("failed to connect to test server: {err}"),
+            }
+        }
+    }
+
+    /// Reads one response, following its framing since the connection may stay open.
+    ///
+    /// Interim `1xx` responses are included. Responses without a length, protocol switches and
+    /// anything that is not an HTTP/1 response are read until the server closes the connection.
+    pub(crate) fn read_response(stream: &mut TcpStream) -> String {
+        let mut response = Vec::new();
+        let mut start = 0;
+        loop {
+            let head_end = loop {
+                if let Some(idx) = response[start..]
+                    .windows(HEAD_TERMINATOR.len())
+                    .position(|window| window == HEAD_TERMINATOR)
+                {
+                    break Some(start + idx + HEAD_TERMINATOR.len());
+                }
+                let mut buf = [0; 1024];
+                match stream.read(&mut buf).unwrap() {
+                    0 => break None,
+                    bytes_read => response.extend_from_slice(&buf[..bytes_read]),
+                }
+            };
+            let Some(head_end) = head_end else {
+                return String::from_utf8_lossy(&response).into_owned();
+            };
+
+            let head = String::from_utf8_lossy(&response[start..head_end]).to_ascii_lowercase();
+            let status = head.get(9..12).unwrap_or_default();
+            if head.starts_with("http/") && status.starts_with('1') && status != "101" {
+                start = head_end;
+                continue;
+            }
+
+            let length = head.lines().find_map(|line| {
+                line.strip_prefix("content-length:")
+                    .map(|length| length.trim().parse::<usize>().unwrap())
+            });
+            let complete = |response: &[u8]| match length {
+                _ if status == "204" || status == "304" => true,
+                Some(length) => response.len() >= head_end + length,
+                None if head.contains("transfer-encoding: chunked") => {
+                    response.ends_with(b"0\r\n\r\n")
+                }
+                None => false,
+            };
+            while !complete(&response) {
+                let mut buf = [0; 1024];
+                match stream.read(&mut buf).unwrap() {
+                    0 => break,
+                    bytes_read => response.extend_from_slice(&buf[..bytes_read]),
+                }
+            }
+            return String::from_utf8_lossy(&response).into_owned();
+        }
+    }
+
+    /// Sends a `GET` request for `path` with the extra `headers`, and reads the response.
+    ///
+    /// Each line of `headers` has to end with `\r\n`.
+    pub(crate) fn get(addr: SocketAddr, path: &str, headers: &str) -> String {
+        let mut stream = connect(addr);
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+            path, headers
+        )
+        .unwrap();
+        read_response(&mut stream)
+    }
+
+    /// Returns the value of the first header called `name` in the head of `response`.
+    pub(crate) fn header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+        response.split("\r\n\r\n").next()?.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    /// Decodes a chunked body, stopping at the last chunk or where the received bytes end.
+    pub(crate) fn dechunk(mut chunked: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        while let Some(line_end) = chunked.windows(2).position(|window| window == b"\r\n") {
+            let size = std::str::from_utf8(&chunked[..line_end]).unwrap();
+            let size = usize::from_str_radix(size, 16).unwrap();
+            let start = line_end + 2;
+            if size == 0 || chunked.len() < start + size {
+                break;
+            }
+            body.extend_from_slice(&chunked[start..start + size]);
+            chunked = &chunked[(start + size + 2).min(chunked.len())..];
+        }
+        body
+    }
+
+    #[test]
+    fn layers_wrap_middleware_and_routes_in_order() {
+        let addr = serve(|app| {
+            app.add_layer(layer_fn(|req, _socket, next| async move {
+                let response = next.run(req).await;
+                let body = format!(
+                    "outer({})",
+                    String::from_utf8_lossy(response.body().unwrap_or_default())
+                );
+                HttpResponseBuilder::new(response.status())
+                    .body(body)
+                    .build()
+            }));
+            app.add_layer(layer_fn(|req, _socket, next| async move {
+                let response = next.run(req).await;
+                let body = format!(
+                    "inner({}, {})",
+                    response.route().unwrap_or("-"),
+                    String::from_utf8_lossy(response.body().unwrap_or_default())
+                );
+                HttpResponseBuilder::new(response.status())
+                    .body(body)
+                    .build()
+            }));
+            app.add_middleware(|req, _socket| async move {
+                match req.uri().as_ref() {
+                    "/blocked" => Middleware::ShortCircuit(
+                        HttpResponseBuilder::forbidden()
+                            .body("blocked".into())
+                            .build(),
+                    ),
+                    _ => Middleware::Continue(req),
+                }
+            });
+            app.add_route("/hello/{name}", HttpMethod::GET, |_req, _params| async move {
+                HttpResponseBuilder::ok().body("hello".into()).build()
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /hello/world HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nouter(inner(/hello/{name}, hello))"));
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /blocked HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(response.ends_with("\r\n\r\nouter(inner(-, blocked))"));
+    }
+
+    #[test]
+    fn state_and_extensions_reach_handlers() {
+        struct Greeting(&'static str);
+        struct User(String);
+
+        let addr = serve(|app| {
+            app.with_state(Greeting("hello"));
+            app.add_middleware(|mut req, _socket| async move {
+                let user = req
+                    .headers()
+                    .get("X-User")
+                    .unwrap_or("anonymous")
+                    .to_string();
+                req.extensions_mut().insert(User(user));
+                Middleware::Continue(req)
+            });
+            app.add_route("/", HttpMethod::GET, |req, _params| async move {
+                let greeting = req.state::<Greeting>().unwrap();
+                let user = req.extensions().get::<User>().unwrap();
+                HttpResponseBuilder::ok()
+                    .body(format!("{} {}", greeting.0, user.0))
+                    .build()
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nX-User: bart\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello bart"));
+    }
+
+    #[test]
+    fn routes_return_into_response_types() {
+        use crate::Json;
+
+        let addr = serve(|app| {
+            app.add_route(
+                "/text",
+                HttpMethod::GET,
+                |_req, _params| async move { "plain" },
+            );
+            app.add_route("/items/{id}", HttpMethod::GET, |_req, params| async move {
+                match params.path_segment().pairs().next() {
+                    Some((_, Some("1"))) => Ok(Json(vec!["tea"])),
+                    _ => Err((HttpStatus::NotFound, "no such item")),
+                }
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /text HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.contains("\r\nContent-Type: text/plain; charset=utf-8\r\n"));
+        assert!(response.ends_with("\r\n\r\nplain"));
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /items/1 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n[\"tea\"]"));
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /items/2 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with("\r\n\r\nno such item"));
+    }
+
+    #[test]
+    fn extractor_handlers_and_rejections() {
+        use crate::{Json, Path, State};
+
+        struct Prefix(&'static str);
+
+        let addr = serve(|app| {
+            app.with_state(Prefix("order"));
+            app.add_handler(
+                "/orders/{id}",
+                HttpMethod::POST,
+                |State(prefix): State<Prefix>,
+                 Path(id): Path<u32>,
+                 Json(items): Json<Vec<String>>| async move {
+                    HttpResponseBuilder::ok()
+                        .body(format!("{} {}: {}", prefix.0, id, items.join(",")))
+                        .build()
+                },
+            );
+        });
+
+        let body = r#"["tea","milk"]"#;
+        let mut stream = connect(addr);
+        stream
+            .write_all(
+                format!(
+                    "POST /orders/7 HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\norder 7: tea,milk"));
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"POST /orders/seven HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n[]")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn header_read_timeout_slow_client() {
+        let addr = serve(|app| {
+            app.set_header_read_timeout(Duration::from_millis(200));
+            app.add_route("/", HttpMethod::GET, |_req, _params| async move {
+                HttpResponseBuilder::ok().build()
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: loc").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        stream.write_all(b"alhost\r\n").unwrap();
+
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+
+    #[test]
+    fn body_read_timeout_slow_client() {
+        let addr = serve(|app| {
+            app.set_body_read_timeout(Duration::from_millis(200));
+            app.add_route("/upload", HttpMethod::POST, |_req, _params| async move {
+                HttpResponseBuilder::ok().build()
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nabc")
+            .unwrap();
+
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+
+    #[test]
+    fn body_read_across_multiple_reads() {
+        let addr = serve(|app| {
+            app.set_body_read_timeout(Duration::from_secs(5));
+            app.add_route("/upload", HttpMethod::POST, |_req, _params| async move {
+                HttpResponseBuilder::created().build()
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\n\r\nabc")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        stream.write_all(b"def").unwrap();
+
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+    }
+
+    #[test]
+    fn handler_timeout_slow_handler() {
+        let addr = serve(|app| {
+            app.set_handler_timeout(Duration::from_millis(200));
+            app.add_route("/slow", HttpMethod::GET, |_req, _params| async move {
+                sleep(Duration::from_secs(5)).await;
+                HttpResponseBuilder::ok().build()
+            });
+        });
+
+        let start = Instant::now();
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn write_timeout_client_not_reading() {
+        const BODY_SIZE: usize = 64 * 1024 * 1024;
+
+        let addr = serve(|app| {
+            app.set_write_timeout(Duration::from_millis(200));
+            app.add_route("/large", HttpMethod::GET, |_req, _params| async move {
+                HttpResponseBuilder::ok()
+                    .body("a".repeat(BODY_SIZE))
+                    .build()
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /large HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+
+        // The server gave up writing, so only what fit in the socket buffers arrives.
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        assert!(response.len() < BODY_SIZE);
+    }
+
+    #[test]
+    fn streaming_body_is_chunked() {
+        let addr = serve(|app| {
+            app.add_route("/stream", HttpMethod::GET, |_req, _params| async move {
+                let chunks = ["hello", "", " streaming", " world"]
+                    .map(|chunk| Ok(chunk.as_bytes().to_vec()));
+                HttpResponseBuilder::ok()
+                    .header("Content-Length", "999")
+                    .body_stream(futures::stream::iter(chunks))
+                    .build()
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /stream HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(!response.contains("Content-Length"));
+        assert!(
+            response.ends_with("\r\n\r\n5\r\nhello\r\nA\r\n streaming\r\n6\r\n world\r\n0\r\n\r\n")
+        );
+    }
+
+    #[test]
+    fn streaming_body_applies_backpressure() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        const CHUNK_SIZE: usize = 64 * 1024;
+        let produced = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&produced);
+        let addr = serve(move |app| {
+            app.add_route("/export", HttpMethod::GET, move |_req, _params| {
+                let counter = Arc::clone(&counter);
+                async move {
+                    let chunks = futures::stream::repeat_with(move || {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        Ok(vec![b'a'; CHUNK_SIZE])
+                    });
+                    HttpResponseBuilder::ok().body_stream(chunks).build()
+                }
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /export HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+
+        // With the client not reading, production stalls once the socket buffers are full.
+        let stalled_at = produced.load(Ordering::SeqCst);
+        assert!(stalled_at > 0);
+        assert!(stalled_at < 1024, "produced {} chunks", stalled_at);
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(produced.load(Ordering::SeqCst), stalled_at);
+
+        // Reading resumes production.
+        let mut buf = vec![0; CHUNK_SIZE];
+        for _ in 0..(stalled_at + 64) {
+            stream.read_exact(&mut buf).unwrap();
+        }
+        assert!(produced.load(Ordering::SeqCst) > stalled_at);
+    }
+
+    /// Registers an upload route that reports how many body bytes it received and their sum.
+    fn upload_route(app: &mut HoochAppBuilder) {
+        app.add_streaming_route("/upload", HttpMethod::POST, |mut req, _params| async move {
+            let mut body = req.take_body_stream().unwrap();
+            let (mut len, mut sum) = (0usize, 0u64);
+            while let Some(chunk) = body.next().await {
+                match chunk {
+                    Ok(chunk) => {
+                        len += chunk.len();
+                        sum += chunk.iter().map(|&byte| byte as u64).sum::<u64>();
+                    }
+                    Err(err) => return (HttpStatus::BadRequest, err.to_string()).into_response(),
+                }
+            }
+            format!("{} {}", len, sum).into_response()
+        });
+    }
+
+    #[test]
+    fn streaming_upload_with_expect_continue() {
+        const BODY_SIZE: usize = 4 * REQUEST_BUFFER_SIZE;
+
+        let addr = serve(upload_route);
+        let body: Vec<u8> = (0..BODY_SIZE).map(|idx| idx as u8).collect();
+        let sum: u64 = body.iter().map(|&byte| byte as u64).sum();
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(
+                format!(
+                    "POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: {}\r\n\r\n",
+                    BODY_SIZE
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+
+        let mut interim = [0; CONTINUE.len()];
+        stream.read_exact(&mut interim).unwrap();
+        assert_eq!(&interim, CONTINUE);
+
+        for part in body.chunks(10_000) {
+            stream.write_all(part).unwrap();
+        }
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&format!("\r\n\r\n{} {}", BODY_SIZE, sum)));
+    }
+
+    #[test]
+    fn streaming_upload_chunked() {
+        let addr = serve(upload_route);
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        stream.write_all(b"\r\n2\r\nde\r\n0\r\n\r\n").unwrap();
+
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n5 495"));
+    }
+
+    #[test]
+    fn streaming_route_rejects_without_continue() {
+        let addr = serve(|app| {
+            app.add_streaming_route("/upload", HttpMethod::POST, |_req, _params| async move {
+                (HttpStatus::Unauthorized, ())
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 1000000000\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    }
+
+    #[test]
+    fn buffered_route_decodes_chunked_body() {
+        let addr = serve(|app| {
+            app.add_route("/echo", HttpMethod::POST, |req, _params| async move {
+                let headers = req.headers();
+                format!(
+                    "{:?} {:?} {}",
+                    headers.get("Transfer-Encoding"),
+                    headers.get("Content-Length"),
+                    String::from_utf8_lossy(req.body())
+                )
+            });
+        });
+
+        // The body arrives in pieces, followed by a pipelined request.
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        stream
+            .write_all(b"lo\r\n6\r\n world\r\n0\r\n\r\nPOST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nConnection: close\r\n\r\nnext")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let responses: Vec<_> = response.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+        assert_eq!(responses.len(), 2);
+        // The head describes the decoded body.
+        assert!(responses[0].ends_with("\r\n\r\nNone Some(\"11\") hello world"));
+        assert!(responses[1].ends_with("\r\n\r\nNone Some(\"4\") next"));
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n")
+            .unwrap();
+        assert!(read_response(&mut stream).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        // The encoded body has to fit in the request buffer.
+        let mut stream = connect(addr);
+        let chunk = vec![b'a'; REQUEST_BUFFER_SIZE];
+        stream
+            .write_all(
+                b"POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n",
+            )
+            .unwrap();
+        stream
+            .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+            .unwrap();
+        let _ = stream.write_all(&chunk);
+        assert!(read_response(&mut stream).starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    }
+
+    #[test]
+    fn ambiguous_framing_is_rejected() {
+        let addr = serve(|app| {
+            app.add_route("/echo", HttpMethod::POST, |req, _params| async move {
+                String::from_utf8_lossy(req.body()).into_owned()
+            });
+            app.add_streaming_route("/upload", HttpMethod::POST, |_req, _params| async move {
+                "streamed"
+            });
+        });
+
+        for path in ["/echo", "/upload"] {
+            for framing in [
+                "Transfer-Encoding: gzip\r\n",
+                "Transfer-Encoding: gzip, chunked\r\n",
+                "Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n",
+                "Content-Length: abc\r\n",
+                "Content-Length: +4\r\n",
+                "Content-Length: 4\r\nContent-Length: 5\r\n",
+                "Content-Length: 4, 5\r\n",
+                "Transfer-Encoding: chunked\r\nContent-Length: 4\r\n",
+                "Content-Length: 4\r\nTransfer-Encoding: chunked\r\n",
+            ] {
+                let mut stream = connect(addr);
+                stream
+                    .write_all(
+                        format!(
+                            "POST {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n4\r\nbody\r\n0\r\n\r\n",
+                            path, framing
+                        )
+                        .as_bytes(),
+                    )
+                    .unwrap();
+                // The connection closes after the response.
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                assert!(
+                    response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+                    "{} {:?}",
+                    path,
+                    framing
+                );
+                assert!(response.contains("Connection: close\r\n"));
+            }
+        }
+
+        // Repeating the same length is allowed.
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nContent-Length: 4\r\n\r\nbody")
+            .unwrap();
+        assert!(read_response(&mut stream).ends_with("\r\n\r\nbody"));
+    }
+
+    #[test]
+    fn ambiguous_framing_closes_before_pipelined_requests() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static SMUGGLED: AtomicUsize = AtomicUsize::new(0);
+        let addr = serve(|app| {
+            app.add_route("/echo", HttpMethod::POST, |req, _params| async move {
+                String::from_utf8_lossy(req.body()).into_owned()
+            });
+            app.add_route("/smuggled", HttpMethod::GET, |_req, _params| async move {
+                SMUGGLED.fetch_add(1, Ordering::SeqCst);
+                "smuggled"
+            });
+        });
+
+        // A proxy ignoring the unknown coding would see the second request as part of the body.
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip\r\n\r\nGET /smuggled HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert_eq!(response.matches("HTTP/1.1").count(), 1);
+
+        // HTTP/1.0 requests with a chunked body are answered, but close the connection.
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"POST /echo HTTP/1.0\r\nConnection: keep-alive\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nbody\r\n0\r\n\r\nGET /smuggled HTTP/1.0\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(!response.contains("Connection: keep-alive"));
+        assert!(response.ends_with("\r\n\r\nbody"));
+
+        assert_eq!(SMUGGLED.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn buffered_route_sends_continue() {
+        let addr = serve(|app| {
+            app.add_route("/echo", HttpMethod::POST, |req, _params| async move {
+                String::from_utf8_lossy(req.body()).into_owned()
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"POST /echo HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n")
+            .unwrap();
+        let mut interim = [0; CONTINUE.len()];
+        stream.read_exact(&mut interim).unwrap();
+        assert_eq!(&interim, CONTINUE);
+
+        stream.write_all(b"hello").unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn sse_streams_events_and_keep_alives() {
+        use crate::{Event, Sse};
+
+        let addr = serve(|app| {
+            app.add_route("/events", HttpMethod::GET, |_req, _params| async move {
+                let first = Event::default().id("1").data("hello");
+                let events = futures::stream::iter([first]).chain(futures::stream::pending());
+                Sse::new(events).keep_alive(Duration::from_millis(50))
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+
+        let mut received = Vec::new();
+        let mut buf = [0; 1024];
+        while !String::from_utf8_lossy(&received).contains(": keep-alive\n\n") {
+            let bytes_read = stream.read(&mut buf).unwrap();
+            assert!(bytes_read > 0);
+            received.extend_from_slice(&buf[..bytes_read]);
+        }
+
+        let received = String::from_utf8_lossy(&received);
+        assert!(received.contains("\r\nContent-Type: text/event-stream\r\n"));
+        assert!(received.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(received.contains("id: 1\ndata: hello\n\n"));
+    }
+
+    #[test]
+    fn sse_stream_dropped_when_client_disconnects() {
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        };
+
+        use crate::Sse;
+
+        /// Sets the flag once the event stream is dropped.
+        struct DropFlag(Arc<AtomicBool>);
+
+        impl Drop for DropFlag {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&dropped);
+        let addr = serve(move |app| {
+            app.add_route("/events", HttpMethod::GET, move |_req, _params| {
+                let guard = DropFlag(Arc::clone(&flag));
+                async move {
+                    let events = futures::stream::pending().map(move |event| {
+                        let _guard = &guard;
+                        event
+                    });
+                    Sse::new(events).keep_alive(Duration::from_millis(20))
+                }
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut buf = [0; 1024];
+        assert!(stream.read(&mut buf).unwrap() > 0);
+        drop(stream);
+
+        let start = Instant::now();
+        while !dropped.load(Ordering::SeqCst) {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "event stream was not dropped"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn upgrade_hands_over_stream_and_buffered_bytes() {
+        let addr = serve(|app| {
+            app.add_route("/shout", HttpMethod::GET, |_req, _params| async move {
+                HttpResponseBuilder::switching_protocols()
+                    .header("Upgrade", "shout")
+                    .header("Connection", "Upgrade")
+                    .on_upgrade(|mut upgraded| async move {
+                        let mut pending = upgraded.buffered;
+                        let mut buf = vec![0; 1024];
+                        loop {
+                            pending.make_ascii_uppercase();
+                            if write_all(&mut upgraded.stream, &pending).await.is_err() {
+                                return;
+                            }
+                            match upgraded.stream.read(&mut buf).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(bytes_read) => pending = buf[..bytes_read].to_vec(),
+                            }
+                        }
+                    })
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /shout HTTP/1.1\r\nHost: localhost\r\nUpgrade: shout\r\nConnection: Upgrade\r\n\r\nearly ")
+            .unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0; 1024];
+        while !received.ends_with(b"EARLY ") {
+            let bytes_read = stream.read(&mut buf).unwrap();
+            assert!(bytes_read > 0);
+            received.extend_from_slice(&buf[..bytes_read]);
+        }
+        stream.write_all(b"late").unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let received =
+            String::from_utf8_lossy(&received).into_owned() + &read_response(&mut stream);
+        assert!(received.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(received.contains("\r\nUpgrade: shout\r\n"));
+        assert!(received.ends_with("\r\n\r\nEARLY LATE"));
+    }
+
+    #[test]
+    fn connect_tunnels_only_on_success() {
+        use crate::Path;
+
+        let addr = serve(|app| {
+            app.add_handler(
+                "{authority}",
+                HttpMethod::CONNECT,
+                |authority: Path<String>| async move {
+                    let authority = authority.into_inner();
+                    if authority.starts_with("blocked") {
+                        return HttpResponseBuilder::forbidden().build();
+                    }
+                    HttpResponseBuilder::ok()
+                        .on_upgrade(move |mut upgraded| async move {
+                            let greeting = format!("tunnel to {}", authority);
+                            let _ = write_all(&mut upgraded.stream, greeting.as_bytes()).await;
+                        })
+                        .build()
+                },
+            );
+            app.add_route("/plain", HttpMethod::GET, |_req, _params| async move {
+                HttpResponseBuilder::ok().body("plain".into()).on_upgrade(
+                    |mut upgraded| async move {
+                        let _ = write_all(&mut upgraded.stream, b"hijacked").await;
+                    },
+                )
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\ntunnel to example.com:443"));
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"CONNECT blocked.example:443 HTTP/1.1\r\nHost: blocked.example:443\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(!response.contains("tunnel"));
+
+        // Only protocol switches and CONNECT responses hand the connection over.
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /plain HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.ends_with("\r\n\r\nplain"));
+    }
+
+    fn hello_routes(app: &mut HoochAppBuilder) {
+        app.add_route(
+            "/hello",
+            HttpMethod::GET,
+            |_req, _params| async move { "hi" },
+        );
+        app.add_route(
+            "/hello",
+            HttpMethod::HEAD,
+            |_req, _params| async move { "hi" },
+        );
+        app.add_route("/stream", HttpMethod::GET, |_req, _params| async move {
+            let chunks = ["hello", " world"].map(|chunk| Ok(chunk.as_bytes().to_vec()));
+            HttpResponseBuilder::ok()
+                .body_stream(futures::stream::iter(chunks))
+                .build()
+        });
+    }
+
+    #[test]
+    fn http_1_0_closes_unless_keep_alive() {
+        let addr = serve(hello_routes);
+
+        // No `Host` header is needed, and the connection closes after the response.
+        let mut stream = connect(addr);
+        stream.write_all(b"GET /hello HTTP/1.0\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 2\r\n"));
+        assert!(!response.contains("Connection"));
+        assert!(response.ends_with("\r\n\r\nhi"));
+
+        let mut stream = connect(addr);
+        for _ in 0..2 {
+            stream
+                .write_all(b"GET /hello HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+                .unwrap();
+            let response = read_response(&mut stream);
+            assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+            assert!(response.contains("Connection: keep-alive\r\n"));
+            assert!(response.ends_with("\r\n\r\nhi"));
+        }
+
+        // Without chunked encoding, closing the connection ends a streamed body.
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /stream HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.ends_with("\r\n\r\nhello world"));
+    }
+
+    #[test]
+    fn unknown_methods_and_versions_are_rejected() {
+        let addr = serve(hello_routes);
+
+        for (request, status) in [
+            (
+                &b"TRACE /hello HTTP/1.1\r\nHost: localhost\r\n\r\n"[..],
+                "501 Not Implemented",
+            ),
+            (
+                b"GET /hello HTTP/0.9\r\n\r\n",
+                "505 HTTP Version Not Supported",
+            ),
+            (
+                b"GET /hello HTTP/3\r\nHost: localhost\r\n\r\n",
+                "505 HTTP Version Not Supported",
+            ),
+            (
+                b"GET /hello HTTP/2.0\r\nHost: localhost\r\n\r\n",
+                "505 HTTP Version Not Supported",
+            ),
+            (b"GET /hello\r\n\r\n", "400 Bad Request"),
+        ] {
+            let mut stream = connect(addr);
+            stream.write_all(request).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with(&format!("HTTP/1.1 {}\r\n", status)));
+        }
+    }
+
+    #[test]
+    fn http_1_1_connections_persist() {
+        let addr = serve(|app| {
+            app.set_header_read_timeout(Duration::from_millis(300));
+            hello_routes(app);
+        });
+
+        // Pipelined requests are answered in order until the client asks to close.
+        let mut stream = connect(addr);
+        stream
+            .write_all(
+                b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                  GET /stream HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                  HEAD /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let responses: Vec<_> = response.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+        assert_eq!(responses.len(), 3);
+        assert!(responses[0].contains("Content-Length: 2\r\n"));
+        assert!(responses[0].ends_with("\r\n\r\nhi"));
+        assert!(responses[1].ends_with("\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"));
+        assert!(responses[2].contains("Content-Length: 2\r\n"));
+        assert!(responses[2].contains("Connection: close\r\n"));
+        assert!(responses[2].ends_with("\r\n\r\n"));
+
+        // An idle connection is closed without a response once the header read timeout elapses.
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut stream).ends_with("hi"));
+        let started = Instant::now();
+        assert!(read_response(&mut stream).is_empty());
+        assert!(started.elapsed() >= Duration::from_millis(250));
+
+        let mut stream = connect(addr);
+        stream.write_all(b"GET /hello HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn malformed_requests_are_rejected() {
+        let addr = serve(hello_routes);
+        for request in [
+            &b"GET /hello HTTP/1.1\r\nHost localhost\r\n\r\n"[..],
+            b"GET /hello HTTP/1.1\r\nHost: \xff\r\n\r\n",
+            b"GET\r\n\r\n",
+        ] {
+            let mut stream = connect(addr);
+            stream.write_all(request).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        }
+        // The server keeps serving.
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut stream).ends_with("hi"));
     }
 }
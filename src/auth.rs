@@ -0,0 +1,568 @@
+//! # Authentication
+//!
+//! Layers checking the credentials of each request before it reaches middleware and routes:
+//!
+//! * [`BasicAuth`] for HTTP Basic authentication, with a callback verifying user name and
+//!   password,
+//! * [`BearerAuth`] for bearer tokens in the `Authorization` header, with a callback validating
+//!   the token,
+//! * [`ApiKeyAuth`] for API keys in a header or a query parameter, with a callback validating
+//!   the key.
+//!
+//! A callback resolves to the authenticated principal, any `Clone` type describing the caller,
+//! or to `None` to reject the credentials. The principal is attached to the request as
+//! [`Authenticated<P>`], which handlers take as an extractor. Requests without valid credentials
+//! are answered with `401 Unauthorized` and a `WWW-Authenticate` challenge for the scheme.
+//!
+//! Layers apply to every request unless limited to some paths with `path_prefix`. Add a
+//! [`Cors`](crate::Cors) layer before an authentication layer, so preflight requests, which
+//! never carry credentials, are answered first.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hooch_http::{Authenticated, BasicAuth, BearerAuth, HoochAppBuilder, HttpMethod};
+//!
+//! #[derive(Clone)]
+//! struct User {
+//!     name: String,
+//! }
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_layer(
+//!     BasicAuth::new(|user: String, password: String| async move {
+//!         (user == "admin" && password == "secret").then(|| User { name: user })
+//!     })
+//!     .realm("admin")
+//!     .path_prefix("/admin/"),
+//! );
+//! app.add_layer(
+//!     BearerAuth::new(|token: String| async move {
+//!         (token == "t0ken").then(|| User { name: "api".into() })
+//!     })
+//!     .path_prefix("/api/"),
+//! );
+//!
+//! app.add_handler(
+//!     "/admin/dashboard",
+//!     HttpMethod::GET,
+//!     |Authenticated(user): Authenticated<User>| async move { format!("hello {}", user.name) },
+//! );
+//! ```
+
+use std::{future::Future, net::SocketAddr, ops::Deref};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::future::BoxFuture;
+
+use crate::{
+    extract::FromRequest,
+    layer::{Layer, Next},
+    request::{HttpRequest, Params},
+    response::{HttpResponse, HttpResponseBuilder},
+    shared::has_path_prefix,
+};
+
+/// The principal a request was authenticated as, attached by an authentication layer.
+///
+/// As an extractor it rejects requests that were not authenticated with
+/// `401 Unauthorized`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Authenticated<P>(pub P);
+
+impl<P> Authenticated<P> {
+    /// Consumes the extractor, returning the principal.
+    pub fn into_inner(self) -> P {
+        self.0
+    }
+}
+
+impl<P> Deref for Authenticated<P> {
+    type Target = P;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<P: Clone + Send + Sync + 'static> FromRequest for Authenticated<P> {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        _params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        req.extensions()
+            .get::<Authenticated<P>>()
+            .cloned()
+            .ok_or_else(|| HttpResponseBuilder::unauthorized().build())
+    }
+}
+
+/// Settings shared by the authentication layers.
+#[derive(Debug, Clone)]
+pub(crate) struct Scope {
+    pub(crate) realm: String,
+    pub(crate) prefixes: Vec<String>,
+}
+
+impl Scope {
+    pub(crate) fn new(realm: &str) -> Self {
+        Self {
+            realm: realm.to_string(),
+            prefixes: Vec::new(),
+        }
+    }
+
+    /// Returns `true` if the request must be authenticated.
+    pub(crate) fn covers(&self, req: &HttpRequest<'_>) -> bool {
+        let path = req.uri().as_ref().split('?').next().unwrap_or_default();
+        self.prefixes.is_empty()
+            || self
+                .prefixes
+                .iter()
+                .any(|prefix| has_path_prefix(path, prefix))
+    }
+
+    /// The response rejecting a request, challenging the client to authenticate with `scheme`.
+    pub(crate) fn challenge(&self, scheme: &str, params: &str) -> HttpResponse {
+        HttpResponseBuilder::unauthorized()
+            .header(
+                "WWW-Authenticate",
+                format!("{} realm={}{}", scheme, quote(&self.realm), params),
+            )
+            .build()
+    }
+}
+
+/// Quotes a challenge parameter value.
+pub(crate) fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Returns the credentials of an `Authorization` header using `scheme`.
+pub(crate) fn credentials<'a>(req: &HttpRequest<'a>, scheme: &str) -> Option<&'a str> {
+    let (name, credentials) = req.headers().get("Authorization")?.trim().split_once(' ')?;
+    let credentials = credentials.trim();
+    (name.eq_ignore_ascii_case(scheme) && !credentials.is_empty()).then_some(credentials)
+}
+
+/// Runs the rest of the pipeline with the principal attached, or rejects the request.
+pub(crate) async fn authenticate<P: Send + Sync + 'static>(
+    mut req: HttpRequest<'static>,
+    next: Next,
+    principal: Option<P>,
+    rejection: impl FnOnce() -> HttpResponse,
+) -> HttpResponse {
+    match principal {
+        Some(principal) => {
+            req.extensions_mut().insert(Authenticated(principal));
+            next.run(req).await
+        }
+        None => rejection(),
+    }
+}
+
+/// Layer requiring HTTP Basic authentication.
+///
+/// See the [module documentation](self) for how requests are authenticated.
+#[derive(Debug, Clone)]
+pub struct BasicAuth<F> {
+    verify: F,
+    scope: Scope,
+}
+
+impl<F> BasicAuth<F> {
+    /// Creates a layer verifying user names and passwords with `verify`, in the realm
+    /// `restricted`.
+    pub fn new<P, Fut>(verify: F) -> Self
+    where
+        F: Fn(String, String) -> Fut,
+        Fut: Future<Output = Option<P>>,
+    {
+        Self {
+            verify,
+            scope: Scope::new("restricted"),
+        }
+    }
+
+    /// Sets the realm named in challenges, which browsers show when asking for credentials.
+    pub fn realm(mut self, realm: impl Into<String>) -> Self {
+        self.scope.realm = realm.into();
+        self
+    }
+
+    /// Only requires authentication for paths under `prefix`, matching whole path segments, so
+    /// `/admin` covers `/admin/users` but not `/administrator`. May be called several times.
+    pub fn path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.scope.prefixes.push(prefix.into());
+        self
+    }
+}
+
+impl<F, Fut, P> Layer for BasicAuth<F>
+where
+    F: Fn(String, String) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Option<P>> + Send + 'static,
+    P: Send + Sync + 'static,
+{
+    fn call<'a>(
+        &'a self,
+        req: HttpRequest<'static>,
+        _socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            if !self.scope.covers(&req) {
+                return next.run(req).await;
+            }
+            let decoded = credentials(&req, "Basic")
+                .and_then(|encoded| STANDARD.decode(encoded).ok())
+                .and_then(|decoded| String::from_utf8(decoded).ok());
+            let principal = match decoded
+                .as_deref()
+                .and_then(|decoded| decoded.split_once(':'))
+            {
+                Some((user, password)) => (self.verify)(user.into(), password.into()).await,
+                None => None,
+            };
+            authenticate(req, next, principal, || {
+                self.scope.challenge("Basic", ", charset=\"UTF-8\"")
+            })
+            .await
+        })
+    }
+}
+
+/// Layer requiring a bearer token.
+///
+/// See the [module documentation](self) for how requests are authenticated.
+#[derive(Debug, Clone)]
+pub struct BearerAuth<F> {
+    validate: F,
+    scope: Scope,
+}
+
+impl<F> BearerAuth<F> {
+    /// Creates a layer validating tokens with `validate`, in the realm `api`.
+    pub fn new<P, Fut>(validate: F) -> Self
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Option<P>>,
+    {
+        Self {
+            validate,
+            scope: Scope::new("api"),
+        }
+    }
+
+    /// Sets the realm named in challenges.
+    pub fn realm(mut self, realm: impl Into<String>) -> Self {
+        self.scope.realm = realm.into();
+        self
+    }
+
+    /// Only requires authentication for paths under `prefix`, matching whole path segments, so
+    /// `/admin` covers `/admin/users` but not `/administrator`. May be called several times.
+    pub fn path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.scope.prefixes.push(prefix.into());
+        self
+    }
+}
+
+impl<F, Fut, P> Layer for BearerAuth<F>
+where
+    F: Fn(String) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Option<P>> + Send + 'static,
+    P: Send + Sync + 'static,
+{
+    fn call<'a>(
+        &'a self,
+        req: HttpRequest<'static>,
+        _socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            if !self.scope.covers(&req) {
+                return next.run(req).await;
+            }
+            // A request without a token is only challenged; a rejected token is reported.
+            let token = credentials(&req, "Bearer");
+            let error = if token.is_some() {
+                ", error=\"invalid_token\""
+            } else {
+                ""
+            };
+            let principal = match token {
+                Some(token) => (self.validate)(token.to_string()).await,
+                None => None,
+            };
+            authenticate(req, next, principal, || {
+                self.scope.challenge("Bearer", error)
+            })
+            .await
+        })
+    }
+}
+
+/// Where an API key is looked up.
+#[derive(Debug, Clone)]
+enum KeySource {
+    Header(String),
+    Query(String),
+}
+
+/// Layer requiring an API key in a header or a query parameter.
+///
+/// Challenges use the non-standard `ApiKey` scheme, naming where the key is expected. See the
+/// [module documentation](self) for how requests are authenticated.
+#[derive(Debug, Clone)]
+pub struct ApiKeyAuth<F> {
+    validate: F,
+    source: KeySource,
+    scope: Scope,
+}
+
+impl<F> ApiKeyAuth<F> {
+    /// Creates a layer reading the key from the header `name`, such as `X-API-Key`, and
+    /// validating it with `validate`.
+    pub fn header<P, Fut>(name: impl Into<String>, validate: F) -> Self
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Option<P>>,
+    {
+        Self {
+            validate,
+            source: KeySource::Header(name.into()),
+            scope: Scope::new("api"),
+        }
+    }
+
+    /// Creates a layer reading the key from the query parameter `name`, such as `api_key`, and
+    /// validating it with `validate`.
+    ///
+    /// Keys in URLs end up in logs and browser histories; prefer a header where possible.
+    pub fn query<P, Fut>(name: impl Into<String>, validate: F) -> Self
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Option<P>>,
+    {
+        Self {
+            validate,
+            source: KeySource::Query(name.into()),
+            scope: Scope::new("api"),
+        }
+    }
+
+    /// Sets the realm named in challenges.
+    pub fn realm(mut self, realm: impl Into<String>) -> Self {
+        self.scope.realm = realm.into();
+        self
+    }
+
+    /// Only requires authentication for paths under `prefix`, matching whole path segments, so
+    /// `/admin` covers `/admin/users` but not `/administrator`. May be called several times.
+    pub fn path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.scope.prefixes.push(prefix.into());
+        self
+    }
+
+    fn key(&self, req: &HttpRequest<'_>) -> Option<String> {
+        let key = match &self.source {
+            KeySource::Header(name) => req.headers().get(name)?.trim().to_string(),
+            KeySource::Query(name) => {
+                let (_, query) = req.uri().as_ref().split_once('?')?;
+                serde_urlencoded::from_str::<Vec<(String, String)>>(query)
+                    .ok()?
+                    .into_iter()
+                    .find(|(key, _)| key == name)?
+                    .1
+            }
+        };
+        (!key.is_empty()).then_some(key)
+    }
+}
+
+impl<F, Fut, P> Layer for ApiKeyAuth<F>
+where
+    F: Fn(String) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Option<P>> + Send + 'static,
+    P: Send + Sync + 'static,
+{
+    fn call<'a>(
+        &'a self,
+        req: HttpRequest<'static>,
+        _socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            if !self.scope.covers(&req) {
+                return next.run(req).await;
+            }
+            let principal = match self.key(&req) {
+                Some(key) => (self.validate)(key).await,
+                None => None,
+            };
+            let location = match &self.source {
+                KeySource::Header(name) => format!(", header={}", quote(name)),
+                KeySource::Query(name) => format!(", query={}", quote(name)),
+            };
+            authenticate(req, next, principal, || {
+                self.scope.challenge("ApiKey", &location)
+            })
+            .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        app::tests::{get, header, serve, serve_layer},
+        HoochAppBuilder, HttpMethod, ServeDir,
+    };
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct User(String);
+
+    fn routes(app: &mut HoochAppBuilder) {
+        for path in ["/private", "/public"] {
+            app.add_handler(
+                path,
+                HttpMethod::GET,
+                |user: Option<Authenticated<User>>| async move {
+                    match user {
+                        Some(Authenticated(User(name))) => format!("hello {}", name),
+                        None => "hello stranger".to_string(),
+                    }
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn basic_auth() {
+        let addr = serve_layer(
+            BasicAuth::new(|user: String, password: String| async move {
+                (password == "p:ss").then_some(User(user))
+            })
+            .realm("my \"app\"")
+            .path_prefix("/private"),
+            routes,
+        );
+
+        // "bart:p:ss"
+        let response = get(addr, "/private", "Authorization: basic YmFydDpwOnNz\r\n");
+        assert!(response.ends_with("hello bart"));
+
+        for headers in [
+            "",
+            "Authorization: Basic YmFydDp3cm9uZw==\r\n",
+            "Authorization: Basic !!!\r\n",
+            "Authorization: Bearer YmFydDpwOnNz\r\n",
+        ] {
+            let response = get(addr, "/private", headers);
+            assert!(
+                response.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+                "{}",
+                headers
+            );
+            assert_eq!(
+                header(&response, "WWW-Authenticate"),
+                Some("Basic realm=\"my \\\"app\\\"\", charset=\"UTF-8\"")
+            );
+        }
+
+        assert!(get(addr, "/public", "").ends_with("hello stranger"));
+        // Prefixes match whole path segments.
+        assert!(get(addr, "/privateer", "").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn prefixes_match_the_paths_files_are_served_from() {
+        let root = std::env::temp_dir().join(format!("hooch-http-auth-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("private")).unwrap();
+        std::fs::write(root.join("private/secret.txt"), "secret").unwrap();
+        let addr = serve_layer(
+            BearerAuth::new(|_token: String| async { None::<User> }).path_prefix("/private"),
+            move |app| {
+                app.add_handler("/{*path}", HttpMethod::GET, ServeDir::new(&root));
+            },
+        );
+
+        for path in [
+            "/private/secret.txt",
+            "/%70rivate/secret.txt",
+            "//private/secret.txt",
+            "/./private/secret.txt",
+        ] {
+            let response = get(addr, path, "");
+            assert!(
+                response.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn bearer_auth() {
+        let bearer = BearerAuth::new(|token: String| async move {
+            token.strip_prefix("token-").map(|name| User(name.into()))
+        });
+        let addr = serve_layer(bearer, routes);
+
+        let response = get(addr, "/private", "Authorization: Bearer token-lisa\r\n");
+        assert!(response.ends_with("hello lisa"));
+
+        let response = get(addr, "/public", "");
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert_eq!(
+            header(&response, "WWW-Authenticate"),
+            Some("Bearer realm=\"api\"")
+        );
+
+        let response = get(addr, "/private", "Authorization: Bearer forged\r\n");
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert_eq!(
+            header(&response, "WWW-Authenticate"),
+            Some("Bearer realm=\"api\", error=\"invalid_token\"")
+        );
+    }
+
+    #[test]
+    fn api_key_auth() {
+        let validate = |key: String| async move { (key == "k3y").then(|| User("service".into())) };
+
+        let addr = serve_layer(ApiKeyAuth::header("X-API-Key", validate), routes);
+        assert!(get(addr, "/private", "x-api-key: k3y\r\n").ends_with("hello service"));
+        let response = get(addr, "/private", "X-API-Key: nope\r\n");
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert_eq!(
+            header(&response, "WWW-Authenticate"),
+            Some("ApiKey realm=\"api\", header=\"X-API-Key\"")
+        );
+
+        let api_key = ApiKeyAuth::query("api_key", validate).path_prefix("/private");
+        let addr = serve_layer(api_key, routes);
+        assert!(get(addr, "/private?page=2&api_key=k3y", "").ends_with("hello service"));
+        assert!(get(addr, "/private?api_key=k%33y", "").ends_with("hello service"));
+        let response = get(addr, "/private?key=k3y", "");
+        assert_eq!(
+            header(&response, "WWW-Authenticate"),
+            Some("ApiKey realm=\"api\", query=\"api_key\"")
+        );
+        assert!(get(addr, "/public", "").ends_with("hello stranger"));
+    }
+
+    #[test]
+    fn extractor_rejects_unauthenticated_requests() {
+        let addr = serve(|app| {
+            app.add_handler(
+                "/me",
+                HttpMethod::GET,
+                |Authenticated(user): Authenticated<User>| async move { user.0 },
+            );
+        });
+        assert!(get(addr, "/me", "").starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    }
+}
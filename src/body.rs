@@ -0,0 +1,122 @@
+//! # Response Bodies
+//!
+//! A [`Body`] is either held fully in memory or produced incrementally by a
+//! [`Stream`](futures::Stream) of byte chunks. Streaming bodies are written with
+//! `Transfer-Encoding: chunked`, and the next chunk is only pulled from the stream once the
+//! previous one has been written to the socket, so a slow client slows the producer down instead
+//! of letting chunks pile up in memory.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use futures::{stream, StreamExt};
+//! use hooch_http::HttpResponseBuilder;
+//!
+//! let rows = stream::iter(0..1_000_000).map(|row| Ok(format!("{}\n", row).into_bytes()));
+//! let response = HttpResponseBuilder::ok()
+//!     .body_stream(rows)
+//!     .build();
+//! ```
+
+use std::{fmt::Debug, io};
+
+use futures::{stream::BoxStream, Stream, StreamExt};
+
+/// A boxed stream of body chunks.
+///
+/// An `Err` item aborts the response; the connection is closed without the terminating chunk so
+/// the client can tell the body is incomplete.
+pub type BodyStream = BoxStream<'static, io::Result<Vec<u8>>>;
+
+/// The body of an HTTP response.
+#[derive(Default)]
+pub enum Body {
+    /// No body.
+    #[default]
+    Empty,
+    /// A body held fully in memory.
+    Full(Vec<u8>),
+    /// A body produced chunk by chunk and sent with chunked transfer-encoding.
+    Stream(BodyStream),
+}
+
+impl Body {
+    /// Create a streaming body from a stream of byte chunks.
+    pub fn from_stream<S>(stream: S) -> Self
+    where
+        S: Stream<Item = io::Result<Vec<u8>>> + Send + 'static,
+    {
+        Self::Stream(stream.boxed())
+    }
+
+    /// Get the body bytes, if the body is held in memory.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Full(bytes) => Some(bytes),
+            Self::Empty | Self::Stream(_) => None,
+        }
+    }
+
+    /// Returns `true` if the body is produced by a stream.
+    pub fn is_stream(&self) -> bool {
+        matches!(self, Self::Stream(_))
+    }
+}
+
+impl Debug for Body {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => f.write_str("Empty"),
+            Self::Full(bytes) => f.debug_tuple("Full").field(&bytes.len()).finish(),
+            Self::Stream(_) => f.write_str("Stream(..)"),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(value: Vec<u8>) -> Self {
+        Self::Full(value)
+    }
+}
+
+impl From<String> for Body {
+    fn from(value: String) -> Self {
+        Self::Full(value.into_bytes())
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(value: &'static str) -> Self {
+        Self::Full(value.as_bytes().to_vec())
+    }
+}
+
+/// Appends `chunk` to `buffer` framed as a single chunk of a chunked body.
+///
+/// Empty chunks are skipped, since a zero-length chunk marks the end of the body.
+pub(crate) fn encode_chunk(chunk: &[u8], buffer: &mut Vec<u8>) {
+    if chunk.is_empty() {
+        return;
+    }
+    buffer.extend_from_slice(format!("{:X}\r\n", chunk.len()).as_bytes());
+    buffer.extend_from_slice(chunk);
+    buffer.extend_from_slice(b"\r\n");
+}
+
+/// The terminating chunk of a chunked body, with no trailers.
+pub(crate) const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_framing() {
+        let mut buffer = Vec::new();
+        encode_chunk(b"hello world, this is hooch", &mut buffer);
+        encode_chunk(b"", &mut buffer);
+        buffer.extend_from_slice(LAST_CHUNK);
+
+        assert_eq!(buffer, b"1A\r\nhello world, this is hooch\r\n0\r\n\r\n");
+    }
+}
@@ -0,0 +1,615 @@
+//! # Response Compression
+//!
+//! The [`Compression`] layer compresses response bodies with the best coding the client lists
+//! in its `Accept-Encoding` header. Each coding is behind its own cargo feature:
+//!
+//! * `gzip` for `gzip`,
+//! * `deflate` for `deflate` (zlib wrapped, as HTTP defines it),
+//! * `brotli` for `br`.
+//!
+//! A response is compressed when its `Content-Type` is compressible, its body is at least the
+//! configured minimum size, and it neither carries a `Content-Encoding` already nor covers just
+//! a `Content-Range`. Compressed responses get a `Content-Encoding` header, lose any
+//! `Content-Length` (it is recomputed when the response is written) and have strong `ETag`s
+//! weakened. Every response that could have been compressed gets `Vary: Accept-Encoding`, so
+//! caches keep the variants apart.
+//!
+//! Streaming bodies are compressed chunk by chunk, and the encoder is flushed after every chunk
+//! so the client never waits on data the handler already produced. Server-sent events are never
+//! compressed.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hooch_http::{Compression, HoochAppBuilder};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_layer(Compression::new().min_size(256));
+//! ```
+
+use std::{
+    io::{self, Write},
+    net::SocketAddr,
+};
+
+use futures::{future::BoxFuture, stream, StreamExt};
+
+use crate::{
+    body::{Body, BodyStream},
+    layer::{Layer, Next},
+    request::HttpRequest,
+    response::HttpResponse,
+    shared::quality,
+    websocket::has_token,
+    HttpMethod,
+};
+
+/// Content types compressed by default.
+///
+/// Entries ending in `/` match a whole top-level type and entries starting with `+` match a
+/// structured syntax suffix; anything else must match the media type exactly.
+const DEFAULT_CONTENT_TYPES: &[&str] = &[
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "image/svg+xml",
+    "+json",
+    "+xml",
+];
+
+/// Content types that are never compressed, whatever the configuration says.
+const EXCLUDED_CONTENT_TYPES: &[&str] = &["text/event-stream"];
+
+/// A content coding supported for compressing responses and decoding requests.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Coding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "deflate")]
+    Deflate,
+}
+
+impl Coding {
+    /// Every enabled coding, in order of preference when the client has no preference.
+    pub(crate) const ALL: &'static [Coding] = &[
+        #[cfg(feature = "brotli")]
+        Coding::Brotli,
+        #[cfg(feature = "gzip")]
+        Coding::Gzip,
+        #[cfg(feature = "deflate")]
+        Coding::Deflate,
+    ];
+
+    /// The name of the coding in `Accept-Encoding` and `Content-Encoding` headers.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Coding::Brotli => "br",
+            #[cfg(feature = "gzip")]
+            Coding::Gzip => "gzip",
+            #[cfg(feature = "deflate")]
+            Coding::Deflate => "deflate",
+        }
+    }
+
+    /// Looks up an enabled coding by its name, ignoring case.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        let name = match name.trim() {
+            name if name.eq_ignore_ascii_case("x-gzip") => "gzip",
+            name => name,
+        };
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|coding| coding.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Layer compressing response bodies according to the client's `Accept-Encoding`.
+///
+/// See the [module documentation](self) for which responses are compressed.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    codings: Vec<Coding>,
+    min_size: usize,
+    content_types: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compression {
+    /// Creates a layer using every enabled coding, compressing bodies of at least 1 KiB with a
+    /// text, JSON, JavaScript, XML, WebAssembly or SVG content type.
+    pub fn new() -> Self {
+        Self {
+            codings: Coding::ALL.to_vec(),
+            min_size: 1024,
+            content_types: DEFAULT_CONTENT_TYPES
+                .iter()
+                .map(|content_type| content_type.to_string())
+                .collect(),
+        }
+    }
+
+    /// Sets the smallest body, in bytes, that is compressed.
+    ///
+    /// Streaming bodies are compressed regardless of size unless they declare a smaller
+    /// `Content-Length`.
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Replaces the list of compressible content types.
+    ///
+    /// Entries ending in `/`, such as `text/`, match a whole top-level type and entries starting
+    /// with `+`, such as `+json`, match a structured syntax suffix. Other entries must equal the
+    /// media type, without parameters. Matching ignores case.
+    pub fn content_types<I, S>(mut self, content_types: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.content_types = content_types
+            .into_iter()
+            .map(|content_type| content_type.into().to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Enables or disables `gzip`.
+    #[cfg(feature = "gzip")]
+    pub fn gzip(self, enabled: bool) -> Self {
+        self.with_coding(Coding::Gzip, enabled)
+    }
+
+    /// Enables or disables `deflate`.
+    #[cfg(feature = "deflate")]
+    pub fn deflate(self, enabled: bool) -> Self {
+        self.with_coding(Coding::Deflate, enabled)
+    }
+
+    /// Enables or disables `br`.
+    #[cfg(feature = "brotli")]
+    pub fn brotli(self, enabled: bool) -> Self {
+        self.with_coding(Coding::Brotli, enabled)
+    }
+
+    fn with_coding(mut self, coding: Coding, enabled: bool) -> Self {
+        self.codings.retain(|enabled| *enabled != coding);
+        if enabled {
+            self.codings.push(coding);
+            self.codings
+                .sort_by_key(|coding| Coding::ALL.iter().position(|c| c == coding));
+        }
+        self
+    }
+
+    /// Returns `true` if the response is one this layer compresses for a willing client.
+    fn is_compressible(&self, response: &HttpResponse) -> bool {
+        if matches!(u16::from(response.status()), 100..=199 | 204 | 304)
+            || response.header("Content-Encoding").is_some()
+            || response.header("Content-Range").is_some()
+            || has_token(response.header("Cache-Control"), "no-transform")
+        {
+            return false;
+        }
+
+        let Some(content_type) = response.header("Content-Type") else {
+            return false;
+        };
+        let media_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if EXCLUDED_CONTENT_TYPES.contains(&media_type.as_str())
+            || !self.content_types.iter().any(|pattern| {
+                if pattern.ends_with('/') {
+                    media_type.starts_with(pattern.as_str())
+                } else if pattern.starts_with('+') {
+                    media_type.ends_with(pattern.as_str())
+                } else {
+                    media_type == *pattern
+                }
+            })
+        {
+            return false;
+        }
+
+        let length = match response.body() {
+            Some(body) => Some(body.len()),
+            None if response.is_streaming() => response
+                .header("Content-Length")
+                .and_then(|length| length.trim().parse().ok()),
+            None => Some(0),
+        };
+        length.is_none_or(|length| length > 0 && length >= self.min_size)
+    }
+
+    /// Picks the coding the client prefers among the enabled ones.
+    ///
+    /// Codings the client rates equally are picked in the order of [`Coding::ALL`].
+    fn negotiate(&self, accept_encoding: &str) -> Option<Coding> {
+        let mut best = None;
+        for &coding in &self.codings {
+            let quality = quality(accept_encoding, coding.name())
+                .or_else(|| quality(accept_encoding, "*"))
+                .unwrap_or(0);
+            if quality > best.map_or(0, |(_, best)| best) {
+                best = Some((coding, quality));
+            }
+        }
+        best.map(|(coding, _)| coding)
+    }
+}
+
+impl Layer for Compression {
+    fn call<'a>(
+        &'a self,
+        req: HttpRequest<'static>,
+        _socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            let accept_encoding = req.headers().get("Accept-Encoding").map(str::to_owned);
+            let connect = req.method() == HttpMethod::CONNECT;
+
+            let mut response = next.run(req).await;
+            if connect || !self.is_compressible(&response) {
+                return response;
+            }
+
+            response.append_vary("Accept-Encoding");
+
+            let Some(coding) = accept_encoding.and_then(|accept| self.negotiate(&accept)) else {
+                return response;
+            };
+            let body = match response.take_body() {
+                Body::Full(body) => match compress(coding, &body) {
+                    Ok(compressed) => Body::Full(compressed),
+                    Err(_) => {
+                        response.set_body(Body::Full(body));
+                        return response;
+                    }
+                },
+                Body::Stream(chunks) => Body::Stream(compress_stream(coding, chunks)),
+                Body::Empty => Body::Empty,
+            };
+            response.set_body(body);
+            response.insert_header("Content-Encoding", coding.name());
+            response.remove_header("Content-Length");
+            if let Some(etag) = response.header("ETag")
+                && !etag.starts_with("W/")
+            {
+                let etag = format!("W/{}", etag);
+                response.insert_header("ETag", etag);
+            }
+            response
+        })
+    }
+}
+
+/// A streaming encoder for one of the supported codings, writing into an in-memory buffer.
+enum Encoder {
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    #[cfg(feature = "deflate")]
+    Deflate(flate2::write::ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(coding: Coding) -> Self {
+        match coding {
+            #[cfg(feature = "brotli")]
+            Coding::Brotli => Self::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                5,
+                22,
+            ))),
+            #[cfg(feature = "gzip")]
+            Coding::Gzip => Self::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "deflate")]
+            Coding::Deflate => Self::Deflate(flate2::write::ZlibEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            #[cfg(feature = "brotli")]
+            Self::Brotli(encoder) => encoder.as_mut(),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder,
+            #[cfg(feature = "deflate")]
+            Self::Deflate(encoder) => encoder,
+        }
+    }
+
+    fn output(&mut self) -> &mut Vec<u8> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Self::Brotli(encoder) => encoder.get_mut(),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.get_mut(),
+            #[cfg(feature = "deflate")]
+            Self::Deflate(encoder) => encoder.get_mut(),
+        }
+    }
+
+    /// Compresses a chunk and flushes the encoder, returning everything it produced so far.
+    fn write(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        let writer = self.writer();
+        writer.write_all(chunk)?;
+        writer.flush()?;
+        Ok(std::mem::take(self.output()))
+    }
+
+    /// Ends the compressed stream, returning the remaining output.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Self::Brotli(encoder) => Ok(encoder.into_inner()),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "deflate")]
+            Self::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Compresses a whole body.
+fn compress(coding: Coding, body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(coding);
+    encoder.writer().write_all(body)?;
+    encoder.finish()
+}
+
+/// Compresses a streaming body, flushing the encoder after every chunk.
+fn compress_stream(coding: Coding, chunks: BodyStream) -> BodyStream {
+    stream::unfold(Some((Encoder::new(coding), chunks)), |state| async move {
+        let (mut encoder, mut chunks) = state?;
+        loop {
+            let compressed = match chunks.next().await {
+                Some(Ok(chunk)) if chunk.is_empty() => continue,
+                Some(Ok(chunk)) => encoder.write(&chunk),
+                Some(Err(err)) => return Some((Err(err), None)),
+                None => return Some((encoder.finish(), None)),
+            };
+            match compressed {
+                Ok(compressed) if compressed.is_empty() => continue,
+                Ok(compressed) => return Some((Ok(compressed), Some((encoder, chunks)))),
+                Err(err) => return Some((Err(err), None)),
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    #[cfg(feature = "deflate")]
+    use flate2::read::ZlibDecoder;
+    #[cfg(feature = "gzip")]
+    use flate2::{read::GzDecoder, write};
+
+    use super::*;
+    use crate::{
+        app::tests::{connect, dechunk, serve},
+        Event, HoochAppBuilder, HttpResponseBuilder, Sse,
+    };
+
+    /// Splits a response into its lowercased head and its body with chunked framing removed.
+    fn split_response(response: &[u8]) -> (String, Vec<u8>) {
+        let head_end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap()
+            + 4;
+        let head = String::from_utf8_lossy(&response[..head_end]).to_ascii_lowercase();
+        let mut body = response[head_end..].to_vec();
+        if head.contains("transfer-encoding: chunked") {
+            body = dechunk(&body);
+        }
+        (head, body)
+    }
+
+    fn request(addr: SocketAddr, path: &str, accept_encoding: &str) -> (String, Vec<u8>) {
+        let mut stream = connect(addr);
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: {}\r\nConnection: close\r\n\r\n",
+            path, accept_encoding
+        )
+        .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        split_response(&response)
+    }
+
+    fn routes(app: &mut HoochAppBuilder) {
+        app.add_layer(Compression::new().min_size(64));
+        app.add_route("/text", HttpMethod::GET, |_req, _params| async move {
+            "hello world ".repeat(100)
+        });
+        app.add_route(
+            "/small",
+            HttpMethod::GET,
+            |_req, _params| async move { "hello" },
+        );
+        app.add_route("/binary", HttpMethod::GET, |_req, _params| async move {
+            vec![7; 4096]
+        });
+        app.add_route("/stream", HttpMethod::GET, |_req, _params| async move {
+            let chunks = (0..50).map(|row| Ok(format!("row {}\n", row).into_bytes()));
+            HttpResponseBuilder::ok()
+                .header("Content-Type", "text/csv")
+                .body_stream(stream::iter(chunks))
+                .build()
+        });
+        app.add_route("/pending", HttpMethod::GET, |_req, _params| async move {
+            let first = stream::iter([Ok(b"first chunk".to_vec())]);
+            HttpResponseBuilder::ok()
+                .header("Content-Type", "text/plain")
+                .body_stream(first.chain(stream::pending()))
+                .build()
+        });
+        app.add_route("/events", HttpMethod::GET, |_req, _params| async move {
+            Sse::new(stream::iter([Event::default().data("x".repeat(500))])).without_keep_alive()
+        });
+    }
+
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    fn negotiates_codings() {
+        let compression = Compression::new();
+        assert_eq!(
+            compression.negotiate("gzip, deflate, br"),
+            Some(Coding::Brotli)
+        );
+        assert_eq!(
+            compression.negotiate("gzip;q=1.0, br;q=0.5"),
+            Some(Coding::Gzip)
+        );
+        assert_eq!(compression.negotiate("br;q=0, *"), Some(Coding::Gzip));
+        assert_eq!(compression.negotiate("DEFLATE"), Some(Coding::Deflate));
+        assert_eq!(compression.negotiate("identity, *;q=0"), None);
+        assert_eq!(compression.negotiate("gzip;q=0.0000"), None);
+        assert_eq!(
+            compression.brotli(false).negotiate("br, deflate"),
+            Some(Coding::Deflate)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn compresses_full_bodies_with_gzip() {
+        let addr = serve(routes);
+
+        let (head, body) = request(addr, "/text", "gzip");
+        assert!(head.contains("content-encoding: gzip\r\n"));
+        assert!(head.contains("vary: accept-encoding\r\n"));
+        assert!(head.contains(&format!("content-length: {}\r\n", body.len())));
+        let mut decoded = String::new();
+        GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "hello world ".repeat(100));
+    }
+
+    #[test]
+    #[cfg(feature = "deflate")]
+    fn compresses_full_bodies_with_deflate() {
+        let addr = serve(routes);
+
+        let (head, body) = request(addr, "/text", "deflate");
+        assert!(head.contains("content-encoding: deflate\r\n"));
+        assert!(head.contains(&format!("content-length: {}\r\n", body.len())));
+        let mut decoded = String::new();
+        ZlibDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "hello world ".repeat(100));
+    }
+
+    #[test]
+    #[cfg(feature = "brotli")]
+    fn compresses_full_bodies_with_brotli() {
+        let addr = serve(routes);
+
+        let (head, body) = request(addr, "/text", "br");
+        assert!(head.contains("content-encoding: br\r\n"));
+        assert!(head.contains(&format!("content-length: {}\r\n", body.len())));
+        let mut decoded = String::new();
+        brotli::Decompressor::new(&body[..], 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "hello world ".repeat(100));
+    }
+
+    #[test]
+    fn sends_identity_without_a_supported_coding() {
+        let addr = serve(routes);
+
+        // The body is sent as is, but caches still learn it varies.
+        let (head, body) = request(addr, "/text", "identity");
+        assert!(!head.contains("content-encoding"));
+        assert!(head.contains("vary: accept-encoding\r\n"));
+        assert_eq!(body, "hello world ".repeat(100).as_bytes());
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn skips_small_and_incompressible_bodies() {
+        let addr = serve(routes);
+
+        let (head, body) = request(addr, "/small", "gzip");
+        assert!(!head.contains("content-encoding"));
+        assert!(!head.contains("vary"));
+        assert_eq!(body, b"hello");
+
+        let (head, body) = request(addr, "/binary", "gzip");
+        assert!(!head.contains("content-encoding"));
+        assert_eq!(body, vec![7; 4096]);
+
+        let (head, body) = request(addr, "/events", "gzip");
+        assert!(head.contains("content-type: text/event-stream\r\n"));
+        assert!(!head.contains("content-encoding"));
+        assert!(String::from_utf8(body).unwrap().starts_with("data: xxx"));
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn compresses_streaming_bodies() {
+        let addr = serve(routes);
+
+        let (head, body) = request(addr, "/stream", "gzip");
+        assert!(head.contains("content-encoding: gzip\r\n"));
+        assert!(head.contains("transfer-encoding: chunked\r\n"));
+        let mut decoded = String::new();
+        GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        let expected: String = (0..50).map(|row| format!("row {}\n", row)).collect();
+        assert_eq!(decoded, expected);
+
+        // Each chunk is flushed through the encoder, so it arrives before the stream ends.
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /pending HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n")
+            .unwrap();
+        let mut decoder = write::GzDecoder::new(Vec::new());
+        let mut received = Vec::new();
+        while decoder.get_ref().as_slice() != b"first chunk" {
+            let mut buf = [0; 1024];
+            let bytes_read = stream.read(&mut buf).unwrap();
+            assert!(bytes_read > 0);
+            received.extend_from_slice(&buf[..bytes_read]);
+            if received.windows(4).any(|window| window == b"\r\n\r\n") {
+                let (_, body) = split_response(&received);
+                decoder = write::GzDecoder::new(Vec::new());
+                decoder.write_all(&body).unwrap();
+                decoder.flush().unwrap();
+            }
+        }
+    }
+}
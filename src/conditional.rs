@@ -0,0 +1,486 @@
+//! # Conditional Requests
+//!
+//! Validators let clients and caches ask whether a resource changed instead of downloading it
+//! again. A response carries them as an entity tag ([`ETag`]), set with
+//! [`HttpResponseBuilder::etag`](crate::HttpResponseBuilder::etag), and a modification date,
+//! set with [`HttpResponseBuilder::last_modified`](crate::HttpResponseBuilder::last_modified).
+//! Strong tags promise byte-for-byte identical representations, weak tags only semantically
+//! equivalent ones.
+//!
+//! The [`ConditionalRequests`] layer answers `GET` and `HEAD` requests carrying `If-Match`,
+//! `If-None-Match`, `If-Modified-Since` or `If-Unmodified-Since` based on the validators of
+//! the `200 OK` response the handler produced: with `304 Not Modified` if the client's copy is
+//! still current, and with `412 Precondition Failed` if a precondition does not hold. Responses
+//! without an `ETag` get a strong one computed from their body, unless hashing is turned off;
+//! streamed bodies are never hashed.
+//!
+//! Requests that change state must have their preconditions checked before the change is made,
+//! which the layer can not do. Handlers of such requests call [`evaluate_preconditions`] with the
+//! current validators of the resource themselves.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! use hooch_http::{ConditionalRequests, ETag, HoochAppBuilder, HttpMethod, HttpResponseBuilder};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_layer(ConditionalRequests::new());
+//!
+//! app.add_route("/article", HttpMethod::GET, |_req, _params| async move {
+//!     HttpResponseBuilder::ok()
+//!         .etag(ETag::strong("article-v7"))
+//!         .last_modified(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+//!         .body("...".into())
+//!         .build()
+//! });
+//! ```
+
+use std::{
+    fmt,
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::future::BoxFuture;
+
+use crate::{
+    layer::{Layer, Next},
+    request::HttpRequest,
+    response::{HttpResponse, HttpResponseBuilder, HttpStatus},
+    HttpMethod,
+};
+
+/// Headers describing the representation, dropped from `304 Not Modified` responses.
+const REPRESENTATION_HEADERS: [&str; 5] = [
+    "Content-Type",
+    "Content-Length",
+    "Content-Encoding",
+    "Content-Range",
+    "Accept-Ranges",
+];
+
+/// An entity tag, identifying one version of a resource's representation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ETag {
+    tag: String,
+    weak: bool,
+}
+
+impl ETag {
+    /// Creates a strong entity tag.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tag` contains characters other than visible ASCII, or a double quote.
+    pub fn strong(tag: impl Into<String>) -> Self {
+        Self::new(tag.into(), false)
+    }
+
+    /// Creates a weak entity tag.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tag` contains characters other than visible ASCII, or a double quote.
+    pub fn weak(tag: impl Into<String>) -> Self {
+        Self::new(tag.into(), true)
+    }
+
+    fn new(tag: String, weak: bool) -> Self {
+        assert!(
+            tag.bytes().all(is_etag_char),
+            "entity tags consist of visible ASCII characters other than '\"'"
+        );
+        Self { tag, weak }
+    }
+
+    /// Creates a strong entity tag from a hash of `bytes`.
+    ///
+    /// The hash (64 bit FNV-1a) is stable across processes and servers, but not cryptographic.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+        Self::strong(format!("{:016x}", hash))
+    }
+
+    /// Parses an entity tag as sent in headers, such as `"abc"` or `W/"abc"`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (weak, quoted) = match value.strip_prefix("W/") {
+            Some(quoted) => (true, quoted),
+            None => (false, value),
+        };
+        let tag = quoted.strip_prefix('"')?.strip_suffix('"')?;
+        tag.bytes().all(is_etag_char).then(|| Self {
+            tag: tag.to_string(),
+            weak,
+        })
+    }
+
+    /// Returns the tag, without quotes.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Returns `true` if the tag is weak.
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Compares two tags strongly: both must be strong and equal.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Compares two tags weakly, ignoring whether they are weak.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            f.write_str("W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+fn is_etag_char(byte: u8) -> bool {
+    byte.is_ascii_graphic() && byte != b'"'
+}
+
+/// Evaluates the conditional headers of a request against the current validators of the
+/// resource, following the order of RFC 9110.
+///
+/// Returns the status to answer with instead of processing the request, if any:
+/// `412 Precondition Failed` when `If-Match` or `If-Unmodified-Since` fail, or when
+/// `If-None-Match` matches a request other than `GET` or `HEAD`, and `304 Not Modified` when
+/// the client's copy is current.
+pub fn evaluate_preconditions(
+    req: &HttpRequest<'_>,
+    etag: Option<&ETag>,
+    last_modified: Option<SystemTime>,
+) -> Option<HttpStatus> {
+    // HTTP dates only carry whole seconds.
+    let last_modified = last_modified.and_then(|modified| {
+        let secs = modified.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        Some(UNIX_EPOCH + Duration::from_secs(secs))
+    });
+    Conditions::of(req).evaluate(etag, last_modified)
+}
+
+/// The conditional headers of a request.
+#[derive(Debug, Clone, Copy)]
+struct Conditions<'a> {
+    if_match: Option<&'a str>,
+    if_none_match: Option<&'a str>,
+    if_modified_since: Option<SystemTime>,
+    if_unmodified_since: Option<SystemTime>,
+    /// Whether the request is a `GET` or `HEAD` request.
+    safe: bool,
+}
+
+impl<'a> Conditions<'a> {
+    fn of(req: &HttpRequest<'a>) -> Self {
+        let headers = req.headers();
+        let date = |name| {
+            headers
+                .get(name)
+                .and_then(|date| httpdate::parse_http_date(date).ok())
+        };
+        Self {
+            if_match: headers.get("If-Match"),
+            if_none_match: headers.get("If-None-Match"),
+            if_modified_since: date("If-Modified-Since"),
+            if_unmodified_since: date("If-Unmodified-Since"),
+            safe: matches!(req.method(), HttpMethod::GET | HttpMethod::HEAD),
+        }
+    }
+
+    fn evaluate(
+        &self,
+        etag: Option<&ETag>,
+        last_modified: Option<SystemTime>,
+    ) -> Option<HttpStatus> {
+        if let Some(if_match) = self.if_match {
+            if !etag_matches(if_match, etag, true) {
+                return Some(HttpStatus::PreconditionFailed);
+            }
+        } else if let (Some(since), Some(modified)) = (self.if_unmodified_since, last_modified)
+            && modified > since
+        {
+            return Some(HttpStatus::PreconditionFailed);
+        }
+
+        if let Some(if_none_match) = self.if_none_match {
+            if etag_matches(if_none_match, etag, false) {
+                return Some(if self.safe {
+                    HttpStatus::NotModified
+                } else {
+                    HttpStatus::PreconditionFailed
+                });
+            }
+        } else if let (true, Some(since), Some(modified)) =
+            (self.safe, self.if_modified_since, last_modified)
+            && modified <= since
+        {
+            return Some(HttpStatus::NotModified);
+        }
+        None
+    }
+}
+
+/// Returns `true` if a list of entity tags, or `*`, matches the current tag.
+///
+/// The strong comparison used for `If-Match` never matches weak tags.
+pub(crate) fn etag_matches(list: &str, etag: Option<&ETag>, strong: bool) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+    let Some(etag) = etag else {
+        return false;
+    };
+    list.split(',')
+        .filter_map(ETag::parse)
+        .any(|candidate| match strong {
+            true => candidate.strong_eq(etag),
+            false => candidate.weak_eq(etag),
+        })
+}
+
+/// Layer answering conditional `GET` and `HEAD` requests with `304 Not Modified` or
+/// `412 Precondition Failed`.
+///
+/// See the [module documentation](self) for which responses are affected.
+#[derive(Debug, Clone, Copy)]
+pub struct ConditionalRequests {
+    hash_bodies: bool,
+}
+
+impl Default for ConditionalRequests {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConditionalRequests {
+    /// Creates a layer that computes missing `ETag`s from response bodies.
+    pub fn new() -> Self {
+        Self { hash_bodies: true }
+    }
+
+    /// Sets whether responses without an `ETag` get one computed from their body.
+    pub fn hash_bodies(mut self, enabled: bool) -> Self {
+        self.hash_bodies = enabled;
+        self
+    }
+}
+
+impl Layer for ConditionalRequests {
+    fn call<'a>(
+        &'a self,
+        req: HttpRequest<'static>,
+        _socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            let conditions = Conditions::of(&req);
+            let mut response = next.run(req).await;
+            if !conditions.safe || response.status() != HttpStatus::Ok {
+                return response;
+            }
+
+            if self.hash_bodies
+                && response.header("ETag").is_none()
+                && let Some(body) = response.body()
+            {
+                response.set_etag(ETag::from_bytes(body));
+            }
+            let etag = response.header("ETag").and_then(ETag::parse);
+            let last_modified = response
+                .header("Last-Modified")
+                .and_then(|date| httpdate::parse_http_date(date).ok());
+
+            match conditions.evaluate(etag.as_ref(), last_modified) {
+                Some(HttpStatus::NotModified) => {
+                    response.set_status(HttpStatus::NotModified);
+                    response.take_body();
+                    for header in REPRESENTATION_HEADERS {
+                        response.remove_header(header);
+                    }
+                    response
+                }
+                Some(status) => HttpResponseBuilder::new(status).build(),
+                None => response,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        app::tests::{get, header, serve},
+        body::Body,
+    };
+
+    fn request(method: &str, headers: &str) -> HttpRequest<'static> {
+        let request = format!(
+            "{} / HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+            method, headers
+        );
+        HttpRequest::from_bytes(request.leak().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn etags() {
+        assert_eq!(ETag::strong("abc").to_string(), "\"abc\"");
+        assert_eq!(ETag::weak("abc").to_string(), "W/\"abc\"");
+        assert_eq!(ETag::parse(" W/\"abc\" "), Some(ETag::weak("abc")));
+        assert_eq!(ETag::parse("\"\""), Some(ETag::strong("")));
+        for invalid in ["abc", "\"abc", "w/\"abc\"", "\"a\"b\"", "\"a b\""] {
+            assert_eq!(ETag::parse(invalid), None, "{}", invalid);
+        }
+
+        assert!(ETag::strong("a").strong_eq(&ETag::strong("a")));
+        assert!(!ETag::weak("a").strong_eq(&ETag::strong("a")));
+        assert!(ETag::weak("a").weak_eq(&ETag::strong("a")));
+        assert!(!ETag::weak("a").weak_eq(&ETag::weak("b")));
+
+        assert_eq!(ETag::from_bytes(b""), ETag::strong("cbf29ce484222325"));
+        assert_eq!(ETag::from_bytes(b"a"), ETag::strong("af63dc4c8601ec8c"));
+        assert!(std::panic::catch_unwind(|| ETag::strong("a\"b")).is_err());
+    }
+
+    #[test]
+    fn preconditions() {
+        let etag = ETag::strong("v2");
+        let modified = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let before = httpdate::fmt_http_date(modified - Duration::from_secs(1));
+        let at = httpdate::fmt_http_date(modified);
+        let check = |method, headers: &str| {
+            // Sub-second precision of the resource is ignored.
+            let modified = modified + Duration::from_millis(500);
+            evaluate_preconditions(&request(method, headers), Some(&etag), Some(modified))
+        };
+        let not_modified = Some(HttpStatus::NotModified);
+        let failed = Some(HttpStatus::PreconditionFailed);
+
+        assert_eq!(check("GET", ""), None);
+        assert_eq!(
+            check("GET", "If-None-Match: \"v1\", W/\"v2\"\r\n"),
+            not_modified
+        );
+        assert_eq!(check("HEAD", "If-None-Match: *\r\n"), not_modified);
+        assert_eq!(check("GET", "If-None-Match: \"v1\"\r\n"), None);
+        assert_eq!(check("PUT", "If-None-Match: *\r\n"), failed);
+        assert_eq!(
+            check("GET", &format!("If-Modified-Since: {}\r\n", at)),
+            not_modified
+        );
+        assert_eq!(
+            check("GET", &format!("If-Modified-Since: {}\r\n", before)),
+            None
+        );
+        assert_eq!(
+            check("PUT", &format!("If-Modified-Since: {}\r\n", at)),
+            None
+        );
+        // `If-None-Match` takes precedence over `If-Modified-Since`.
+        let headers = format!("If-None-Match: \"v1\"\r\nIf-Modified-Since: {}\r\n", at);
+        assert_eq!(check("GET", &headers), None);
+
+        assert_eq!(check("PUT", "If-Match: \"v2\"\r\n"), None);
+        assert_eq!(check("PUT", "If-Match: W/\"v2\"\r\n"), failed);
+        assert_eq!(check("PUT", "If-Match: \"v1\"\r\n"), failed);
+        assert_eq!(
+            check("PUT", &format!("If-Unmodified-Since: {}\r\n", at)),
+            None
+        );
+        assert_eq!(
+            check("PUT", &format!("If-Unmodified-Since: {}\r\n", before)),
+            failed
+        );
+        // `If-Match` takes precedence over `If-Unmodified-Since`.
+        let headers = format!("If-Match: *\r\nIf-Unmodified-Since: {}\r\n", before);
+        assert_eq!(check("PUT", &headers), None);
+
+        // Without a current tag, only `*` matches.
+        let req = request("PUT", "If-Match: \"v2\"\r\n");
+        assert_eq!(evaluate_preconditions(&req, None, None), failed);
+        let req = request("PUT", "If-Match: *\r\n");
+        assert_eq!(evaluate_preconditions(&req, None, None), None);
+    }
+
+    #[test]
+    fn layer() {
+        let addr = serve(|app| {
+            app.add_layer(ConditionalRequests::new());
+            app.add_route("/hashed", HttpMethod::GET, |_, _| async {
+                HttpResponseBuilder::ok()
+                    .header("Content-Type", "text/plain")
+                    .header("Cache-Control", "max-age=60")
+                    .body("hello".into())
+                    .build()
+            });
+            app.add_route("/tagged", HttpMethod::GET, |_, _| async {
+                HttpResponseBuilder::ok()
+                    .etag(ETag::weak("v1"))
+                    .last_modified(UNIX_EPOCH + Duration::from_secs(1_000_000_000))
+                    .body("tagged".into())
+                    .build()
+            });
+            app.add_route("/missing", HttpMethod::GET, |_, _| async {
+                HttpResponseBuilder::not_found().build()
+            });
+            app.add_route("/streamed", HttpMethod::GET, |_, _| async {
+                let mut response = HttpResponseBuilder::ok().build();
+                let chunks = futures::stream::iter([Ok(b"chunk".to_vec())]);
+                response.set_body(Body::Stream(Box::pin(chunks)));
+                response
+            });
+        });
+
+        let response = get(addr, "/hashed", "");
+        let etag = header(&response, "ETag").unwrap().to_string();
+        assert_eq!(etag, ETag::from_bytes(b"hello").to_string());
+        assert!(response.ends_with("hello"));
+
+        let response = get(addr, "/hashed", &format!("If-None-Match: {}\r\n", etag));
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert_eq!(header(&response, "ETag"), Some(etag.as_str()));
+        assert_eq!(header(&response, "Cache-Control"), Some("max-age=60"));
+        assert_eq!(header(&response, "Content-Type"), None);
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = get(addr, "/hashed", "If-Match: \"other\"\r\n");
+        assert!(response.starts_with("HTTP/1.1 412 Precondition Failed\r\n"));
+
+        let response = get(addr, "/tagged", "If-None-Match: \"v1\"\r\n");
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert_eq!(header(&response, "ETag"), Some("W/\"v1\""));
+        let response = get(
+            addr,
+            "/tagged",
+            "If-Modified-Since: Sun, 09 Sep 2001 01:46:40 GMT\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        // Weak tags never satisfy `If-Match`.
+        let response = get(addr, "/tagged", "If-Match: W/\"v1\"\r\n");
+        assert!(response.starts_with("HTTP/1.1 412 Precondition Failed\r\n"));
+
+        let response = get(addr, "/missing", "If-None-Match: *\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = get(addr, "/streamed", "If-None-Match: *\r\n");
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        let response = get(addr, "/streamed", "");
+        assert_eq!(header(&response, "ETag"), None);
+    }
+}
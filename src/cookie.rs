@@ -0,0 +1,597 @@
+//! # Cookies
+//!
+//! [`HttpRequest::cookies`] parses the `Cookie` header into [`Cookies`], which is also available
+//! as an extractor. Cookies are set with the [`Cookie`] builder, attached to a response with
+//! [`HttpResponseBuilder::cookie`](crate::HttpResponseBuilder::cookie) or
+//! [`HttpResponse::add_cookie`](crate::HttpResponse::add_cookie) as often as needed; each one is
+//! sent in its own `Set-Cookie` header.
+//!
+//! With the `secure-cookies` feature, a [`CookieKey`] derived from a server secret signs cookie
+//! values, so clients can read but not alter them, or encrypts them, so clients can do neither.
+//! [`Cookies::signed`] and [`Cookies::private`] read such cookies back, ignoring any that fail
+//! verification.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use hooch_http::{Cookie, Cookies, HoochAppBuilder, HttpMethod, HttpResponseBuilder, SameSite};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_handler("/visit", HttpMethod::GET, |cookies: Cookies<'static>| async move {
+//!     let visits = cookies
+//!         .get("visits")
+//!         .and_then(|visits| visits.parse::<u32>().ok())
+//!         .unwrap_or(0)
+//!         + 1;
+//!     HttpResponseBuilder::ok()
+//!         .cookie(
+//!             Cookie::new("visits", visits.to_string())
+//!                 .path("/")
+//!                 .max_age(Duration::from_secs(30 * 24 * 60 * 60))
+//!                 .http_only(true)
+//!                 .same_site(SameSite::Lax),
+//!         )
+//!         .cookie(Cookie::removal("legacy"))
+//!         .body(format!("visit {}", visits))
+//!         .build()
+//! });
+//! ```
+
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    extract::FromRequest,
+    request::{HttpRequest, Params},
+    response::HttpResponse,
+};
+
+/// The cookies sent with a request, parsed from its `Cookie` headers.
+///
+/// Pairs without a `=` are skipped and values wrapped in double quotes are unquoted. No
+/// percent-decoding is applied.
+#[derive(Debug, Clone, Default)]
+pub struct Cookies<'a> {
+    headers: Vec<&'a str>,
+}
+
+impl<'a> Cookies<'a> {
+    /// Parses the `Cookie` headers of a request.
+    pub(crate) fn from_request(req: &HttpRequest<'a>) -> Self {
+        Self {
+            headers: req
+                .headers()
+                .iter()
+                .filter(|(key, _)| key.eq_ignore_ascii_case("Cookie"))
+                .map(|(_, value)| value)
+                .collect(),
+        }
+    }
+
+    /// Get the value of the first cookie named `name`.
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.iter()
+            .find(|(cookie, _)| *cookie == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Iterate over all name-value pairs in the order they were sent.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> + '_ {
+        self.headers
+            .iter()
+            .flat_map(|header| header.split(';'))
+            .filter_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                let name = name.trim();
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                (!name.is_empty()).then_some((name, value))
+            })
+    }
+
+    /// Returns `true` if the request carries no cookies.
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Reads cookies signed with [`CookieKey::sign`].
+    #[cfg(feature = "secure-cookies")]
+    pub fn signed<'k>(&self, key: &'k CookieKey) -> SignedJar<'a, 'k> {
+        SignedJar {
+            cookies: self.clone(),
+            key,
+        }
+    }
+
+    /// Reads cookies encrypted with [`CookieKey::encrypt`].
+    #[cfg(feature = "secure-cookies")]
+    pub fn private<'k>(&self, key: &'k CookieKey) -> PrivateJar<'a, 'k> {
+        PrivateJar {
+            cookies: self.clone(),
+            key,
+        }
+    }
+}
+
+impl FromRequest for Cookies<'static> {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        _params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        Ok(req.cookies())
+    }
+}
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    /// Sent only with requests originating from the same site.
+    Strict,
+    /// Also sent when navigating to the site from elsewhere.
+    Lax,
+    /// Sent with cross-site requests too. Browsers require such cookies to be `Secure`, so the
+    /// attribute is always added.
+    None,
+}
+
+impl From<SameSite> for &'static str {
+    fn from(value: SameSite) -> Self {
+        match value {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// A cookie to set with a `Set-Cookie` response header.
+///
+/// Its [`Display`](fmt::Display) output is the header value. Names and values are sent as
+/// given, so they must not contain control characters, whitespace, double quotes, commas,
+/// semicolons or backslashes; signed and encrypted values are always safe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Creates a session cookie, which the browser discards when it closes.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// Creates a cookie telling the browser to delete the cookie named `name`.
+    ///
+    /// The path and domain must match the ones the cookie was set with.
+    pub fn removal(name: impl Into<String>) -> Self {
+        Self::new(name, "")
+            .max_age(Duration::ZERO)
+            .expires(UNIX_EPOCH)
+    }
+
+    /// Get the name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Sets the path the cookie is sent for.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Sets the domain the cookie is sent to, including its subdomains.
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Sets how long the cookie is kept, taking precedence over [`expires`](Self::expires) in
+    /// browsers supporting both.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets when the cookie expires.
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Sets whether the cookie is only sent over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Sets whether the cookie is hidden from scripts.
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Sets which cross-site requests the cookie is sent with.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Replaces the value, keeping the attributes.
+    #[cfg(feature = "secure-cookies")]
+    fn with_value(mut self, value: String) -> Self {
+        self.value = value;
+        self
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", httpdate::fmt_http_date(expires))?;
+        }
+        if self.secure || self.same_site == Some(SameSite::None) {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", <&str>::from(same_site))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "secure-cookies")]
+pub use self::secure::*;
+
+#[cfg(feature = "secure-cookies")]
+mod secure {
+    use aes_gcm::{
+        aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+        Aes256Gcm, Nonce,
+    };
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    use super::{Cookie, Cookies};
+
+    /// Length of an AES-GCM nonce.
+    const NONCE_LEN: usize = 12;
+
+    /// Secret key signing and encrypting cookie values.
+    ///
+    /// Separate keys for signing and encryption are derived from the secret, so one secret
+    /// serves both. Every server sharing cookies must use the same secret.
+    #[derive(Clone)]
+    pub struct CookieKey {
+        signing: [u8; 32],
+        encryption: [u8; 32],
+    }
+
+    impl std::fmt::Debug for CookieKey {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("CookieKey")
+        }
+    }
+
+    impl CookieKey {
+        /// Derives a key from a server secret.
+        ///
+        /// # Panics
+        ///
+        /// Panics if the secret is shorter than 32 bytes.
+        pub fn from_secret(secret: &[u8]) -> Self {
+            assert!(
+                secret.len() >= 32,
+                "cookie secret must be at least 32 bytes"
+            );
+            Self {
+                signing: derive(secret, b"hooch-http cookie signing"),
+                encryption: derive(secret, b"hooch-http cookie encryption"),
+            }
+        }
+
+        /// Creates a random key, for cookies that need not outlive the process.
+        pub fn generate() -> Self {
+            let mut secret = [0; 32];
+            OsRng.fill_bytes(&mut secret);
+            Self::from_secret(&secret)
+        }
+
+        /// Signs the cookie's value, binding it to the cookie's name.
+        ///
+        /// The value stays readable and is prefixed with its signature.
+        pub fn sign(&self, cookie: Cookie) -> Cookie {
+            let tag = self
+                .mac(cookie.name(), cookie.value())
+                .finalize()
+                .into_bytes();
+            let value = format!("{}.{}", URL_SAFE_NO_PAD.encode(tag), cookie.value());
+            cookie.with_value(value)
+        }
+
+        /// Returns the original value of a signed cookie, or `None` if it was altered.
+        pub fn verify(&self, name: &str, value: &str) -> Option<String> {
+            let (tag, value) = value.split_once('.')?;
+            let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+            self.mac(name, value).verify_slice(&tag).ok()?;
+            Some(value.to_string())
+        }
+
+        /// Encrypts the cookie's value, binding it to the cookie's name.
+        pub fn encrypt(&self, cookie: Cookie) -> Cookie {
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let payload = Payload {
+                msg: cookie.value().as_bytes(),
+                aad: cookie.name().as_bytes(),
+            };
+            let ciphertext = self
+                .cipher()
+                .encrypt(&nonce, payload)
+                .expect("encrypting a cookie cannot fail");
+            let mut sealed = nonce.to_vec();
+            sealed.extend_from_slice(&ciphertext);
+            let value = URL_SAFE_NO_PAD.encode(sealed);
+            cookie.with_value(value)
+        }
+
+        /// Returns the original value of an encrypted cookie, or `None` if it cannot be
+        /// decrypted.
+        pub fn decrypt(&self, name: &str, value: &str) -> Option<String> {
+            let sealed = URL_SAFE_NO_PAD.decode(value).ok()?;
+            if sealed.len() < NONCE_LEN {
+                return None;
+            }
+            let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+            let payload = Payload {
+                msg: ciphertext,
+                aad: name.as_bytes(),
+            };
+            let plaintext = self
+                .cipher()
+                .decrypt(Nonce::from_slice(nonce), payload)
+                .ok()?;
+            String::from_utf8(plaintext).ok()
+        }
+
+        fn mac(&self, name: &str, value: &str) -> Hmac<Sha256> {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.signing)
+                .expect("HMAC accepts keys of any length");
+            mac.update(name.as_bytes());
+            mac.update(b"=");
+            mac.update(value.as_bytes());
+            mac
+        }
+
+        fn cipher(&self) -> Aes256Gcm {
+            Aes256Gcm::new(&self.encryption.into())
+        }
+    }
+
+    /// Derives a 32 byte key for one purpose from the secret.
+    fn derive(secret: &[u8], purpose: &[u8]) -> [u8; 32] {
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+        mac.update(purpose);
+        mac.finalize().into_bytes().into()
+    }
+
+    /// The signed cookies of a request; see [`Cookies::signed`].
+    #[derive(Debug, Clone)]
+    pub struct SignedJar<'a, 'k> {
+        pub(super) cookies: Cookies<'a>,
+        pub(super) key: &'k CookieKey,
+    }
+
+    impl SignedJar<'_, '_> {
+        /// Get the verified value of the first cookie named `name`.
+        ///
+        /// Returns `None` if there is no such cookie or its signature does not match.
+        pub fn get(&self, name: &str) -> Option<String> {
+            self.key.verify(name, self.cookies.get(name)?)
+        }
+    }
+
+    /// The encrypted cookies of a request; see [`Cookies::private`].
+    #[derive(Debug, Clone)]
+    pub struct PrivateJar<'a, 'k> {
+        pub(super) cookies: Cookies<'a>,
+        pub(super) key: &'k CookieKey,
+    }
+
+    impl PrivateJar<'_, '_> {
+        /// Get the decrypted value of the first cookie named `name`.
+        ///
+        /// Returns `None` if there is no such cookie or it cannot be decrypted.
+        pub fn get(&self, name: &str) -> Option<String> {
+            self.key.decrypt(name, self.cookies.get(name)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::{
+        app::tests::{connect, read_response, serve},
+        HttpMethod, HttpResponseBuilder,
+    };
+
+    #[test]
+    fn parses_cookie_headers() {
+        let raw = b"GET / HTTP/1.1\r\nHost: localhost\r\nCookie: a=1; b = \"two\" ;flag; c=x=y\r\nCookie: a=3; d=\r\n\r\n";
+        let req = HttpRequest::from_bytes(raw).unwrap();
+        let cookies = req.cookies();
+        assert_eq!(cookies.get("a"), Some("1"));
+        assert_eq!(cookies.get("b"), Some("two"));
+        assert_eq!(cookies.get("c"), Some("x=y"));
+        assert_eq!(cookies.get("d"), Some(""));
+        assert_eq!(cookies.get("flag"), None);
+        assert_eq!(
+            cookies.iter().collect::<Vec<_>>(),
+            [
+                ("a", "1"),
+                ("b", "two"),
+                ("c", "x=y"),
+                ("a", "3"),
+                ("d", "")
+            ]
+        );
+
+        let req = HttpRequest::from_bytes(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert!(req.cookies().is_empty());
+    }
+
+    #[test]
+    fn renders_set_cookie_values() {
+        assert_eq!(Cookie::new("id", "abc").to_string(), "id=abc");
+        let cookie = Cookie::new("id", "abc")
+            .path("/")
+            .domain("example.com")
+            .max_age(Duration::from_secs(3600))
+            .expires(UNIX_EPOCH + Duration::from_secs(1_000_000_000))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Strict);
+        assert_eq!(
+            cookie.to_string(),
+            "id=abc; Path=/; Domain=example.com; Max-Age=3600; \
+             Expires=Sun, 09 Sep 2001 01:46:40 GMT; Secure; HttpOnly; SameSite=Strict"
+        );
+        assert_eq!(
+            Cookie::new("x", "1").same_site(SameSite::None).to_string(),
+            "x=1; Secure; SameSite=None"
+        );
+        assert_eq!(
+            Cookie::removal("id").path("/").to_string(),
+            "id=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn responses_carry_several_cookies() {
+        let addr = serve(|app| {
+            app.add_handler("/login", HttpMethod::GET, |cookies: Cookies<'static>| {
+                let user = cookies.get("user").unwrap_or("anonymous").to_string();
+                async move {
+                    let mut response = HttpResponseBuilder::ok()
+                        .cookie(Cookie::new("session", "s1").http_only(true))
+                        .cookie(Cookie::new("theme", "dark"))
+                        .body(user)
+                        .build();
+                    response.add_cookie(Cookie::removal("legacy"));
+                    response
+                }
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /login HTTP/1.1\r\nHost: localhost\r\nCookie: user=bart\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.ends_with("\r\n\r\nbart"));
+        let set_cookies: Vec<_> = response
+            .lines()
+            .filter_map(|line| line.strip_prefix("Set-Cookie: "))
+            .collect();
+        assert_eq!(
+            set_cookies,
+            [
+                "session=s1; HttpOnly",
+                "theme=dark",
+                "legacy=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+            ]
+        );
+    }
+
+    #[cfg(feature = "secure-cookies")]
+    #[test]
+    fn signed_and_private_cookies() {
+        let key = CookieKey::from_secret(&[7; 32]);
+        let other = CookieKey::from_secret(&[8; 32]);
+
+        let signed = key.sign(Cookie::new("user", "bart").path("/"));
+        assert!(signed.value().ends_with(".bart"));
+        assert!(signed.to_string().ends_with("; Path=/"));
+        assert_eq!(key.verify("user", signed.value()).as_deref(), Some("bart"));
+        assert_eq!(key.verify("admin", signed.value()), None);
+        assert_eq!(other.verify("user", signed.value()), None);
+        let tampered = signed.value().replace("bart", "lisa");
+        assert_eq!(key.verify("user", &tampered), None);
+
+        let sealed = key.encrypt(Cookie::new("cart", "3 items"));
+        assert!(!sealed.value().contains("items"));
+        assert_ne!(
+            sealed.value(),
+            key.encrypt(Cookie::new("cart", "3 items")).value()
+        );
+        assert_eq!(
+            key.decrypt("cart", sealed.value()).as_deref(),
+            Some("3 items")
+        );
+        assert_eq!(key.decrypt("other", sealed.value()), None);
+        assert_eq!(other.decrypt("cart", sealed.value()), None);
+        assert_eq!(key.decrypt("cart", "AAAA"), None);
+
+        let header = format!(
+            "GET / HTTP/1.1\r\nCookie: user={}; cart={}; plain=1\r\n\r\n",
+            signed.value(),
+            sealed.value()
+        );
+        let req = HttpRequest::from_bytes(header.as_bytes()).unwrap();
+        let cookies = req.cookies();
+        assert_eq!(cookies.signed(&key).get("user").as_deref(), Some("bart"));
+        assert_eq!(cookies.signed(&key).get("plain"), None);
+        assert_eq!(
+            cookies.private(&key).get("cart").as_deref(),
+            Some("3 items")
+        );
+        assert_eq!(cookies.private(&other).get("cart"), None);
+    }
+}
@@ -0,0 +1,480 @@
+//! # CORS
+//!
+//! The [`Cors`] layer implements [Cross-Origin Resource Sharing] for browser clients calling the
+//! server from another origin. It answers `OPTIONS` preflight requests itself, before any
+//! middleware or route runs, and adds the `Access-Control-*` headers to the responses of actual
+//! cross-origin requests.
+//!
+//! Allowed origins are given as a single origin, a list, or a predicate; a layer created with
+//! [`Cors::new`] allows none until configured. Requests without an `Origin` header are passed
+//! through untouched, as are requests from origins that are not allowed: the browser then
+//! withholds the response from the calling script. Preflights from origins that are not allowed,
+//! or asking for a method or headers that are not allowed, are answered with `403 Forbidden`.
+//!
+//! When credentials are allowed, wildcards are never sent: the request's own origin and headers
+//! are echoed instead, as browsers require. Responses whose headers depend on the request's
+//! origin carry `Vary: Origin`, so caches keep them apart.
+//!
+//! [Cross-Origin Resource Sharing]: https://fetch.spec.whatwg.org/#http-cors-protocol
+//!
+//! ## Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use hooch_http::{Cors, HoochAppBuilder, HttpMethod};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_layer(
+//!     Cors::new()
+//!         .allow_origins(["https://app.example.com", "https://admin.example.com"])
+//!         .allow_methods([HttpMethod::GET, HttpMethod::POST, HttpMethod::DELETE])
+//!         .allow_headers(["Content-Type", "Authorization"])
+//!         .expose_headers(["X-Request-Id"])
+//!         .allow_credentials(true)
+//!         .max_age(Duration::from_secs(600)),
+//! );
+//! ```
+
+use std::{fmt, net::SocketAddr, sync::Arc, time::Duration};
+
+use futures::future::BoxFuture;
+
+use crate::{
+    layer::{Layer, Next},
+    request::HttpRequest,
+    response::{HttpResponse, HttpResponseBuilder},
+    HttpMethod,
+};
+
+/// Which origins may make cross-origin requests.
+#[derive(Clone)]
+enum AllowOrigin {
+    Any,
+    List(Vec<String>),
+    Predicate(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+impl fmt::Debug for AllowOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Any => f.write_str("Any"),
+            Self::List(origins) => f.debug_tuple("List").field(origins).finish(),
+            Self::Predicate(_) => f.write_str("Predicate"),
+        }
+    }
+}
+
+/// Which request headers cross-origin requests may carry.
+#[derive(Debug, Clone)]
+enum AllowHeaders {
+    Any,
+    List(Vec<String>),
+}
+
+/// Layer implementing CORS, answering preflight requests and annotating actual responses.
+///
+/// See the [module documentation](self) for how requests are handled.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    origins: AllowOrigin,
+    methods: Vec<HttpMethod>,
+    headers: AllowHeaders,
+    expose_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cors {
+    /// Creates a layer allowing no origins, the `GET`, `HEAD` and `POST` methods, and no request
+    /// headers beyond those browsers always allow.
+    pub fn new() -> Self {
+        Self {
+            origins: AllowOrigin::List(Vec::new()),
+            methods: vec![HttpMethod::GET, HttpMethod::HEAD, HttpMethod::POST],
+            headers: AllowHeaders::List(Vec::new()),
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Creates a layer allowing any origin, any of the methods [`HttpMethod`] knows and any
+    /// request headers. Useful for public APIs and during development.
+    pub fn permissive() -> Self {
+        Self::new()
+            .allow_any_origin()
+            .allow_methods([
+                HttpMethod::GET,
+                HttpMethod::HEAD,
+                HttpMethod::POST,
+                HttpMethod::PUT,
+                HttpMethod::PATCH,
+                HttpMethod::DELETE,
+            ])
+            .allow_any_header()
+    }
+
+    /// Allows requests from any origin.
+    pub fn allow_any_origin(mut self) -> Self {
+        self.origins = AllowOrigin::Any;
+        self
+    }
+
+    /// Allows requests from `origin`, such as `https://example.com`, in addition to the origins
+    /// allowed already.
+    ///
+    /// Origins are compared case-insensitively and must not end in a `/`.
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Self {
+        match &mut self.origins {
+            AllowOrigin::List(origins) => origins.push(origin.into()),
+            _ => self.origins = AllowOrigin::List(vec![origin.into()]),
+        }
+        self
+    }
+
+    /// Allows requests from exactly the listed origins.
+    pub fn allow_origins<I, S>(mut self, origins: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.origins = AllowOrigin::List(origins.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Allows requests from the origins `predicate` returns `true` for, such as every subdomain
+    /// of a site.
+    pub fn allow_origin_fn<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.origins = AllowOrigin::Predicate(Arc::new(predicate));
+        self
+    }
+
+    /// Replaces the methods cross-origin requests may use.
+    pub fn allow_methods(mut self, methods: impl IntoIterator<Item = HttpMethod>) -> Self {
+        self.methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Replaces the request headers cross-origin requests may carry, compared case-insensitively.
+    pub fn allow_headers<I, S>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.headers = AllowHeaders::List(headers.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Allows cross-origin requests to carry any request headers.
+    pub fn allow_any_header(mut self) -> Self {
+        self.headers = AllowHeaders::Any;
+        self
+    }
+
+    /// Sets the response headers, beyond the CORS-safelisted ones, that scripts may read.
+    pub fn expose_headers<I, S>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.expose_headers = headers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets whether cross-origin requests may include credentials such as cookies.
+    pub fn allow_credentials(mut self, allow: bool) -> Self {
+        self.credentials = allow;
+        self
+    }
+
+    /// Sets how long browsers may cache the result of a preflight request.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Returns `true` if the response depends on the request's origin.
+    fn varies_by_origin(&self) -> bool {
+        !matches!(self.origins, AllowOrigin::Any) || self.credentials
+    }
+
+    fn is_allowed_origin(&self, origin: &str) -> bool {
+        match &self.origins {
+            AllowOrigin::Any => true,
+            AllowOrigin::List(origins) => origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin)),
+            AllowOrigin::Predicate(predicate) => predicate(origin),
+        }
+    }
+
+    /// The value of `Access-Control-Allow-Origin` for an allowed origin.
+    fn allow_origin_value(&self, origin: &str) -> String {
+        if self.varies_by_origin() {
+            origin.to_string()
+        } else {
+            "*".to_string()
+        }
+    }
+
+    /// Adds the headers shared by preflight and actual responses.
+    fn add_origin_headers(&self, response: &mut HttpResponse, origin: &str) {
+        response.insert_header(
+            "Access-Control-Allow-Origin",
+            self.allow_origin_value(origin),
+        );
+        if self.credentials {
+            response.insert_header("Access-Control-Allow-Credentials", "true");
+        }
+    }
+
+    /// Answers a preflight request.
+    fn preflight(&self, origin: &str, method: &str, request_headers: Option<&str>) -> HttpResponse {
+        let requested_headers: Vec<&str> = request_headers
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect();
+        let method_allowed = self
+            .methods
+            .iter()
+            .any(|&allowed| <&str>::from(allowed) == method);
+        let headers_allowed = match &self.headers {
+            AllowHeaders::Any => true,
+            AllowHeaders::List(allowed) => requested_headers.iter().all(|requested| {
+                allowed
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(requested))
+            }),
+        };
+
+        let mut response = if self.is_allowed_origin(origin) && method_allowed && headers_allowed {
+            let mut response = HttpResponseBuilder::no_content().build();
+            self.add_origin_headers(&mut response, origin);
+            let methods = self
+                .methods
+                .iter()
+                .map(|&method| <&str>::from(method))
+                .collect::<Vec<_>>()
+                .join(", ");
+            response.insert_header("Access-Control-Allow-Methods", methods);
+            let headers = match &self.headers {
+                AllowHeaders::Any => requested_headers.join(", "),
+                AllowHeaders::List(allowed) => allowed.join(", "),
+            };
+            if !headers.is_empty() {
+                response.insert_header("Access-Control-Allow-Headers", headers);
+            }
+            if let Some(max_age) = self.max_age {
+                response.insert_header("Access-Control-Max-Age", max_age.as_secs().to_string());
+            }
+            response
+        } else {
+            HttpResponseBuilder::forbidden()
+                .body("CORS preflight rejected".into())
+                .build()
+        };
+
+        if self.varies_by_origin() {
+            response.append_vary("Origin");
+        }
+        response.append_vary("Access-Control-Request-Method");
+        response.append_vary("Access-Control-Request-Headers");
+        response
+    }
+}
+
+impl Layer for Cors {
+    fn call<'a>(
+        &'a self,
+        req: HttpRequest<'static>,
+        _socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            let headers = req.headers();
+            let Some(origin) = headers.get("Origin").map(str::to_owned) else {
+                let mut response = next.run(req).await;
+                if self.varies_by_origin() {
+                    response.append_vary("Origin");
+                }
+                return response;
+            };
+
+            if req.method() == HttpMethod::OPTIONS
+                && let Some(method) = headers.get("Access-Control-Request-Method")
+            {
+                let request_headers = headers.get("Access-Control-Request-Headers");
+                return self.preflight(&origin, method.trim(), request_headers);
+            }
+
+            let mut response = next.run(req).await;
+            if self.varies_by_origin() {
+                response.append_vary("Origin");
+            }
+            if self.is_allowed_origin(&origin) {
+                self.add_origin_headers(&mut response, &origin);
+                if !self.expose_headers.is_empty() {
+                    response.insert_header(
+                        "Access-Control-Expose-Headers",
+                        self.expose_headers.join(", "),
+                    );
+                }
+            }
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::app::tests::{connect, header, read_response, serve};
+
+    fn exchange(addr: SocketAddr, method: &str, headers: &str) -> String {
+        let mut stream = connect(addr);
+        write!(
+            stream,
+            "{} /items HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+            method, headers
+        )
+        .unwrap();
+        read_response(&mut stream)
+    }
+
+    fn serve_cors(cors: Cors) -> SocketAddr {
+        serve(move |app| {
+            app.add_layer(cors);
+            app.add_route("/items", HttpMethod::GET, |_req, _params| async move {
+                HttpResponseBuilder::ok()
+                    .header("X-Total", "3")
+                    .body("items".into())
+                    .build()
+            });
+        })
+    }
+
+    #[test]
+    fn answers_preflights() {
+        let addr = serve_cors(
+            Cors::new()
+                .allow_origins(["https://app.example.com"])
+                .allow_methods([HttpMethod::GET, HttpMethod::DELETE])
+                .allow_headers(["Content-Type", "Authorization"])
+                .max_age(Duration::from_secs(600)),
+        );
+
+        let response = exchange(
+            addr,
+            "OPTIONS",
+            "Origin: https://app.example.com\r\nAccess-Control-Request-Method: DELETE\r\nAccess-Control-Request-Headers: authorization\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Methods"),
+            Some("GET, DELETE")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Headers"),
+            Some("Content-Type, Authorization")
+        );
+        assert_eq!(header(&response, "Access-Control-Max-Age"), Some("600"));
+        assert_eq!(header(&response, "Access-Control-Allow-Credentials"), None);
+        assert_eq!(
+            header(&response, "Vary"),
+            Some("Origin, Access-Control-Request-Method, Access-Control-Request-Headers")
+        );
+
+        for headers in [
+            "Origin: https://evil.example.com\r\nAccess-Control-Request-Method: GET\r\n",
+            "Origin: https://app.example.com\r\nAccess-Control-Request-Method: PUT\r\n",
+            "Origin: https://app.example.com\r\nAccess-Control-Request-Method: GET\r\nAccess-Control-Request-Headers: X-Secret\r\n",
+        ] {
+            let response = exchange(addr, "OPTIONS", headers);
+            assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", headers);
+            assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+        }
+
+        // Plain `OPTIONS` requests reach the router.
+        let response = exchange(addr, "OPTIONS", "Origin: https://app.example.com\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn annotates_actual_responses() {
+        let addr = serve_cors(
+            Cors::new()
+                .allow_origin_fn(|origin| origin.ends_with(".example.com"))
+                .expose_headers(["X-Total"])
+                .allow_credentials(true),
+        );
+
+        let response = exchange(addr, "GET", "Origin: https://shop.example.com\r\n");
+        assert!(response.ends_with("\r\n\r\nitems"));
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin"),
+            Some("https://shop.example.com")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Expose-Headers"),
+            Some("X-Total")
+        );
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+
+        let response = exchange(addr, "GET", "Origin: https://example.org\r\n");
+        assert!(response.ends_with("\r\n\r\nitems"));
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+
+        let response = exchange(addr, "GET", "");
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn wildcards_unless_credentials_are_allowed() {
+        let addr = serve_cors(Cors::permissive());
+        let response = exchange(
+            addr,
+            "OPTIONS",
+            "Origin: https://a.test\r\nAccess-Control-Request-Method: PATCH\r\nAccess-Control-Request-Headers: X-One, X-Two\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Headers"),
+            Some("X-One, X-Two")
+        );
+        let response = exchange(addr, "GET", "Origin: https://a.test\r\n");
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(header(&response, "Vary"), None);
+
+        let addr = serve_cors(Cors::permissive().allow_credentials(true));
+        let response = exchange(addr, "GET", "Origin: https://a.test\r\n");
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin"),
+            Some("https://a.test")
+        );
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+    }
+}
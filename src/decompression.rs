@@ -0,0 +1,378 @@
+//! # Request Decompression
+//!
+//! [`RequestDecompression`] decodes request bodies that clients send with a `Content-Encoding`,
+//! so handlers and extractors see the original bytes. It is opt-in and supports the same codings
+//! as [`Compression`](crate::Compression), each behind its cargo feature: `gzip`, `deflate` and
+//! `br` with the `brotli` feature. Several codings listed in one header are undone in reverse
+//! order.
+//!
+//! Bodies are decoded before the request is parsed, so unlike a [`Layer`](crate::Layer) it is
+//! enabled on the builder and applies to every buffered route; routes taking the body as a
+//! stream receive it as sent. A decoded request carries a `Content-Length` matching the decoded
+//! body and neither `Content-Encoding` nor `Transfer-Encoding`; its other headers are kept as sent.
+//!
+//! Requests are rejected with:
+//!
+//! * `415 Unsupported Media Type` and an `Accept-Encoding` header listing the supported codings
+//!   if a coding is not supported,
+//! * `413 Payload Too Large` if the decoded body would exceed the configured maximum, which
+//!   protects against small payloads expanding into huge ones,
+//! * `400 Bad Request` if the body cannot be decoded.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hooch_http::{HoochAppBuilder, RequestDecompression};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.set_request_decompression(RequestDecompression::new().max_size(1024 * 1024));
+//! ```
+
+use std::io::Read;
+
+use crate::{
+    app::REQUEST_BUFFER_SIZE,
+    compression::Coding,
+    request::HttpRequest,
+    request_body::reframed_head,
+    response::{HttpResponse, HttpResponseBuilder, IntoResponse},
+};
+
+/// Marker for the end of the request head.
+const HEAD_TERMINATOR: &[u8; 4] = b"\r\n\r\n";
+
+/// Configuration for decoding compressed request bodies.
+///
+/// See the [module documentation](self) for how requests are decoded.
+#[derive(Debug, Copy, Clone)]
+pub struct RequestDecompression {
+    max_size: usize,
+}
+
+impl Default for RequestDecompression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestDecompression {
+    /// Creates a configuration accepting decoded bodies as large as the request buffer, the
+    /// largest body accepted without compression.
+    pub fn new() -> Self {
+        Self {
+            max_size: REQUEST_BUFFER_SIZE,
+        }
+    }
+
+    /// Sets the largest decoded body, in bytes, that is accepted.
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Decodes the body of a raw request consisting of its head and its complete body.
+    ///
+    /// Returns the rewritten request, or `None` if the body is not encoded.
+    pub(crate) fn decode(&self, request: &[u8]) -> Result<Option<Vec<u8>>, HttpResponse> {
+        let head_end = request
+            .windows(HEAD_TERMINATOR.len())
+            .position(|window| window == HEAD_TERMINATOR)
+            .map_or(request.len(), |idx| idx + HEAD_TERMINATOR.len());
+        let head =
+            HttpRequest::from_bytes(&request[..head_end]).map_err(IntoResponse::into_response)?;
+        let Some(content_encoding) = head.headers().get("Content-Encoding") else {
+            return Ok(None);
+        };
+
+        let codings = content_encoding
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty() && !name.eq_ignore_ascii_case("identity"))
+            .map(Coding::from_name)
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(unsupported_encoding)?;
+        if codings.is_empty() {
+            return Ok(None);
+        }
+
+        let mut body = request[head_end..].to_vec();
+        for &coding in codings.iter().rev() {
+            body = self.decode_body(coding, &body)?;
+        }
+
+        // Rebuild the head without the encoding and with the length of the decoded body.
+        let mut decoded = reframed_head(&request[..head_end], &["Content-Encoding"], body.len());
+        decoded.extend_from_slice(&body);
+        Ok(Some(decoded))
+    }
+
+    /// Undoes a single coding, giving up once the output exceeds the maximum size.
+    fn decode_body(&self, coding: Coding, body: &[u8]) -> Result<Vec<u8>, HttpResponse> {
+        let decoder: Box<dyn Read + '_> = match coding {
+            #[cfg(feature = "brotli")]
+            Coding::Brotli => Box::new(brotli::Decompressor::new(body, 4096)),
+            #[cfg(feature = "gzip")]
+            Coding::Gzip => Box::new(flate2::read::MultiGzDecoder::new(body)),
+            #[cfg(feature = "deflate")]
+            Coding::Deflate => Box::new(flate2::read::ZlibDecoder::new(body)),
+        };
+
+        let mut decoded = Vec::new();
+        decoder
+            .take(self.max_size as u64 + 1)
+            .read_to_end(&mut decoded)
+            .map_err(|_| {
+                HttpResponseBuilder::bad_request()
+                    .body(format!("Malformed {} request body", coding.name()))
+                    .build()
+            })?;
+        if decoded.len() > self.max_size {
+            return Err(HttpResponseBuilder::payload_too_large().build());
+        }
+        Ok(decoded)
+    }
+}
+
+/// The response to a request body in a coding that is not supported.
+fn unsupported_encoding() -> HttpResponse {
+    let supported = Coding::ALL
+        .iter()
+        .map(|coding| coding.name())
+        .collect::<Vec<_>>()
+        .join(", ");
+    HttpResponseBuilder::unsupported_media_type()
+        .header("Accept-Encoding", supported)
+        .body("Unsupported Content-Encoding".into())
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    #[cfg(feature = "gzip")]
+    use flate2::write::GzEncoder;
+    #[cfg(feature = "deflate")]
+    use flate2::write::ZlibEncoder;
+
+    use super::*;
+    use crate::HttpStatus;
+    #[cfg(feature = "gzip")]
+    use crate::{
+        app::tests::{connect, read_response, serve},
+        HttpMethod,
+    };
+
+    #[cfg(feature = "gzip")]
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[cfg(feature = "deflate")]
+    fn deflate(body: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[cfg(feature = "brotli")]
+    fn brotli(body: &[u8]) -> Vec<u8> {
+        let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        encoder.write_all(body).unwrap();
+        encoder.into_inner()
+    }
+
+    fn request(content_encoding: &str, body: &[u8]) -> Vec<u8> {
+        let mut request = format!(
+            "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Encoding: {}\r\nContent-Length: {}\r\n\r\n",
+            content_encoding,
+            body.len()
+        )
+        .into_bytes();
+        request.extend_from_slice(body);
+        request
+    }
+
+    /// Decodes `request`, expecting its body to have been encoded.
+    fn decode(request: &[u8]) -> Vec<u8> {
+        RequestDecompression::new()
+            .decode(request)
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn decodes_gzip_bodies() {
+        let decoded = decode(&request("gzip", &gzip(b"hello gzip")));
+        let decoded = HttpRequest::from_bytes(&decoded).unwrap();
+        assert_eq!(decoded.body(), b"hello gzip");
+        assert_eq!(decoded.headers().get("Content-Encoding"), None);
+        assert_eq!(decoded.headers().get("Content-Length"), Some("10"));
+        assert_eq!(decoded.headers().get("Host"), Some("localhost"));
+
+        // Framing headers are replaced, and the rest of the head is kept as sent.
+        let mut encoded =
+            b"POST /echo HTTP/1.1\r\nhost:  localhost\r\nTransfer-Encoding: chunked\r\nContent-Encoding: gzip\r\n\r\n".to_vec();
+        encoded.extend_from_slice(&gzip(b"hello gzip"));
+        let decoded = decode(&encoded);
+        assert!(decoded.starts_with(
+            b"POST /echo HTTP/1.1\r\nhost:  localhost\r\nContent-Length: 10\r\n\r\nhello gzip"
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "deflate")]
+    fn decodes_deflate_bodies() {
+        let decoded = decode(&request("deflate", &deflate(b"hello deflate")));
+        assert_eq!(
+            HttpRequest::from_bytes(&decoded).unwrap().body(),
+            b"hello deflate"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "brotli")]
+    fn decodes_brotli_bodies() {
+        let decoded = decode(&request("br", &brotli(b"hello brotli")));
+        assert_eq!(
+            HttpRequest::from_bytes(&decoded).unwrap().body(),
+            b"hello brotli"
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "deflate"))]
+    fn decodes_stacked_codings() {
+        // Codings are listed in the order they were applied.
+        let body = gzip(&deflate(b"twice"));
+        let decoded = decode(&request("deflate, gzip", &body));
+        assert_eq!(HttpRequest::from_bytes(&decoded).unwrap().body(), b"twice");
+    }
+
+    #[test]
+    fn passes_on_unencoded_bodies() {
+        let decompression = RequestDecompression::new();
+        let plain = b"POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\nhi";
+        assert!(decompression.decode(plain).unwrap().is_none());
+        assert!(decompression
+            .decode(&request("identity", b"hi"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn rejects_unsupported_codings() {
+        let response = RequestDecompression::new()
+            .decode(&request("compress", b"hi"))
+            .unwrap_err();
+        assert_eq!(response.status(), HttpStatus::UnsupportedMediaType);
+        assert!(response.header("Accept-Encoding").is_some());
+        #[cfg(all(feature = "gzip", feature = "deflate", feature = "brotli"))]
+        assert_eq!(
+            response.header("Accept-Encoding"),
+            Some("br, gzip, deflate")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn rejects_oversized_bodies() {
+        let decompression = RequestDecompression::new().max_size(1000);
+
+        // A megabyte of zeros compresses to about a kilobyte.
+        let bomb = gzip(&vec![0; 1024 * 1024]);
+        assert!(bomb.len() < 2048);
+        let response = decompression.decode(&request("gzip", &bomb)).unwrap_err();
+        assert_eq!(response.status(), HttpStatus::PayloadTooLarge);
+    }
+
+    #[test]
+    #[cfg(feature = "brotli")]
+    fn rejects_malformed_bodies() {
+        let response = RequestDecompression::new()
+            .decode(&request("br", b"not brotli"))
+            .unwrap_err();
+        assert_eq!(response.status(), HttpStatus::BadRequest);
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn handlers_receive_decoded_bodies() {
+        let addr = serve(|app| {
+            app.set_request_decompression(RequestDecompression::new());
+            app.add_route("/echo", HttpMethod::POST, |req, _params| async move {
+                String::from_utf8_lossy(req.body()).into_owned()
+            });
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(&request("gzip", &gzip(b"{\"user\":\"bart\"}")))
+            .unwrap();
+        assert!(read_response(&mut stream).ends_with("\r\n\r\n{\"user\":\"bart\"}"));
+
+        // Chunked bodies are decoded after their chunked encoding.
+        let body = gzip(b"chunked");
+        let mut chunked = format!(
+            "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n",
+            body.len()
+        )
+        .into_bytes();
+        chunked.extend_from_slice(&body);
+        chunked.extend_from_slice(b"\r\n0\r\n\r\n");
+        stream.write_all(&chunked).unwrap();
+        assert!(read_response(&mut stream).ends_with("\r\n\r\nchunked"));
+
+        stream.write_all(&request("zstd", b"??")).unwrap();
+        assert!(read_response(&mut stream).starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"));
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn binary_bodies_are_passed_on_as_bytes() {
+        fn app(app: &mut crate::HoochAppBuilder) {
+            app.add_route("/echo", HttpMethod::POST, |req, _params| async move {
+                let sum: usize = req.body().iter().map(|&byte| byte as usize).sum();
+                format!("{} bytes, sum {}", req.body().len(), sum)
+            });
+            app.add_handler(
+                "/json",
+                HttpMethod::POST,
+                |_: crate::Json<serde_json::Value>| async { "parsed" },
+            );
+        }
+        let binary: Vec<u8> = (0..1000).map(|i| (i % 256) as u8).collect();
+        let sum: usize = binary.iter().map(|&byte| byte as usize).sum();
+        let compressed = gzip(&binary);
+
+        let addr = serve(|builder| {
+            builder.set_request_decompression(RequestDecompression::new());
+            app(builder);
+        });
+        let mut stream = connect(addr);
+        stream.write_all(&request("gzip", &compressed)).unwrap();
+        let expected = format!("\r\n\r\n1000 bytes, sum {}", sum);
+        assert!(read_response(&mut stream).ends_with(&expected));
+
+        // Bodies that are not valid UTF-8 are rejected by text extractors.
+        let mut json = request("gzip", &compressed);
+        let json_head = b"POST /json HTTP/1.1\r\nContent-Type: application/json\r\n";
+        json.splice(
+            ..b"POST /echo HTTP/1.1\r\n".len(),
+            json_head.iter().copied(),
+        );
+        stream.write_all(&json).unwrap();
+        assert!(read_response(&mut stream).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        // Without decompression, handlers receive the compressed bytes.
+        let addr = serve(app);
+        let mut stream = connect(addr);
+        stream.write_all(&request("gzip", &compressed)).unwrap();
+        let sum: usize = compressed.iter().map(|&byte| byte as usize).sum();
+        let expected = format!("\r\n\r\n{} bytes, sum {}", compressed.len(), sum);
+        assert!(read_response(&mut stream).ends_with(&expected));
+    }
+}
@@ -0,0 +1,138 @@
+//! # Type Map Storage
+//!
+//! This module provides [`Extensions`], a map keyed by type that stores at most one value of
+//! each type. It backs both the application state registered through
+//! [`HoochAppBuilder::with_state`](crate::HoochAppBuilder::with_state) and the per-request
+//! extensions carried by every [`HttpRequest`](crate::HttpRequest), which middleware can use to
+//! pass data such as an authenticated user on to later handlers.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hooch_http::Extensions;
+//!
+//! #[derive(Debug, PartialEq)]
+//! struct UserId(u64);
+//!
+//! let mut extensions = Extensions::new();
+//! extensions.insert(UserId(7));
+//! assert_eq!(extensions.get::<UserId>(), Some(&UserId(7)));
+//! ```
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::Debug,
+};
+
+/// A map storing at most one value per type.
+///
+/// No allocation happens until the first value is inserted.
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
+
+impl Extensions {
+    /// Create an empty `Extensions` map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a value, returning the previous value of the same type if there was one.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    /// Get a reference to the value of type `T`, if present.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Get a mutable reference to the value of type `T`, if present.
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.map
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    /// Remove and return the value of type `T`, if present.
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    /// Return `true` if a value of type `T` is present.
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Return the number of stored values.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Return `true` if no values are stored.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct User(&'static str);
+
+    #[test]
+    fn insert_and_get() {
+        let mut extensions = Extensions::new();
+        assert!(extensions.is_empty());
+
+        assert_eq!(extensions.insert(User("bart")), None);
+        assert_eq!(extensions.insert(5u32), None);
+
+        assert_eq!(extensions.get::<User>(), Some(&User("bart")));
+        assert_eq!(extensions.get::<u32>(), Some(&5));
+        assert_eq!(extensions.get::<u64>(), None);
+        assert_eq!(extensions.len(), 2);
+    }
+
+    #[test]
+    fn insert_replaces_value_of_same_type() {
+        let mut extensions = Extensions::new();
+        extensions.insert(User("bart"));
+
+        assert_eq!(extensions.insert(User("lisa")), Some(User("bart")));
+        assert_eq!(extensions.get::<User>(), Some(&User("lisa")));
+        assert_eq!(extensions.len(), 1);
+    }
+
+    #[test]
+    fn get_mut_and_remove() {
+        let mut extensions = Extensions::new();
+        extensions.insert(1u8);
+
+        *extensions.get_mut::<u8>().unwrap() += 1;
+        assert!(extensions.contains::<u8>());
+        assert_eq!(extensions.remove::<u8>(), Some(2));
+        assert!(!extensions.contains::<u8>());
+        assert_eq!(extensions.remove::<u8>(), None);
+    }
+}
@@ -0,0 +1,839 @@
+//! # Request Extractors
+//!
+//! Extractors pull typed values out of an incoming request so that handlers registered with
+//! [`HoochAppBuilder::add_handler`](crate::HoochAppBuilder::add_handler) can declare exactly
+//! what they need as arguments instead of parsing the raw [`HttpRequest`] themselves.
+//!
+//! | Extractor        | Source                                                     | Rejection                 |
+//! |------------------|------------------------------------------------------------|---------------------------|
+//! | [`Path<T>`]      | Route parameters such as `{id}` in `/users/{id}`            | `400 Bad Request`         |
+//! | [`Query<T>`]     | The URI query string                                       | `400 Bad Request`         |
+//! | [`Json<T>`]      | An `application/json` body                                 | `415`, `400` or `422`     |
+//! | [`Form<T>`]      | An `application/x-www-form-urlencoded` body                | `415` or `400`            |
+//! | [`Header<T>`]    | A single header described by a [`TypedHeader`]             | `400 Bad Request`         |
+//! | [`State<T>`]     | State registered with `HoochAppBuilder::with_state`        | `500 Internal Server Error` |
+//! | [`HttpRequest`]  | The request itself; must be the last handler argument      | never                     |
+//! | [`Params`]       | The raw path and query parameters                          | never                     |
+//! | [`RequestBody`]  | The body on streaming routes; must be the last argument    | `500 Internal Server Error` |
+//!
+//! Wrapping an extractor in `Option` turns a rejection into `None`, and wrapping it in
+//! `Result<T, HttpResponse>` hands the rejection to the handler instead of responding with it.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hooch_http::{HoochAppBuilder, HttpMethod, HttpResponseBuilder, Path, Query};
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct Pagination {
+//!     page: u32,
+//! }
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_handler(
+//!     "/users/{id}",
+//!     HttpMethod::GET,
+//!     |Path(id): Path<u64>, Query(pagination): Query<Pagination>| async move {
+//!         HttpResponseBuilder::ok()
+//!             .body(format!("user {} page {}", id, pagination.page))
+//!             .build()
+//!     },
+//! );
+//! ```
+
+use std::{
+    fmt::Display,
+    ops::{Deref, DerefMut},
+};
+
+use serde::{
+    de::{self, value::StrDeserializer, DeserializeOwned, IntoDeserializer},
+    forward_to_deserialize_any, Deserializer,
+};
+
+use crate::{
+    request::{HttpRequest, Params},
+    request_body::RequestBody,
+    response::{HttpResponse, HttpResponseBuilder},
+};
+
+/// Types that can be extracted from a request without consuming it.
+///
+/// Any number of these can appear as handler arguments.
+pub trait FromRequest: Sized {
+    /// Extracts the value, or returns the response to send back if extraction fails.
+    fn from_request(
+        req: &HttpRequest<'static>,
+        params: &Params<'static>,
+    ) -> Result<Self, HttpResponse>;
+}
+
+/// Types that can be extracted from a request by taking ownership of it.
+///
+/// Only the last argument of a handler is extracted this way. Every [`FromRequest`] type
+/// implements this trait, and so does [`HttpRequest`] itself.
+pub trait FromRequestOwned: Sized {
+    /// Extracts the value, or returns the response to send back if extraction fails.
+    fn from_request_owned(
+        req: HttpRequest<'static>,
+        params: Params<'static>,
+    ) -> Result<Self, HttpResponse>;
+}
+
+impl<T: FromRequest> FromRequestOwned for T {
+    fn from_request_owned(
+        req: HttpRequest<'static>,
+        params: Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        T::from_request(&req, &params)
+    }
+}
+
+impl FromRequestOwned for HttpRequest<'static> {
+    fn from_request_owned(
+        req: HttpRequest<'static>,
+        _params: Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        Ok(req)
+    }
+}
+
+impl FromRequestOwned for RequestBody {
+    fn from_request_owned(
+        mut req: HttpRequest<'static>,
+        _params: Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        req.take_body_stream().ok_or_else(|| {
+            HttpResponseBuilder::internal_server_error()
+                .body("Request body is only streamed on streaming routes".into())
+                .build()
+        })
+    }
+}
+
+impl FromRequest for Params<'static> {
+    fn from_request(
+        _req: &HttpRequest<'static>,
+        params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        Ok(*params)
+    }
+}
+
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        Ok(T::from_request(req, params).ok())
+    }
+}
+
+impl<T: FromRequest> FromRequest for Result<T, HttpResponse> {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        Ok(T::from_request(req, params))
+    }
+}
+
+/// Implements `Deref`, `DerefMut` and `into_inner` for a single-field extractor.
+macro_rules! impl_extractor_wrapper {
+    ($name:ident) => {
+        impl<T> $name<T> {
+            /// Consumes the extractor, returning the extracted value.
+            pub fn into_inner(self) -> T {
+                self.0
+            }
+        }
+
+        impl<T> Deref for $name<T> {
+            type Target = T;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl<T> DerefMut for $name<T> {
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                &mut self.0
+            }
+        }
+    };
+}
+
+/// Extracts route parameters, deserialized into `T`.
+///
+/// `T` can be a single value when the route has one parameter, a tuple to take parameters in
+/// order, or a struct or map to take them by name. Values are percent-decoded first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Path<T>(pub T);
+
+impl_extractor_wrapper!(Path);
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    fn from_request(
+        _req: &HttpRequest<'static>,
+        params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        let pairs = params
+            .path_segment()
+            .pairs()
+            .map(|(key, value)| Some((key, percent_decode(value.unwrap_or_default())?)))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| bad_request("Invalid path parameters: invalid percent-encoding"))?;
+
+        T::deserialize(PathDeserializer { pairs: &pairs })
+            .map(Path)
+            .map_err(|err| bad_request(format!("Invalid path parameters: {}", err)))
+    }
+}
+
+/// Extracts the URI query string, deserialized into `T`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Query<T>(pub T);
+
+impl_extractor_wrapper!(Query);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        _params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        let query = req
+            .uri()
+            .as_ref()
+            .split_once('?')
+            .map(|(_, query)| query)
+            .unwrap_or_default();
+
+        serde_urlencoded::from_str(query)
+            .map(Query)
+            .map_err(|err| bad_request(format!("Invalid query string: {}", err)))
+    }
+}
+
+/// Extracts a JSON request body, deserialized into `T`.
+///
+/// The request must carry an `application/json` (or `application/*+json`) content type.
+/// Malformed JSON is rejected with `400 Bad Request`, and JSON that does not match `T` with
+/// `422 Unprocessable Entity`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+impl_extractor_wrapper!(Json);
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        _params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        let is_json = content_type(req).is_some_and(|mime| {
+            mime.eq_ignore_ascii_case("application/json")
+                || (mime.len() > "application/+json".len()
+                    && mime[.."application/".len()].eq_ignore_ascii_case("application/")
+                    && mime[mime.len() - "+json".len()..].eq_ignore_ascii_case("+json"))
+        });
+        if !is_json {
+            return Err(HttpResponseBuilder::unsupported_media_type()
+                .body("Expected request with `Content-Type: application/json`".into())
+                .build());
+        }
+
+        serde_json::from_slice(req.body()).map(Json).map_err(|err| {
+            let builder = if err.is_data() {
+                HttpResponseBuilder::unprocessable_entity()
+            } else {
+                HttpResponseBuilder::bad_request()
+            };
+            builder.body(format!("Invalid JSON body: {}", err)).build()
+        })
+    }
+}
+
+/// Extracts a URL-encoded form body, deserialized into `T`.
+///
+/// The request must carry an `application/x-www-form-urlencoded` content type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Form<T>(pub T);
+
+impl_extractor_wrapper!(Form);
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        _params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        let is_form = content_type(req)
+            .is_some_and(|mime| mime.eq_ignore_ascii_case("application/x-www-form-urlencoded"));
+        if !is_form {
+            return Err(HttpResponseBuilder::unsupported_media_type()
+                .body(
+                    "Expected request with `Content-Type: application/x-www-form-urlencoded`"
+                        .into(),
+                )
+                .build());
+        }
+
+        serde_urlencoded::from_bytes(req.body())
+            .map(Form)
+            .map_err(|err| bad_request(format!("Invalid form body: {}", err)))
+    }
+}
+
+/// A header that can be extracted with [`Header`].
+pub trait TypedHeader: Sized {
+    /// The header name, matched case-insensitively.
+    const NAME: &'static str;
+
+    /// Parses the header value, returning `None` if it is invalid.
+    fn decode(value: &str) -> Option<Self>;
+}
+
+/// Extracts a single header described by a [`TypedHeader`].
+///
+/// Missing or invalid headers are rejected with `400 Bad Request`; use `Option<Header<T>>`
+/// for optional headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header<T>(pub T);
+
+impl_extractor_wrapper!(Header);
+
+impl<T: TypedHeader> FromRequest for Header<T> {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        _params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        let value = req
+            .headers()
+            .get(T::NAME)
+            .ok_or_else(|| bad_request(format!("Missing header `{}`", T::NAME)))?;
+
+        T::decode(value)
+            .map(Header)
+            .ok_or_else(|| bad_request(format!("Invalid header `{}`", T::NAME)))
+    }
+}
+
+/// Defines a [`TypedHeader`] holding the raw header value as a `String`.
+macro_rules! string_header {
+    ($(#[$doc:meta])* $name:ident, $header:literal) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct $name(pub String);
+
+        impl TypedHeader for $name {
+            const NAME: &'static str = $header;
+
+            fn decode(value: &str) -> Option<Self> {
+                Some(Self(value.to_string()))
+            }
+        }
+    };
+}
+
+string_header!(
+    /// The `Authorization` header.
+    Authorization,
+    "Authorization"
+);
+string_header!(
+    /// The `Content-Type` header.
+    ContentType,
+    "Content-Type"
+);
+string_header!(
+    /// The `Host` header.
+    Host,
+    "Host"
+);
+string_header!(
+    /// The `User-Agent` header.
+    UserAgent,
+    "User-Agent"
+);
+
+/// Extracts application state registered with
+/// [`HoochAppBuilder::with_state`](crate::HoochAppBuilder::with_state).
+///
+/// Asking for state that was never registered is a programming error and is answered with
+/// `500 Internal Server Error`.
+#[derive(Debug)]
+pub struct State<T: 'static>(pub &'static T);
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for State<T> {}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl<T: Send + Sync + 'static> FromRequest for State<T> {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        _params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        req.state::<T>().map(State).ok_or_else(|| {
+            HttpResponseBuilder::internal_server_error()
+                .body(format!(
+                    "Missing application state `{}`",
+                    std::any::type_name::<T>()
+                ))
+                .build()
+        })
+    }
+}
+
+/// Builds a `400 Bad Request` response carrying `message`.
+fn bad_request(message: impl Into<String>) -> HttpResponse {
+    HttpResponseBuilder::bad_request()
+        .body(message.into())
+        .build()
+}
+
+/// Returns the media type of the request's `Content-Type` header, without parameters.
+fn content_type<'a>(req: &HttpRequest<'a>) -> Option<&'a str> {
+    let value = req.headers().get("Content-Type")?;
+    Some(value.split(';').next().unwrap_or_default().trim())
+}
+
+/// Decodes `%XX` escapes in a URI component.
+///
+/// Returns `None` if an escape is malformed or the decoded bytes are not valid UTF-8.
+pub(crate) fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' {
+            let hex = bytes.get(idx + 1..idx + 3)?;
+            // `from_str_radix` would also accept a leading sign.
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            idx += 3;
+        } else {
+            decoded.push(bytes[idx]);
+            idx += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// Error produced while deserializing route parameters.
+#[derive(Debug)]
+struct PathError(String);
+
+impl Display for PathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PathError {}
+
+impl de::Error for PathError {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+/// Deserializes the full set of route parameters.
+///
+/// Structs and maps receive parameters by name, sequences and tuples receive them in order,
+/// and any other type requires exactly one parameter.
+struct PathDeserializer<'de> {
+    pairs: &'de [(&'de str, String)],
+}
+
+impl<'de> PathDeserializer<'de> {
+    /// Returns the only parameter, failing if there is not exactly one.
+    fn single(&self) -> Result<ValueDeserializer<'de>, PathError> {
+        match self.pairs {
+            [(_, value)] => Ok(ValueDeserializer(value)),
+            pairs => Err(PathError(format!(
+                "expected 1 path parameter, found {}",
+                pairs.len()
+            ))),
+        }
+    }
+}
+
+/// Forwards primitive deserialization to the single route parameter.
+macro_rules! forward_to_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for PathDeserializer<'de> {
+    type Error = PathError;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    forward_to_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+        deserialize_string deserialize_bytes deserialize_byte_buf deserialize_option
+        deserialize_unit deserialize_identifier deserialize_ignored_any
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.single()?.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(de::value::SeqDeserializer::new(
+            self.pairs.iter().map(|(_, value)| ValueDeserializer(value)),
+        ))
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if self.pairs.len() != len {
+            return Err(PathError(format!(
+                "expected {} path parameters, found {}",
+                len,
+                self.pairs.len()
+            )));
+        }
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(de::value::MapDeserializer::new(
+            self.pairs
+                .iter()
+                .map(|(key, value)| (*key, ValueDeserializer(value))),
+        ))
+    }
+
+    fn deserialize_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+}
+
+/// Deserializes a single route parameter value, parsing it into the requested type.
+struct ValueDeserializer<'de>(&'de str);
+
+impl<'de> IntoDeserializer<'de, PathError> for ValueDeserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+/// Parses the value with `FromStr` and hands it to the matching visitor method.
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident),* $(,)?) => {
+        $(
+            fn $method<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match self.0.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(err) => Err(PathError(format!("cannot parse `{}`: {}", self.0, err))),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = PathError;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let deserializer: StrDeserializer<'de, PathError> = self.0.into_deserializer();
+        deserializer.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::HttpStatus;
+
+    fn request_and_params(
+        raw_request: &'static [u8],
+        route: &'static str,
+    ) -> (HttpRequest<'static>, Params<'static>) {
+        let req = HttpRequest::from_bytes(raw_request).unwrap();
+        let params = req.uri().is_match(route).unwrap();
+        (req, params)
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Order {
+        user: String,
+        order_id: u32,
+    }
+
+    #[test]
+    fn path_single_value() {
+        let (req, params) = request_and_params(
+            b"GET /users/42 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "/users/{id}",
+        );
+
+        let Path(id) = Path::<u64>::from_request(&req, &params).unwrap();
+        assert_eq!(id, 42);
+    }
+
+    #[test]
+    fn path_tuple_struct_and_map() {
+        let (req, params) = request_and_params(
+            b"GET /users/bart%20s/orders/7 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "/users/{user}/orders/{order_id}",
+        );
+
+        let Path((user, order_id)) = Path::<(String, u32)>::from_request(&req, &params).unwrap();
+        assert_eq!((user.as_str(), order_id), ("bart s", 7));
+
+        let Path(order) = Path::<Order>::from_request(&req, &params).unwrap();
+        assert_eq!(
+            order,
+            Order {
+                user: "bart s".into(),
+                order_id: 7
+            }
+        );
+
+        let Path(map) = Path::<HashMap<String, String>>::from_request(&req, &params).unwrap();
+        assert_eq!(map["order_id"], "7");
+    }
+
+    #[test]
+    fn path_rejections() {
+        let (req, params) = request_and_params(
+            b"GET /users/abc HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "/users/{id}",
+        );
+        let rejection = Path::<u64>::from_request(&req, &params).unwrap_err();
+        assert_eq!(rejection.status(), HttpStatus::BadRequest);
+
+        let rejection = Path::<(u64, u64)>::from_request(&req, &params).unwrap_err();
+        assert_eq!(rejection.status(), HttpStatus::BadRequest);
+    }
+
+    #[test]
+    fn query_struct() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Search {
+            q: String,
+            limit: Option<u32>,
+        }
+
+        let (req, params) = request_and_params(
+            b"GET /search?q=hooch+http&limit=10 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "/search",
+        );
+        let Query(search) = Query::<Search>::from_request(&req, &params).unwrap();
+        assert_eq!(
+            search,
+            Search {
+                q: "hooch http".into(),
+                limit: Some(10)
+            }
+        );
+
+        let (req, params) = request_and_params(
+            b"GET /search HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "/search",
+        );
+        let rejection = Query::<Search>::from_request(&req, &params).unwrap_err();
+        assert_eq!(rejection.status(), HttpStatus::BadRequest);
+    }
+
+    #[test]
+    fn json_body() {
+        let (req, params) = request_and_params(
+            b"POST /orders HTTP/1.1\r\nContent-Type: application/json; charset=utf-8\r\n\r\n{\"user\":\"bart\",\"order_id\":3}",
+            "/orders",
+        );
+        let Json(order) = Json::<Order>::from_request(&req, &params).unwrap();
+        assert_eq!(order.order_id, 3);
+    }
+
+    #[test]
+    fn json_rejections() {
+        let (req, params) = request_and_params(
+            b"POST /orders HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n{}",
+            "/orders",
+        );
+        let rejection = Json::<Order>::from_request(&req, &params).unwrap_err();
+        assert_eq!(rejection.status(), HttpStatus::UnsupportedMediaType);
+
+        let (req, params) = request_and_params(
+            b"POST /orders HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{\"user\":",
+            "/orders",
+        );
+        let rejection = Json::<Order>::from_request(&req, &params).unwrap_err();
+        assert_eq!(rejection.status(), HttpStatus::BadRequest);
+
+        let (req, params) = request_and_params(
+            b"POST /orders HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{\"user\":1}",
+            "/orders",
+        );
+        let rejection = Json::<Order>::from_request(&req, &params).unwrap_err();
+        assert_eq!(rejection.status(), HttpStatus::UnprocessableEntity);
+    }
+
+    #[test]
+    fn form_body() {
+        let (req, params) = request_and_params(
+            b"POST /orders HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\nuser=bart&order_id=9",
+            "/orders",
+        );
+        let Form(order) = Form::<Order>::from_request(&req, &params).unwrap();
+        assert_eq!(order.order_id, 9);
+
+        let (req, params) = request_and_params(
+            b"POST /orders HTTP/1.1\r\nContent-Type: application/json\r\n\r\nuser=bart&order_id=9",
+            "/orders",
+        );
+        let rejection = Form::<Order>::from_request(&req, &params).unwrap_err();
+        assert_eq!(rejection.status(), HttpStatus::UnsupportedMediaType);
+    }
+
+    #[test]
+    fn header_and_optional_extractors() {
+        let (req, params) = request_and_params(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nuser-agent: curl/8.5.0\r\n\r\n",
+            "/",
+        );
+        let Header(UserAgent(user_agent)) =
+            Header::<UserAgent>::from_request(&req, &params).unwrap();
+        assert_eq!(user_agent, "curl/8.5.0");
+
+        let rejection = Header::<Authorization>::from_request(&req, &params).unwrap_err();
+        assert_eq!(rejection.status(), HttpStatus::BadRequest);
+        assert!(Option::<Header<Authorization>>::from_request(&req, &params)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn missing_state_is_server_error() {
+        let (req, params) = request_and_params(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n", "/");
+        let rejection = State::<String>::from_request(&req, &params).unwrap_err();
+        assert_eq!(rejection.status(), HttpStatus::InternalServerError);
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("a%2Fb%20c").as_deref(), Some("a/b c"));
+        assert_eq!(percent_decode("%E2%9C%93").as_deref(), Some("✓"));
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%FF"), None);
+        assert_eq!(percent_decode("%+1"), None);
+        assert_eq!(percent_decode("%-1"), None);
+    }
+}
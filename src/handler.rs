@@ -0,0 +1,93 @@
+//! # Extractor Handlers
+//!
+//! A [`Handler`] is an async function whose arguments are [extractors](crate::FromRequest) and
+//! whose output implements [`IntoResponse`].
+//! Each argument is extracted from the request before the function runs; the first extractor
+//! that fails short-circuits the call and its rejection is sent back instead.
+//!
+//! Handlers take up to eight arguments. All but the last must implement
+//! [`FromRequest`]; the last may also take ownership of the request through
+//! [`FromRequestOwned`].
+
+use std::future::Future;
+
+use futures::{future::BoxFuture, FutureExt};
+
+use crate::{
+    extract::{FromRequest, FromRequestOwned},
+    request::{HttpRequest, Params},
+    response::{HttpResponse, IntoResponse},
+};
+
+/// An async function that can be registered with
+/// [`HoochAppBuilder::add_handler`](crate::HoochAppBuilder::add_handler).
+///
+/// `Args` is a tuple of the function's argument types and only exists to keep the
+/// implementations for different arities apart.
+pub trait Handler<Args>: Send + Sync + 'static {
+    /// Extracts the arguments from the request and runs the handler.
+    fn call(
+        &self,
+        req: HttpRequest<'static>,
+        params: Params<'static>,
+    ) -> BoxFuture<'static, HttpResponse>;
+}
+
+impl<F, Fut> Handler<()> for F
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
+{
+    fn call(
+        &self,
+        _req: HttpRequest<'static>,
+        _params: Params<'static>,
+    ) -> BoxFuture<'static, HttpResponse> {
+        self().map(IntoResponse::into_response).boxed()
+    }
+}
+
+/// Implements [`Handler`] for functions taking the given extractor arguments.
+macro_rules! impl_handler {
+    ($($arg:ident),* ; $last:ident) => {
+        #[allow(non_snake_case)]
+        impl<F, Fut, $($arg,)* $last> Handler<($($arg,)* $last,)> for F
+        where
+            F: Fn($($arg,)* $last) -> Fut + Send + Sync + 'static,
+            Fut: Future + Send + 'static,
+            Fut::Output: IntoResponse,
+            $($arg: FromRequest,)*
+            $last: FromRequestOwned,
+        {
+            fn call(
+                &self,
+                req: HttpRequest<'static>,
+                params: Params<'static>,
+            ) -> BoxFuture<'static, HttpResponse> {
+                $(
+                    let $arg = match $arg::from_request(&req, &params) {
+                        Ok(value) => value,
+                        Err(rejection) => return futures::future::ready(rejection).boxed(),
+                    };
+                )*
+                let $last = match $last::from_request_owned(req, params) {
+                    Ok(value) => value,
+                    Err(rejection) => return futures::future::ready(rejection).boxed(),
+                };
+                self($($arg,)* $last)
+                    .map(IntoResponse::into_response)
+                    .boxed()
+            }
+        }
+    };
+}
+
+impl_handler!(; A1);
+impl_handler!(A1; A2);
+impl_handler!(A1, A2; A3);
+impl_handler!(A1, A2, A3; A4);
+impl_handler!(A1, A2, A3, A4; A5);
+impl_handler!(A1, A2, A3, A4, A5; A6);
+impl_handler!(A1, A2, A3, A4, A5, A6; A7);
+impl_handler!(A1, A2, A3, A4, A5, A6, A7; A8);
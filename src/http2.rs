@@ -0,0 +1,2029 @@
+//! # HTTP/2
+//!
+//! With the `http2` feature enabled, connections may speak HTTP/2 over cleartext TCP ("h2c"),
+//! either by opening with the HTTP/2 connection preface ("prior knowledge") or by upgrading an
+//! HTTP/1.1 request carrying `Upgrade: h2c`, which is then answered as the first HTTP/2 stream.
+//!
+//! Every stream is turned into an [`HttpRequest`] reporting [`HttpVersion::Two`] and runs through
+//! the same layers, middleware and routes as HTTP/1.1 requests, so handlers do not need to know
+//! which protocol a request arrived over. Handlers for different streams run concurrently on the
+//! connection task and are dropped if the client resets their stream. Response bodies are
+//! interleaved as HTTP/2 flow control allows; a streaming body is only pulled once its previous
+//! chunk has been sent. While no stream is open,
+//! the header read timeout bounds how long the connection may stay idle.
+//!
+//! Some differences to HTTP/1.1 remain:
+//!
+//! - Request bodies are received in full before the handler runs and are bounded by the same
+//!   limit as buffered HTTP/1.1 bodies. Streaming routes receive the buffered body as their
+//!   [`RequestBody`].
+//! - Connection upgrades, including WebSockets, and server push are not available.
+//! - Header lists decoding to more than the request buffer size close the connection instead of
+//!   being answered with `431 Request Header Fields Too Large`.
+//!
+//! This crate does not handle TLS itself. To serve HTTP/2 over TLS, accept connections with a
+//! TLS library, offer `h2` through ALPN and hand connections that negotiated it, wrapped in an
+//! [`Http2Transport`], to [`HoochApp::serve_h2`]. A TLS-terminating proxy can also forward h2c to
+//! the server instead.
+//!
+//! [`HttpVersion::Two`]: crate::HttpVersion::Two
+//! [`HoochApp::serve_h2`]: crate::HoochApp::serve_h2
+
+use std::{collections::BTreeMap, future::poll_fn, io, net::SocketAddr, pin::pin, task::Poll};
+
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use futures::{
+    future::{self, AbortHandle, Aborted, BoxFuture},
+    stream::FuturesUnordered,
+    task::Context,
+    FutureExt, StreamExt,
+};
+use hooch::{net::HoochTcpStream, time::sleep};
+use hpack::Decoder;
+
+use crate::{
+    app::{timeout, write_all, Pipeline, TimedOut, REQUEST_BUFFER_SIZE},
+    body::{Body, BodyStream},
+    layer::Next,
+    logging::RequestSpan,
+    request::HttpRequest,
+    request_body::RequestBody,
+    response::{HttpResponse, HttpResponseBuilder, HttpStatus, IntoResponse},
+    websocket::has_token,
+    HttpMethod, HttpVersion,
+};
+
+/// The connection preface every HTTP/2 client opens with.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The part of the connection preface that reads like an HTTP/1 request head.
+pub(crate) const PREFACE_HEAD: &[u8] = b"PRI * HTTP/2.0\r\n\r\n";
+
+/// Response switching an HTTP/1.1 connection over to HTTP/2.
+const SWITCHING_PROTOCOLS: &[u8] =
+    b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+
+/// Decoding of the `HTTP2-Settings` header, which is base64url with optional padding.
+const SETTINGS_ENCODING: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Length of the header preceding every frame payload.
+const FRAME_HEADER_LEN: usize = 9;
+
+/// Largest frame payload accepted from clients; the protocol default, which is never raised.
+const MAX_FRAME_SIZE: usize = 16_384;
+
+/// Flow-control window every connection and stream starts with.
+const DEFAULT_WINDOW: i64 = 65_535;
+
+/// Largest flow-control window allowed.
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+/// Dynamic table size clients may use in header blocks; the protocol default, which is never
+/// raised.
+const HEADER_TABLE_SIZE: usize = 4_096;
+
+/// Most streams a client may have open at once.
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+
+/// Largest decoded header list accepted, counting 32 bytes per field on top of the name and
+/// value as HTTP/2 does. A few bytes of header block can reference large table entries, so this
+/// bounds the decoded size rather than the encoded one.
+const MAX_HEADER_LIST_SIZE: usize = REQUEST_BUFFER_SIZE;
+
+/// Number of bytes requested from the socket per read.
+const READ_SIZE: usize = 16 * 1024;
+
+// Frame types.
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// Frame flags.
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+// Setting identifiers.
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// Response headers that only apply to HTTP/1 connections and are dropped from HTTP/2 responses.
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// A decoded header list, as name and value pairs.
+type Fields = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Error codes carried by `RST_STREAM` and `GOAWAY` frames.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ErrorCode {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    InternalError = 0x2,
+    FlowControlError = 0x3,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    CompressionError = 0x9,
+    EnhanceYourCalm = 0xb,
+}
+
+/// The fixed-size header preceding every frame payload.
+#[derive(Debug, Copy, Clone)]
+struct FrameHeader {
+    len: usize,
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+}
+
+impl FrameHeader {
+    /// Parses a frame header from the first [`FRAME_HEADER_LEN`] bytes of `bytes`.
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            len: u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize,
+            kind: bytes[3],
+            flags: bytes[4],
+            stream_id: u32::from_be_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]) & 0x7fff_ffff,
+        }
+    }
+}
+
+/// Appends a frame to `out`.
+fn encode_frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&stream_id.to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Removes the padding from the payload of a frame with the `PADDED` flag set.
+fn strip_padding(flags: u8, mut payload: Vec<u8>) -> Result<Vec<u8>, ErrorCode> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+    let pad_len = *payload.first().ok_or(ErrorCode::FrameSizeError)? as usize;
+    if pad_len + 1 > payload.len() {
+        return Err(ErrorCode::ProtocolError);
+    }
+    payload.truncate(payload.len() - pad_len);
+    payload.remove(0);
+    Ok(payload)
+}
+
+/// Appends an HPACK integer with a `prefix_bits` prefix, OR-ing `flags` into the first byte.
+fn encode_integer(value: usize, prefix_bits: u32, flags: u8, block: &mut Vec<u8>) {
+    let max_prefix = (1 << prefix_bits) - 1;
+    if value < max_prefix {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | max_prefix as u8);
+    let mut value = value - max_prefix;
+    while value >= 0x80 {
+        block.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+/// Reads an HPACK integer with a `prefix_bits` prefix, returning it and the number of bytes it
+/// takes up.
+///
+/// Truncated integers and integers longer than five bytes yield `None`.
+fn decode_integer(block: &[u8], prefix_bits: u32) -> Option<(usize, usize)> {
+    let max_prefix = (1 << prefix_bits) - 1;
+    let value = (*block.first()? & max_prefix as u8) as usize;
+    if value < max_prefix {
+        return Some((value, 1));
+    }
+    let mut value = max_prefix;
+    for (idx, &byte) in block.iter().enumerate().skip(1).take(4) {
+        value += ((byte & 0x7f) as usize) << (7 * (idx - 1));
+        if byte & 0x80 == 0 {
+            return Some((value, idx + 1));
+        }
+    }
+    None
+}
+
+/// Returns the number of bytes the HPACK string literal at the start of `block` takes up.
+fn string_len(block: &[u8]) -> Option<usize> {
+    let (len, consumed) = decode_integer(block, 7)?;
+    let total = consumed.checked_add(len)?;
+    (total <= block.len()).then_some(total)
+}
+
+/// Returns the number of bytes the literal field at the start of `block`, whose index has a
+/// `prefix_bits` prefix, takes up.
+fn literal_len(block: &[u8], prefix_bits: u32) -> Option<usize> {
+    let (index, mut len) = decode_integer(block, prefix_bits)?;
+    if index == 0 {
+        len += string_len(&block[len..])?;
+    }
+    Some(len + string_len(&block[len..])?)
+}
+
+/// Checks the framing of a header block before it is decoded, and splits it into its field
+/// representations so they can be decoded one by one.
+///
+/// The decoder panics on some truncated blocks and applies any dynamic table size update, so
+/// truncated integers and strings are rejected here, as are size updates larger than
+/// [`HEADER_TABLE_SIZE`] or following a field.
+fn split_header_block(block: &[u8]) -> Result<Vec<&[u8]>, ErrorCode> {
+    let mut representations = Vec::new();
+    let mut pos = 0;
+    let mut fields_seen = false;
+    while let Some(&first) = block.get(pos) {
+        let rest = &block[pos..];
+        let len = if first & 0x80 != 0 {
+            // Indexed field.
+            decode_integer(rest, 7).map(|(_, len)| len)
+        } else if first & 0x40 != 0 {
+            // Literal field added to the dynamic table.
+            literal_len(rest, 6)
+        } else if first & 0x20 != 0 {
+            // Dynamic table size update.
+            match decode_integer(rest, 5) {
+                Some((size, len)) if size <= HEADER_TABLE_SIZE && !fields_seen => Some(len),
+                _ => None,
+            }
+        } else {
+            // Literal field not added to the dynamic table.
+            literal_len(rest, 4)
+        };
+        fields_seen |= first & 0xe0 != 0x20;
+        let len = len.ok_or(ErrorCode::CompressionError)?;
+        representations.push(&rest[..len]);
+        pos += len;
+    }
+    Ok(representations)
+}
+
+/// Appends an HPACK string literal without Huffman coding.
+fn encode_string(value: &[u8], block: &mut Vec<u8>) {
+    encode_integer(value.len(), 7, 0, block);
+    block.extend_from_slice(value);
+}
+
+/// Appends a header field as a literal that is not added to the dynamic table.
+///
+/// Never indexing responses keeps the encoder stateless, so the table size the client asks for
+/// does not matter.
+fn encode_field(name: &[u8], value: &[u8], block: &mut Vec<u8>) {
+    block.push(0);
+    encode_string(name, block);
+    encode_string(value, block);
+}
+
+/// Appends the `:status` pseudo-header, naming it by its static table index.
+fn encode_status(status: HttpStatus, block: &mut Vec<u8>) {
+    const STATUS_INDEX: usize = 8;
+    encode_integer(STATUS_INDEX, 4, 0, block);
+    encode_string(u16::from(status).to_string().as_bytes(), block);
+}
+
+/// Returns `true` if `byte` may appear in a header name.
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// Why the header fields of a request could not be turned into an [`HttpRequest`].
+#[derive(Debug, PartialEq, Eq)]
+enum Rejection {
+    /// The fields break the protocol rules; the stream is reset.
+    Malformed,
+    /// The request is well-formed but cannot be served; it is answered with `400 Bad Request`.
+    Unsupported,
+}
+
+/// Builds the request head [`HttpRequest::from_bytes`] parses from the fields of an HTTP/2 request.
+///
+/// The head carries an HTTP/1.1 request line, since the parser only accepts HTTP/1 versions; the
+/// parsed request is marked as [`HttpVersion::Two`] afterwards.
+/// Pseudo-headers become the request line, `:authority` becomes the `host` header unless one
+/// was sent, and split `cookie` fields are joined again. A `content-length` header is added for
+/// non-empty bodies that did not declare one.
+fn request_head(fields: Fields, body_len: usize) -> Result<Vec<u8>, Rejection> {
+    let mut method = None;
+    let mut scheme = None;
+    let mut path = None;
+    let mut authority = None;
+    let mut headers = Vec::new();
+    let mut cookies = Vec::new();
+
+    for (name, value) in fields {
+        let name = String::from_utf8(name).map_err(|_| Rejection::Malformed)?;
+        let value = String::from_utf8(value).map_err(|_| Rejection::Malformed)?;
+        if value.contains(['\r', '\n', '\0']) {
+            return Err(Rejection::Malformed);
+        }
+
+        if let Some(pseudo) = name.strip_prefix(':') {
+            let slot = match pseudo {
+                "method" => &mut method,
+                "scheme" => &mut scheme,
+                "path" => &mut path,
+                "authority" => &mut authority,
+                _ => return Err(Rejection::Malformed),
+            };
+            // Pseudo-headers come first and only once.
+            if !headers.is_empty() || !cookies.is_empty() || slot.replace(value).is_some() {
+                return Err(Rejection::Malformed);
+            }
+            continue;
+        }
+
+        if name.is_empty()
+            || name
+                .bytes()
+                .any(|byte| !is_token_byte(byte) || byte.is_ascii_uppercase())
+        {
+            return Err(Rejection::Malformed);
+        }
+        match name.as_str() {
+            "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade" => {
+                return Err(Rejection::Malformed);
+            }
+            "te" if value != "trailers" => return Err(Rejection::Malformed),
+            "content-length" if value.trim().parse() != Ok(body_len) => {
+                return Err(Rejection::Malformed);
+            }
+            "cookie" => cookies.push(value),
+            _ => headers.push((name, value)),
+        }
+    }
+
+    let method = method.ok_or(Rejection::Malformed)?;
+    let target = if method == "CONNECT" {
+        if scheme.is_some() || path.is_some() {
+            return Err(Rejection::Malformed);
+        }
+        authority.clone().ok_or(Rejection::Malformed)?
+    } else {
+        scheme.ok_or(Rejection::Malformed)?;
+        path.filter(|path| !path.is_empty())
+            .ok_or(Rejection::Malformed)?
+    };
+    if target.contains(' ') {
+        return Err(Rejection::Malformed);
+    }
+    if !matches!(
+        method.as_str(),
+        "GET" | "HEAD" | "OPTIONS" | "POST" | "PUT" | "PATCH" | "DELETE" | "CONNECT"
+    ) {
+        return Err(Rejection::Unsupported);
+    }
+
+    let has_host = headers.iter().any(|(name, _)| name == "host");
+    match authority {
+        Some(authority) if !has_host => headers.insert(0, ("host".into(), authority)),
+        // The request head needs at least one header to parse.
+        None if !has_host => return Err(Rejection::Unsupported),
+        _ => {}
+    }
+    if !cookies.is_empty() {
+        headers.push(("cookie".into(), cookies.join("; ")));
+    }
+    if body_len > 0 && !headers.iter().any(|(name, _)| name == "content-length") {
+        headers.push(("content-length".into(), body_len.to_string()));
+    }
+
+    let mut head = format!("{} {} HTTP/1.1\r\n", method, target);
+    for (name, value) in headers {
+        head.push_str(&name);
+        head.push_str(": ");
+        head.push_str(&value);
+        head.push_str("\r\n");
+    }
+    head.push_str("\r\n");
+    Ok(head.into_bytes())
+}
+
+/// An HTTP/1.1 request asking to switch the connection to HTTP/2.
+pub(crate) struct Upgrade {
+    /// The client's settings, decoded from the `HTTP2-Settings` header.
+    settings: Vec<u8>,
+    /// The request translated into HTTP/2 header fields.
+    fields: Fields,
+    body: Vec<u8>,
+}
+
+impl Upgrade {
+    /// Returns the upgrade `req` asks for, if it is a valid `Upgrade: h2c` request.
+    pub(crate) fn from_request(req: &HttpRequest<'_>) -> Option<Self> {
+        let headers = req.headers();
+        let connection = headers.get("Connection");
+        if req.version() != HttpVersion::OnePointOne
+            || !has_token(headers.get("Upgrade"), "h2c")
+            || !has_token(connection, "upgrade")
+            || !has_token(connection, "http2-settings")
+        {
+            return None;
+        }
+        let settings = SETTINGS_ENCODING
+            .decode(headers.get("HTTP2-Settings")?.trim())
+            .ok()?;
+
+        let mut fields: Fields = vec![
+            (b":method".to_vec(), <&str>::from(req.method()).into()),
+            (b":scheme".to_vec(), b"http".to_vec()),
+            (b":path".to_vec(), req.uri().as_ref().into()),
+        ];
+        if let Some(host) = headers.get("Host") {
+            fields.push((b":authority".to_vec(), host.into()));
+        }
+        for (name, value) in headers.iter() {
+            let name = name.to_ascii_lowercase();
+            if name == "host" || name == "http2-settings" || name == "te" {
+                continue;
+            }
+            if CONNECTION_HEADERS.contains(&name.as_str()) {
+                continue;
+            }
+            fields.push((name.into_bytes(), value.into()));
+        }
+
+        Some(Self {
+            settings,
+            fields,
+            body: req.body().to_vec(),
+        })
+    }
+}
+
+/// A connection HTTP/2 can be served over.
+///
+/// Implemented for [`HoochTcpStream`]. Implement it for a TLS stream that negotiated `h2`
+/// through ALPN to serve it with [`HoochApp::serve_h2`](crate::HoochApp::serve_h2).
+pub trait Http2Transport: Send + 'static {
+    /// Reads bytes into `buf`, returning how many were read, or `0` once the peer closed.
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<usize>>;
+
+    /// Writes the whole of `buf`.
+    fn write_all<'a>(&'a mut self, buf: &'a [u8]) -> BoxFuture<'a, io::Result<()>>;
+}
+
+impl Http2Transport for HoochTcpStream {
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<usize>> {
+        Box::pin(HoochTcpStream::read(self, buf))
+    }
+
+    fn write_all<'a>(&'a mut self, buf: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(write_all(self, buf))
+    }
+}
+
+/// Serves an HTTP/2 connection that opened with the connection preface.
+///
+/// `buffered` holds the bytes already read from the connection, starting with the preface.
+pub(crate) async fn serve(
+    stream: impl Http2Transport,
+    socket_addr: SocketAddr,
+    pipeline: Pipeline,
+    buffered: Vec<u8>,
+) {
+    Connection::new(pipeline, socket_addr)
+        .run(stream, buffered)
+        .await
+}
+
+/// Switches an HTTP/1.1 connection to HTTP/2 and answers the upgrade request on stream 1.
+///
+/// `buffered` holds the bytes the client sent after the upgrade request.
+pub(crate) async fn serve_upgraded(
+    mut stream: HoochTcpStream,
+    socket_addr: SocketAddr,
+    pipeline: Pipeline,
+    upgrade: Upgrade,
+    buffered: Vec<u8>,
+) {
+    let mut connection = Connection::new(pipeline, socket_addr);
+    if connection.apply_settings(&upgrade.settings).is_err() {
+        return;
+    }
+    match timeout(
+        write_all(&mut stream, SWITCHING_PROTOCOLS),
+        pipeline.timeouts.write,
+    )
+    .await
+    {
+        Ok(Ok(())) => {}
+        Ok(Err(_)) | Err(TimedOut) => return,
+    }
+
+    // The upgrade request is complete, so stream 1 starts out half-closed.
+    connection.last_stream_id = 1;
+    let mut upgraded = Stream::new(connection.initial_window);
+    upgraded.remote_closed = true;
+    connection.streams.insert(1, upgraded);
+    connection.dispatch(
+        1,
+        PendingRequest {
+            fields: upgrade.fields,
+            body: upgrade.body,
+        },
+    );
+    connection.run(stream, buffered).await
+}
+
+/// A request whose header fields have arrived but which has not been dispatched yet.
+struct PendingRequest {
+    fields: Fields,
+    /// The body received so far.
+    body: Vec<u8>,
+}
+
+/// A header block waiting for its `CONTINUATION` frames.
+struct HeaderBlock {
+    stream_id: u32,
+    fragment: Vec<u8>,
+    end_stream: bool,
+}
+
+/// A request that went through the pipeline.
+struct Dispatched {
+    /// Backs the request the response may still borrow from, so it must outlive the response.
+    request: Vec<u8>,
+    span: Option<RequestSpan>,
+    response: HttpResponse,
+    stream_id: u32,
+}
+
+/// A response being sent on a stream.
+struct Outgoing {
+    /// Body bytes waiting for flow-control window, sent from `sent` on.
+    pending: Vec<u8>,
+    sent: usize,
+    /// The rest of a streaming body; `None` once no more chunks follow `pending`.
+    chunks: Option<BodyStream>,
+    span: Option<RequestSpan>,
+    route: Option<&'static str>,
+    status: HttpStatus,
+    bytes_written: usize,
+    /// Backs the request; declared last so it is dropped after the body.
+    _request: Vec<u8>,
+}
+
+/// The state of a single stream.
+struct Stream {
+    /// The request, until the client finished sending it and it was dispatched.
+    request: Option<PendingRequest>,
+    /// Whether the client finished sending on this stream.
+    remote_closed: bool,
+    /// Whether the request is a `HEAD` request, whose response carries no body.
+    head: bool,
+    /// Flow-control window for sending `DATA` frames on this stream.
+    send_window: i64,
+    /// The response, once the handler produced it.
+    response: Option<Outgoing>,
+    /// Aborts the handler if the stream is reset before it responds.
+    handler: Option<AbortHandle>,
+}
+
+impl Stream {
+    fn new(send_window: i64) -> Self {
+        Self {
+            request: None,
+            remote_closed: false,
+            head: false,
+            send_window,
+            response: None,
+            handler: None,
+        }
+    }
+}
+
+/// What woke the connection task up.
+enum Event {
+    /// Bytes were read from the socket.
+    Read,
+    /// The client closed the connection or the socket errored.
+    Closed,
+    /// A handler produced a response.
+    Response(Box<Dispatched>),
+    /// The handler of a reset stream was dropped.
+    Aborted,
+    /// A streaming response body yielded a chunk or ended.
+    Chunk(u32, Option<io::Result<Vec<u8>>>),
+    /// The connection stayed idle for longer than the header read timeout.
+    Idle,
+}
+
+/// The state of an HTTP/2 connection.
+struct Connection {
+    pipeline: Pipeline,
+    socket_addr: SocketAddr,
+    decoder: Decoder<'static>,
+    streams: BTreeMap<u32, Stream>,
+    /// The highest stream ID the client opened.
+    last_stream_id: u32,
+    /// A header block still missing `CONTINUATION` frames.
+    continuation: Option<HeaderBlock>,
+    /// Handlers that have not responded yet, including aborted ones until they are polled.
+    responses: FuturesUnordered<BoxFuture<'static, Result<Box<Dispatched>, Aborted>>>,
+    /// Connection-level flow-control window for sending `DATA` frames.
+    send_window: i64,
+    /// The window new streams start with, as set by the client.
+    initial_window: i64,
+    /// The largest frame payload the client accepts.
+    max_frame_size: usize,
+    /// Whether the client sent `GOAWAY`, so no new streams will open.
+    going_away: bool,
+    /// Frames waiting to be written.
+    out: Vec<u8>,
+}
+
+impl Connection {
+    fn new(pipeline: Pipeline, socket_addr: SocketAddr) -> Self {
+        Self {
+            pipeline,
+            socket_addr,
+            decoder: Decoder::new(),
+            streams: BTreeMap::new(),
+            last_stream_id: 0,
+            continuation: None,
+            responses: FuturesUnordered::new(),
+            send_window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame_size: MAX_FRAME_SIZE,
+            going_away: false,
+            out: Vec::new(),
+        }
+    }
+
+    /// Drives the connection until either side closes it.
+    ///
+    /// `input` holds bytes already read from the connection, which must start with the rest of
+    /// the connection preface.
+    async fn run(mut self, mut stream: impl Http2Transport, mut input: Vec<u8>) {
+        let mut settings = Vec::new();
+        settings.extend_from_slice(&SETTINGS_MAX_CONCURRENT_STREAMS.to_be_bytes());
+        settings.extend_from_slice(&MAX_CONCURRENT_STREAMS.to_be_bytes());
+        settings.extend_from_slice(&SETTINGS_MAX_HEADER_LIST_SIZE.to_be_bytes());
+        settings.extend_from_slice(&(MAX_HEADER_LIST_SIZE as u32).to_be_bytes());
+        encode_frame(SETTINGS, 0, 0, &settings, &mut self.out);
+        if self.flush(&mut stream).await.is_err() {
+            return;
+        }
+
+        let preface = read_preface(&mut stream, &mut input);
+        if !timeout(preface, self.pipeline.timeouts.header_read)
+            .await
+            .unwrap_or(false)
+        {
+            return;
+        }
+        input.drain(..PREFACE.len());
+
+        loop {
+            if let Err(code) = self.handle_frames(&mut input) {
+                self.go_away(code);
+                let _ = self.flush(&mut stream).await;
+                return;
+            }
+            self.queue_data();
+            if self.flush(&mut stream).await.is_err() {
+                return;
+            }
+            if self.going_away && self.streams.is_empty() && self.responses.is_empty() {
+                return;
+            }
+
+            match self.next_event(&mut stream, &mut input).await {
+                Event::Read => {}
+                Event::Closed => return,
+                Event::Response(dispatched) => self.respond(*dispatched),
+                Event::Aborted => {}
+                Event::Chunk(stream_id, chunk) => self.receive_chunk(stream_id, chunk),
+                Event::Idle => {
+                    self.go_away(ErrorCode::NoError);
+                    let _ = self.flush(&mut stream).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Waits until the socket is readable, a handler responds or a response body yields.
+    ///
+    /// Bytes read from the socket are appended to `input`.
+    async fn next_event(&mut self, stream: &mut impl Http2Transport, input: &mut Vec<u8>) -> Event {
+        let filled = input.len();
+        input.resize(filled + READ_SIZE, 0);
+        let idle = self.streams.is_empty() && self.responses.is_empty();
+        let idle_timeout = self.pipeline.timeouts.header_read.filter(|_| idle);
+
+        let mut bytes_read = 0;
+        let event = {
+            let mut read = pin!(stream.read(&mut input[filled..]));
+            let mut idle = pin!(async move {
+                match idle_timeout {
+                    Some(duration) => sleep(duration).await,
+                    None => std::future::pending().await,
+                }
+            });
+            poll_fn(|cx| {
+                if let Poll::Ready(read) = read.as_mut().poll(cx) {
+                    return Poll::Ready(match read {
+                        Ok(0) | Err(_) => Event::Closed,
+                        Ok(len) => {
+                            bytes_read = len;
+                            Event::Read
+                        }
+                    });
+                }
+                if let Poll::Ready(Some(dispatched)) = self.responses.poll_next_unpin(cx) {
+                    return Poll::Ready(match dispatched {
+                        Ok(dispatched) => Event::Response(dispatched),
+                        Err(Aborted) => Event::Aborted,
+                    });
+                }
+                if let Poll::Ready((stream_id, chunk)) = self.poll_chunks(cx) {
+                    return Poll::Ready(Event::Chunk(stream_id, chunk));
+                }
+                if idle.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Event::Idle);
+                }
+                Poll::Pending
+            })
+            .await
+        };
+        input.truncate(filled + bytes_read);
+        event
+    }
+
+    /// Polls the body streams of responses that sent everything pulled from them so far.
+    fn poll_chunks(&mut self, cx: &mut Context<'_>) -> Poll<(u32, Option<io::Result<Vec<u8>>>)> {
+        for (&stream_id, stream) in &mut self.streams {
+            let Some(outgoing) = stream.response.as_mut() else {
+                continue;
+            };
+            if outgoing.sent < outgoing.pending.len() {
+                continue;
+            }
+            if let Some(chunks) = outgoing.chunks.as_mut()
+                && let Poll::Ready(chunk) = chunks.poll_next_unpin(cx)
+            {
+                return Poll::Ready((stream_id, chunk));
+            }
+        }
+        Poll::Pending
+    }
+
+    /// Writes the queued frames to the socket.
+    async fn flush(&mut self, stream: &mut impl Http2Transport) -> io::Result<()> {
+        if self.out.is_empty() {
+            return Ok(());
+        }
+        let written = timeout(stream.write_all(&self.out), self.pipeline.timeouts.write).await;
+        self.out.clear();
+        written.unwrap_or_else(|TimedOut| Err(io::ErrorKind::TimedOut.into()))
+    }
+
+    /// Handles every complete frame in `input`, removing them from it.
+    fn handle_frames(&mut self, input: &mut Vec<u8>) -> Result<(), ErrorCode> {
+        let mut consumed = 0;
+        let result = loop {
+            let rest = &input[consumed..];
+            if rest.len() < FRAME_HEADER_LEN {
+                break Ok(());
+            }
+            let header = FrameHeader::parse(rest);
+            if header.len > MAX_FRAME_SIZE {
+                break Err(ErrorCode::FrameSizeError);
+            }
+            let frame_end = FRAME_HEADER_LEN + header.len;
+            if rest.len() < frame_end {
+                break Ok(());
+            }
+            let payload = rest[FRAME_HEADER_LEN..frame_end].to_vec();
+            consumed += frame_end;
+            if let Err(code) = self.handle_frame(header, payload) {
+                break Err(code);
+            }
+        };
+        input.drain(..consumed);
+        result
+    }
+
+    /// Handles a single frame, returning the error code if it is a connection error.
+    fn handle_frame(&mut self, header: FrameHeader, payload: Vec<u8>) -> Result<(), ErrorCode> {
+        // Nothing may come between a header block and its continuations.
+        if let Some(block) = &self.continuation
+            && (header.kind != CONTINUATION || header.stream_id != block.stream_id)
+        {
+            return Err(ErrorCode::ProtocolError);
+        }
+
+        match header.kind {
+            DATA => self.on_data(header, payload),
+            HEADERS => self.on_headers(header, payload),
+            PRIORITY => {
+                if header.stream_id == 0 {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                if payload.len() != 5 {
+                    self.reset(header.stream_id, ErrorCode::FrameSizeError);
+                }
+                Ok(())
+            }
+            RST_STREAM => {
+                if header.stream_id == 0 || header.stream_id > self.last_stream_id {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                if payload.len() != 4 {
+                    return Err(ErrorCode::FrameSizeError);
+                }
+                self.close(header.stream_id);
+                Ok(())
+            }
+            SETTINGS => {
+                if header.stream_id != 0 {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                if header.flags & ACK != 0 {
+                    return match payload.is_empty() {
+                        true => Ok(()),
+                        false => Err(ErrorCode::FrameSizeError),
+                    };
+                }
+                self.apply_settings(&payload)?;
+                encode_frame(SETTINGS, ACK, 0, &[], &mut self.out);
+                Ok(())
+            }
+            PUSH_PROMISE => Err(ErrorCode::ProtocolError),
+            PING => {
+                if header.stream_id != 0 {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                if payload.len() != 8 {
+                    return Err(ErrorCode::FrameSizeError);
+                }
+                if header.flags & ACK == 0 {
+                    encode_frame(PING, ACK, 0, &payload, &mut self.out);
+                }
+                Ok(())
+            }
+            GOAWAY => {
+                if header.stream_id != 0 {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                self.going_away = true;
+                Ok(())
+            }
+            WINDOW_UPDATE => self.on_window_update(header, &payload),
+            CONTINUATION => {
+                let Some(mut block) = self.continuation.take() else {
+                    return Err(ErrorCode::ProtocolError);
+                };
+                block.fragment.extend_from_slice(&payload);
+                if block.fragment.len() > REQUEST_BUFFER_SIZE {
+                    return Err(ErrorCode::EnhanceYourCalm);
+                }
+                if header.flags & END_HEADERS == 0 {
+                    self.continuation = Some(block);
+                    return Ok(());
+                }
+                self.on_header_block(block)
+            }
+            // Unknown frame types are ignored.
+            _ => Ok(()),
+        }
+    }
+
+    /// Handles a `DATA` frame, collecting the request body.
+    fn on_data(&mut self, header: FrameHeader, payload: Vec<u8>) -> Result<(), ErrorCode> {
+        let stream_id = header.stream_id;
+        if stream_id == 0 {
+            return Err(ErrorCode::ProtocolError);
+        }
+        let data = strip_padding(header.flags, payload)?;
+
+        // Bodies are bounded by the request buffer size, so the window can be given back at once.
+        if header.len > 0 {
+            self.window_update(0, header.len);
+        }
+
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            // Frames for streams that were reset may still be in flight.
+            return match stream_id > self.last_stream_id {
+                true => Err(ErrorCode::ProtocolError),
+                false => Ok(()),
+            };
+        };
+        if stream.remote_closed {
+            self.reset(stream_id, ErrorCode::StreamClosed);
+            return Ok(());
+        }
+
+        let end_stream = header.flags & END_STREAM != 0;
+        stream.remote_closed = end_stream;
+        let Some(request) = stream.request.as_mut() else {
+            // The stream was already answered; the rest of the body is discarded.
+            return Ok(());
+        };
+        if request.body.len() + data.len() > REQUEST_BUFFER_SIZE {
+            stream.request = None;
+            self.respond_with(stream_id, HttpResponseBuilder::payload_too_large().build());
+            return Ok(());
+        }
+        request.body.extend_from_slice(&data);
+
+        if end_stream {
+            let request = stream.request.take().unwrap();
+            self.dispatch(stream_id, request);
+        } else if header.len > 0 {
+            self.window_update(stream_id, header.len);
+        }
+        Ok(())
+    }
+
+    /// Handles a `HEADERS` frame, waiting for `CONTINUATION` frames unless the block is complete.
+    fn on_headers(&mut self, header: FrameHeader, payload: Vec<u8>) -> Result<(), ErrorCode> {
+        if header.stream_id == 0 {
+            return Err(ErrorCode::ProtocolError);
+        }
+        let mut fragment = strip_padding(header.flags, payload)?;
+        if header.flags & PRIORITY_FLAG != 0 {
+            if fragment.len() < 5 {
+                return Err(ErrorCode::FrameSizeError);
+            }
+            fragment.drain(..5);
+        }
+
+        let block = HeaderBlock {
+            stream_id: header.stream_id,
+            fragment,
+            end_stream: header.flags & END_STREAM != 0,
+        };
+        if header.flags & END_HEADERS == 0 {
+            self.continuation = Some(block);
+            return Ok(());
+        }
+        self.on_header_block(block)
+    }
+
+    /// Handles a complete header block, opening a stream or ending it with trailers.
+    fn on_header_block(&mut self, block: HeaderBlock) -> Result<(), ErrorCode> {
+        let HeaderBlock {
+            stream_id,
+            fragment,
+            end_stream,
+        } = block;
+        // Every block is decoded, even for streams that are ignored, to keep the table in sync.
+        // Decoding field by field stops an oversized list before all of it is in memory; the
+        // table is then out of sync, so the connection has to close.
+        let mut fields = Vec::new();
+        let mut list_size = 0;
+        for representation in split_header_block(&fragment)? {
+            let decoded = self
+                .decoder
+                .decode(representation)
+                .map_err(|_| ErrorCode::CompressionError)?;
+            for (name, value) in decoded {
+                list_size += name.len() + value.len() + 32;
+                if list_size > MAX_HEADER_LIST_SIZE {
+                    return Err(ErrorCode::EnhanceYourCalm);
+                }
+                fields.push((name, value));
+            }
+        }
+
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            // Trailers end the request; their fields are not passed on.
+            if stream.remote_closed {
+                self.reset(stream_id, ErrorCode::StreamClosed);
+            } else if !end_stream {
+                self.reset(stream_id, ErrorCode::ProtocolError);
+            } else {
+                stream.remote_closed = true;
+                if let Some(request) = stream.request.take() {
+                    self.dispatch(stream_id, request);
+                }
+            }
+            return Ok(());
+        }
+
+        if stream_id.is_multiple_of(2) {
+            return Err(ErrorCode::ProtocolError);
+        }
+        if stream_id <= self.last_stream_id {
+            // A stream that was already closed or reset.
+            return Ok(());
+        }
+        self.last_stream_id = stream_id;
+        if self.going_away {
+            return Ok(());
+        }
+        // Aborted handlers count until they are dropped, so streams reset in quick succession
+        // cannot pile up handlers either.
+        if self.streams.len().max(self.responses.len()) >= MAX_CONCURRENT_STREAMS as usize {
+            self.reset(stream_id, ErrorCode::RefusedStream);
+            return Ok(());
+        }
+
+        let request = PendingRequest {
+            fields,
+            body: Vec::new(),
+        };
+        let mut stream = Stream::new(self.initial_window);
+        stream.remote_closed = end_stream;
+        if end_stream {
+            self.streams.insert(stream_id, stream);
+            self.dispatch(stream_id, request);
+        } else {
+            stream.request = Some(request);
+            self.streams.insert(stream_id, stream);
+        }
+        Ok(())
+    }
+
+    /// Handles a `WINDOW_UPDATE` frame, widening the connection or a stream window.
+    fn on_window_update(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), ErrorCode> {
+        let Ok(increment) = <[u8; 4]>::try_from(payload) else {
+            return Err(ErrorCode::FrameSizeError);
+        };
+        let increment = (u32::from_be_bytes(increment) & 0x7fff_ffff) as i64;
+
+        let stream_id = header.stream_id;
+        if stream_id == 0 {
+            if increment == 0 {
+                return Err(ErrorCode::ProtocolError);
+            }
+            self.send_window += increment;
+            if self.send_window > MAX_WINDOW {
+                return Err(ErrorCode::FlowControlError);
+            }
+            return Ok(());
+        }
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return match stream_id > self.last_stream_id {
+                true => Err(ErrorCode::ProtocolError),
+                false => Ok(()),
+            };
+        };
+        stream.send_window += increment;
+        if increment == 0 {
+            self.reset(stream_id, ErrorCode::ProtocolError);
+        } else if stream.send_window > MAX_WINDOW {
+            self.reset(stream_id, ErrorCode::FlowControlError);
+        }
+        Ok(())
+    }
+
+    /// Applies the settings in a `SETTINGS` payload or `HTTP2-Settings` header.
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), ErrorCode> {
+        if !payload.len().is_multiple_of(6) {
+            return Err(ErrorCode::FrameSizeError);
+        }
+        for setting in payload.chunks_exact(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                SETTINGS_ENABLE_PUSH if value > 1 => return Err(ErrorCode::ProtocolError),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let window = value as i64;
+                    if window > MAX_WINDOW {
+                        return Err(ErrorCode::FlowControlError);
+                    }
+                    // Open streams take the change into account as well.
+                    let delta = window - self.initial_window;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > MAX_WINDOW {
+                            return Err(ErrorCode::FlowControlError);
+                        }
+                    }
+                    self.initial_window = window;
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(MAX_FRAME_SIZE as u32..=0xff_ffff).contains(&value) {
+                        return Err(ErrorCode::ProtocolError);
+                    }
+                    self.max_frame_size = value as usize;
+                }
+                // Everything else concerns features the server does not use.
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues a `WINDOW_UPDATE` frame giving `increment` bytes of window back to the client.
+    fn window_update(&mut self, stream_id: u32, increment: usize) {
+        let increment = increment as u32;
+        encode_frame(
+            WINDOW_UPDATE,
+            0,
+            stream_id,
+            &increment.to_be_bytes(),
+            &mut self.out,
+        );
+    }
+
+    /// Resets a stream, dropping its state.
+    fn reset(&mut self, stream_id: u32, code: ErrorCode) {
+        encode_frame(
+            RST_STREAM,
+            0,
+            stream_id,
+            &(code as u32).to_be_bytes(),
+            &mut self.out,
+        );
+        self.close(stream_id);
+    }
+
+    /// Drops the state of a stream, aborting its handler if it is still running.
+    fn close(&mut self, stream_id: u32) {
+        if let Some(stream) = self.streams.remove(&stream_id)
+            && let Some(handler) = stream.handler
+        {
+            handler.abort();
+        }
+    }
+
+    /// Queues a `GOAWAY` frame; no streams after the last one opened are processed.
+    fn go_away(&mut self, code: ErrorCode) {
+        let mut payload = self.last_stream_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&(code as u32).to_be_bytes());
+        encode_frame(GOAWAY, 0, 0, &payload, &mut self.out);
+    }
+
+    /// Runs a complete request through the pipeline.
+    fn dispatch(&mut self, stream_id: u32, request: PendingRequest) {
+        let head = match request_head(request.fields, request.body.len()) {
+            Ok(head) => head,
+            Err(Rejection::Malformed) => return self.reset(stream_id, ErrorCode::ProtocolError),
+            Err(Rejection::Unsupported) => {
+                return self.respond_with(stream_id, HttpResponseBuilder::bad_request().build());
+            }
+        };
+        let (is_head, streams_body) = match HttpRequest::from_bytes(&head) {
+            Ok(parsed) => (
+                parsed.method() == HttpMethod::HEAD,
+                self.pipeline.streams_body(&parsed),
+            ),
+            Err(err) => {
+                let response = err.into_response();
+                return self.respond_with(stream_id, response);
+            }
+        };
+        let (bytes, body) = if streams_body {
+            (head, Some(RequestBody::full(request.body)))
+        } else {
+            ([head, request.body].concat(), None)
+        };
+        let request = run_request(stream_id, bytes, body, self.pipeline, self.socket_addr);
+        let (request, handler) = future::abortable(request);
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.head = is_head;
+            stream.handler = Some(handler);
+        }
+        self.responses.push(request.boxed());
+    }
+
+    /// Answers a stream with a response that did not come from the pipeline.
+    fn respond_with(&mut self, stream_id: u32, response: HttpResponse) {
+        self.respond(Dispatched {
+            request: Vec::new(),
+            span: None,
+            response,
+            stream_id,
+        });
+    }
+
+    /// Starts sending the response to a stream.
+    fn respond(&mut self, dispatched: Dispatched) {
+        let Dispatched {
+            request,
+            span,
+            mut response,
+            stream_id,
+        } = dispatched;
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            // The stream was reset while the handler ran.
+            drop(response);
+            return;
+        };
+
+        let status = response.status();
+        let mut body = response.take_body();
+        let mut block = Vec::new();
+        encode_status(status, &mut block);
+        for (name, value) in response.headers() {
+            let name = name.to_ascii_lowercase();
+            let framing = name == "content-length" && body.is_stream();
+            if framing || CONNECTION_HEADERS.contains(&name.as_str()) {
+                continue;
+            }
+            encode_field(name.as_bytes(), value.as_bytes(), &mut block);
+        }
+        if let Body::Full(bytes) = &body
+            && response.header("Content-Length").is_none()
+        {
+            encode_field(
+                b"content-length",
+                bytes.len().to_string().as_bytes(),
+                &mut block,
+            );
+        }
+        if stream.head {
+            body = Body::Empty;
+        }
+
+        let (pending, chunks) = match body {
+            Body::Empty => (Vec::new(), None),
+            Body::Full(bytes) => (bytes, None),
+            Body::Stream(chunks) => (Vec::new(), Some(chunks)),
+        };
+        let end_stream = pending.is_empty() && chunks.is_none();
+        stream.response = Some(Outgoing {
+            pending,
+            sent: 0,
+            chunks,
+            span,
+            route: response.route(),
+            status,
+            bytes_written: block.len(),
+            _request: request,
+        });
+        self.queue_headers(stream_id, &block, end_stream);
+        if end_stream {
+            self.finish(stream_id);
+        }
+    }
+
+    /// Queues a header block, split into `CONTINUATION` frames if it exceeds the frame size.
+    fn queue_headers(&mut self, stream_id: u32, block: &[u8], end_stream: bool) {
+        let mut fragments = block.chunks(self.max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+        while let Some(fragment) = fragments.next() {
+            if fragments.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            encode_frame(kind, flags, stream_id, fragment, &mut self.out);
+            kind = CONTINUATION;
+            flags = 0;
+        }
+    }
+
+    /// Takes the next chunk of a streaming response body.
+    fn receive_chunk(&mut self, stream_id: u32, chunk: Option<io::Result<Vec<u8>>>) {
+        let Some(outgoing) = self
+            .streams
+            .get_mut(&stream_id)
+            .and_then(|stream| stream.response.as_mut())
+        else {
+            return;
+        };
+        match chunk {
+            Some(Ok(chunk)) => {
+                outgoing.pending = chunk;
+                outgoing.sent = 0;
+            }
+            None => outgoing.chunks = None,
+            Some(Err(_err)) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(error = %_err, "response body stream failed");
+                self.reset(stream_id, ErrorCode::InternalError);
+            }
+        }
+    }
+
+    /// Queues as much pending response data as the flow-control windows allow.
+    fn queue_data(&mut self) {
+        let mut finished = Vec::new();
+        for (&stream_id, stream) in &mut self.streams {
+            let Some(outgoing) = stream.response.as_mut() else {
+                continue;
+            };
+            loop {
+                let remaining = outgoing.pending.len() - outgoing.sent;
+                let last = outgoing.chunks.is_none();
+                if remaining == 0 {
+                    if last {
+                        encode_frame(DATA, END_STREAM, stream_id, &[], &mut self.out);
+                        finished.push(stream_id);
+                    }
+                    break;
+                }
+
+                let window = self.send_window.min(stream.send_window).max(0) as usize;
+                let len = remaining.min(window).min(self.max_frame_size);
+                if len == 0 {
+                    break;
+                }
+                let end_stream = last && len == remaining;
+                let flags = if end_stream { END_STREAM } else { 0 };
+                let data = &outgoing.pending[outgoing.sent..outgoing.sent + len];
+                encode_frame(DATA, flags, stream_id, data, &mut self.out);
+                outgoing.sent += len;
+                outgoing.bytes_written += len;
+                self.send_window -= len as i64;
+                stream.send_window -= len as i64;
+                if end_stream {
+                    finished.push(stream_id);
+                    break;
+                }
+            }
+        }
+        for stream_id in finished {
+            self.finish(stream_id);
+        }
+    }
+
+    /// Closes a stream whose response has been sent completely.
+    fn finish(&mut self, stream_id: u32) {
+        let Some(stream) = self.streams.remove(&stream_id) else {
+            return;
+        };
+        // The client is told to stop sending a body the response no longer needs.
+        if !stream.remote_closed {
+            encode_frame(
+                RST_STREAM,
+                0,
+                stream_id,
+                &(ErrorCode::NoError as u32).to_be_bytes(),
+                &mut self.out,
+            );
+        }
+        if let Some(outgoing) = stream.response
+            && let Some(span) = outgoing.span
+        {
+            span.finish(outgoing.route, outgoing.status, outgoing.bytes_written);
+        }
+    }
+}
+
+/// Reads until `input` holds the connection preface, returning `false` if the client sent
+/// anything else.
+async fn read_preface(stream: &mut impl Http2Transport, input: &mut Vec<u8>) -> bool {
+    while input.len() < PREFACE.len() {
+        if !PREFACE.starts_with(input) {
+            return false;
+        }
+        let filled = input.len();
+        input.resize(filled + READ_SIZE, 0);
+        match stream.read(&mut input[filled..]).await {
+            Ok(0) | Err(_) => return false,
+            Ok(bytes_read) => input.truncate(filled + bytes_read),
+        }
+    }
+    input.starts_with(PREFACE)
+}
+
+/// Runs the request in `bytes` through the pipeline.
+///
+/// `bytes` is handed back along with the response, which may borrow from it. As a parameter it
+/// is dropped after the request if the future is dropped early.
+async fn run_request(
+    stream_id: u32,
+    bytes: Vec<u8>,
+    body: Option<RequestBody>,
+    pipeline: Pipeline,
+    socket_addr: SocketAddr,
+) -> Box<Dispatched> {
+    let bytes = match body {
+        Some(_) => bytes,
+        None => match pipeline.decode_request(&bytes) {
+            Ok(decoded) => decoded.unwrap_or(bytes),
+            Err(response) => {
+                return Box::new(Dispatched {
+                    request: bytes,
+                    span: None,
+                    response,
+                    stream_id,
+                })
+            }
+        },
+    };
+    let mut request = match HttpRequest::from_bytes(&bytes) {
+        Ok(request) => request,
+        Err(err) => {
+            let response = err.into_response();
+            return Box::new(Dispatched {
+                request: bytes,
+                span: None,
+                response,
+                stream_id,
+            });
+        }
+    };
+    request.set_version(HttpVersion::Two);
+
+    // SAFETY: `bytes` outlives the request, and is kept alive alongside the response.
+    let mut request: HttpRequest<'static> = unsafe { std::mem::transmute(request) };
+    request.set_state(pipeline.state);
+    let client_addr = pipeline.identify_client(&mut request, socket_addr);
+    if let Some(body) = body {
+        request.extensions_mut().insert(body);
+    }
+
+    let span = RequestSpan::new(&request);
+    let response = span
+        .instrument(Next::new(pipeline, client_addr).run(request))
+        .await;
+    Box::new(Dispatched {
+        request: bytes,
+        span: Some(span),
+        response,
+        stream_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::{ErrorKind, Read, Write},
+        net::TcpStream,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc,
+        },
+        time::Duration,
+    };
+
+    use hooch::{net::HoochTcpListener, runtime::RuntimeBuilder};
+    use hpack::Encoder;
+
+    use super::*;
+    use crate::{
+        app::tests::{connect, serve},
+        HoochAppBuilder,
+    };
+
+    /// A response read back from the server.
+    #[derive(Debug, Default)]
+    struct Response {
+        fields: HashMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_frame(kind, flags, stream_id, payload, &mut out);
+        out
+    }
+
+    fn settings(settings: &[(u16, u32)]) -> Vec<u8> {
+        let payload: Vec<u8> = settings
+            .iter()
+            .flat_map(|(id, value)| [&id.to_be_bytes()[..], &value.to_be_bytes()[..]].concat())
+            .collect();
+        frame(SETTINGS, 0, 0, &payload)
+    }
+
+    fn request(
+        encoder: &mut Encoder,
+        stream_id: u32,
+        method: &str,
+        path: &str,
+        end_stream: bool,
+    ) -> Vec<u8> {
+        let fields = vec![
+            (b":method".to_vec(), method.as_bytes().to_vec()),
+            (b":scheme".to_vec(), b"http".to_vec()),
+            (b":path".to_vec(), path.as_bytes().to_vec()),
+            (b":authority".to_vec(), b"localhost".to_vec()),
+            (b"cookie".to_vec(), b"a=1".to_vec()),
+            (b"cookie".to_vec(), b"b=2".to_vec()),
+        ];
+        let flags = END_HEADERS | if end_stream { END_STREAM } else { 0 };
+        frame(HEADERS, flags, stream_id, &encoder.encode(&fields))
+    }
+
+    fn read_frame(stream: &mut TcpStream) -> (FrameHeader, Vec<u8>) {
+        let mut header = [0; FRAME_HEADER_LEN];
+        stream.read_exact(&mut header).unwrap();
+        let header = FrameHeader::parse(&header);
+        let mut payload = vec![0; header.len];
+        stream.read_exact(&mut payload).unwrap();
+        (header, payload)
+    }
+
+    /// Reads frames until `count` streams have ended, returning them in the order they ended.
+    fn read_responses(
+        stream: &mut TcpStream,
+        decoder: &mut Decoder,
+        count: usize,
+    ) -> Vec<(u32, Response)> {
+        let mut open: HashMap<u32, Response> = HashMap::new();
+        let mut ended = Vec::new();
+        while ended.len() < count {
+            let (header, payload) = read_frame(stream);
+            let response = open.entry(header.stream_id).or_default();
+            match header.kind {
+                HEADERS => {
+                    assert_ne!(header.flags & END_HEADERS, 0);
+                    for (name, value) in decoder.decode(&payload).unwrap() {
+                        response.fields.insert(
+                            String::from_utf8(name).unwrap(),
+                            String::from_utf8(value).unwrap(),
+                        );
+                    }
+                }
+                DATA => response.body.extend_from_slice(&payload),
+                RST_STREAM | GOAWAY => panic!("unexpected frame {:?} {:?}", header, payload),
+                _ => continue,
+            }
+            if header.flags & END_STREAM != 0 {
+                ended.push((header.stream_id, open.remove(&header.stream_id).unwrap()));
+            }
+        }
+        ended
+    }
+
+    /// Opens a prior-knowledge connection, sending the preface and `settings_frame`.
+    fn open(addr: SocketAddr, settings_frame: Vec<u8>) -> TcpStream {
+        let mut stream = connect(addr);
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(PREFACE).unwrap();
+        stream.write_all(&settings_frame).unwrap();
+        stream
+    }
+
+    fn app(app: &mut HoochAppBuilder) {
+        app.add_route("/hello", HttpMethod::GET, |req, _params| async move {
+            assert_eq!(req.version(), HttpVersion::Two);
+            format!(
+                "hello {} {}",
+                req.headers().get("Host").unwrap_or("-"),
+                req.headers().get("Cookie").unwrap_or("-")
+            )
+        });
+        app.add_route("/echo", HttpMethod::POST, |req, _params| async move {
+            String::from_utf8_lossy(req.body()).into_owned()
+        });
+        app.add_streaming_route("/count", HttpMethod::POST, |mut req, _params| async move {
+            let mut body = req.take_body_stream().unwrap();
+            let mut len = 0;
+            while let Some(chunk) = body.next().await {
+                len += chunk.unwrap().len();
+            }
+            len.to_string()
+        });
+        app.add_route("/slow", HttpMethod::GET, |_req, _params| async move {
+            sleep(Duration::from_millis(300)).await;
+            "slow"
+        });
+        app.add_route("/letters", HttpMethod::GET, |_req, _params| async move {
+            HttpResponseBuilder::ok().body_stream(futures::stream::iter([
+                Ok(b"abcdefghij".to_vec()),
+                Ok(b"klmnopqrst".to_vec()),
+            ]))
+        });
+    }
+
+    #[test]
+    fn prior_knowledge_requests() {
+        let addr = serve(app);
+        let mut stream = open(addr, settings(&[]));
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+
+        stream
+            .write_all(&request(&mut encoder, 1, "GET", "/hello", true))
+            .unwrap();
+        let responses = read_responses(&mut stream, &mut decoder, 1);
+        let (stream_id, response) = &responses[0];
+        assert_eq!(*stream_id, 1);
+        assert_eq!(response.fields[":status"], "200");
+        assert_eq!(response.fields["content-length"], "24");
+        assert_eq!(response.body, b"hello localhost a=1; b=2");
+
+        // Bodies may span several DATA frames.
+        stream
+            .write_all(&request(&mut encoder, 3, "POST", "/echo", false))
+            .unwrap();
+        stream.write_all(&frame(DATA, 0, 3, b"split ")).unwrap();
+        stream
+            .write_all(&frame(DATA, END_STREAM, 3, b"body"))
+            .unwrap();
+        stream
+            .write_all(&request(&mut encoder, 5, "POST", "/count", false))
+            .unwrap();
+        stream
+            .write_all(&frame(DATA, END_STREAM, 5, &[7; 1000]))
+            .unwrap();
+        let mut responses = read_responses(&mut stream, &mut decoder, 2);
+        responses.sort_by_key(|(stream_id, _)| *stream_id);
+        assert_eq!(responses[0].1.body, b"split body");
+        assert_eq!(responses[1].1.body, b"1000");
+
+        // Unknown routes still get an answer, and HEAD responses carry no body.
+        stream
+            .write_all(&request(&mut encoder, 7, "GET", "/missing", true))
+            .unwrap();
+        stream
+            .write_all(&request(&mut encoder, 9, "HEAD", "/letters", true))
+            .unwrap();
+        let mut responses = read_responses(&mut stream, &mut decoder, 2);
+        responses.sort_by_key(|(stream_id, _)| *stream_id);
+        assert_eq!(responses[0].1.fields[":status"], "404");
+        assert!(responses[1].1.body.is_empty());
+    }
+
+    #[test]
+    fn streams_are_multiplexed() {
+        let addr = serve(app);
+        let mut stream = open(addr, settings(&[]));
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+
+        stream
+            .write_all(&request(&mut encoder, 1, "GET", "/slow", true))
+            .unwrap();
+        stream
+            .write_all(&request(&mut encoder, 3, "GET", "/letters", true))
+            .unwrap();
+
+        let responses = read_responses(&mut stream, &mut decoder, 2);
+        assert_eq!(responses[0].0, 3);
+        assert_eq!(responses[0].1.body, b"abcdefghijklmnopqrst");
+        assert!(!responses[0].1.fields.contains_key("transfer-encoding"));
+        assert_eq!(responses[1].0, 1);
+        assert_eq!(responses[1].1.body, b"slow");
+    }
+
+    #[test]
+    fn response_data_respects_flow_control() {
+        let addr = serve(app);
+        let mut stream = open(addr, settings(&[(SETTINGS_INITIAL_WINDOW_SIZE, 5)]));
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+
+        stream
+            .write_all(&request(&mut encoder, 1, "GET", "/letters", true))
+            .unwrap();
+        let mut body = Vec::new();
+        while body.len() < 5 {
+            let (header, payload) = read_frame(&mut stream);
+            match header.kind {
+                HEADERS => assert_eq!(decoder.decode(&payload).unwrap()[0].1, b"200"),
+                DATA => body.extend_from_slice(&payload),
+                _ => {}
+            }
+        }
+        assert_eq!(body, b"abcde");
+
+        // Nothing but the acknowledgement of our settings arrives until the window is widened.
+        stream
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut header = [0; FRAME_HEADER_LEN];
+        loop {
+            match stream.read_exact(&mut header) {
+                Ok(()) => {
+                    let header = FrameHeader::parse(&header);
+                    assert_ne!(header.kind, DATA);
+                    stream.read_exact(&mut vec![0; header.len]).unwrap();
+                }
+                Err(err) => {
+                    assert!(matches!(
+                        err.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut
+                    ));
+                    break;
+                }
+            }
+        }
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(&frame(WINDOW_UPDATE, 0, 1, &100u32.to_be_bytes()))
+            .unwrap();
+        let responses = read_responses(&mut stream, &mut decoder, 1);
+        assert_eq!(responses[0].1.body, b"fghijklmnopqrst");
+    }
+
+    #[test]
+    fn upgrades_from_http1() {
+        let addr = serve(app);
+        let mut stream = connect(addr);
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\nCookie: c=3\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n")
+            .unwrap();
+
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("\r\nUpgrade: h2c\r\n"));
+
+        stream.write_all(PREFACE).unwrap();
+        stream.write_all(&settings(&[])).unwrap();
+        let mut decoder = Decoder::new();
+        let responses = read_responses(&mut stream, &mut decoder, 1);
+        assert_eq!(responses[0].0, 1);
+        assert_eq!(responses[0].1.body, b"hello localhost c=3");
+    }
+
+    /// Stands in for a TLS stream that negotiated `h2` through ALPN.
+    struct Negotiated(HoochTcpStream);
+
+    impl Http2Transport for Negotiated {
+        fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<usize>> {
+            Http2Transport::read(&mut self.0, buf)
+        }
+
+        fn write_all<'a>(&'a mut self, buf: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+            Http2Transport::write_all(&mut self.0, buf)
+        }
+    }
+
+    #[test]
+    fn serves_negotiated_connections() {
+        let mut builder = HoochAppBuilder::new(("127.0.0.1", 0)).unwrap();
+        app(&mut builder);
+        let app = builder.build();
+        let (addr_tx, addr_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let handle = RuntimeBuilder::default().build();
+            handle.run_blocking(async move {
+                let listener = HoochTcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+                addr_tx.send(listener.local_addr().unwrap()).unwrap();
+                let (stream, socket_addr) = listener.accept().await.unwrap();
+                app.serve_h2(Negotiated(stream), socket_addr).await;
+            });
+        });
+
+        let mut stream = open(addr_rx.recv().unwrap(), settings(&[]));
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+        stream
+            .write_all(&request(&mut encoder, 1, "GET", "/hello", true))
+            .unwrap();
+        let responses = read_responses(&mut stream, &mut decoder, 1);
+        assert_eq!(responses[0].1.fields[":status"], "200");
+        assert_eq!(responses[0].1.body, b"hello localhost a=1; b=2");
+    }
+
+    #[test]
+    fn reset_streams_drop_their_handlers() {
+        static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+        struct Running;
+
+        impl Drop for Running {
+            fn drop(&mut self) {
+                RUNNING.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+
+        let wait_for = |running: usize| {
+            let start = std::time::Instant::now();
+            while RUNNING.load(Ordering::SeqCst) != running {
+                assert!(
+                    start.elapsed() < Duration::from_secs(5),
+                    "{} running",
+                    running
+                );
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        };
+
+        let addr = serve(|builder| {
+            app(builder);
+            builder.add_route("/stuck", HttpMethod::GET, |_req, _params| async move {
+                RUNNING.fetch_add(1, Ordering::SeqCst);
+                let _running = Running;
+                std::future::pending::<&str>().await
+            });
+        });
+        let mut stream = open(addr, settings(&[]));
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+
+        // More streams than may be open at once are opened and reset, a batch at a time.
+        let mut stream_id = 1;
+        for _ in 0..3 {
+            let batch: Vec<u32> = (0..60).map(|idx| stream_id + 2 * idx).collect();
+            stream_id += 120;
+            for &id in &batch {
+                stream
+                    .write_all(&request(&mut encoder, id, "GET", "/stuck", true))
+                    .unwrap();
+            }
+            wait_for(batch.len());
+            for &id in &batch {
+                stream
+                    .write_all(&frame(RST_STREAM, 0, id, &[0, 0, 0, 8]))
+                    .unwrap();
+            }
+            wait_for(0);
+        }
+
+        stream
+            .write_all(&request(&mut encoder, stream_id, "GET", "/hello", true))
+            .unwrap();
+        let responses = read_responses(&mut stream, &mut decoder, 1);
+        assert_eq!(responses[0].1.fields[":status"], "200");
+    }
+
+    #[test]
+    fn protocol_errors_close_the_connection() {
+        let addr = serve(app);
+        let mut stream = open(addr, settings(&[]));
+
+        stream.write_all(&frame(PING, 0, 1, &[0; 8])).unwrap();
+        loop {
+            let (header, payload) = read_frame(&mut stream);
+            if header.kind == GOAWAY {
+                assert_eq!(
+                    payload[4..],
+                    (ErrorCode::ProtocolError as u32).to_be_bytes()
+                );
+                break;
+            }
+        }
+        assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+    }
+
+    #[test]
+    fn malformed_header_blocks_close_the_connection() {
+        let addr = serve(app);
+        // A truncated table size update, a size update above the table size, a size update
+        // following a field and a literal whose value runs past the end of the block.
+        for block in [
+            &[0x3f][..],
+            &[0x3f, 0xe2, 0x1f],
+            &[0x82, 0x20],
+            &[0x0f, 0x01, 0x05, b'a'],
+        ] {
+            let mut stream = open(addr, settings(&[]));
+            stream
+                .write_all(&frame(HEADERS, END_HEADERS | END_STREAM, 1, block))
+                .unwrap();
+            loop {
+                let (header, payload) = read_frame(&mut stream);
+                if header.kind == GOAWAY {
+                    assert_eq!(
+                        payload[4..],
+                        (ErrorCode::CompressionError as u32).to_be_bytes(),
+                        "{:?}",
+                        block
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn header_lists_are_limited_after_decoding() {
+        let addr = serve(app);
+        // Adds a 4000-byte field to the dynamic table, then references it `copies` times with a
+        // single byte each.
+        let block = |copies: usize| {
+            let mut block = Vec::new();
+            encode_field(b":method", b"GET", &mut block);
+            encode_field(b":scheme", b"http", &mut block);
+            encode_field(b":path", b"/hello", &mut block);
+            encode_field(b":authority", b"localhost", &mut block);
+            block.push(0x40);
+            encode_string(b"x-large", &mut block);
+            encode_string(&[b'a'; 4000], &mut block);
+            block.extend(std::iter::repeat_n(0x80 | 62, copies));
+            block
+        };
+
+        let mut stream = open(addr, settings(&[]));
+        let (header, payload) = read_frame(&mut stream);
+        assert_eq!(header.kind, SETTINGS);
+        assert!(payload
+            .chunks(6)
+            .any(|setting| setting[..2] == SETTINGS_MAX_HEADER_LIST_SIZE.to_be_bytes()));
+        stream
+            .write_all(&frame(HEADERS, END_HEADERS | END_STREAM, 1, &block(10)))
+            .unwrap();
+        let responses = read_responses(&mut stream, &mut Decoder::new(), 1);
+        assert_eq!(responses[0].1.fields[":status"], "200");
+
+        let mut stream = open(addr, settings(&[]));
+        stream
+            .write_all(&frame(HEADERS, END_HEADERS | END_STREAM, 1, &block(100)))
+            .unwrap();
+        loop {
+            let (header, payload) = read_frame(&mut stream);
+            if header.kind == GOAWAY {
+                assert_eq!(
+                    payload[4..],
+                    (ErrorCode::EnhanceYourCalm as u32).to_be_bytes()
+                );
+                break;
+            }
+        }
+        assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+    }
+
+    #[test]
+    fn header_block_checks() {
+        assert_eq!(decode_integer(&[0x0a], 5), Some((10, 1)));
+        assert_eq!(decode_integer(&[0x1f, 0x9a, 0x0a], 5), Some((1337, 3)));
+        assert_eq!(decode_integer(&[0x1f, 0x9a], 5), None);
+        assert_eq!(
+            decode_integer(&[0x1f, 0xff, 0xff, 0xff, 0xff, 0x01], 5),
+            None
+        );
+
+        let mut encoder = Encoder::new();
+        let fields = vec![
+            (b":method".to_vec(), b"GET".to_vec()),
+            (b"x-custom".to_vec(), b"value".to_vec()),
+        ];
+        let block = encoder.encode(&fields);
+        assert_eq!(split_header_block(&block).unwrap().concat(), block);
+        // A size update to the full table size, then an indexed field.
+        assert_eq!(
+            split_header_block(&[0x3f, 0xe1, 0x1f, 0x82]),
+            Ok(vec![&[0x3f, 0xe1, 0x1f][..], &[0x82]])
+        );
+    }
+
+    #[test]
+    fn request_head_from_fields() {
+        let field = |name: &str, value: &str| (name.as_bytes().to_vec(), value.as_bytes().to_vec());
+        let fields = vec![
+            field(":method", "POST"),
+            field(":scheme", "http"),
+            field(":path", "/orders?page=2"),
+            field(":authority", "example.com"),
+            field("content-type", "application/json"),
+        ];
+        assert_eq!(
+            String::from_utf8(request_head(fields, 2).unwrap()).unwrap(),
+            "POST /orders?page=2 HTTP/1.1\r\nhost: example.com\r\ncontent-type: application/json\r\ncontent-length: 2\r\n\r\n"
+        );
+
+        let connect = vec![
+            field(":method", "CONNECT"),
+            field(":authority", "example.com:443"),
+        ];
+        assert!(request_head(connect, 0)
+            .unwrap()
+            .starts_with(b"CONNECT example.com:443 HTTP/1.1\r\n"));
+
+        let malformed = [
+            vec![field(":method", "GET"), field(":path", "/")],
+            vec![
+                field(":method", "GET"),
+                field(":scheme", "http"),
+                field(":path", "/"),
+                field("Host", "example.com"),
+            ],
+            vec![
+                field(":method", "GET"),
+                field(":scheme", "http"),
+                field("host", "example.com"),
+                field(":path", "/"),
+            ],
+            vec![
+                field(":method", "GET"),
+                field(":scheme", "http"),
+                field(":path", "/"),
+                field("connection", "keep-alive"),
+            ],
+        ];
+        for fields in malformed {
+            assert_eq!(request_head(fields, 0), Err(Rejection::Malformed));
+        }
+    }
+}
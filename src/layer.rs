@@ -0,0 +1,109 @@
+//! # Layers
+//!
+//! Layers wrap the whole request pipeline: they receive the request before any middleware or
+//! route handler runs, and get to inspect or replace the response the rest of the pipeline
+//! produced. This makes them the place for cross-cutting concerns that need to see both sides
+//! of an exchange, such as access logging or adding response headers.
+//!
+//! Layers run in the order they were added, so the first layer added is the outermost one.
+//! Each layer decides whether, and with which request, to call [`Next::run`].
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hooch_http::{layer_fn, HoochAppBuilder};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//!
+//! app.add_layer(layer_fn(|req, socket, next| async move {
+//!     let method = req.method();
+//!     let response = next.run(req).await;
+//!     println!("{} {:?} -> {:?}", socket, method, response.status());
+//!     response
+//! }));
+//! ```
+
+use std::{future::Future, net::SocketAddr};
+
+use futures::{future::BoxFuture, FutureExt};
+
+use crate::{app::Pipeline, request::HttpRequest, response::HttpResponse, HoochApp};
+
+/// A component wrapping the request pipeline.
+///
+/// Implementors receive the request, the client's socket address and a [`Next`] handle to the
+/// rest of the pipeline, and resolve to the response sent back to the client.
+pub trait Layer: Send + Sync + 'static {
+    /// Handles a request, usually by calling [`Next::run`] and working with its response.
+    fn call<'a>(
+        &'a self,
+        req: HttpRequest<'static>,
+        socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse>;
+}
+
+/// Handle to the remainder of the request pipeline: the layers added after the current one,
+/// followed by middleware and routing.
+pub struct Next {
+    pipeline: Pipeline,
+    layers: &'static [Box<dyn Layer>],
+    socket_addr: SocketAddr,
+}
+
+impl Next {
+    /// Creates a handle that runs the full pipeline, starting with the outermost layer.
+    pub(crate) fn new(pipeline: Pipeline, socket_addr: SocketAddr) -> Self {
+        Self {
+            pipeline,
+            layers: pipeline.layers,
+            socket_addr,
+        }
+    }
+
+    /// Runs the rest of the pipeline for `req` and resolves to its response.
+    ///
+    /// The returned future is boxed so that nesting several layers keeps each layer's own future
+    /// small, regardless of how deep the pipeline is.
+    pub fn run(self, req: HttpRequest<'static>) -> BoxFuture<'static, HttpResponse> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => {
+                let next = Next {
+                    pipeline: self.pipeline,
+                    layers,
+                    socket_addr: self.socket_addr,
+                };
+                layer.call(req, self.socket_addr, next)
+            }
+            None => HoochApp::dispatch(req, self.socket_addr, self.pipeline).boxed(),
+        }
+    }
+}
+
+/// A [`Layer`] built from a closure; see [`layer_fn`].
+pub struct LayerFn<F>(F);
+
+/// Creates a [`Layer`] from an async closure taking the request, the client's socket address and
+/// the [`Next`] handle.
+pub fn layer_fn<F, Fut>(f: F) -> LayerFn<F>
+where
+    F: Fn(HttpRequest<'static>, SocketAddr, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HttpResponse> + Send + 'static,
+{
+    LayerFn(f)
+}
+
+impl<F, Fut> Layer for LayerFn<F>
+where
+    F: Fn(HttpRequest<'static>, SocketAddr, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HttpResponse> + Send + 'static,
+{
+    fn call<'a>(
+        &'a self,
+        req: HttpRequest<'static>,
+        socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse> {
+        (self.0)(req, socket_addr, next).boxed()
+    }
+}
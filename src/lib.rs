@@ -1,9 +1,64 @@
 mod app;
+mod auth;
+mod body;
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+mod compression;
+mod conditional;
+mod cookie;
+mod cors;
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+mod decompression;
+mod extensions;
+mod extract;
+mod handler;
+#[cfg(feature = "http2")]
+mod http2;
+#[cfg(feature = "jwt")]
+mod jwt;
+mod layer;
+mod logging;
+mod proxy;
+mod range;
+mod rate_limit;
 mod request;
+mod request_body;
+mod request_id;
 mod response;
+mod serve_dir;
+mod session;
 mod shared;
+mod sse;
+mod websocket;
 
 pub use self::app::*;
+pub use self::auth::*;
+pub use self::body::*;
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+pub use self::compression::*;
+pub use self::conditional::*;
+pub use self::cookie::*;
+pub use self::cors::*;
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+pub use self::decompression::*;
+pub use self::extensions::*;
+pub use self::extract::*;
+pub use self::handler::*;
+#[cfg(feature = "http2")]
+pub use self::http2::*;
+#[cfg(feature = "jwt")]
+pub use self::jwt::*;
+pub use self::layer::*;
+#[cfg(feature = "tracing")]
+pub use self::logging::*;
+pub use self::proxy::*;
+pub use self::range::*;
+pub use self::rate_limit::*;
 pub use self::request::*;
+pub use self::request_body::*;
+pub use self::request_id::*;
 pub use self::response::*;
+pub use self::serve_dir::*;
+pub use self::session::*;
 pub use self::shared::*;
+pub use self::sse::*;
+pub use self::websocket::*;
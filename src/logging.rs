@@ -0,0 +1,372 @@
+//! # Request Logging
+//!
+//! Structured logging for the server, available behind the `tracing` cargo feature.
+//!
+//! With the feature enabled, every request is handled inside a `request` span carrying the
+//! request's method and URI. Once the response has been written the span records the matched
+//! route pattern, the response status, the number of bytes written and the latency, and a
+//! `request completed` event is emitted. The [`RequestIdLayer`](crate::RequestIdLayer) adds the
+//! request's id to the span. Without the feature nothing is logged.
+//!
+//! The [`AccessLog`] layer can additionally be added to an application to emit one line per
+//! request in the Apache/NCSA combined log format, under the `hooch_http::access` target.
+
+use std::future::Future;
+
+#[cfg(feature = "tracing")]
+use std::{
+    net::SocketAddr,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "tracing")]
+use futures::future::BoxFuture;
+#[cfg(feature = "tracing")]
+use tracing::{field::Empty, Instrument, Span};
+
+#[cfg(feature = "tracing")]
+use crate::{
+    layer::{Layer, Next},
+    response::HttpResponse,
+};
+use crate::{request::HttpRequest, response::HttpStatus};
+
+/// Tracks a single request for structured logging.
+///
+/// This is a no-op unless the `tracing` feature is enabled.
+pub(crate) struct RequestSpan {
+    #[cfg(feature = "tracing")]
+    span: Span,
+    #[cfg(feature = "tracing")]
+    start: Instant,
+}
+
+impl RequestSpan {
+    /// Opens the span for a freshly parsed request.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn new(req: &HttpRequest<'_>) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "request",
+                method = <&str>::from(req.method()),
+                uri = req.uri().as_ref(),
+                route = Empty,
+                request_id = Empty,
+                status = Empty,
+                bytes = Empty,
+                latency_us = Empty,
+            ),
+            #[cfg(feature = "tracing")]
+            start: Instant::now(),
+        }
+    }
+
+    /// Runs `fut` inside the request span.
+    pub(crate) fn instrument<F: Future>(&self, fut: F) -> impl Future<Output = F::Output> {
+        #[cfg(feature = "tracing")]
+        return fut.instrument(self.span.clone());
+        #[cfg(not(feature = "tracing"))]
+        fut
+    }
+
+    /// Records the outcome of the request and emits the completion event.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn finish(self, route: Option<&str>, status: HttpStatus, bytes: usize) {
+        #[cfg(feature = "tracing")]
+        {
+            let latency = self.start.elapsed();
+            if let Some(route) = route {
+                self.span.record("route", route);
+            }
+            self.span.record("status", u16::from(status));
+            self.span.record("bytes", bytes);
+            self.span.record("latency_us", latency.as_micros() as u64);
+            tracing::info!(
+                parent: &self.span,
+                status = u16::from(status),
+                bytes,
+                latency_us = latency.as_micros() as u64,
+                "request completed"
+            );
+        }
+    }
+}
+
+/// A [`Layer`] logging every request in the combined log format.
+///
+/// Lines are emitted as `INFO` events with the `hooch_http::access` target, for example:
+///
+/// ```text
+/// 127.0.0.1 - - [10/Oct/2025:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326 "-" "curl/8.5.0"
+/// ```
+#[cfg(feature = "tracing")]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AccessLog;
+
+#[cfg(feature = "tracing")]
+impl AccessLog {
+    /// Creates an access log layer using the combined log format.
+    pub fn combined() -> Self {
+        Self
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Layer for AccessLog {
+    fn call<'a>(
+        &'a self,
+        req: HttpRequest<'static>,
+        socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            let received = SystemTime::now();
+            let request_line = format!(
+                "{} {} {}",
+                <&str>::from(req.method()),
+                req.uri().as_ref(),
+                <&str>::from(req.version())
+            );
+            let referer = req.headers().get("Referer").unwrap_or("-");
+            let user_agent = req.headers().get("User-Agent").unwrap_or("-");
+
+            let response = next.run(req).await;
+
+            let bytes = match response.body().map(<[u8]>::len) {
+                Some(len) if len > 0 => len.to_string(),
+                _ => "-".to_string(),
+            };
+            tracing::info!(
+                target: "hooch_http::access",
+                "{} - - [{}] \"{}\" {} {} \"{}\" \"{}\"",
+                socket_addr.ip(),
+                format_clf_time(received),
+                request_line,
+                u16::from(response.status()),
+                bytes,
+                referer,
+                user_agent
+            );
+
+            response
+        })
+    }
+}
+
+/// Formats a timestamp as used in common/combined log lines, e.g. `10/Oct/2025:13:55:36 +0000`.
+///
+/// Times are always rendered in UTC.
+#[cfg(feature = "tracing")]
+fn format_clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Converts days since the Unix epoch into a `(year, month, day)` civil date.
+#[cfg(feature = "tracing")]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fmt::Debug,
+        io::Write,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            LazyLock, Mutex,
+        },
+        time::{Duration, Instant},
+    };
+
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Event, Metadata, Subscriber,
+    };
+
+    use super::*;
+    use crate::{
+        app::tests::{connect, read_response, serve},
+        HttpMethod,
+    };
+
+    /// Field values by name, formatted with `Debug` except for strings.
+    #[derive(Debug, Default, Clone)]
+    struct Fields(HashMap<&'static str, String>);
+
+    impl Visit for Fields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.insert(field.name(), format!("{:?}", value));
+        }
+    }
+
+    /// Spans by id and events, with their target and explicit parent, seen by [`Capture`].
+    #[derive(Default)]
+    struct Captured {
+        spans: HashMap<u64, Fields>,
+        events: Vec<(&'static str, Option<u64>, Fields)>,
+    }
+
+    static CAPTURED: LazyLock<Mutex<Captured>> = LazyLock::new(Mutex::default);
+
+    /// A subscriber recording everything into [`CAPTURED`].
+    struct Capture {
+        next_id: AtomicU64,
+    }
+
+    impl Subscriber for Capture {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let mut fields = Fields::default();
+            span.record(&mut fields);
+            CAPTURED.lock().unwrap().spans.insert(id, fields);
+            Id::from_u64(id)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            if let Some(fields) = CAPTURED.lock().unwrap().spans.get_mut(&span.into_u64()) {
+                values.record(fields);
+            }
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            let parent = event.parent().map(Id::into_u64);
+            let target = event.metadata().target();
+            CAPTURED
+                .lock()
+                .unwrap()
+                .events
+                .push((target, parent, fields));
+        }
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    /// Waits for the first captured event `find` matches.
+    fn wait_for<T>(mut find: impl FnMut(&Captured) -> Option<T>) -> T {
+        let start = Instant::now();
+        loop {
+            if let Some(found) = find(&CAPTURED.lock().unwrap()) {
+                return found;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "no matching event"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn request_span_and_access_log() {
+        // The server handles requests on runtime threads, so the subscriber has to be global.
+        tracing::subscriber::set_global_default(Capture {
+            next_id: AtomicU64::new(1),
+        })
+        .unwrap();
+        let addr = serve(|app| {
+            app.add_layer(AccessLog::combined());
+            app.add_route(
+                "/logged",
+                HttpMethod::GET,
+                |_req, _params| async move { "hi" },
+            );
+        });
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /logged?page=1 HTTP/1.1\r\nHost: localhost\r\nUser-Agent: test-agent\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let span = wait_for(|captured| {
+            captured.events.iter().find_map(|(_, parent, fields)| {
+                let span = captured.spans.get(&(*parent)?)?;
+                let completed = fields.0.get("message")? == "request completed";
+                (completed && span.0.get("uri")? == "/logged?page=1").then(|| span.clone())
+            })
+        });
+        assert_eq!(span.0["method"], "GET");
+        assert_eq!(span.0["route"], "/logged");
+        assert_eq!(span.0["status"], "200");
+        assert_eq!(span.0["bytes"], response.len().to_string());
+        assert!(span.0["latency_us"].parse::<u64>().is_ok());
+
+        let line = wait_for(|captured| {
+            captured.events.iter().find_map(|(target, _, fields)| {
+                let message = fields.0.get("message")?;
+                (*target == "hooch_http::access" && message.contains("/logged?page=1"))
+                    .then(|| message.clone())
+            })
+        });
+        assert!(line.starts_with("127.0.0.1 - - ["), "{}", line);
+        assert!(
+            line.ends_with("] \"GET /logged?page=1 HTTP/1.1\" 200 2 \"-\" \"test-agent\""),
+            "{}",
+            line
+        );
+    }
+
+    #[test]
+    fn clf_time_epoch() {
+        assert_eq!(format_clf_time(UNIX_EPOCH), "01/Jan/1970:00:00:00 +0000");
+    }
+
+    #[test]
+    fn clf_time_leap_day() {
+        // 2024-02-29T13:55:36Z
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_214_936);
+        assert_eq!(format_clf_time(time), "29/Feb/2024:13:55:36 +0000");
+    }
+}
@@ -0,0 +1,689 @@
+//! # Trusted Proxies
+//!
+//! Behind a load balancer or reverse proxy, the peer of every connection is the proxy, not the
+//! client. Proxies report the client in request headers, or at the start of the connection with
+//! the HAProxy PROXY protocol, but any client can send such headers too. [`TrustedProxies`]
+//! lists the networks whose reports are believed; register it with
+//! [`HoochAppBuilder::set_trusted_proxies`](crate::HoochAppBuilder::set_trusted_proxies).
+//!
+//! For requests from a trusted peer, the `Forwarded` header, or failing that the
+//! `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers, are walked from the
+//! nearest hop outwards for as long as the hops are trusted proxies themselves. The first
+//! untrusted hop is the client. Hops named `unknown` or by an obfuscated identifier end the walk
+//! at the last known address.
+//!
+//! The derived client address replaces the peer address handed to layers and middleware, so
+//! that logging or [rate limiting](crate::RateLimit) see the client. Handlers can take the full
+//! [`ClientInfo`], including the scheme and host the client used, as an extractor. Without
+//! trusted proxies, it describes the peer itself.
+//!
+//! With the PROXY protocol enabled, connections from trusted peers must begin with a PROXY
+//! header, version 1 or 2, whose source address then stands in for the peer. Connections
+//! failing to send one are answered with `400 Bad Request` and closed.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hooch_http::{ClientInfo, HoochAppBuilder, HttpMethod, TrustedProxies};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.set_trusted_proxies(
+//!     TrustedProxies::new()
+//!         .trust("10.0.0.0/8")
+//!         .unwrap()
+//!         .trust("fd00::/8")
+//!         .unwrap(),
+//! );
+//!
+//! app.add_handler("/whoami", HttpMethod::GET, |client: ClientInfo| async move {
+//!     format!(
+//!         "{} via {}",
+//!         client.addr.ip(),
+//!         client.scheme.as_deref().unwrap_or("http")
+//!     )
+//! });
+//! ```
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use crate::{
+    extract::FromRequest,
+    request::{HttpRequest, Params},
+    response::{HttpResponse, HttpResponseBuilder},
+};
+
+/// Signature opening a version 2 PROXY header.
+const PROXY_V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Maximum length of a version 1 PROXY header, including its line ending.
+const PROXY_V1_MAX_LEN: usize = 107;
+
+/// The client of a request, as reported by trusted proxies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// The address of the client. The port is `0` if proxies reported the address without one.
+    pub addr: SocketAddr,
+    /// The scheme the client used, such as `https`, if a proxy reported it.
+    pub scheme: Option<String>,
+    /// The host the client addressed, if a proxy reported it.
+    pub host: Option<String>,
+}
+
+impl FromRequest for ClientInfo {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        _params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        req.extensions()
+            .get::<ClientInfo>()
+            .cloned()
+            .ok_or_else(|| HttpResponseBuilder::internal_server_error().build())
+    }
+}
+
+/// A network of trusted proxies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Network {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Network {
+    fn parse(network: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid network {:?}", network),
+            )
+        };
+        let (addr, prefix_len) = match network.trim().split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (network.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| invalid())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+        Ok(Self { addr, prefix_len })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len));
+                let mask = mask.unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len));
+                let mask = mask.unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The networks whose reports of the client are trusted.
+///
+/// See the [module documentation](self) for how the client is derived.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<Network>,
+    proxy_protocol: bool,
+}
+
+impl TrustedProxies {
+    /// Creates an empty list, trusting no one.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts an address, such as `10.1.2.3`, or a network in CIDR notation, such as
+    /// `10.0.0.0/8` or `fd00::/8`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `network` is neither.
+    pub fn trust(mut self, network: &str) -> io::Result<Self> {
+        self.networks.push(Network::parse(network)?);
+        Ok(self)
+    }
+
+    /// Requires connections from trusted peers to begin with a PROXY protocol header.
+    pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /// Returns `true` if `ip` belongs to a trusted network.
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    /// Returns `true` if a connection from `peer` must begin with a PROXY header.
+    pub(crate) fn expects_proxy_header(&self, peer: SocketAddr) -> bool {
+        self.proxy_protocol && self.is_trusted(peer.ip())
+    }
+
+    /// Derives the client of a request received from `peer`.
+    pub(crate) fn client_info(&self, req: &HttpRequest<'_>, peer: SocketAddr) -> ClientInfo {
+        let mut client = ClientInfo {
+            addr: peer,
+            scheme: None,
+            host: None,
+        };
+        if !self.is_trusted(peer.ip()) {
+            return client;
+        }
+        let hops = match joined(req, "Forwarded") {
+            Some(forwarded) => forwarded_hops(&forwarded),
+            None => x_forwarded_hops(req),
+        };
+        for hop in hops.into_iter().rev() {
+            let Some(addr) = hop.addr else {
+                break;
+            };
+            client = ClientInfo {
+                addr,
+                scheme: hop.scheme,
+                host: hop.host,
+            };
+            if !self.is_trusted(addr.ip()) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// One proxy hop reported in forwarding headers.
+#[derive(Debug, Default)]
+struct Hop {
+    /// The address the proxy received the request from, if it reported a usable one.
+    addr: Option<SocketAddr>,
+    scheme: Option<String>,
+    host: Option<String>,
+}
+
+/// Returns all values of a header, joined as a list.
+fn joined(req: &HttpRequest<'_>, name: &str) -> Option<String> {
+    let values: Vec<&str> = req
+        .headers()
+        .iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+        .collect();
+    (!values.is_empty()).then(|| values.join(","))
+}
+
+/// Parses the hops of a `Forwarded` header, nearest last.
+fn forwarded_hops(forwarded: &str) -> Vec<Hop> {
+    forwarded
+        .split(',')
+        .map(|element| {
+            let mut hop = Hop::default();
+            for pair in element.split(';') {
+                let Some((key, value)) = pair.split_once('=') else {
+                    continue;
+                };
+                let value = value.trim().trim_matches('"');
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "for" => hop.addr = parse_node(value),
+                    "proto" => hop.scheme = Some(value.to_ascii_lowercase()),
+                    "host" => hop.host = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            hop
+        })
+        .collect()
+}
+
+/// Parses the hops of the `X-Forwarded-*` headers, nearest last.
+///
+/// Lists of schemes or hosts are matched to addresses from the nearest hop outwards; when
+/// shorter, their first entry applies to the remaining hops.
+fn x_forwarded_hops(req: &HttpRequest<'_>) -> Vec<Hop> {
+    let Some(addrs) = joined(req, "X-Forwarded-For") else {
+        return Vec::new();
+    };
+    let list = |name| {
+        joined(req, name).map_or_else(Vec::new, |values| {
+            values
+                .split(',')
+                .map(|value| value.trim().to_string())
+                .collect()
+        })
+    };
+    let (schemes, hosts) = (list("X-Forwarded-Proto"), list("X-Forwarded-Host"));
+    let addrs: Vec<&str> = addrs.split(',').collect();
+    let aligned = |values: &[String], idx: usize| {
+        let from_end = addrs.len() - 1 - idx;
+        values
+            .len()
+            .checked_sub(from_end + 1)
+            .or((!values.is_empty()).then_some(0))
+            .map(|idx| values[idx].clone())
+    };
+    addrs
+        .iter()
+        .enumerate()
+        .map(|(idx, addr)| Hop {
+            addr: parse_node(addr.trim()),
+            scheme: aligned(&schemes, idx).map(|scheme| scheme.to_ascii_lowercase()),
+            host: aligned(&hosts, idx),
+        })
+        .collect()
+}
+
+/// Parses a node as reported by proxies: an IPv4 address or a bracketed IPv6 address, either
+/// with an optional port, or a bare IPv6 address.
+fn parse_node(node: &str) -> Option<SocketAddr> {
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    let ip = node
+        .strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'));
+    ip.unwrap_or(node)
+        .parse::<IpAddr>()
+        .ok()
+        .map(|ip| SocketAddr::new(ip, 0))
+}
+
+/// The outcome of parsing the start of a connection as a PROXY header.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ProxyHeader {
+    /// More bytes are needed.
+    Incomplete,
+    /// The bytes are not a valid PROXY header.
+    Invalid,
+    /// A header of `len` bytes, reporting the client's address unless the proxy sent it on its
+    /// own behalf.
+    Parsed {
+        source: Option<SocketAddr>,
+        len: usize,
+    },
+}
+
+/// Parses a version 1 or 2 PROXY header at the start of `bytes`.
+pub(crate) fn parse_proxy_header(bytes: &[u8]) -> ProxyHeader {
+    let is_prefix = |signature: &[u8]| {
+        let len = bytes.len().min(signature.len());
+        bytes[..len] == signature[..len]
+    };
+    if is_prefix(b"PROXY ") {
+        parse_proxy_v1(bytes)
+    } else if is_prefix(PROXY_V2_SIGNATURE) {
+        parse_proxy_v2(bytes)
+    } else {
+        ProxyHeader::Invalid
+    }
+}
+
+fn parse_proxy_v1(bytes: &[u8]) -> ProxyHeader {
+    let search = &bytes[..bytes.len().min(PROXY_V1_MAX_LEN)];
+    let Some(end) = search.windows(2).position(|window| window == b"\r\n") else {
+        return match bytes.len() < PROXY_V1_MAX_LEN {
+            true => ProxyHeader::Incomplete,
+            false => ProxyHeader::Invalid,
+        };
+    };
+    let Ok(line) = std::str::from_utf8(&bytes[..end]) else {
+        return ProxyHeader::Invalid;
+    };
+    let parts: Vec<&str> = line.split(' ').collect();
+    let source = match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => None,
+        ["PROXY", protocol, source, _destination, port, _destination_port] => {
+            let ip = match *protocol {
+                "TCP4" => source.parse::<Ipv4Addr>().map(IpAddr::V4).ok(),
+                "TCP6" => source.parse::<Ipv6Addr>().map(IpAddr::V6).ok(),
+                _ => None,
+            };
+            match (ip, port.parse::<u16>()) {
+                (Some(ip), Ok(port)) => Some(SocketAddr::new(ip, port)),
+                _ => return ProxyHeader::Invalid,
+            }
+        }
+        _ => return ProxyHeader::Invalid,
+    };
+    ProxyHeader::Parsed {
+        source,
+        len: end + 2,
+    }
+}
+
+fn parse_proxy_v2(bytes: &[u8]) -> ProxyHeader {
+    if bytes.len() < 16 {
+        return ProxyHeader::Incomplete;
+    }
+    let len = 16 + usize::from(u16::from_be_bytes([bytes[14], bytes[15]]));
+    if bytes.len() < len {
+        return ProxyHeader::Incomplete;
+    }
+    let addresses = &bytes[16..len];
+    let source = match (bytes[12], bytes[13] >> 4) {
+        // LOCAL: the proxy connected on its own behalf.
+        (0x20, _) => None,
+        (0x21, 0x1) if addresses.len() >= 12 => {
+            let ip: [u8; 4] = addresses[..4].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Some(SocketAddr::new(IpAddr::from(ip), port))
+        }
+        (0x21, 0x2) if addresses.len() >= 36 => {
+            let ip: [u8; 16] = addresses[..16].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Some(SocketAddr::new(IpAddr::from(ip), port))
+        }
+        // Unspecified or UNIX socket addresses carry nothing usable.
+        (0x21, 0x0 | 0x3) => None,
+        _ => return ProxyHeader::Invalid,
+    };
+    ProxyHeader::Parsed { source, len }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::{
+        app::tests::{connect, get, read_response, serve},
+        HoochAppBuilder, HttpMethod,
+    };
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    fn client(proxies: &TrustedProxies, peer: &str, headers: &str) -> ClientInfo {
+        let request = format!("GET / HTTP/1.1\r\nHost: localhost\r\n{}\r\n", headers);
+        proxies.client_info(
+            &HttpRequest::from_bytes(request.as_bytes()).unwrap(),
+            addr(peer),
+        )
+    }
+
+    #[test]
+    fn trusted_networks() {
+        let proxies = TrustedProxies::new()
+            .trust("10.0.0.0/8")
+            .unwrap()
+            .trust("192.168.1.7")
+            .unwrap()
+            .trust("fd00::/8")
+            .unwrap();
+        for trusted in ["10.1.2.3", "192.168.1.7", "fd12::1", "::ffff:10.0.0.1"] {
+            assert!(proxies.is_trusted(trusted.parse().unwrap()), "{}", trusted);
+        }
+        for untrusted in ["11.0.0.1", "192.168.1.8", "fe80::1", "::1"] {
+            assert!(
+                !proxies.is_trusted(untrusted.parse().unwrap()),
+                "{}",
+                untrusted
+            );
+        }
+
+        let everyone = TrustedProxies::new().trust("0.0.0.0/0").unwrap();
+        assert!(everyone.is_trusted("203.0.113.9".parse().unwrap()));
+
+        for invalid in [
+            "10.0.0.0/33",
+            "fd00::/129",
+            "10.0.0/8",
+            "localhost",
+            "10.0.0.0/x",
+        ] {
+            assert!(TrustedProxies::new().trust(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn forwarded() {
+        let proxies = TrustedProxies::new().trust("10.0.0.1").unwrap();
+
+        let info = client(
+            &proxies,
+            "10.0.0.1:4000",
+            "Forwarded: for=203.0.113.9;proto=HTTPS;host=example.com, for=\"10.0.0.2:8080\"\r\n",
+        );
+        assert_eq!(info.addr, addr("10.0.0.2:8080"));
+        assert_eq!(info.scheme, None);
+
+        let proxies = proxies.trust("10.0.0.2").unwrap();
+        let info = client(
+            &proxies,
+            "10.0.0.1:4000",
+            "Forwarded: for=203.0.113.9;proto=HTTPS;host=example.com\r\nForwarded: for=10.0.0.2\r\n",
+        );
+        assert_eq!(
+            info,
+            ClientInfo {
+                addr: addr("203.0.113.9:0"),
+                scheme: Some("https".into()),
+                host: Some("example.com".into()),
+            }
+        );
+
+        // Spoofed hops beyond the first untrusted one are ignored.
+        let info = client(
+            &proxies,
+            "10.0.0.1:4000",
+            "Forwarded: for=1.2.3.4, for=\"[2001:db8::1]:443\", for=10.0.0.2\r\n",
+        );
+        assert_eq!(info.addr, addr("[2001:db8::1]:443"));
+
+        // Unknown hops end the walk at the last known address.
+        let info = client(
+            &proxies,
+            "10.0.0.1:4000",
+            "Forwarded: for=unknown, for=10.0.0.2\r\n",
+        );
+        assert_eq!(info.addr, addr("10.0.0.2:0"));
+
+        // Untrusted peers are taken at their word.
+        let info = client(&proxies, "203.0.113.9:5000", "Forwarded: for=1.2.3.4\r\n");
+        assert_eq!(info.addr, addr("203.0.113.9:5000"));
+    }
+
+    #[test]
+    fn x_forwarded() {
+        let proxies = TrustedProxies::new().trust("10.0.0.0/8").unwrap();
+
+        let info = client(
+            &proxies,
+            "10.0.0.1:4000",
+            "X-Forwarded-For: 1.2.3.4, 203.0.113.9, 10.0.0.2\r\n\
+             X-Forwarded-Proto: https\r\nX-Forwarded-Host: example.com\r\n",
+        );
+        assert_eq!(
+            info,
+            ClientInfo {
+                addr: addr("203.0.113.9:0"),
+                scheme: Some("https".into()),
+                host: Some("example.com".into()),
+            }
+        );
+
+        let info = client(
+            &proxies,
+            "10.0.0.1:4000",
+            "X-Forwarded-For: 203.0.113.9, 10.0.0.2\r\nX-Forwarded-Proto: https, http\r\n",
+        );
+        assert_eq!(info.scheme.as_deref(), Some("https"));
+
+        let info = client(
+            &proxies,
+            "10.0.0.1:4000",
+            "X-Forwarded-For: 2001:db8::1\r\n",
+        );
+        assert_eq!(info.addr, addr("[2001:db8::1]:0"));
+
+        let info = client(&proxies, "10.0.0.1:4000", "X-Forwarded-Proto: https\r\n");
+        assert_eq!(info.addr, addr("10.0.0.1:4000"));
+        assert_eq!(info.scheme, None);
+    }
+
+    #[test]
+    fn proxy_header_v1() {
+        let header = b"PROXY TCP4 203.0.113.9 10.0.0.1 51234 80\r\nGET /";
+        assert_eq!(
+            parse_proxy_header(header),
+            ProxyHeader::Parsed {
+                source: Some(addr("203.0.113.9:51234")),
+                len: 42,
+            }
+        );
+        assert_eq!(
+            parse_proxy_header(b"PROXY TCP6 2001:db8::1 ::1 443 80\r\n"),
+            ProxyHeader::Parsed {
+                source: Some(addr("[2001:db8::1]:443")),
+                len: 35,
+            }
+        );
+        assert_eq!(
+            parse_proxy_header(b"PROXY UNKNOWN\r\n"),
+            ProxyHeader::Parsed {
+                source: None,
+                len: 15,
+            }
+        );
+        for incomplete in [&b"PRO"[..], b"PROXY TCP4 203.0.113.9"] {
+            assert_eq!(parse_proxy_header(incomplete), ProxyHeader::Incomplete);
+        }
+        for invalid in [
+            &b"GET / HTTP/1.1\r\n"[..],
+            b"PROXY TCP4 2001:db8::1 ::1 443 80\r\n",
+            b"PROXY TCP4 203.0.113.9 10.0.0.1 70000 80\r\n",
+            b"PROXY TCP4 203.0.113.9\r\n",
+            &[b'P', b'R', b'O', b'X', b'Y', b' '].repeat(20),
+        ] {
+            assert_eq!(parse_proxy_header(invalid), ProxyHeader::Invalid);
+        }
+    }
+
+    fn proxy_v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = PROXY_V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[command, family]);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[test]
+    fn proxy_header_v2() {
+        let mut addresses = vec![203, 0, 113, 9, 10, 0, 0, 1];
+        addresses.extend_from_slice(&51234u16.to_be_bytes());
+        addresses.extend_from_slice(&80u16.to_be_bytes());
+        // A TLV following the addresses is skipped.
+        addresses.extend_from_slice(&[0x04, 0x00, 0x01, 0xff]);
+        let header = proxy_v2(0x21, 0x11, &addresses);
+        assert_eq!(
+            parse_proxy_header(&header),
+            ProxyHeader::Parsed {
+                source: Some(addr("203.0.113.9:51234")),
+                len: 32,
+            }
+        );
+        assert_eq!(parse_proxy_header(&header[..20]), ProxyHeader::Incomplete);
+        assert_eq!(parse_proxy_header(&header[..5]), ProxyHeader::Incomplete);
+
+        let mut addresses = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        addresses.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        addresses.extend_from_slice(&[0x01, 0xbb, 0x00, 0x50]);
+        assert_eq!(
+            parse_proxy_header(&proxy_v2(0x21, 0x21, &addresses)),
+            ProxyHeader::Parsed {
+                source: Some(addr("[2001:db8::1]:443")),
+                len: 52,
+            }
+        );
+
+        assert_eq!(
+            parse_proxy_header(&proxy_v2(0x20, 0x00, &[])),
+            ProxyHeader::Parsed {
+                source: None,
+                len: 16,
+            }
+        );
+        assert_eq!(
+            parse_proxy_header(&proxy_v2(0x11, 0x11, &[0; 12])),
+            ProxyHeader::Invalid
+        );
+        assert_eq!(
+            parse_proxy_header(&proxy_v2(0x21, 0x11, &[0; 4])),
+            ProxyHeader::Invalid
+        );
+    }
+
+    fn routes(app: &mut HoochAppBuilder) {
+        app.add_middleware(|req, socket| async move {
+            assert_eq!(req.extensions().get::<ClientInfo>().unwrap().addr, socket);
+            crate::Middleware::Continue(req)
+        });
+        app.add_handler("/", HttpMethod::GET, |client: ClientInfo| async move {
+            format!(
+                "{} {}",
+                client.addr,
+                client.scheme.as_deref().unwrap_or("http")
+            )
+        });
+    }
+
+    #[test]
+    fn resolves_clients_of_connections() {
+        let forwarded = "X-Forwarded-For: 203.0.113.9\r\nX-Forwarded-Proto: https\r\n";
+        let addr = serve(|app| {
+            app.set_trusted_proxies(TrustedProxies::new().trust("127.0.0.0/8").unwrap());
+            routes(app);
+        });
+        assert!(get(addr, "/", forwarded).ends_with("203.0.113.9:0 https"));
+
+        let addr = serve(routes);
+        let response = get(addr, "/", forwarded);
+        assert!(response.contains("127.0.0.1:"), "{}", response);
+    }
+
+    #[test]
+    fn proxy_protocol() {
+        let addr = serve(|app| {
+            app.set_trusted_proxies(
+                TrustedProxies::new()
+                    .trust("127.0.0.1")
+                    .unwrap()
+                    .proxy_protocol(true),
+            );
+            routes(app);
+        });
+
+        let mut stream = connect(addr);
+        write!(
+            stream,
+            "PROXY TCP4 203.0.113.9 10.0.0.1 51234 80\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n"
+        )
+        .unwrap();
+        assert!(read_response(&mut stream).ends_with("203.0.113.9:51234 http"));
+        // The header applies to the whole connection.
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert!(read_response(&mut stream).ends_with("203.0.113.9:51234 http"));
+
+        let mut stream = connect(addr);
+        stream.write_all(&proxy_v2(0x20, 0x00, &[])).unwrap();
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert!(read_response(&mut stream).contains("127.0.0.1:"));
+
+        let mut stream = connect(addr);
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert!(read_response(&mut stream).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
@@ -0,0 +1,499 @@
+//! # Range Requests
+//!
+//! Clients resume downloads and seek in media by asking for parts of a representation with the
+//! `Range` header. [`requested_range`] works out what a `GET` request asks for, honouring
+//! `If-Range` so that parts of a changed representation are never stitched together.
+//!
+//! The [`RangeRequests`] layer answers range requests for responses held in memory; files
+//! served by [`ServeDir`](crate::ServeDir) and [`ServeFile`](crate::ServeFile) support them on
+//! their own. A single range is answered with `206 Partial Content` and a `Content-Range`
+//! header, several ranges with a `multipart/byteranges` body holding one part per range.
+//! Overlapping ranges are merged. Requests whose ranges all lie past the end are answered with
+//! `416 Range Not Satisfiable`, while malformed `Range` headers and requests for more than
+//! 64 ranges are ignored in favour of the full response.
+//!
+//! The layer handles `200 OK` responses with a body held in memory and no `Content-Range` of
+//! their own, and advertises range support on them with `Accept-Ranges: bytes`. The validators
+//! `If-Range` is checked against are the response's `ETag` and `Last-Modified` headers.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hooch_http::{HoochAppBuilder, HttpMethod, RangeRequests};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_layer(RangeRequests::new());
+//! app.add_handler("/report.csv", HttpMethod::GET, || async { "id,total\n1,42\n" });
+//! ```
+
+use std::{fmt::Write, net::SocketAddr, time::SystemTime};
+
+use futures::future::BoxFuture;
+
+use crate::{
+    body::Body,
+    conditional::ETag,
+    layer::{Layer, Next},
+    request::HttpRequest,
+    response::{HttpResponse, HttpResponseBuilder, HttpStatus},
+    HttpMethod,
+};
+
+/// Most ranges answered in one response.
+const MAX_RANGES: usize = 64;
+
+/// A range of bytes, from `start` up to, but excluding, `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    /// Offset of the first byte.
+    pub start: u64,
+    /// Offset past the last byte.
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// Returns the number of bytes in the range.
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Returns `true` if the range holds no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    /// Formats the range as a `Content-Range` value for a representation of `length` bytes.
+    pub fn content_range(&self, length: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end - 1, length)
+    }
+}
+
+/// The part of a representation a request asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// The whole representation.
+    Full,
+    /// One or more ranges, in the order they are to be sent.
+    Partial(Vec<ByteRange>),
+    /// Ranges lying entirely past the end of the representation.
+    Unsatisfiable,
+}
+
+/// Works out which part of a representation of `length` bytes a request asks for.
+///
+/// Only `GET` requests ask for ranges. Requests whose `If-Range` validator does not match `etag`
+/// (compared strongly) or `last_modified` ask for the full representation.
+pub fn requested_range(
+    req: &HttpRequest<'_>,
+    length: u64,
+    etag: Option<&ETag>,
+    last_modified: Option<SystemTime>,
+) -> RangeRequest {
+    let headers = req.headers();
+    match (req.method(), headers.get("Range")) {
+        (HttpMethod::GET, Some(range)) => {
+            let if_range = headers.get("If-Range");
+            if if_range.is_some_and(|if_range| !is_current(if_range, etag, last_modified)) {
+                return RangeRequest::Full;
+            }
+            parse_range(range, length)
+        }
+        _ => RangeRequest::Full,
+    }
+}
+
+/// Returns `true` if an `If-Range` validator matches the representation.
+fn is_current(if_range: &str, etag: Option<&ETag>, last_modified: Option<SystemTime>) -> bool {
+    match httpdate::parse_http_date(if_range.trim()) {
+        Ok(date) => last_modified.map(httpdate::HttpDate::from) == Some(date.into()),
+        Err(_) => ETag::parse(if_range)
+            .zip(etag)
+            .is_some_and(|(if_range, etag)| if_range.strong_eq(etag)),
+    }
+}
+
+/// Parses a `Range` header for a representation of `length` bytes.
+///
+/// Malformed headers, units other than bytes and more than 64 ranges yield
+/// [`RangeRequest::Full`].
+pub fn parse_range(range: &str, length: u64) -> RangeRequest {
+    let Some((unit, specs)) = range.trim().split_once('=') else {
+        return RangeRequest::Full;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return RangeRequest::Full;
+    }
+
+    let mut ranges = Vec::new();
+    let mut specs_seen = 0;
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        specs_seen += 1;
+        if specs_seen > MAX_RANGES {
+            return RangeRequest::Full;
+        }
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let (first, last) = (first.trim(), last.trim());
+        let range = if first.is_empty() {
+            // A suffix range asks for the last bytes.
+            match last.parse::<u64>() {
+                Ok(suffix) => ByteRange {
+                    start: length.saturating_sub(suffix),
+                    end: length,
+                },
+                Err(_) => return RangeRequest::Full,
+            }
+        } else {
+            let Ok(first) = first.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            let end = match last {
+                "" => length,
+                last => match last.parse::<u64>() {
+                    Ok(last) if last >= first => last.saturating_add(1).min(length),
+                    _ => return RangeRequest::Full,
+                },
+            };
+            ByteRange { start: first, end }
+        };
+        if !range.is_empty() {
+            ranges.push(range);
+        }
+    }
+    if specs_seen == 0 {
+        return RangeRequest::Full;
+    }
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Partial(coalesce(ranges))
+}
+
+/// Merges the ranges if any of them overlap, sorting them by offset; leaves them as they are
+/// otherwise.
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    let overlap = ranges.iter().enumerate().any(|(idx, range)| {
+        ranges[idx + 1..]
+            .iter()
+            .any(|other| range.start < other.end && other.start < range.end)
+    });
+    if !overlap {
+        return ranges;
+    }
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// The framing of a `multipart/byteranges` body.
+#[derive(Debug)]
+pub(crate) struct Multipart {
+    boundary: String,
+    content_type: Option<String>,
+    length: u64,
+}
+
+impl Multipart {
+    /// Returns the `Content-Type` of the whole body.
+    pub(crate) fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    /// Returns the delimiter and headers preceding the bytes of `range`.
+    pub(crate) fn part_head(&self, range: ByteRange) -> Vec<u8> {
+        let mut head = format!("\r\n--{}\r\n", self.boundary);
+        if let Some(content_type) = &self.content_type {
+            let _ = write!(head, "Content-Type: {}\r\n", content_type);
+        }
+        let _ = write!(
+            head,
+            "Content-Range: {}\r\n\r\n",
+            range.content_range(self.length)
+        );
+        head.into_bytes()
+    }
+
+    /// Returns the delimiter closing the body.
+    pub(crate) fn closing(&self) -> Vec<u8> {
+        format!("\r\n--{}--\r\n", self.boundary).into_bytes()
+    }
+
+    /// Returns the length of the body holding `ranges`.
+    pub(crate) fn body_length(&self, ranges: &[ByteRange]) -> u64 {
+        let parts: u64 = ranges
+            .iter()
+            .map(|range| self.part_head(*range).len() as u64 + range.len())
+            .sum();
+        parts + self.closing().len() as u64
+    }
+}
+
+/// Turns a full response to a representation of `length` bytes into a `206 Partial Content`
+/// response for `ranges`, setting its `Content-Range` or multipart `Content-Type`.
+///
+/// Returns the framing of the body if several ranges are sent; the body itself is left to the
+/// caller.
+pub(crate) fn into_partial(
+    response: &mut HttpResponse,
+    ranges: &[ByteRange],
+    length: u64,
+) -> Option<Multipart> {
+    response.set_status(HttpStatus::PartialContent);
+    response.remove_header("Content-Length");
+    if let [range] = ranges {
+        response.insert_header("Content-Range", range.content_range(length));
+        return None;
+    }
+    let mut boundary = [0; 16];
+    getrandom::fill(&mut boundary).expect("the operating system provides randomness");
+    let multipart = Multipart {
+        boundary: boundary
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+        content_type: response
+            .remove_header("Content-Type")
+            .map(|content_type| content_type.as_ref().to_string()),
+        length,
+    };
+    response.insert_header("Content-Type", multipart.content_type());
+    Some(multipart)
+}
+
+/// The response to a request whose ranges all lie past the end of a representation of
+/// `length` bytes.
+pub(crate) fn unsatisfiable(length: u64) -> HttpResponse {
+    HttpResponseBuilder::range_not_satisfiable()
+        .header("Content-Range", format!("bytes */{}", length))
+        .build()
+}
+
+/// Layer answering range requests for responses held in memory.
+///
+/// See the [module documentation](self) for which responses are affected.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RangeRequests;
+
+impl RangeRequests {
+    /// Creates the layer.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Layer for RangeRequests {
+    fn call<'a>(
+        &'a self,
+        req: HttpRequest<'static>,
+        _socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            let method = req.method();
+            let headers = req.headers();
+            let (range, if_range) = (headers.get("Range"), headers.get("If-Range"));
+            let mut response = next.run(req).await;
+            if response.status() != HttpStatus::Ok
+                || response.header("Content-Range").is_some()
+                || response.body().is_none()
+            {
+                return response;
+            }
+            if response.header("Accept-Ranges").is_none() {
+                response.insert_header("Accept-Ranges", "bytes");
+            }
+            let (HttpMethod::GET, Some(range)) = (method, range) else {
+                return response;
+            };
+
+            let Body::Full(body) = response.take_body() else {
+                unreachable!("the body is held in memory");
+            };
+            let length = body.len() as u64;
+            let etag = response.header("ETag").and_then(ETag::parse);
+            let last_modified = response
+                .header("Last-Modified")
+                .and_then(|date| httpdate::parse_http_date(date).ok());
+            let current =
+                if_range.is_none_or(|if_range| is_current(if_range, etag.as_ref(), last_modified));
+            let request = match current {
+                true => parse_range(range, length),
+                false => RangeRequest::Full,
+            };
+
+            let ranges = match request {
+                RangeRequest::Full => {
+                    response.set_body(Body::Full(body));
+                    return response;
+                }
+                RangeRequest::Unsatisfiable => return unsatisfiable(length),
+                RangeRequest::Partial(ranges) => ranges,
+            };
+            let slice = |range: &ByteRange| &body[range.start as usize..range.end as usize];
+            let partial = match into_partial(&mut response, &ranges, length) {
+                None => slice(&ranges[0]).to_vec(),
+                Some(multipart) => {
+                    let mut partial = Vec::with_capacity(multipart.body_length(&ranges) as usize);
+                    for range in &ranges {
+                        partial.extend_from_slice(&multipart.part_head(*range));
+                        partial.extend_from_slice(slice(range));
+                    }
+                    partial.extend_from_slice(&multipart.closing());
+                    partial
+                }
+            };
+            response.set_body(Body::Full(partial));
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::app::tests::{connect, get, header, read_response, serve};
+
+    fn partial(ranges: &[(u64, u64)]) -> RangeRequest {
+        let ranges = ranges.iter().map(|&(start, end)| ByteRange { start, end });
+        RangeRequest::Partial(ranges.collect())
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("bytes=0-4", 10), partial(&[(0, 5)]));
+        assert_eq!(parse_range("bytes=5-", 10), partial(&[(5, 10)]));
+        assert_eq!(parse_range("bytes=-3", 10), partial(&[(7, 10)]));
+        assert_eq!(parse_range("bytes=-30", 10), partial(&[(0, 10)]));
+        assert_eq!(parse_range("bytes=8-100", 10), partial(&[(8, 10)]));
+        assert_eq!(
+            parse_range("Bytes = 6-7, 0-1 ,, 20-30", 10),
+            partial(&[(6, 8), (0, 2)])
+        );
+        assert_eq!(
+            parse_range("bytes=8-, 0-3, 2-5", 10),
+            partial(&[(0, 6), (8, 10)])
+        );
+
+        for unsatisfiable in ["bytes=10-", "bytes=-0", "bytes=10-20, 15-"] {
+            assert_eq!(
+                parse_range(unsatisfiable, 10),
+                RangeRequest::Unsatisfiable,
+                "{}",
+                unsatisfiable
+            );
+        }
+        assert_eq!(parse_range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+
+        let too_many = format!("bytes={}", vec!["0-0"; 65].join(","));
+        for full in [
+            "items=0-1",
+            "bytes=5-2",
+            "bytes=a-b",
+            "bytes=1",
+            "bytes=",
+            &too_many,
+        ] {
+            assert_eq!(parse_range(full, 10), RangeRequest::Full, "{}", full);
+        }
+    }
+
+    #[test]
+    fn layer_answers_ranges() {
+        let addr = serve(|app| {
+            app.add_layer(RangeRequests::new());
+            app.add_handler("/", HttpMethod::GET, || async {
+                HttpResponseBuilder::ok()
+                    .header("Content-Type", "text/plain")
+                    .etag(ETag::strong("v1"))
+                    .body("0123456789".into())
+                    .build()
+            });
+        });
+
+        let response = get(addr, "/", "");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(header(&response, "Accept-Ranges"), Some("bytes"));
+
+        let response = get(addr, "/", "Range: bytes=2-4\r\n");
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(header(&response, "Content-Length"), Some("3"));
+        assert!(response.ends_with("\r\n\r\n234"));
+
+        let response = get(addr, "/", "Range: bytes=0-0,-2\r\n");
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        let boundary = header(&response, "Content-Type")
+            .and_then(|value| value.strip_prefix("multipart/byteranges; boundary="))
+            .unwrap();
+        let expected = format!(
+            "\r\n\r\n\r\n--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-0/10\r\n\r\n0\
+             \r\n--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--{b}--\r\n",
+            b = boundary
+        );
+        assert!(response.ends_with(&expected));
+
+        let response = get(addr, "/", "Range: bytes=10-\r\n");
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert_eq!(header(&response, "Content-Range"), Some("bytes */10"));
+
+        let response = get(addr, "/", "Range: bytes=2-4\r\nIf-Range: \"v1\"\r\n");
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        for if_range in ["\"v0\"", "W/\"v1\"", "Sat, 01 Jan 2000 00:00:00 GMT"] {
+            let headers = format!("Range: bytes=2-4\r\nIf-Range: {}\r\n", if_range);
+            let response = get(addr, "/", &headers);
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", if_range);
+            assert!(response.ends_with("\r\n\r\n0123456789"));
+        }
+    }
+
+    #[test]
+    fn layer_leaves_other_responses_alone() {
+        let addr = serve(|app| {
+            app.add_layer(RangeRequests::new());
+            app.add_handler("/missing", HttpMethod::GET, || async {
+                HttpResponseBuilder::not_found()
+                    .body("missing".into())
+                    .build()
+            });
+            app.add_handler("/stream", HttpMethod::GET, || async {
+                let mut response = HttpResponseBuilder::ok().build();
+                let chunks = futures::stream::iter([Ok(b"streamed".to_vec())]);
+                response.set_body(Body::Stream(Box::pin(chunks)));
+                response
+            });
+            app.add_handler("/", HttpMethod::POST, || async { "created" });
+        });
+
+        let response = get(addr, "/missing", "Range: bytes=0-1\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert_eq!(header(&response, "Accept-Ranges"), None);
+
+        let response = get(addr, "/stream", "Range: bytes=0-1\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(header(&response, "Accept-Ranges"), None);
+
+        let mut stream = connect(addr);
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-1\r\nContent-Length: 0\r\n\r\n"
+        )
+        .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\ncreated"));
+    }
+}
@@ -0,0 +1,699 @@
+//! # Rate Limiting
+//!
+//! The [`RateLimit`] layer caps how many requests each client may make. Clients are told apart
+//! by a key: by default the IP address of the peer, alternatively the value of a request header,
+//! such as an API key, or anything a custom function derives from the request.
+//!
+//! Limits are expressed as a [`Quota`] using one of two algorithms:
+//!
+//! - A **token bucket** holds up to a number of tokens and refills at a steady rate; each
+//!   request takes one token. Clients may burst up to the bucket's size, then are held to the
+//!   refill rate.
+//! - A **sliding window** allows a number of requests within any window of the given length,
+//!   so that clients can not double their rate at the edges of fixed windows.
+//!
+//! One quota applies to every request the layer covers, unless a route pattern with a quota of
+//! its own matches. Each route keeps its own count. To limit only part of an application, scope
+//! the layer with [`path_prefix`](RateLimit::path_prefix); several layers may be added for
+//! different scopes.
+//!
+//! Responses to limited requests carry the `RateLimit-Limit`, `RateLimit-Remaining`,
+//! `RateLimit-Reset` and `RateLimit-Policy` headers. Requests over the limit are answered with
+//! `429 Too Many Requests` and a `Retry-After` header, without running the rest of the pipeline.
+//!
+//! Counts are kept in a [`RateLimitStore`]. The default [`MemoryRateLimitStore`] keeps them in
+//! the process; implement the trait to share limits between several servers. Should the store
+//! fail, requests are let through rather than rejected.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use hooch_http::{HoochAppBuilder, Quota, RateLimit};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//!
+//! // 100 requests per minute per client, in bursts of up to 20, but only 5 login attempts in
+//! // any 10 minutes.
+//! app.add_layer(
+//!     RateLimit::new(Quota::token_bucket(20, Duration::from_millis(600)))
+//!         .route("/login", Quota::sliding_window(5, Duration::from_secs(600))),
+//! );
+//!
+//! // Clients of the API are limited per API key.
+//! app.add_layer(
+//!     RateLimit::new(Quota::sliding_window(1000, Duration::from_secs(3600)))
+//!         .key_header("X-API-Key")
+//!         .path_prefix("/api/"),
+//! );
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use futures::future::{self, BoxFuture};
+
+use crate::{
+    layer::{Layer, Next},
+    request::HttpRequest,
+    response::{HttpResponse, HttpResponseBuilder},
+    shared::has_path_prefix,
+};
+
+/// How many requests a client may make, and how.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    algorithm: Algorithm,
+    limit: u32,
+    period: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    TokenBucket,
+    SlidingWindow,
+}
+
+impl Quota {
+    /// A token bucket holding `capacity` tokens, adding one token every `refill`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` or `refill` is zero.
+    pub fn token_bucket(capacity: u32, refill: Duration) -> Self {
+        Self::new(Algorithm::TokenBucket, capacity, refill)
+    }
+
+    /// A sliding window allowing `limit` requests within any `window`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` or `window` is zero.
+    pub fn sliding_window(limit: u32, window: Duration) -> Self {
+        Self::new(Algorithm::SlidingWindow, limit, window)
+    }
+
+    fn new(algorithm: Algorithm, limit: u32, period: Duration) -> Self {
+        assert!(limit > 0, "a quota must allow at least one request");
+        assert!(!period.is_zero(), "a quota needs a non-zero period");
+        Self {
+            algorithm,
+            limit,
+            period,
+        }
+    }
+
+    /// Returns the number of requests that may be made at once.
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    /// Returns the time in which a spent quota is fully restored: the window of a sliding
+    /// window, or the time to refill an empty token bucket.
+    pub fn window(&self) -> Duration {
+        match self.algorithm {
+            Algorithm::TokenBucket => self.period * self.limit,
+            Algorithm::SlidingWindow => self.period,
+        }
+    }
+}
+
+/// The outcome of counting a request against a [`Quota`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    /// Whether the request is within the quota.
+    pub allowed: bool,
+    /// The number of requests that may be made at once.
+    pub limit: u32,
+    /// The number of requests that may still be made right now.
+    pub remaining: u32,
+    /// The time until the full quota is available again.
+    pub reset: Duration,
+    /// The time until another request will be allowed; zero if one would be allowed right away.
+    pub retry_after: Duration,
+}
+
+/// The count of one client's requests against a [`Quota`].
+///
+/// Stores keep one state per key and call [`hit`](Self::hit) for every request.
+#[derive(Debug, Clone)]
+pub struct RateLimitState {
+    counter: Counter,
+    idle_at: Instant,
+}
+
+#[derive(Debug, Clone)]
+enum Counter {
+    Bucket { tokens: f64, updated: Instant },
+    Window(VecDeque<Instant>),
+}
+
+impl RateLimitState {
+    /// Creates the state of a client that has not made any request yet.
+    pub fn new(quota: &Quota, now: Instant) -> Self {
+        let counter = match quota.algorithm {
+            Algorithm::TokenBucket => Counter::Bucket {
+                tokens: f64::from(quota.limit),
+                updated: now,
+            },
+            Algorithm::SlidingWindow => Counter::Window(VecDeque::new()),
+        };
+        Self {
+            counter,
+            idle_at: now,
+        }
+    }
+
+    /// Counts a request made at `now`, if the quota allows it.
+    pub fn hit(&mut self, quota: &Quota, now: Instant) -> RateLimitDecision {
+        let matches = matches!(
+            (&self.counter, quota.algorithm),
+            (Counter::Bucket { .. }, Algorithm::TokenBucket)
+                | (Counter::Window(_), Algorithm::SlidingWindow)
+        );
+        if !matches {
+            *self = Self::new(quota, now);
+        }
+
+        let decision = match &mut self.counter {
+            Counter::Bucket { tokens, updated } => {
+                let per_token = quota.period.as_secs_f64();
+                let limit = f64::from(quota.limit);
+                *tokens = (*tokens
+                    + now.saturating_duration_since(*updated).as_secs_f64() / per_token)
+                    .min(limit);
+                *updated = now;
+                let allowed = *tokens >= 1.0;
+                if allowed {
+                    *tokens -= 1.0;
+                }
+                RateLimitDecision {
+                    allowed,
+                    limit: quota.limit,
+                    remaining: *tokens as u32,
+                    reset: Duration::from_secs_f64((limit - *tokens) * per_token),
+                    retry_after: Duration::from_secs_f64((1.0 - *tokens).max(0.0) * per_token),
+                }
+            }
+            Counter::Window(hits) => {
+                while hits
+                    .front()
+                    .is_some_and(|&hit| now.saturating_duration_since(hit) >= quota.period)
+                {
+                    hits.pop_front();
+                }
+                // The quota may have been lowered since the earlier hits were counted.
+                if hits.len() > quota.limit as usize {
+                    hits.drain(..hits.len() - quota.limit as usize);
+                }
+                let allowed = hits.len() < quota.limit as usize;
+                if allowed {
+                    hits.push_back(now);
+                }
+                let until_expired = |hit: Option<&Instant>| {
+                    hit.map_or(Duration::ZERO, |&hit| {
+                        (hit + quota.period).saturating_duration_since(now)
+                    })
+                };
+                RateLimitDecision {
+                    allowed,
+                    limit: quota.limit,
+                    remaining: quota.limit.saturating_sub(hits.len() as u32),
+                    reset: until_expired(hits.back()),
+                    retry_after: if hits.len() < quota.limit as usize {
+                        Duration::ZERO
+                    } else {
+                        until_expired(hits.front())
+                    },
+                }
+            }
+        };
+        self.idle_at = now + decision.reset;
+        decision
+    }
+
+    /// Returns `true` if the full quota is available again at `now`, so that the state can be
+    /// dropped.
+    pub fn is_idle(&self, now: Instant) -> bool {
+        self.idle_at <= now
+    }
+}
+
+/// Storage for rate limit counts, keyed by client and route.
+pub trait RateLimitStore: Send + Sync + 'static {
+    /// Counts a request of the client `key` against `quota`.
+    fn hit<'a>(
+        &'a self,
+        key: &'a str,
+        quota: &'a Quota,
+    ) -> BoxFuture<'a, io::Result<RateLimitDecision>>;
+}
+
+/// Rate limit store keeping counts in memory.
+///
+/// Clones share the same counts. Counts of clients whose quota is fully restored are dropped
+/// from time to time, or by [`remove_idle`](Self::remove_idle).
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRateLimitStore {
+    inner: Arc<Mutex<MemoryInner>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryInner {
+    states: HashMap<String, RateLimitState>,
+    hits: u32,
+}
+
+/// Number of requests after which idle counts are dropped.
+const CLEANUP_INTERVAL: u32 = 1024;
+
+impl MemoryRateLimitStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of clients being counted, including idle ones not yet removed.
+    pub fn len(&self) -> usize {
+        self.inner().states.len()
+    }
+
+    /// Returns `true` if no clients are being counted.
+    pub fn is_empty(&self) -> bool {
+        self.inner().states.is_empty()
+    }
+
+    /// Removes the counts of all clients whose quota is fully restored.
+    pub fn remove_idle(&self) {
+        let now = Instant::now();
+        self.inner().states.retain(|_, state| !state.is_idle(now));
+    }
+
+    fn inner(&self) -> MutexGuard<'_, MemoryInner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl RateLimitStore for MemoryRateLimitStore {
+    fn hit<'a>(
+        &'a self,
+        key: &'a str,
+        quota: &'a Quota,
+    ) -> BoxFuture<'a, io::Result<RateLimitDecision>> {
+        let now = Instant::now();
+        let mut inner = self.inner();
+        inner.hits += 1;
+        if inner.hits >= CLEANUP_INTERVAL {
+            inner.hits = 0;
+            inner.states.retain(|_, state| !state.is_idle(now));
+        }
+        let decision = match inner.states.get_mut(key) {
+            Some(state) => state.hit(quota, now),
+            None => {
+                let mut state = RateLimitState::new(quota, now);
+                let decision = state.hit(quota, now);
+                inner.states.insert(key.to_string(), state);
+                decision
+            }
+        };
+        Box::pin(future::ready(Ok(decision)))
+    }
+}
+
+type KeyFn = dyn Fn(&HttpRequest<'_>, SocketAddr) -> Option<String> + Send + Sync;
+
+/// How clients are told apart.
+#[derive(Clone)]
+enum ClientKey {
+    Peer,
+    Header(String),
+    Custom(Arc<KeyFn>),
+}
+
+/// Layer limiting the rate of requests per client.
+///
+/// See the [module documentation](self) for how requests are counted.
+#[derive(Clone)]
+pub struct RateLimit {
+    quota: Quota,
+    routes: Vec<(&'static str, Quota)>,
+    prefixes: Vec<String>,
+    key: ClientKey,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl fmt::Debug for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimit")
+            .field("quota", &self.quota)
+            .field("routes", &self.routes)
+            .field("prefixes", &self.prefixes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RateLimit {
+    /// Creates a layer applying `quota` to every request, per peer IP address, counted in a new
+    /// [`MemoryRateLimitStore`].
+    pub fn new(quota: Quota) -> Self {
+        Self {
+            quota,
+            routes: Vec::new(),
+            prefixes: Vec::new(),
+            key: ClientKey::Peer,
+            store: Arc::new(MemoryRateLimitStore::new()),
+        }
+    }
+
+    /// Applies `quota` instead to requests whose path matches the route pattern `path`, such as
+    /// `/users/{id}`. The first matching route wins.
+    pub fn route(mut self, path: &'static str, quota: Quota) -> Self {
+        self.routes.push((path, quota));
+        self
+    }
+
+    /// Only limits requests for paths under `prefix`, matching whole path segments: `/api`
+    /// covers `/api/users` but not `/apiary`. May be called several times.
+    pub fn path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefixes.push(prefix.into());
+        self
+    }
+
+    /// Tells clients apart by the value of the header `name`. Requests without the header are
+    /// counted per peer IP address.
+    pub fn key_header(mut self, name: impl Into<String>) -> Self {
+        self.key = ClientKey::Header(name.into());
+        self
+    }
+
+    /// Tells clients apart by the key `key` returns for a request. Requests for which it
+    /// returns `None` are not limited.
+    pub fn key_fn<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&HttpRequest<'_>, SocketAddr) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = ClientKey::Custom(Arc::new(key));
+        self
+    }
+
+    /// Counts requests in `store` instead of a new [`MemoryRateLimitStore`].
+    pub fn store(mut self, store: impl RateLimitStore) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    /// Returns the store key and quota for a request, or `None` if it is not limited.
+    fn classify(
+        &self,
+        req: &HttpRequest<'static>,
+        socket_addr: SocketAddr,
+    ) -> Option<(String, Quota)> {
+        let path = req.uri().as_ref().split('?').next().unwrap_or_default();
+        if !self.prefixes.is_empty()
+            && !self
+                .prefixes
+                .iter()
+                .any(|prefix| has_path_prefix(path, prefix))
+        {
+            return None;
+        }
+
+        let client = match &self.key {
+            ClientKey::Peer => socket_addr.ip().to_string(),
+            ClientKey::Header(name) => match req.headers().get(name) {
+                Some(value) => format!("header:{}", value.trim()),
+                None => socket_addr.ip().to_string(),
+            },
+            ClientKey::Custom(key) => key(req, socket_addr)?,
+        };
+        let (route, quota) = self
+            .routes
+            .iter()
+            .find(|(route, _)| req.uri().is_match(route).is_some())
+            .copied()
+            .unwrap_or(("", self.quota));
+        Some((format!("{} {}", route, client), quota))
+    }
+}
+
+impl Layer for RateLimit {
+    fn call<'a>(
+        &'a self,
+        req: HttpRequest<'static>,
+        socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            let Some((key, quota)) = self.classify(&req, socket_addr) else {
+                return next.run(req).await;
+            };
+            let decision = match self.store.hit(&key, &quota).await {
+                Ok(decision) => decision,
+                Err(_err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(error = %_err, "rate limit store failed");
+                    return next.run(req).await;
+                }
+            };
+
+            let mut response = if decision.allowed {
+                next.run(req).await
+            } else {
+                let mut response = HttpResponseBuilder::too_many_requests()
+                    .body("Too Many Requests".to_string())
+                    .build();
+                response.insert_header("Retry-After", seconds(decision.retry_after).to_string());
+                response
+            };
+            response.insert_header("RateLimit-Limit", decision.limit.to_string());
+            response.insert_header("RateLimit-Remaining", decision.remaining.to_string());
+            response.insert_header("RateLimit-Reset", seconds(decision.reset).to_string());
+            response.insert_header(
+                "RateLimit-Policy",
+                format!("{};w={}", quota.limit(), seconds(quota.window())),
+            );
+            response
+        })
+    }
+}
+
+/// Rounds a duration up to whole seconds, as headers count them.
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        app::tests::{get, header, serve_layer},
+        HoochAppBuilder, HttpMethod,
+    };
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn token_bucket() {
+        let quota = Quota::token_bucket(3, SECOND);
+        let start = Instant::now();
+        let mut state = RateLimitState::new(&quota, start);
+
+        for remaining in [2, 1, 0] {
+            let decision = state.hit(&quota, start);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+        let decision = state.hit(&quota, start);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, SECOND);
+        assert_eq!(decision.reset, 3 * SECOND);
+        assert!(!state.is_idle(start + 2 * SECOND));
+
+        // Tokens trickle back in one by one.
+        let decision = state.hit(&quota, start + SECOND / 2);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, SECOND / 2);
+        assert!(state.hit(&quota, start + SECOND).allowed);
+        assert!(!state.hit(&quota, start + SECOND).allowed);
+
+        // A full bucket does not overflow.
+        let later = start + 60 * SECOND;
+        for _ in 0..3 {
+            assert!(state.hit(&quota, later).allowed);
+        }
+        assert!(!state.hit(&quota, later).allowed);
+        assert!(state.is_idle(later + 3 * SECOND));
+    }
+
+    #[test]
+    fn sliding_window() {
+        let quota = Quota::sliding_window(2, 10 * SECOND);
+        let start = Instant::now();
+        let mut state = RateLimitState::new(&quota, start);
+
+        assert!(state.hit(&quota, start).allowed);
+        let decision = state.hit(&quota, start + 4 * SECOND);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.retry_after, 6 * SECOND);
+        assert_eq!(decision.reset, 10 * SECOND);
+
+        let decision = state.hit(&quota, start + 9 * SECOND);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, SECOND);
+        assert_eq!(decision.reset, 5 * SECOND);
+
+        // Only the first request has left the window.
+        let decision = state.hit(&quota, start + 10 * SECOND);
+        assert!(decision.allowed);
+        assert_eq!(decision.retry_after, 4 * SECOND);
+        assert!(!state.hit(&quota, start + 13 * SECOND).allowed);
+        assert!(state.is_idle(start + 20 * SECOND));
+
+        // Lowering the quota keeps only the latest hits.
+        let lowered = Quota::sliding_window(1, 10 * SECOND);
+        let decision = state.hit(&lowered, start + 13 * SECOND);
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.retry_after, 7 * SECOND);
+
+        // Switching algorithms starts a new count.
+        let bucket = Quota::token_bucket(1, SECOND);
+        assert!(state.hit(&bucket, start + 13 * SECOND).allowed);
+    }
+
+    #[test]
+    fn memory_store() {
+        let store = MemoryRateLimitStore::new();
+        let quota = Quota::sliding_window(1, Duration::from_millis(50));
+        let hit = |key| futures::executor::block_on(store.hit(key, &quota)).unwrap();
+
+        assert!(hit("a").allowed);
+        assert!(!hit("a").allowed);
+        assert!(hit("b").allowed);
+        assert_eq!(store.len(), 2);
+
+        store.remove_idle();
+        assert_eq!(store.len(), 2);
+        std::thread::sleep(Duration::from_millis(60));
+        store.remove_idle();
+        assert!(store.is_empty());
+    }
+
+    fn routes(app: &mut HoochAppBuilder) {
+        for path in ["/", "/login", "/users/{id}"] {
+            app.add_handler(path, HttpMethod::GET, || async { "hello" });
+        }
+    }
+
+    #[test]
+    fn limits_and_headers() {
+        let addr = serve_layer(
+            RateLimit::new(Quota::sliding_window(2, 60 * SECOND))
+                .route("/users/{id}", Quota::token_bucket(1, 30 * SECOND)),
+            routes,
+        );
+
+        let response = get(addr, "/", "");
+        assert!(response.ends_with("hello"));
+        assert_eq!(header(&response, "RateLimit-Limit"), Some("2"));
+        assert_eq!(header(&response, "RateLimit-Remaining"), Some("1"));
+        assert_eq!(header(&response, "RateLimit-Reset"), Some("60"));
+        assert_eq!(header(&response, "RateLimit-Policy"), Some("2;w=60"));
+        assert_eq!(header(&response, "Retry-After"), None);
+
+        assert!(get(addr, "/login?next=home", "").ends_with("hello"));
+        let response = get(addr, "/", "");
+        assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert_eq!(header(&response, "RateLimit-Remaining"), Some("0"));
+        assert!(header(&response, "Retry-After").is_some_and(|secs| secs == "59" || secs == "60"));
+
+        // Routes with a quota of their own are counted separately.
+        let response = get(addr, "/users/1", "");
+        assert!(response.ends_with("hello"));
+        assert_eq!(header(&response, "RateLimit-Policy"), Some("1;w=30"));
+        let response = get(addr, "/users/2", "");
+        assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert_eq!(header(&response, "Retry-After"), Some("30"));
+    }
+
+    #[test]
+    fn keys_and_scope() {
+        let addr = serve_layer(
+            RateLimit::new(Quota::token_bucket(1, 60 * SECOND))
+                .key_header("X-API-Key")
+                .path_prefix("/users/"),
+            routes,
+        );
+
+        assert!(get(addr, "/users/1", "X-API-Key: a\r\n").ends_with("hello"));
+        assert!(get(addr, "/users/1", "X-API-Key: b\r\n").ends_with("hello"));
+        assert!(get(addr, "/users/1", "").ends_with("hello"));
+        for headers in ["X-API-Key: a\r\n", ""] {
+            let response = get(addr, "/users/1", headers);
+            assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        }
+        let response = get(addr, "/", "X-API-Key: a\r\n");
+        assert!(response.ends_with("hello"));
+        assert_eq!(header(&response, "RateLimit-Limit"), None);
+
+        let addr = serve_layer(
+            RateLimit::new(Quota::token_bucket(1, 60 * SECOND)).key_fn(|req, _| {
+                req.headers()
+                    .get("X-Tenant")
+                    .filter(|tenant| *tenant != "internal")
+                    .map(str::to_string)
+            }),
+            routes,
+        );
+        for _ in 0..2 {
+            assert!(get(addr, "/", "X-Tenant: internal\r\n").ends_with("hello"));
+        }
+        assert!(get(addr, "/", "X-Tenant: acme\r\n").ends_with("hello"));
+        assert!(get(addr, "/", "X-Tenant: acme\r\n").starts_with("HTTP/1.1 429"));
+
+        // Prefixes match whole path segments.
+        let addr = serve_layer(
+            RateLimit::new(Quota::token_bucket(1, 60 * SECOND)).path_prefix("/log"),
+            routes,
+        );
+        for _ in 0..2 {
+            let response = get(addr, "/login", "");
+            assert!(response.ends_with("hello"));
+            assert_eq!(header(&response, "RateLimit-Limit"), None);
+        }
+    }
+
+    #[test]
+    fn store_failures_let_requests_through() {
+        struct Failing;
+
+        impl RateLimitStore for Failing {
+            fn hit<'a>(
+                &'a self,
+                _key: &'a str,
+                _quota: &'a Quota,
+            ) -> BoxFuture<'a, io::Result<RateLimitDecision>> {
+                Box::pin(future::ready(Err(io::Error::other("down"))))
+            }
+        }
+
+        let addr = serve_layer(
+            RateLimit::new(Quota::token_bucket(1, 60 * SECOND)).store(Failing),
+            routes,
+        );
+        for _ in 0..2 {
+            let response = get(addr, "/", "");
+            assert!(response.ends_with("hello"));
+            assert_eq!(header(&response, "RateLimit-Limit"), None);
+        }
+    }
+}
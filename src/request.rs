@@ -7,7 +7,8 @@
 //!
 //! - Parses HTTP request lines to extract the method, URI, and HTTP version.
 //! - Parses raw headers and stores them efficiently in a fixed-size array.
-//! - Extracts and returns the body as a UTF-8 string.
+//! - Extracts the body as raw bytes, readable as UTF-8 text where it is valid.
+//! - Rejects malformed requests with a [`ParseError`] instead of panicking.
 //! - Supports route matching via a lightweight URI matcher that extracts named parameters.
 //! - Splits URI segments into path and query parameters, with type-safe iteration.
 //! - All data structures avoid heap allocation by design.
@@ -17,7 +18,7 @@
 //! ```rust
 //! use hooch_http::HttpRequest;
 //! let raw_request = b"GET /orders/123?status=shipped&sort=desc HTTP/1.1\r\nHost: localhost\r\n\r\n";
-//! let request = HttpRequest::from_bytes(raw_request);
+//! let request = HttpRequest::from_bytes(raw_request).unwrap();
 //! let uri = request.uri();
 //!
 //! // Match against a route with path parameters
@@ -41,9 +42,17 @@ use std::{
     collections::HashMap,
     fmt::{Debug, Display},
     marker::PhantomData,
+    str::Utf8Error,
 };
 
-use crate::{shared::HttpVersion, HttpMethod};
+use crate::{
+    cookie::Cookies,
+    extensions::Extensions,
+    request_body::RequestBody,
+    response::{HttpResponse, HttpResponseBuilder, IntoResponse},
+    shared::HttpVersion,
+    HttpMethod,
+};
 
 const CARRIAGE_RETURN_LINE_FEED: &[u8; 2] = b"\r\n";
 const CARRIAGE_RETURN_LINE_FEED_TWICE: &[u8; 4] = b"\r\n\r\n";
@@ -52,6 +61,49 @@ const COLON_BYTE: u8 = 58;
 
 const MAX_NUM_HEADERS: usize = 1000;
 
+/// Reasons a request cannot be parsed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The request line is missing or does not consist of a method, a target and a version.
+    InvalidRequestLine,
+    /// A header line has no colon, an empty field name or whitespace in it, or is not valid
+    /// UTF-8, or the head is not terminated.
+    InvalidHeader,
+    /// The request has more than 1000 headers.
+    TooManyHeaders,
+    /// The method is not one of the [`HttpMethod`]s.
+    UnknownMethod,
+    /// The protocol version is not one of the [`HttpVersion`]s.
+    UnsupportedVersion,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidRequestLine => f.write_str("Invalid request line"),
+            Self::InvalidHeader => f.write_str("Invalid header"),
+            Self::TooManyHeaders => f.write_str("Too many headers"),
+            Self::UnknownMethod => f.write_str("Unknown method"),
+            Self::UnsupportedVersion => f.write_str("Unsupported HTTP version"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// `501 Not Implemented` for unknown methods, `505 HTTP Version Not Supported` for unsupported
+/// versions and `400 Bad Request` otherwise.
+impl IntoResponse for ParseError {
+    fn into_response(self) -> HttpResponse {
+        let builder = match self {
+            Self::UnknownMethod => HttpResponseBuilder::not_implemented(),
+            Self::UnsupportedVersion => HttpResponseBuilder::http_version_not_supported(),
+            _ => HttpResponseBuilder::bad_request(),
+        };
+        builder.body(self.to_string()).build()
+    }
+}
+
 /// Representation of a parsed HTTP request.
 #[derive(Debug)]
 pub struct HttpRequest<'a> {
@@ -59,7 +111,11 @@ pub struct HttpRequest<'a> {
     uri: Uri<'a>,
     version: HttpVersion,
     headers: Headers<'a>,
-    body: &'a str,
+    body: &'a [u8],
+    /// Data attached to this request by middleware or layers.
+    extensions: Extensions,
+    /// Application state registered on the builder, shared by all requests.
+    state: Option<&'static Extensions>,
 }
 
 /// Allow `HttpRequest` to be sent across threads.
@@ -79,33 +135,37 @@ impl Display for HttpRequest<'_> {
                 .take(self.headers.num)
                 .map(|(key, value)| (key.unwrap(), value.unwrap()))
                 .collect::<HashMap<&str, &str>>(),
-            self.body
+            String::from_utf8_lossy(self.body)
         ))
     }
 }
 
 impl<'a> HttpRequest<'a> {
     /// Parse an HTTP request from raw bytes.
-    pub fn from_bytes(bytes: &'a [u8]) -> Self {
-        let request_line = Self::get_request_line(bytes);
-        let http_method = Self::extract_http_method(request_line);
-        let http_version = Self::extract_http_version(request_line);
-        let uri = Self::extract_request_uri(request_line);
-        let header_bytes = Self::get_headers(bytes);
-        let headers = Self::extract_headers(header_bytes);
-        let body = Self::get_body(bytes);
-
-        Self {
+    ///
+    /// The bytes must hold the complete head, terminated by an empty line, followed by the body.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let request_line = Self::get_request_line(bytes)?;
+        let http_method = Self::extract_http_method(request_line)?;
+        let http_version = Self::extract_http_version(request_line)?;
+        let uri = Self::extract_request_uri(request_line)?;
+        let header_bytes = Self::get_headers(bytes)?;
+        let headers = Self::extract_headers(header_bytes)?;
+        let body = Self::get_body(bytes)?;
+
+        Ok(Self {
             method: http_method,
             uri,
             version: http_version,
             headers,
             body,
-        }
+            extensions: Extensions::new(),
+            state: None,
+        })
     }
 
     /// Get a reference to the URI.
-    pub fn uri(&self) -> &Uri {
+    pub fn uri(&self) -> &Uri<'a> {
         &self.uri
     }
 
@@ -114,103 +174,185 @@ impl<'a> HttpRequest<'a> {
         self.method
     }
 
+    /// Get the HTTP version.
+    pub fn version(&self) -> HttpVersion {
+        self.version
+    }
+
+    /// Get the raw request body.
+    pub fn body(&self) -> &'a [u8] {
+        self.body
+    }
+
+    /// Get the request body as text, failing if it is not valid UTF-8.
+    pub fn body_str(&self) -> Result<&'a str, Utf8Error> {
+        std::str::from_utf8(self.body)
+    }
+
+    /// Get a reference to the parsed headers.
+    pub fn headers(&self) -> &Headers<'a> {
+        &self.headers
+    }
+
+    /// Get the cookies sent with the request.
+    pub fn cookies(&self) -> Cookies<'a> {
+        Cookies::from_request(self)
+    }
+
+    /// Get the per-request extensions.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Get the per-request extensions mutably, e.g. to attach data for later handlers.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Get the application state of type `T` registered with
+    /// [`HoochAppBuilder::with_state`](crate::HoochAppBuilder::with_state).
+    ///
+    /// Returns `None` if no state of that type was registered.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&'static T> {
+        self.state?.get()
+    }
+
+    /// Take the streaming request body.
+    ///
+    /// This is only `Some` for requests to routes registered with
+    /// [`HoochAppBuilder::add_streaming_route`](crate::HoochAppBuilder::add_streaming_route) or
+    /// [`HoochAppBuilder::add_streaming_handler`](crate::HoochAppBuilder::add_streaming_handler),
+    /// and only the first time it is called.
+    pub fn take_body_stream(&mut self) -> Option<RequestBody> {
+        self.extensions.remove::<RequestBody>()
+    }
+
+    /// Mark a request received over HTTP/2, whose head is parsed as an HTTP/1.1 one.
+    #[cfg(feature = "http2")]
+    pub(crate) fn set_version(&mut self, version: HttpVersion) {
+        self.version = version;
+    }
+
+    /// Attach the application state to this request.
+    pub(crate) fn set_state(&mut self, state: &'static Extensions) {
+        self.state = Some(state);
+    }
+
+    /// Get the value of the `Content-Length` header, if present and valid.
+    pub fn content_length(&self) -> Option<usize> {
+        self.headers.get("Content-Length")?.trim().parse().ok()
+    }
+
     /// Extract the request line from the HTTP request.
-    fn get_request_line(bytes: &[u8]) -> &[u8] {
+    fn get_request_line(bytes: &[u8]) -> Result<&[u8], ParseError> {
         let idx = bytes
             .windows(CARRIAGE_RETURN_LINE_FEED.len())
             .position(|window| window == CARRIAGE_RETURN_LINE_FEED)
-            .unwrap();
+            .ok_or(ParseError::InvalidRequestLine)?;
 
-        &bytes[..idx]
+        Ok(&bytes[..idx])
     }
 
     /// Extract header block (excluding request line and body).
-    fn get_headers(bytes: &[u8]) -> &[u8] {
+    fn get_headers(bytes: &[u8]) -> Result<&[u8], ParseError> {
         let idx = bytes
             .windows(CARRIAGE_RETURN_LINE_FEED.len())
             .position(|window| window == CARRIAGE_RETURN_LINE_FEED)
             .map(|idx| idx + CARRIAGE_RETURN_LINE_FEED.len())
-            .unwrap();
+            .ok_or(ParseError::InvalidRequestLine)?;
+
+        // The head may end right after the request line.
+        if bytes[idx..].starts_with(CARRIAGE_RETURN_LINE_FEED) {
+            return Ok(&bytes[idx..idx]);
+        }
 
         let header_bytes_idx = bytes[idx..]
             .windows(CARRIAGE_RETURN_LINE_FEED_TWICE.len())
             .position(|window| window == CARRIAGE_RETURN_LINE_FEED_TWICE)
-            .unwrap();
+            .ok_or(ParseError::InvalidHeader)?;
 
-        &bytes[idx..header_bytes_idx + idx]
+        Ok(&bytes[idx..header_bytes_idx + idx])
     }
 
     /// Extract the body section of the HTTP request.
-    fn get_body(bytes: &[u8]) -> &str {
+    fn get_body(bytes: &[u8]) -> Result<&[u8], ParseError> {
         let idx = bytes
             .windows(CARRIAGE_RETURN_LINE_FEED_TWICE.len())
             .position(|window| window == CARRIAGE_RETURN_LINE_FEED_TWICE)
             .map(|idx| idx + CARRIAGE_RETURN_LINE_FEED_TWICE.len())
-            .unwrap();
+            .ok_or(ParseError::InvalidHeader)?;
 
-        std::str::from_utf8(&bytes[idx..]).unwrap()
+        Ok(&bytes[idx..])
     }
 
     /// Extract HTTP method from request line.
-    fn extract_http_method(bytes: &[u8]) -> HttpMethod {
-        bytes
-            .split(|b| *b == WHITESPACE_BYTE)
-            .next()
-            .unwrap()
-            .into()
+    fn extract_http_method(bytes: &[u8]) -> Result<HttpMethod, ParseError> {
+        let method = bytes.split(|b| *b == WHITESPACE_BYTE).next();
+        method.ok_or(ParseError::InvalidRequestLine)?.try_into()
     }
 
     /// Extract URI from request line.
-    fn extract_request_uri(bytes: &[u8]) -> Uri {
-        let mut uri_bytes_split = bytes.split(|b| *b == WHITESPACE_BYTE);
-        uri_bytes_split.next().unwrap();
-        let uri_bytes = uri_bytes_split.next().unwrap();
-        Uri(std::str::from_utf8(uri_bytes).unwrap())
+    fn extract_request_uri(bytes: &[u8]) -> Result<Uri<'_>, ParseError> {
+        let uri_bytes = bytes
+            .split(|b| *b == WHITESPACE_BYTE)
+            .nth(1)
+            .ok_or(ParseError::InvalidRequestLine)?;
+        let uri = std::str::from_utf8(uri_bytes).map_err(|_| ParseError::InvalidRequestLine)?;
+        Ok(Uri(uri))
     }
 
     /// Extract HTTP version from request line.
-    fn extract_http_version(bytes: &[u8]) -> HttpVersion {
-        let mut http_version_bytes_split = bytes.split(|b| *b == WHITESPACE_BYTE);
-        http_version_bytes_split.next().unwrap();
-        http_version_bytes_split.next().unwrap();
-        http_version_bytes_split.next().unwrap().into()
+    fn extract_http_version(bytes: &[u8]) -> Result<HttpVersion, ParseError> {
+        let version = bytes.split(|b| *b == WHITESPACE_BYTE).nth(2);
+        version.ok_or(ParseError::InvalidRequestLine)?.try_into()
     }
 
     /// Extract headers from raw header bytes.
-    fn extract_headers(bytes: &[u8]) -> Headers {
+    fn extract_headers(bytes: &[u8]) -> Result<Headers<'_>, ParseError> {
         let mut headers = Headers::new();
+        if bytes.is_empty() {
+            return Ok(headers);
+        }
         let mut start_idx = 0;
 
-        loop {
-            let Some(carriage_return_idx) = bytes[start_idx..]
-                .windows(CARRIAGE_RETURN_LINE_FEED.len())
-                .position(|window| window == CARRIAGE_RETURN_LINE_FEED)
-            else {
-                break;
-            };
-
+        while let Some(carriage_return_idx) = bytes[start_idx..]
+            .windows(CARRIAGE_RETURN_LINE_FEED.len())
+            .position(|window| window == CARRIAGE_RETURN_LINE_FEED)
+        {
             let (key, value) = HttpRequest::get_header_key_and_value(
                 &bytes[start_idx..carriage_return_idx + start_idx],
-            );
+            )?;
 
-            headers.add_key_value(key, value);
+            headers.add_key_value(key, value)?;
 
             start_idx += carriage_return_idx + CARRIAGE_RETURN_LINE_FEED.len();
         }
 
-        let (key, value) = HttpRequest::get_header_key_and_value(&bytes[start_idx..]);
-        headers.add_key_value(key, value);
+        let (key, value) = HttpRequest::get_header_key_and_value(&bytes[start_idx..])?;
+        headers.add_key_value(key, value)?;
 
-        headers
+        Ok(headers)
     }
 
     /// Extract a single header's key and value.
-    fn get_header_key_and_value(bytes: &'a [u8]) -> (&'a str, &'a str) {
-        let colon_idx = bytes.iter().position(|byte| *byte == COLON_BYTE).unwrap();
-        let key = std::str::from_utf8(&bytes[..colon_idx]);
-        let whitespace_offset = (bytes[colon_idx + 1] == WHITESPACE_BYTE) as usize;
+    fn get_header_key_and_value(bytes: &'a [u8]) -> Result<(&'a str, &'a str), ParseError> {
+        let colon_idx = bytes
+            .iter()
+            .position(|byte| *byte == COLON_BYTE)
+            .ok_or(ParseError::InvalidHeader)?;
+        // Whitespace around a field name would hide it from lookups, such as those deciding how
+        // the body is framed.
+        let key = &bytes[..colon_idx];
+        if key.is_empty() || key.iter().any(u8::is_ascii_whitespace) {
+            return Err(ParseError::InvalidHeader);
+        }
+        let key = std::str::from_utf8(key);
+        let whitespace_offset = (bytes.get(colon_idx + 1) == Some(&WHITESPACE_BYTE)) as usize;
         let value = std::str::from_utf8(&bytes[colon_idx + 1 + whitespace_offset..]);
-        (key.unwrap(), value.unwrap())
+        match (key, value) {
+            (Ok(key), Ok(value)) => Ok((key, value)),
+            _ => Err(ParseError::InvalidHeader),
+        }
     }
 }
 
@@ -314,6 +456,15 @@ where
     marker: PhantomData<T>,
 }
 
+impl<T> Default for Segment<'_, T>
+where
+    T: Copy + Debug + PartialEq + Eq + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'a, T> Segment<'a, T>
 where
     T: Copy + Debug + PartialEq + Eq + Clone,
@@ -357,6 +508,15 @@ where
         self.iter_cnt = 0;
         self
     }
+
+    /// Iterate over the stored key-value pairs without touching the iteration state.
+    pub(crate) fn pairs(&self) -> impl Iterator<Item = (&'a str, Option<&'a str>)> + '_ {
+        self.key
+            .iter()
+            .zip(self.value.iter())
+            .take(self.num)
+            .filter_map(|(key, value)| Some(((*key)?, *value)))
+    }
 }
 
 /// Iterator implementation for path segments where all values are guaranteed to exist.
@@ -425,7 +585,18 @@ impl AsRef<str> for Uri<'_> {
 
 impl<'a> Uri<'a> {
     /// Match the current URI against a parameterized pattern, extracting both path and query parameters.
+    ///
+    /// A pattern ending in a `{*name}` segment, such as `/assets/{*path}`, matches any remainder
+    /// of the path, slashes included, and captures it as the `name` parameter.
     pub fn is_match(&self, cmp_uri: &'a str) -> Option<Params<'a>> {
+        if let Some((prefix, last)) = cmp_uri.rsplit_once('/')
+            && let Some(name) = last
+                .strip_prefix("{*")
+                .and_then(|name| name.strip_suffix('}'))
+        {
+            return self.match_catch_all(prefix, name);
+        }
+
         // TODO, find a better way, this is to handle the case both paths are home, i.e. cmp_uri =
         // '/' && self.0 = '/'
         // These are some sanity check
@@ -523,11 +694,9 @@ impl<'a> Uri<'a> {
         let mut params: Option<Params> = None;
 
         // Handle query string (if any)
-        if self.0.contains('?') {
-            if let Some(query_fragment) = self.0.split('?').last() {
-                let query_segment = Uri::parse_segment(query_fragment);
-                params.get_or_insert_default().query_fragment = query_segment;
-            }
+        if let Some((_, query_fragment)) = self.0.rsplit_once('?') {
+            let query_segment = Uri::parse_segment(query_fragment);
+            params.get_or_insert_default().query_fragment = query_segment;
         }
 
         // If URI ended while parsing a parameter, store the final value
@@ -564,6 +733,25 @@ impl<'a> Uri<'a> {
         params
     }
 
+    /// Match a pattern made of `prefix` followed by a catch-all segment named `name`.
+    fn match_catch_all(&self, prefix: &'a str, name: &'a str) -> Option<Params<'a>> {
+        let (path, query) = match self.0.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (self.0, None),
+        };
+
+        // The remainder starts after the slash closing the last segment of the prefix.
+        let (rest_idx, _) = path.match_indices('/').nth(prefix.split('/').count() - 1)?;
+        let mut params = Uri(&path[..rest_idx]).is_match(prefix)?;
+        params
+            .path_segment
+            .insert_key_value(name, Some(&path[rest_idx + 1..]));
+        if let Some(query) = query {
+            params.query_fragment = Uri::parse_segment(query);
+        }
+        Some(params)
+    }
+
     /// Parse the query portion of a URI (e.g., `?a=1&b`) into a Segment structure.
     fn parse_segment(segment_part: &'a str) -> Segment<'a, QuerySegment> {
         let mut segment: Segment<'a, QuerySegment> = Segment::new();
@@ -605,10 +793,35 @@ impl<'a> Headers<'a> {
     }
 
     /// Add a header key-value pair to the collection.
-    fn add_key_value(&mut self, key: &'a str, value: &'a str) {
+    fn add_key_value(&mut self, key: &'a str, value: &'a str) -> Result<(), ParseError> {
+        if self.num == MAX_NUM_HEADERS {
+            return Err(ParseError::TooManyHeaders);
+        }
         self.keys[self.num] = Some(key);
         self.values[self.num] = Some(value);
         self.num += 1;
+        Ok(())
+    }
+
+    /// Look up the first value for a header name, compared case-insensitively.
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.iter()
+            .find(|(header_key, _)| header_key.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value)
+    }
+
+    /// Iterate over all header key-value pairs in the order they were received.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> + '_ {
+        self.keys
+            .iter()
+            .zip(self.values.iter())
+            .take(self.num)
+            .filter_map(|(key, value)| Some(((*key)?, (*value)?)))
+    }
+
+    /// Return the number of stored headers.
+    pub fn size(&self) -> usize {
+        self.num
     }
 }
 
@@ -640,7 +853,7 @@ mod tests {
         let expected = [
             80, 79, 83, 84, 32, 47, 117, 115, 101, 114, 32, 72, 84, 84, 80, 47, 49, 46, 49,
         ];
-        let actual = HttpRequest::get_request_line(request);
+        let actual = HttpRequest::get_request_line(request).unwrap();
 
         assert_eq!(actual, expected);
     }
@@ -653,7 +866,7 @@ mod tests {
 
         let expected = HttpMethod::POST;
 
-        let actual = HttpRequest::extract_http_method(&request_line);
+        let actual = HttpRequest::extract_http_method(&request_line).unwrap();
 
         assert_eq!(actual, expected);
     }
@@ -661,33 +874,36 @@ mod tests {
     #[test]
     fn bytes_to_http_method() {
         let get = [71, 69, 84];
-        let actual: HttpMethod = get.as_slice().into();
+        let actual: HttpMethod = get.as_slice().try_into().unwrap();
 
         assert_eq!(actual, HttpMethod::GET);
 
         let head = [72, 69, 65, 68];
-        let actual: HttpMethod = head.as_slice().into();
+        let actual: HttpMethod = head.as_slice().try_into().unwrap();
         assert_eq!(actual, HttpMethod::HEAD);
 
         let options = [79, 80, 84, 73, 79, 78, 83];
-        let actual: HttpMethod = options.as_slice().into();
+        let actual: HttpMethod = options.as_slice().try_into().unwrap();
         assert_eq!(actual, HttpMethod::OPTIONS);
 
         let post = [80, 79, 83, 84];
-        let actual: HttpMethod = post.as_slice().into();
+        let actual: HttpMethod = post.as_slice().try_into().unwrap();
         assert_eq!(actual, HttpMethod::POST);
 
         let put = [80, 85, 84];
-        let actual: HttpMethod = put.as_slice().into();
+        let actual: HttpMethod = put.as_slice().try_into().unwrap();
         assert_eq!(actual, HttpMethod::PUT);
 
         let patch = [80, 65, 84, 67, 72];
-        let actual: HttpMethod = patch.as_slice().into();
+        let actual: HttpMethod = patch.as_slice().try_into().unwrap();
         assert_eq!(actual, HttpMethod::PATCH);
 
         let delete = [68, 69, 76, 69, 84, 69];
-        let actual: HttpMethod = delete.as_slice().into();
+        let actual: HttpMethod = delete.as_slice().try_into().unwrap();
         assert_eq!(actual, HttpMethod::DELETE);
+
+        let trace = HttpMethod::try_from(b"TRACE".as_slice());
+        assert_eq!(trace, Err(ParseError::UnknownMethod));
     }
 
     #[test]
@@ -696,7 +912,7 @@ mod tests {
             80, 79, 83, 84, 32, 47, 117, 115, 101, 114, 32, 72, 84, 84, 80, 47, 49, 46, 49,
         ];
 
-        let actual = HttpRequest::extract_request_uri(&request_line);
+        let actual = HttpRequest::extract_request_uri(&request_line).unwrap();
         let expected = Uri("/user");
 
         assert_eq!(actual, expected);
@@ -710,7 +926,7 @@ mod tests {
 
         let expected = HttpVersion::OnePointOne;
 
-        let actual = HttpRequest::extract_http_version(&request_line);
+        let actual = HttpRequest::extract_http_version(&request_line).unwrap();
 
         assert_eq!(actual, expected);
     }
@@ -719,7 +935,7 @@ mod tests {
     fn get_headers() {
         let request = get_test_post_request();
 
-        let actual = HttpRequest::get_headers(request);
+        let actual = HttpRequest::get_headers(request).unwrap();
 
         let expected = b"Host: localhost:8080\r\n\
      User-Agent: curl/7.81.0\r\n\
@@ -730,6 +946,63 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn request_without_headers() {
+        let request = HttpRequest::from_bytes(b"GET /health HTTP/1.0\r\n\r\n").unwrap();
+
+        assert_eq!(request.version(), HttpVersion::OneZero);
+        assert_eq!(request.headers().get("Host"), None);
+        assert_eq!(request.body(), b"");
+    }
+
+    #[test]
+    fn binary_body() {
+        let request =
+            HttpRequest::from_bytes(b"POST / HTTP/1.1\r\nHost: x\r\n\r\n\xff\x00\xfe").unwrap();
+
+        assert_eq!(request.body(), b"\xff\x00\xfe");
+        assert!(request.body_str().is_err());
+    }
+
+    #[test]
+    fn malformed_requests() {
+        let too_many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "a: b\r\n".repeat(1001));
+        let cases: [(&[u8], ParseError); 13] = [
+            (b"GET / HTTP/1.1", ParseError::InvalidRequestLine),
+            (b"GET\r\n\r\n", ParseError::InvalidRequestLine),
+            (
+                b"GET /\xff HTTP/1.1\r\n\r\n",
+                ParseError::InvalidRequestLine,
+            ),
+            (b"GET / HTTP/1.1\r\nHost\r\n\r\n", ParseError::InvalidHeader),
+            (
+                b"GET / HTTP/1.1\r\nHost : localhost\r\n\r\n",
+                ParseError::InvalidHeader,
+            ),
+            (
+                b"GET / HTTP/1.1\r\n: localhost\r\n\r\n",
+                ParseError::InvalidHeader,
+            ),
+            (
+                b"GET / HTTP/1.1\r\nHost: \xff\r\n\r\n",
+                ParseError::InvalidHeader,
+            ),
+            (too_many_headers.as_bytes(), ParseError::TooManyHeaders),
+            (b"TRACE / HTTP/1.1\r\n\r\n", ParseError::UnknownMethod),
+            (b"GET / HTTP/0.9\r\n\r\n", ParseError::UnsupportedVersion),
+            (b"GET / HTTP/3\r\n\r\n", ParseError::UnsupportedVersion),
+            (b"GET / HTTP/2.0\r\n\r\n", ParseError::UnsupportedVersion),
+            (b"GET / HTTP/2\r\n\r\n", ParseError::UnsupportedVersion),
+        ];
+        for (bytes, expected) in cases {
+            let actual = HttpRequest::from_bytes(bytes).unwrap_err();
+            assert_eq!(actual, expected, "{}", String::from_utf8_lossy(bytes));
+        }
+
+        let request = HttpRequest::from_bytes(b"GET / HTTP/1.1\r\nX-Empty:\r\n\r\n").unwrap();
+        assert_eq!(request.headers().get("X-Empty"), Some(""));
+    }
+
     #[test]
     fn extract_headers() {
         let headers = b"Host: localhost:8080\r\n\
@@ -738,7 +1011,7 @@ mod tests {
      Content-Type:application/json\r\n\
      Content-Length: 26";
 
-        let actual = HttpRequest::extract_headers(headers);
+        let actual = HttpRequest::extract_headers(headers).unwrap();
 
         let mut expected_keys = [None; MAX_NUM_HEADERS];
         let mut expected_values = [None; MAX_NUM_HEADERS];
@@ -769,17 +1042,17 @@ mod tests {
     fn get_body() {
         let request = get_test_post_request();
 
-        let actual = HttpRequest::get_body(request);
+        let actual = HttpRequest::get_body(request).unwrap();
 
-        let expected = "{\"message\": \"hello world\"}";
+        let expected = b"{\"message\": \"hello world\"}";
 
         assert_eq!(actual, expected);
 
         let request = get_test_post_request_no_headers();
 
-        let actual = HttpRequest::get_body(request);
+        let actual = HttpRequest::get_body(request).unwrap();
 
-        let expected = "{\"message\": \"hello world\"}";
+        let expected = b"{\"message\": \"hello world\"}";
 
         assert_eq!(actual, expected);
     }
@@ -947,6 +1220,32 @@ mod tests {
         assert_eq!(actual, expected)
     }
 
+    #[test]
+    fn uri_is_match_catch_all() {
+        let uri = Uri("/users/7/files/docs/2024/report.pdf?download=1");
+        let mut params = uri.is_match("/users/{id}/files/{*path}").unwrap();
+        let path: Vec<_> = params
+            .iter_path()
+            .map(|(key, value)| (key.as_ref().to_string(), value.as_ref().to_string()))
+            .collect();
+        assert_eq!(
+            path,
+            [
+                ("id".to_string(), "7".to_string()),
+                ("path".to_string(), "docs/2024/report.pdf".to_string())
+            ]
+        );
+        assert_eq!(params.query_segment().size(), 1);
+
+        let mut params = Uri("/").is_match("/{*path}").unwrap();
+        assert_eq!(params.iter_path().next().unwrap().1.as_ref(), "");
+        let mut params = Uri("/assets/").is_match("/assets/{*path}").unwrap();
+        assert_eq!(params.iter_path().next().unwrap().1.as_ref(), "");
+
+        assert!(Uri("/assets").is_match("/assets/{*path}").is_none());
+        assert!(Uri("/other/app.js").is_match("/assets/{*path}").is_none());
+    }
+
     #[test]
     fn path_segment_iter() {
         let mut path_segment = Segment::<PathSegment>::new();
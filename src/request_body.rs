@@ -0,0 +1,545 @@
+//! # Streaming Request Bodies
+//!
+//! Routes registered with [`HoochAppBuilder::add_streaming_route`] or
+//! [`HoochAppBuilder::add_streaming_handler`] receive the request as soon as its head has been
+//! read. The body is not buffered; instead the request carries a [`RequestBody`] stream yielding
+//! the body in chunks as they arrive from the client, decoded from either `Content-Length` or
+//! `Transfer-Encoding: chunked` framing.
+//!
+//! Nothing is read from the socket until the handler first polls the stream. Clients that sent
+//! `Expect: 100-continue` receive `100 Continue` at that point, so a handler that rejects the
+//! request without touching the body spares the client the upload. The next chunk is only read
+//! once the previous one has been taken, so a slow consumer slows the upload down instead of
+//! buffering it in memory.
+//!
+//! [`HoochAppBuilder::add_streaming_route`]: crate::HoochAppBuilder::add_streaming_route
+//! [`HoochAppBuilder::add_streaming_handler`]: crate::HoochAppBuilder::add_streaming_handler
+//!
+//! ## Example
+//!
+//! ```rust
+//! use futures::StreamExt;
+//! use hooch_http::{HoochAppBuilder, HttpMethod, HttpResponseBuilder};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_streaming_route("/upload", HttpMethod::POST, |mut req, _params| async move {
+//!     let mut body = req.take_body_stream().unwrap();
+//!     let mut received = 0;
+//!     while let Some(chunk) = body.next().await {
+//!         match chunk {
+//!             Ok(chunk) => received += chunk.len(),
+//!             Err(_) => return HttpResponseBuilder::bad_request().build(),
+//!         }
+//!     }
+//!     HttpResponseBuilder::ok().body(format!("{} bytes", received)).build()
+//! });
+//! ```
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    SinkExt, Stream, StreamExt,
+};
+use hooch::net::HoochTcpStream;
+
+use crate::{
+    app::{timeout, write_all, TimedOut},
+    request::HttpRequest,
+};
+
+/// Interim response sent to clients waiting on `Expect: 100-continue`.
+pub(crate) const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+/// Size of the buffer body bytes are read into from the socket.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Longest chunk-size or trailer line accepted in a chunked body.
+const MAX_CHUNK_LINE: usize = 4 * 1024;
+
+/// A request body delivered as a stream of byte chunks.
+///
+/// The stream ends once the whole body has been received. If the client disconnects, stalls
+/// past the body read timeout, or sends a malformed chunked body, an `Err` item is yielded and
+/// the stream ends.
+#[derive(Debug)]
+pub struct RequestBody {
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    /// Signals the connection task to start reading the body on first poll.
+    demand: Option<oneshot::Sender<()>>,
+    content_length: Option<u64>,
+}
+
+impl RequestBody {
+    /// Get the declared body length, if the body is not chunked.
+    pub fn content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
+    /// Creates a body that was already received in full.
+    #[cfg(feature = "http2")]
+    pub(crate) fn full(body: Vec<u8>) -> Self {
+        let (mut sender, chunks) = mpsc::channel(1);
+        let content_length = Some(body.len() as u64);
+        if !body.is_empty() {
+            let _ = sender.try_send(Ok(body));
+        }
+        Self {
+            chunks,
+            demand: None,
+            content_length,
+        }
+    }
+}
+
+impl Stream for RequestBody {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(demand) = self.demand.take() {
+            let _ = demand.send(());
+        }
+        self.chunks.poll_next_unpin(cx)
+    }
+}
+
+/// How the length of a request body is determined.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Framing {
+    /// The body is exactly this many bytes long.
+    Length(u64),
+    /// The body uses chunked transfer-encoding.
+    Chunked,
+}
+
+/// Reasons the body framing of a request cannot be determined unambiguously.
+///
+/// Guessing at the framing would let the bytes after the guessed end of the body be read as the
+/// next request on the connection, so such requests are rejected instead.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum FramingError {
+    /// `Transfer-Encoding` is present but is not just `chunked`.
+    UnsupportedTransferEncoding,
+    /// A `Content-Length` value is not a decimal number.
+    InvalidContentLength,
+    /// `Content-Length` is repeated with different values.
+    ConflictingContentLength,
+    /// Both `Transfer-Encoding` and `Content-Length` are present.
+    TransferEncodingWithContentLength,
+}
+
+impl Framing {
+    /// Determines the body framing declared by the request head.
+    ///
+    /// A request with neither `Transfer-Encoding` nor `Content-Length` has no body. The only
+    /// transfer coding supported is `chunked` on its own, and repeated `Content-Length` values
+    /// have to agree.
+    pub(crate) fn of(req: &HttpRequest<'_>) -> Result<Self, FramingError> {
+        let header_values = |name: &'static str| {
+            req.headers()
+                .iter()
+                .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+                .flat_map(|(_, value)| value.split(','))
+                .map(str::trim)
+        };
+
+        let mut codings = header_values("Transfer-Encoding").peekable();
+        let chunked = codings.peek().is_some();
+        if chunked {
+            let coding = codings.next().unwrap_or_default();
+            if !coding.eq_ignore_ascii_case("chunked") || codings.next().is_some() {
+                return Err(FramingError::UnsupportedTransferEncoding);
+            }
+        }
+
+        let mut length = None;
+        for value in header_values("Content-Length") {
+            if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(FramingError::InvalidContentLength);
+            }
+            let value = value
+                .parse()
+                .map_err(|_| FramingError::InvalidContentLength)?;
+            if length
+                .replace(value)
+                .is_some_and(|previous| previous != value)
+            {
+                return Err(FramingError::ConflictingContentLength);
+            }
+        }
+
+        match (chunked, length) {
+            (true, Some(_)) => Err(FramingError::TransferEncodingWithContentLength),
+            (true, None) => Ok(Self::Chunked),
+            (false, length) => Ok(Self::Length(length.unwrap_or(0))),
+        }
+    }
+}
+
+/// Rewrites the head of a request whose body was decoded to frame the decoded body.
+///
+/// `Transfer-Encoding`, `Content-Length` and the headers named in `dropped` are left out and a
+/// `Content-Length` of `body_len` is appended, so the head describes the body handlers actually
+/// receive. Every other line is kept byte for byte.
+pub(crate) fn reframed_head(head: &[u8], dropped: &[&str], body_len: usize) -> Vec<u8> {
+    let mut rewritten = Vec::with_capacity(head.len());
+    // Leave out the empty line ending the head; it is added back after `Content-Length`.
+    let lines = head.strip_suffix(b"\r\n").unwrap_or(head);
+    for line in lines.split_inclusive(|&byte| byte == b'\n') {
+        let name = line.split(|&byte| byte == b':').next().unwrap_or_default();
+        let name = name.trim_ascii();
+        if ["Transfer-Encoding", "Content-Length"]
+            .iter()
+            .chain(dropped)
+            .any(|dropped| name.eq_ignore_ascii_case(dropped.as_bytes()))
+        {
+            continue;
+        }
+        rewritten.extend_from_slice(line);
+    }
+    rewritten.extend_from_slice(format!("Content-Length: {}\r\n\r\n", body_len).as_bytes());
+    rewritten
+}
+
+/// Creates a [`RequestBody`] and the [`BodyPump`] that feeds it from the connection.
+///
+/// `prefetched` holds body bytes that were read together with the request head.
+pub(crate) fn channel(
+    framing: Framing,
+    prefetched: Vec<u8>,
+    expect_continue: bool,
+) -> (RequestBody, BodyPump) {
+    let (chunks_tx, chunks_rx) = mpsc::channel(0);
+    let (demand_tx, demand_rx) = oneshot::channel();
+
+    let body = RequestBody {
+        chunks: chunks_rx,
+        demand: Some(demand_tx),
+        content_length: match framing {
+            Framing::Length(length) => Some(length),
+            Framing::Chunked => None,
+        },
+    };
+    let pump = BodyPump {
+        chunks: chunks_tx,
+        demand: demand_rx,
+        decoder: Decoder::new(framing),
+        prefetched,
+        expect_continue,
+    };
+    (body, pump)
+}
+
+/// Reads a request body from the connection and forwards it to a [`RequestBody`].
+pub(crate) struct BodyPump {
+    chunks: mpsc::Sender<io::Result<Vec<u8>>>,
+    demand: oneshot::Receiver<()>,
+    decoder: Decoder,
+    prefetched: Vec<u8>,
+    expect_continue: bool,
+}
+
+impl BodyPump {
+    /// Pumps the body until it is complete, fails, or the [`RequestBody`] is dropped.
+    ///
+    /// `read_timeout` bounds each individual read rather than the whole body, so long uploads
+    /// are fine as long as the client keeps sending.
+    pub(crate) async fn run(mut self, stream: &mut HoochTcpStream, read_timeout: Option<Duration>) {
+        // Wait for the handler to ask for the body; give up if it drops the body unread.
+        if (&mut self.demand).await.is_err() || self.decoder.is_done() {
+            return;
+        }
+
+        if self.expect_continue && write_all(stream, CONTINUE).await.is_err() {
+            return;
+        }
+
+        let prefetched = std::mem::take(&mut self.prefetched);
+        if !self.forward(&prefetched).await {
+            return;
+        }
+
+        let mut buffer = vec![0; READ_CHUNK_SIZE];
+        while !self.decoder.is_done() {
+            let bytes_read = match timeout(stream.read(&mut buffer), read_timeout).await {
+                Ok(Ok(0)) => Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(Ok(bytes_read)) => Ok(bytes_read),
+                Ok(Err(err)) => Err(err),
+                Err(TimedOut) => Err(io::ErrorKind::TimedOut.into()),
+            };
+            match bytes_read {
+                Ok(bytes_read) => {
+                    if !self.forward(&buffer[..bytes_read]).await {
+                        return;
+                    }
+                }
+                Err(err) => {
+                    let _ = self.chunks.send(Err(err)).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Decodes `input` and sends the resulting bytes on.
+    ///
+    /// Returns `false` if pumping should stop, either because the input was malformed or
+    /// because the receiving end went away.
+    async fn forward(&mut self, input: &[u8]) -> bool {
+        let mut decoded = Vec::new();
+        let result = self.decoder.decode(input, &mut decoded).map(|_| ());
+        if !decoded.is_empty() && self.chunks.send(Ok(decoded)).await.is_err() {
+            return false;
+        }
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                let _ = self.chunks.send(Err(err)).await;
+                false
+            }
+        }
+    }
+}
+
+/// Incrementally strips the framing from a request body.
+#[derive(Debug)]
+pub(crate) enum Decoder {
+    Length { remaining: u64 },
+    Chunked { state: ChunkState, line: Vec<u8> },
+}
+
+/// Position within a chunked body.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum ChunkState {
+    /// Reading a chunk-size line.
+    Size,
+    /// Reading chunk data, with this many bytes left in the chunk.
+    Data(u64),
+    /// Reading the line break that ends chunk data.
+    DataEnd,
+    /// Reading trailer lines after the last chunk.
+    Trailers,
+    /// The body is complete.
+    Done,
+}
+
+impl Decoder {
+    pub(crate) fn new(framing: Framing) -> Self {
+        match framing {
+            Framing::Length(length) => Self::Length { remaining: length },
+            Framing::Chunked => Self::Chunked {
+                state: ChunkState::Size,
+                line: Vec::new(),
+            },
+        }
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        match self {
+            Self::Length { remaining } => *remaining == 0,
+            Self::Chunked { state, .. } => *state == ChunkState::Done,
+        }
+    }
+
+    /// Appends the body bytes contained in `input` to `out`.
+    ///
+    /// Returns the number of bytes of `input` that belong to the body; bytes following the end
+    /// of the body are ignored.
+    pub(crate) fn decode(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> io::Result<usize> {
+        let (state, line) = match self {
+            Self::Length { remaining } => {
+                let take = input.len().min(*remaining as usize);
+                out.extend_from_slice(&input[..take]);
+                *remaining -= take as u64;
+                return Ok(take);
+            }
+            Self::Chunked { state, line } => (state, line),
+        };
+        let input_len = input.len();
+
+        while !input.is_empty() {
+            match *state {
+                ChunkState::Data(remaining) => {
+                    let take = input.len().min(remaining as usize);
+                    out.extend_from_slice(&input[..take]);
+                    input = &input[take..];
+                    *state = match remaining - take as u64 {
+                        0 => ChunkState::DataEnd,
+                        remaining => ChunkState::Data(remaining),
+                    };
+                }
+                ChunkState::Done => return Ok(input_len - input.len()),
+                ChunkState::Size | ChunkState::DataEnd | ChunkState::Trailers => {
+                    let Some(newline) = input.iter().position(|&byte| byte == b'\n') else {
+                        line.extend_from_slice(input);
+                        if line.len() > MAX_CHUNK_LINE {
+                            return Err(invalid_chunk("line too long"));
+                        }
+                        return Ok(input_len);
+                    };
+                    line.extend_from_slice(&input[..newline]);
+                    input = &input[newline + 1..];
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    *state = Self::next_state(*state, line)?;
+                    line.clear();
+                }
+            }
+        }
+        Ok(input_len)
+    }
+
+    /// Interprets a complete line read in `state`.
+    fn next_state(state: ChunkState, line: &[u8]) -> io::Result<ChunkState> {
+        match state {
+            ChunkState::Size => {
+                let size = std::str::from_utf8(line)
+                    .ok()
+                    .map(|line| line.split(';').next().unwrap_or_default().trim())
+                    // `from_str_radix` would also accept a leading sign.
+                    .filter(|size| !size.is_empty() && size.bytes().all(|b| b.is_ascii_hexdigit()))
+                    .and_then(|size| u64::from_str_radix(size, 16).ok())
+                    .ok_or_else(|| invalid_chunk("invalid chunk size"))?;
+                Ok(match size {
+                    0 => ChunkState::Trailers,
+                    size => ChunkState::Data(size),
+                })
+            }
+            ChunkState::DataEnd if line.is_empty() => Ok(ChunkState::Size),
+            ChunkState::DataEnd => Err(invalid_chunk("missing line break after chunk data")),
+            ChunkState::Trailers if line.is_empty() => Ok(ChunkState::Done),
+            state => Ok(state),
+        }
+    }
+}
+
+fn invalid_chunk(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("malformed chunked body: {}", reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNKED: &[u8] =
+        b"5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nX-Trailer: yes\r\n\r\nGET / HTTP/1.1";
+
+    #[test]
+    fn chunked_decoding_across_every_split() {
+        for split in 0..CHUNKED.len() {
+            let mut decoder = Decoder::new(Framing::Chunked);
+            let mut out = Vec::new();
+            let mut consumed = decoder.decode(&CHUNKED[..split], &mut out).unwrap();
+            consumed += decoder.decode(&CHUNKED[split..], &mut out).unwrap();
+            assert!(decoder.is_done(), "split at {}", split);
+            assert_eq!(out, b"hello, world");
+            assert_eq!(&CHUNKED[consumed..], b"GET / HTTP/1.1");
+        }
+    }
+
+    #[test]
+    fn chunked_decoding_rejects_malformed_input() {
+        for size in ["zz", "+5", "-5", "", " "] {
+            let mut decoder = Decoder::new(Framing::Chunked);
+            let input = format!("{}\r\nhello\r\n0\r\n\r\n", size);
+            assert!(
+                decoder.decode(input.as_bytes(), &mut Vec::new()).is_err(),
+                "{:?}",
+                size
+            );
+        }
+
+        let mut decoder = Decoder::new(Framing::Chunked);
+        assert!(decoder.decode(b"2\r\nabc\r\n", &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn framing_of_heads() {
+        let framing = |head: &str| {
+            let request = format!("POST / HTTP/1.1\r\nHost: localhost\r\n{}\r\n", head);
+            Framing::of(&HttpRequest::from_bytes(request.as_bytes()).unwrap())
+        };
+        assert_eq!(framing(""), Ok(Framing::Length(0)));
+        assert_eq!(framing("Content-Length: 5\r\n"), Ok(Framing::Length(5)));
+        assert_eq!(
+            framing("Content-Length: 5\r\nContent-Length: 5, 5\r\n"),
+            Ok(Framing::Length(5))
+        );
+        assert_eq!(
+            framing("Transfer-Encoding: Chunked\r\n"),
+            Ok(Framing::Chunked)
+        );
+
+        for (head, expected) in [
+            (
+                "Transfer-Encoding: gzip\r\n",
+                FramingError::UnsupportedTransferEncoding,
+            ),
+            (
+                "Transfer-Encoding: gzip, chunked\r\n",
+                FramingError::UnsupportedTransferEncoding,
+            ),
+            (
+                "Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n",
+                FramingError::UnsupportedTransferEncoding,
+            ),
+            (
+                "Content-Length: abc\r\n",
+                FramingError::InvalidContentLength,
+            ),
+            ("Content-Length: +5\r\n", FramingError::InvalidContentLength),
+            ("Content-Length: \r\n", FramingError::InvalidContentLength),
+            (
+                "Content-Length: 5\r\nContent-Length: 6\r\n",
+                FramingError::ConflictingContentLength,
+            ),
+            (
+                "Content-Length: 5, 6\r\n",
+                FramingError::ConflictingContentLength,
+            ),
+            (
+                "Transfer-Encoding: chunked\r\nContent-Length: 5\r\n",
+                FramingError::TransferEncodingWithContentLength,
+            ),
+        ] {
+            assert_eq!(framing(head), Err(expected), "{:?}", head);
+        }
+    }
+
+    #[test]
+    fn reframed_head_frames_decoded_body() {
+        let head =
+            b"POST / HTTP/1.1\r\nHost: localhost\r\ntransfer-encoding: chunked\r\nX-A: b\r\n\r\n";
+        assert_eq!(
+            reframed_head(head, &[], 12),
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nX-A: b\r\nContent-Length: 12\r\n\r\n"
+        );
+
+        // Other lines are kept as sent, even when they are not UTF-8.
+        let head = b"POST / HTTP/1.1\r\nX-Name: caf\xe9\r\nContent-Encoding: gzip\r\nContent-Length: 3\r\n\r\n";
+        assert_eq!(
+            reframed_head(head, &["Content-Encoding"], 7),
+            b"POST / HTTP/1.1\r\nX-Name: caf\xe9\r\nContent-Length: 7\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn length_decoding_stops_at_content_length() {
+        let mut decoder = Decoder::new(Framing::Length(4));
+        let mut out = Vec::new();
+        decoder.decode(b"ab", &mut out).unwrap();
+        assert!(!decoder.is_done());
+        assert_eq!(decoder.decode(b"cdef", &mut out).unwrap(), 2);
+        assert!(decoder.is_done());
+        assert_eq!(out, b"abcd");
+    }
+}
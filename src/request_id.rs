@@ -0,0 +1,278 @@
+//! # Request IDs
+//!
+//! The [`RequestIdLayer`] gives every request an identifier that can be followed across
+//! services. An id sent by the client or an upstream service in the `X-Request-Id` header is
+//! kept; requests without one, or with one that is empty, longer than 128 bytes or contains
+//! anything but visible ASCII, get a fresh id. By default ids are random UUIDs; ULIDs, which
+//! sort by creation time, or ids from a custom generator can be used instead.
+//!
+//! The id is attached to the request as a [`RequestId`], available as an extractor or from the
+//! request's extensions, so that handlers can pass it on to the services they call. It is
+//! echoed in the same header on the response and, with the `tracing` feature, recorded as the
+//! `request_id` field of the request span.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hooch_http::{HoochAppBuilder, HttpMethod, RequestId, RequestIdLayer};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_layer(RequestIdLayer::new().header("X-Correlation-Id").ulid());
+//!
+//! app.add_handler("/", HttpMethod::GET, |id: RequestId| async move {
+//!     format!("handling {}", id)
+//! });
+//! ```
+
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use futures::future::BoxFuture;
+
+use crate::{
+    extract::FromRequest,
+    layer::{Layer, Next},
+    request::{HttpRequest, Params},
+    response::{HttpResponse, HttpResponseBuilder},
+};
+
+/// Longest id accepted from a request.
+const MAX_ID_LEN: usize = 128;
+
+/// The identifier of a request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Returns the id as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Unwraps the id.
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for RequestId {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        _params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        req.extensions().get::<RequestId>().cloned().ok_or_else(|| {
+            HttpResponseBuilder::internal_server_error()
+                .body("Request ids require the `RequestIdLayer`".into())
+                .build()
+        })
+    }
+}
+
+type Generator = dyn Fn() -> String + Send + Sync;
+
+/// Layer reading or assigning the [`RequestId`] of every request.
+///
+/// See the [module documentation](self) for how ids are assigned.
+#[derive(Clone)]
+pub struct RequestIdLayer {
+    header: String,
+    generate: Arc<Generator>,
+}
+
+impl fmt::Debug for RequestIdLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestIdLayer")
+            .field("header", &self.header)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for RequestIdLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestIdLayer {
+    /// Creates a layer using the `X-Request-Id` header and generating random UUIDs.
+    pub fn new() -> Self {
+        Self {
+            header: "X-Request-Id".to_string(),
+            generate: Arc::new(uuid_v4),
+        }
+    }
+
+    /// Reads and echoes the id in the header `name`.
+    pub fn header(mut self, name: impl Into<String>) -> Self {
+        self.header = name.into();
+        self
+    }
+
+    /// Generates random (version 4) UUIDs, such as `5f0c7b3e-8a4d-4c8e-9b1a-2f6d3e4c5b6a`.
+    pub fn uuid(self) -> Self {
+        self.generator(uuid_v4)
+    }
+
+    /// Generates ULIDs, such as `01JAB3XKQZ8M5V6W7Y9C2D4E6F`.
+    pub fn ulid(self) -> Self {
+        self.generator(ulid)
+    }
+
+    /// Generates ids with `generate`.
+    pub fn generator<F>(mut self, generate: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.generate = Arc::new(generate);
+        self
+    }
+}
+
+impl Layer for RequestIdLayer {
+    fn call<'a>(
+        &'a self,
+        mut req: HttpRequest<'static>,
+        _socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            let id = match req.headers().get(&self.header).map(str::trim) {
+                Some(id) if is_valid(id) => id.to_string(),
+                _ => (self.generate)(),
+            };
+            #[cfg(feature = "tracing")]
+            tracing::Span::current().record("request_id", id.as_str());
+            req.extensions_mut().insert(RequestId(id.clone()));
+
+            let mut response = next.run(req).await;
+            if response.header(&self.header).is_none() {
+                response.insert_header(self.header.as_str(), id);
+            }
+            response
+        })
+    }
+}
+
+/// Returns `true` if an id received in a request can be used as is.
+fn is_valid(id: &str) -> bool {
+    (1..=MAX_ID_LEN).contains(&id.len()) && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).expect("the operating system provides randomness");
+    bytes
+}
+
+/// Generates a random (version 4) UUID.
+fn uuid_v4() -> String {
+    let mut bytes = random::<16>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Generates a ULID: a 48 bit millisecond timestamp followed by 80 random bits, in Crockford's
+/// base 32.
+fn ulid() -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64);
+    let mut value = u128::from(millis & 0xffff_ffff_ffff) << 80;
+    let mut randomness = [0; 16];
+    randomness[6..].copy_from_slice(&random::<10>());
+    value |= u128::from_be_bytes(randomness);
+    (0..26)
+        .rev()
+        .map(|idx| char::from(ALPHABET[(value >> (idx * 5)) as usize & 0x1f]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        app::tests::{get, header, serve_layer},
+        HoochAppBuilder, HttpMethod,
+    };
+
+    fn routes(app: &mut HoochAppBuilder) {
+        app.add_handler("/", HttpMethod::GET, |id: RequestId| async move {
+            id.into_inner()
+        });
+    }
+
+    #[test]
+    fn keeps_or_generates_ids() {
+        let addr = serve_layer(RequestIdLayer::new(), routes);
+
+        let response = get(addr, "/", "X-Request-Id: abc-123\r\n");
+        assert_eq!(header(&response, "X-Request-Id"), Some("abc-123"));
+        assert!(response.ends_with("\r\n\r\nabc-123"));
+
+        for headers in [
+            "",
+            "X-Request-Id: \r\n",
+            "X-Request-Id: two words\r\n",
+            &format!("X-Request-Id: {}\r\n", "a".repeat(129)),
+        ] {
+            let response = get(addr, "/", headers);
+            let id = header(&response, "X-Request-Id").unwrap();
+            assert_eq!(id.len(), 36, "{}", headers);
+            assert!(response.ends_with(id));
+        }
+    }
+
+    #[test]
+    fn custom_header_and_generator() {
+        let addr = serve_layer(
+            RequestIdLayer::new()
+                .header("X-Correlation-Id")
+                .generator(|| "generated".to_string()),
+            routes,
+        );
+        let response = get(addr, "/", "X-Request-Id: ignored\r\n");
+        assert_eq!(header(&response, "X-Correlation-Id"), Some("generated"));
+        assert_eq!(header(&response, "X-Request-Id"), None);
+        assert!(response.ends_with("generated"));
+    }
+
+    #[test]
+    fn id_formats() {
+        let uuid = uuid_v4();
+        let groups: Vec<usize> = uuid.split('-').map(str::len).collect();
+        assert_eq!(groups, [8, 4, 4, 4, 12]);
+        assert_eq!(&uuid[14..15], "4");
+        assert!("89ab".contains(&uuid[19..20]));
+        assert_ne!(uuid, uuid_v4());
+
+        let first = ulid();
+        assert_eq!(first.len(), 26);
+        assert!(first.starts_with('0'));
+        assert!(first
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || byte.is_ascii_uppercase()));
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(ulid() > first);
+    }
+}
@@ -3,41 +3,87 @@
 //! This module provides a minimal `HttpResponseBuilder` utility for constructing HTTP
 //! responses, along with associated types like `HttpStatus`, `HeaderKey`, and `HeaderValue`.
 //! Responses can be serialized into byte buffers for sending over a network.
-
-use std::collections::HashMap;
+//!
+//! Handlers may return any type implementing [`IntoResponse`] rather than building an
+//! [`HttpResponse`] by hand:
+//!
+//! ```rust
+//! use hooch_http::{HttpStatus, IntoResponse};
+//!
+//! let response = (HttpStatus::Created, "stored").into_response();
+//! assert_eq!(response.status(), HttpStatus::Created);
+//! assert_eq!(response.header("Content-Type"), Some("text/plain; charset=utf-8"));
+//! ```
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
 use std::io::Write;
+use std::time::SystemTime;
+
+use futures::{future::BoxFuture, FutureExt, Stream};
+use hooch::net::HoochTcpStream;
+use serde::Serialize;
 
-use crate::shared::HttpVersion;
+use crate::{body::Body, conditional::ETag, cookie::Cookie, extract::Json, shared::HttpVersion};
 
 /// Common HTTP status codes.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum HttpStatus {
+    SwitchingProtocols,
     Ok,
     Created,
     NoContent,
+    PartialContent,
+    NotModified,
     BadRequest,
     Unauthorized,
     Forbidden,
     NotFound,
+    RequestTimeout,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    UnprocessableEntity,
+    UpgradeRequired,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
     InternalServerError,
+    NotImplemented,
     BadGateway,
     ServiceUnavailable,
+    HttpVersionNotSupported,
 }
 
 /// Convert an `HttpStatus` to its numeric status code.
 impl From<HttpStatus> for u16 {
     fn from(status: HttpStatus) -> u16 {
         match status {
+            HttpStatus::SwitchingProtocols => 101,
             HttpStatus::Ok => 200,
             HttpStatus::Created => 201,
             HttpStatus::NoContent => 204,
+            HttpStatus::PartialContent => 206,
+            HttpStatus::NotModified => 304,
             HttpStatus::BadRequest => 400,
             HttpStatus::Unauthorized => 401,
             HttpStatus::Forbidden => 403,
             HttpStatus::NotFound => 404,
+            HttpStatus::RequestTimeout => 408,
+            HttpStatus::PreconditionFailed => 412,
+            HttpStatus::PayloadTooLarge => 413,
+            HttpStatus::UnsupportedMediaType => 415,
+            HttpStatus::RangeNotSatisfiable => 416,
+            HttpStatus::UnprocessableEntity => 422,
+            HttpStatus::UpgradeRequired => 426,
+            HttpStatus::TooManyRequests => 429,
+            HttpStatus::RequestHeaderFieldsTooLarge => 431,
             HttpStatus::InternalServerError => 500,
+            HttpStatus::NotImplemented => 501,
             HttpStatus::BadGateway => 502,
             HttpStatus::ServiceUnavailable => 503,
+            HttpStatus::HttpVersionNotSupported => 505,
         }
     }
 }
@@ -46,24 +92,57 @@ impl From<HttpStatus> for u16 {
 impl From<HttpStatus> for &'static str {
     fn from(status: HttpStatus) -> &'static str {
         match status {
+            HttpStatus::SwitchingProtocols => "Switching Protocols",
             HttpStatus::Ok => "OK",
             HttpStatus::Created => "Created",
             HttpStatus::NoContent => "No Content",
+            HttpStatus::PartialContent => "Partial Content",
+            HttpStatus::NotModified => "Not Modified",
             HttpStatus::BadRequest => "Bad Request",
             HttpStatus::Unauthorized => "Unauthorized",
             HttpStatus::Forbidden => "Forbidden",
             HttpStatus::NotFound => "Not Found",
+            HttpStatus::RequestTimeout => "Request Timeout",
+            HttpStatus::PreconditionFailed => "Precondition Failed",
+            HttpStatus::PayloadTooLarge => "Payload Too Large",
+            HttpStatus::UnsupportedMediaType => "Unsupported Media Type",
+            HttpStatus::RangeNotSatisfiable => "Range Not Satisfiable",
+            HttpStatus::UnprocessableEntity => "Unprocessable Entity",
+            HttpStatus::UpgradeRequired => "Upgrade Required",
+            HttpStatus::TooManyRequests => "Too Many Requests",
+            HttpStatus::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
             HttpStatus::InternalServerError => "Internal Server Error",
+            HttpStatus::NotImplemented => "Not Implemented",
             HttpStatus::BadGateway => "Bad Gateway",
             HttpStatus::ServiceUnavailable => "Service Unavailable",
+            HttpStatus::HttpVersionNotSupported => "HTTP Version Not Supported",
         }
     }
 }
 
 /// Wrapper for HTTP header keys.
-#[derive(Debug)]
+///
+/// Keys compare and hash case-insensitively, as header names do in HTTP.
+#[derive(Debug, Clone)]
 pub struct HeaderKey(String);
 
+impl PartialEq for HeaderKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Eq for HeaderKey {}
+
+impl Hash for HeaderKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for byte in self.0.bytes() {
+            state.write_u8(byte.to_ascii_lowercase());
+        }
+        state.write_u8(0xff);
+    }
+}
+
 impl AsRef<str> for HeaderKey {
     fn as_ref(&self) -> &str {
         &self.0
@@ -82,8 +161,14 @@ impl From<String> for HeaderKey {
     }
 }
 
+impl From<&str> for HeaderKey {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
 /// Wrapper for HTTP header values.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HeaderValue(String);
 
 impl AsRef<str> for HeaderValue {
@@ -104,13 +189,20 @@ impl From<String> for HeaderValue {
     }
 }
 
+impl From<&str> for HeaderValue {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
 /// Builder struct for constructing an HTTP response.
 #[derive(Debug)]
 pub struct HttpResponseBuilder {
     status: HttpStatus,
     protocal: Option<HttpVersion>,
-    headers: Option<HashMap<HeaderKey, HeaderValue>>,
-    body: Option<String>,
+    headers: Option<Vec<(HeaderKey, HeaderValue)>>,
+    body: Body,
+    upgrade: Option<OnUpgrade>,
 }
 
 impl HttpResponseBuilder {
@@ -120,10 +212,16 @@ impl HttpResponseBuilder {
             status,
             protocal: None,
             headers: None,
-            body: None,
+            body: Body::Empty,
+            upgrade: None,
         }
     }
 
+    /// Shortcut for 101 Switching Protocols.
+    pub fn switching_protocols() -> Self {
+        Self::new(HttpStatus::SwitchingProtocols)
+    }
+
     /// Shortcut for 200 OK.
     pub fn ok() -> Self {
         Self::new(HttpStatus::Ok)
@@ -139,6 +237,16 @@ impl HttpResponseBuilder {
         Self::new(HttpStatus::NoContent)
     }
 
+    /// Shortcut for 206 Partial Content.
+    pub fn partial_content() -> Self {
+        Self::new(HttpStatus::PartialContent)
+    }
+
+    /// Shortcut for 304 Not Modified.
+    pub fn not_modified() -> Self {
+        Self::new(HttpStatus::NotModified)
+    }
+
     /// Shortcut for 400 Bad Request.
     pub fn bad_request() -> Self {
         Self::new(HttpStatus::BadRequest)
@@ -159,11 +267,61 @@ impl HttpResponseBuilder {
         Self::new(HttpStatus::NotFound)
     }
 
+    /// Shortcut for 408 Request Timeout.
+    pub fn request_timeout() -> Self {
+        Self::new(HttpStatus::RequestTimeout)
+    }
+
+    /// Shortcut for 412 Precondition Failed.
+    pub fn precondition_failed() -> Self {
+        Self::new(HttpStatus::PreconditionFailed)
+    }
+
+    /// Shortcut for 413 Payload Too Large.
+    pub fn payload_too_large() -> Self {
+        Self::new(HttpStatus::PayloadTooLarge)
+    }
+
+    /// Shortcut for 415 Unsupported Media Type.
+    pub fn unsupported_media_type() -> Self {
+        Self::new(HttpStatus::UnsupportedMediaType)
+    }
+
+    /// Shortcut for 416 Range Not Satisfiable.
+    pub fn range_not_satisfiable() -> Self {
+        Self::new(HttpStatus::RangeNotSatisfiable)
+    }
+
+    /// Shortcut for 422 Unprocessable Entity.
+    pub fn unprocessable_entity() -> Self {
+        Self::new(HttpStatus::UnprocessableEntity)
+    }
+
+    /// Shortcut for 426 Upgrade Required.
+    pub fn upgrade_required() -> Self {
+        Self::new(HttpStatus::UpgradeRequired)
+    }
+
+    /// Shortcut for 429 Too Many Requests.
+    pub fn too_many_requests() -> Self {
+        Self::new(HttpStatus::TooManyRequests)
+    }
+
+    /// Shortcut for 431 Request Header Fields Too Large.
+    pub fn request_header_fields_too_large() -> Self {
+        Self::new(HttpStatus::RequestHeaderFieldsTooLarge)
+    }
+
     /// Shortcut for 500 Internal Server Error.
     pub fn internal_server_error() -> Self {
         Self::new(HttpStatus::InternalServerError)
     }
 
+    /// Shortcut for 501 Not Implemented.
+    pub fn not_implemented() -> Self {
+        Self::new(HttpStatus::NotImplemented)
+    }
+
     /// Shortcut for 502 Bad Gateway.
     pub fn bad_gateway() -> Self {
         Self::new(HttpStatus::BadGateway)
@@ -174,26 +332,121 @@ impl HttpResponseBuilder {
         Self::new(HttpStatus::ServiceUnavailable)
     }
 
+    /// Shortcut for 505 HTTP Version Not Supported.
+    pub fn http_version_not_supported() -> Self {
+        Self::new(HttpStatus::HttpVersionNotSupported)
+    }
+
     /// Set the HTTP protocol version (defaults to 1.1).
     pub fn protocal(mut self, protocal: HttpVersion) -> Self {
         self.protocal = Some(protocal);
         self
     }
 
-    /// Set the response headers.
-    pub fn headers(mut self, headers: HashMap<HeaderKey, HeaderValue>) -> Self {
-        self.headers = Some(headers);
+    /// Set the response headers, in the order they are sent.
+    ///
+    /// A key may appear several times to send the header several times.
+    pub fn headers(mut self, headers: impl IntoIterator<Item = (HeaderKey, HeaderValue)>) -> Self {
+        self.headers = Some(headers.into_iter().collect());
+        self
+    }
+
+    /// Add a single header, replacing any existing values for the same key.
+    pub fn header(mut self, key: impl Into<HeaderKey>, value: impl Into<HeaderValue>) -> Self {
+        insert_value(self.headers.get_or_insert_with(Vec::new), key, value);
+        self
+    }
+
+    /// Add a header, keeping any existing values for the same key so it is sent several times.
+    pub fn append_header(
+        mut self,
+        key: impl Into<HeaderKey>,
+        value: impl Into<HeaderValue>,
+    ) -> Self {
+        self.headers
+            .get_or_insert_with(Vec::new)
+            .push((key.into(), value.into()));
         self
     }
 
-    /// Get a mutable reference to the headers (if present).
-    pub fn get_mut_headers(&mut self) -> Option<&mut HashMap<HeaderKey, HeaderValue>> {
+    /// Add a cookie, sent in a `Set-Cookie` header of its own.
+    pub fn cookie(self, cookie: Cookie) -> Self {
+        self.append_header("Set-Cookie", cookie.to_string())
+    }
+
+    /// Set the `ETag` header.
+    pub fn etag(self, etag: ETag) -> Self {
+        self.header("ETag", etag.to_string())
+    }
+
+    /// Set the `Last-Modified` header. The time is rounded down to whole seconds.
+    pub fn last_modified(self, modified: SystemTime) -> Self {
+        self.header("Last-Modified", httpdate::fmt_http_date(modified))
+    }
+
+    /// Get a mutable reference to the headers (if present), in the order they are sent.
+    pub fn get_mut_headers(&mut self) -> Option<&mut Vec<(HeaderKey, HeaderValue)>> {
         self.headers.as_mut()
     }
 
     /// Set the response body.
     pub fn body(mut self, body: String) -> Self {
-        self.body = Some(body);
+        self.body = Body::from(body);
+        self
+    }
+
+    /// Set a binary response body.
+    pub fn body_bytes(mut self, body: Vec<u8>) -> Self {
+        self.body = Body::from(body);
+        self
+    }
+
+    /// Set a streaming response body, sent with chunked transfer-encoding.
+    ///
+    /// See [`Body`] for how chunks are written.
+    pub fn body_stream<S>(mut self, stream: S) -> Self
+    where
+        S: Stream<Item = std::io::Result<Vec<u8>>> + Send + 'static,
+    {
+        self.body = Body::from_stream(stream);
+        self
+    }
+
+    /// Take over the connection once this response has been written.
+    ///
+    /// `callback` receives the raw connection as an [`Upgraded`] and runs until it returns, after
+    /// which the connection is closed. It only runs for `101 Switching Protocols` responses and
+    /// for `2xx` responses to `CONNECT` requests; for any other response, or if the response
+    /// could not be written, it is dropped and the connection is handled as usual.
+    ///
+    /// The response should carry the headers the client expects, such as `Upgrade` and
+    /// `Connection: Upgrade` for a protocol switch.
+    ///
+    /// ```rust
+    /// use hooch_http::HttpResponseBuilder;
+    ///
+    /// let response = HttpResponseBuilder::switching_protocols()
+    ///     .header("Upgrade", "echo")
+    ///     .header("Connection", "Upgrade")
+    ///     .on_upgrade(|mut upgraded| async move {
+    ///         let mut buffer = vec![0; 1024];
+    ///         if !upgraded.buffered.is_empty() {
+    ///             let _ = upgraded.stream.write(&upgraded.buffered).await;
+    ///         }
+    ///         while let Ok(n @ 1..) = upgraded.stream.read(&mut buffer).await {
+    ///             let _ = upgraded.stream.write(&buffer[..n]).await;
+    ///         }
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn on_upgrade<F, Fut>(mut self, callback: F) -> Self
+    where
+        F: FnOnce(Upgraded) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.upgrade = Some(OnUpgrade::new(move |stream, buffered| {
+            callback(Upgraded { stream, buffered })
+        }));
         self
     }
 
@@ -204,6 +457,8 @@ impl HttpResponseBuilder {
             protocal: self.protocal.unwrap_or(HttpVersion::OnePointOne),
             headers: self.headers,
             body: self.body,
+            route: None,
+            upgrade: self.upgrade,
         }
     }
 }
@@ -213,19 +468,231 @@ impl HttpResponseBuilder {
 pub struct HttpResponse {
     status: HttpStatus,
     protocal: HttpVersion,
-    headers: Option<HashMap<HeaderKey, HeaderValue>>,
-    body: Option<String>,
+    headers: Option<Vec<(HeaderKey, HeaderValue)>>,
+    body: Body,
+    /// The route pattern whose handler produced this response, if any.
+    route: Option<&'static str>,
+    /// Takes over the connection once the response has been written.
+    upgrade: Option<OnUpgrade>,
+}
+
+/// A connection handed over to a handler after an upgrade response.
+///
+/// See [`HttpResponseBuilder::on_upgrade`].
+pub struct Upgraded {
+    /// The client connection, positioned right after the request.
+    pub stream: HoochTcpStream,
+    /// Bytes the client sent after the request that were already read from the socket.
+    ///
+    /// These come before anything read from `stream` and must be processed first.
+    pub buffered: Vec<u8>,
+}
+
+/// Callback taking over a connection after an upgrade response.
+///
+/// It receives the connection and any bytes the client sent after the request, which were
+/// already read from the socket.
+pub(crate) struct OnUpgrade(
+    Box<dyn FnOnce(HoochTcpStream, Vec<u8>) -> BoxFuture<'static, ()> + Send>,
+);
+
+impl OnUpgrade {
+    pub(crate) fn new<F, Fut>(callback: F) -> Self
+    where
+        F: FnOnce(HoochTcpStream, Vec<u8>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Self(Box::new(move |stream, buffered| {
+            callback(stream, buffered).boxed()
+        }))
+    }
+
+    /// Hands the connection over to the callback and runs it to completion.
+    pub(crate) async fn run(self, stream: HoochTcpStream, buffered: Vec<u8>) {
+        (self.0)(stream, buffered).await
+    }
+}
+
+impl Debug for Upgraded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Upgraded")
+            .field("buffered", &self.buffered.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Debug for OnUpgrade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OnUpgrade(..)")
+    }
 }
 
 impl HttpResponse {
+    /// Get the response status.
+    pub fn status(&self) -> HttpStatus {
+        self.status
+    }
+
+    /// Set the response status.
+    pub fn set_status(&mut self, status: HttpStatus) {
+        self.status = status;
+    }
+
+    /// Get the HTTP version of the status line.
+    pub fn version(&self) -> HttpVersion {
+        self.protocal
+    }
+
+    /// Set the HTTP version of the status line.
+    pub(crate) fn set_version(&mut self, version: HttpVersion) {
+        self.protocal = version;
+    }
+
+    /// Get the value of a header, matching the key case-insensitively.
+    ///
+    /// For a header added several times with [`append_header`](Self::append_header) this is the
+    /// first value; [`header_values`](Self::header_values) returns all of them.
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.header_values(key).next()
+    }
+
+    /// Set a header, replacing any existing values for the same key.
+    pub fn insert_header(&mut self, key: impl Into<HeaderKey>, value: impl Into<HeaderValue>) {
+        insert_value(self.headers.get_or_insert_with(Vec::new), key, value);
+    }
+
+    /// Get every value of a header in the order they are sent, matching the key
+    /// case-insensitively.
+    pub fn header_values(&self, key: &str) -> impl Iterator<Item = &str> {
+        self.headers
+            .iter()
+            .flatten()
+            .filter(move |(k, _)| k.as_ref().eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_ref())
+    }
+
+    /// Add a header, keeping any existing values for the same key so it is sent several times.
+    pub fn append_header(&mut self, key: impl Into<HeaderKey>, value: impl Into<HeaderValue>) {
+        self.headers
+            .get_or_insert_with(Vec::new)
+            .push((key.into(), value.into()));
+    }
+
+    /// Add a cookie, sent in a `Set-Cookie` header of its own.
+    pub fn add_cookie(&mut self, cookie: Cookie) {
+        self.append_header("Set-Cookie", cookie.to_string());
+    }
+
+    /// Set the `ETag` header.
+    pub fn set_etag(&mut self, etag: ETag) {
+        self.insert_header("ETag", etag.to_string());
+    }
+
+    /// Set the `Last-Modified` header. The time is rounded down to whole seconds.
+    pub fn set_last_modified(&mut self, modified: SystemTime) {
+        self.insert_header("Last-Modified", httpdate::fmt_http_date(modified));
+    }
+
+    /// Remove a header, matching the key case-insensitively, and return its first value.
+    ///
+    /// Every value of a header added several times is removed.
+    pub fn remove_header(&mut self, key: &str) -> Option<HeaderValue> {
+        remove_values(self.headers.as_mut()?, key)
+    }
+
+    /// Add a header name to `Vary`, unless it is listed already or `Vary` is `*`.
+    pub fn append_vary(&mut self, name: &str) {
+        let vary = match self.header("Vary") {
+            Some(vary)
+                if vary.trim() == "*"
+                    || vary
+                        .split(',')
+                        .any(|listed| listed.trim().eq_ignore_ascii_case(name)) =>
+            {
+                return;
+            }
+            Some(vary) => format!("{}, {}", vary, name),
+            None => name.to_string(),
+        };
+        self.insert_header("Vary", vary);
+    }
+
+    /// Get the response body (if present and held in memory).
+    pub fn body(&self) -> Option<&[u8]> {
+        self.body.as_bytes()
+    }
+
+    /// Returns `true` if the body is produced by a stream.
+    pub fn is_streaming(&self) -> bool {
+        self.body.is_stream()
+    }
+
+    /// Get the pattern of the route whose handler produced this response.
+    ///
+    /// This is `None` for responses produced by middleware, layers or the server itself.
+    pub fn route(&self) -> Option<&'static str> {
+        self.route
+    }
+
+    /// Iterate over the response headers.
+    #[cfg(feature = "http2")]
+    pub(crate) fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .iter()
+            .flatten()
+            .map(|(key, value)| (key.as_ref(), value.as_ref()))
+    }
+
+    /// Take the body out of the response, leaving it empty.
+    pub fn take_body(&mut self) -> Body {
+        std::mem::take(&mut self.body)
+    }
+
+    /// Replace the response body.
+    pub fn set_body(&mut self, body: Body) {
+        self.body = body;
+    }
+
+    /// Take the callback taking over the connection after this response, if any.
+    pub(crate) fn take_upgrade(&mut self) -> Option<OnUpgrade> {
+        self.upgrade.take()
+    }
+
+    /// Record the pattern of the route that produced this response.
+    pub(crate) fn with_route(mut self, route: &'static str) -> Self {
+        self.route = Some(route);
+        self
+    }
+
     /// Serialize the HTTP response to a byte buffer, suitable for sending over the network.
-    pub fn serialize(self, mut buffer: Vec<u8>) -> Vec<u8> {
+    ///
+    /// Streaming bodies cannot be serialized up front; for those only the head is written.
+    pub fn serialize(self, buffer: Vec<u8>) -> Vec<u8> {
+        let (mut buffer, body) = self.serialize_head(buffer);
+        if let Body::Full(body) = body {
+            buffer.extend_from_slice(&body);
+        }
+        buffer
+    }
+
+    /// Serialize the status line and headers, returning them together with the body.
+    ///
+    /// Streaming bodies get a `Transfer-Encoding: chunked` header in place of any
+    /// `Content-Length`, except for HTTP/1.0 which has no chunked encoding.
+    pub(crate) fn serialize_head(mut self, mut buffer: Vec<u8>) -> (Vec<u8>, Body) {
+        if self.body.is_stream() && self.protocal != HttpVersion::OneZero {
+            let headers = self.headers.get_or_insert_with(Vec::new);
+            remove_values(headers, "Content-Length");
+            insert_value(headers, "Transfer-Encoding", "chunked");
+        }
+
         // Write status line
         write!(
             &mut buffer,
-            "{} {}\r\n",
+            "{} {} {}\r\n",
             <&str>::from(self.protocal),
-            format!("{} {}", u16::from(self.status), <&str>::from(self.status))
+            u16::from(self.status),
+            <&str>::from(self.status)
         )
         .unwrap();
 
@@ -239,11 +706,281 @@ impl HttpResponse {
         // End of headers
         write!(&mut buffer, "\r\n").unwrap();
 
-        // Write body if present
-        if let Some(body) = self.body {
-            write!(&mut buffer, "{}", body).unwrap();
+        (buffer, self.body)
+    }
+}
+
+/// Sets a header, replacing the first value for the same key in place and removing the others.
+fn insert_value(
+    headers: &mut Vec<(HeaderKey, HeaderValue)>,
+    key: impl Into<HeaderKey>,
+    value: impl Into<HeaderValue>,
+) {
+    let key = key.into();
+    let mut value = Some(value.into());
+    headers.retain_mut(|(k, v)| {
+        if *k != key {
+            return true;
         }
+        match value.take() {
+            Some(value) => {
+                *v = value;
+                true
+            }
+            None => false,
+        }
+    });
+    if let Some(value) = value {
+        headers.push((key, value));
+    }
+}
 
-        buffer
+/// Removes every value of a header, returning the first.
+fn remove_values(headers: &mut Vec<(HeaderKey, HeaderValue)>, key: &str) -> Option<HeaderValue> {
+    let key = HeaderKey::from(key);
+    let first = headers.iter().position(|(k, _)| *k == key)?;
+    let (_, value) = headers.remove(first);
+    headers.retain(|(k, _)| *k != key);
+    Some(value)
+}
+
+/// Conversion into an [`HttpResponse`].
+///
+/// Route handlers may return any type implementing this trait.
+pub trait IntoResponse {
+    /// Convert `self` into a response.
+    fn into_response(self) -> HttpResponse;
+}
+
+impl IntoResponse for HttpResponse {
+    fn into_response(self) -> HttpResponse {
+        self
+    }
+}
+
+impl IntoResponse for HttpResponseBuilder {
+    fn into_response(self) -> HttpResponse {
+        self.build()
+    }
+}
+
+/// An empty `200 OK` response.
+impl IntoResponse for () {
+    fn into_response(self) -> HttpResponse {
+        HttpResponseBuilder::ok().build()
+    }
+}
+
+/// A `200 OK` response with a UTF-8 plain text body.
+impl IntoResponse for String {
+    fn into_response(self) -> HttpResponse {
+        HttpResponseBuilder::ok()
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(self)
+            .build()
+    }
+}
+
+/// A `200 OK` response with a UTF-8 plain text body.
+impl IntoResponse for &'static str {
+    fn into_response(self) -> HttpResponse {
+        self.to_string().into_response()
+    }
+}
+
+/// A `200 OK` response with an `application/octet-stream` body.
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> HttpResponse {
+        HttpResponseBuilder::ok()
+            .header("Content-Type", "application/octet-stream")
+            .body_bytes(self)
+            .build()
+    }
+}
+
+/// The response for `T`, with its status replaced.
+impl<T: IntoResponse> IntoResponse for (HttpStatus, T) {
+    fn into_response(self) -> HttpResponse {
+        let mut response = self.1.into_response();
+        response.set_status(self.0);
+        response
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> HttpResponse {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+/// A `200 OK` response with an `application/json` body.
+///
+/// Values that fail to serialize produce `500 Internal Server Error`.
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> HttpResponse {
+        match serde_json::to_vec(&self.0) {
+            Ok(body) => HttpResponseBuilder::ok()
+                .header("Content-Type", "application/json")
+                .body_bytes(body)
+                .build(),
+            Err(err) => HttpResponseBuilder::internal_server_error()
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body(format!("Failed to serialize JSON response: {}", err))
+                .build(),
+        }
+    }
+}
+
+/// A `200 OK` response with the given body.
+impl IntoResponse for Body {
+    fn into_response(self) -> HttpResponse {
+        let mut response = HttpResponseBuilder::ok().build();
+        response.body = self;
+        response
+    }
+}
+
+/// An HTML response body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Html<T>(pub T);
+
+/// A `200 OK` response with a `text/html` body.
+impl<T: Into<String>> IntoResponse for Html<T> {
+    fn into_response(self) -> HttpResponse {
+        HttpResponseBuilder::ok()
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(self.0.into())
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialized(response: impl IntoResponse) -> String {
+        String::from_utf8(response.into_response().serialize(Vec::new())).unwrap()
+    }
+
+    #[test]
+    fn header_keys_are_case_insensitive() {
+        let response = HttpResponseBuilder::ok()
+            .header("content-type", "text/plain")
+            .header("Content-Type", "text/html")
+            .build();
+
+        assert_eq!(response.header("CONTENT-TYPE"), Some("text/html"));
+        assert_eq!(
+            serialized(response),
+            "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn appended_headers_are_sent_separately() {
+        let mut response = HttpResponseBuilder::ok()
+            .append_header("Link", "</a.css>; rel=preload")
+            .append_header("link", "</b.js>; rel=preload")
+            .build();
+        response.append_header("LINK", "</c.png>; rel=preload");
+
+        assert_eq!(
+            response.header_values("Link").collect::<Vec<_>>(),
+            [
+                "</a.css>; rel=preload",
+                "</b.js>; rel=preload",
+                "</c.png>; rel=preload"
+            ]
+        );
+        assert_eq!(response.header("link"), Some("</a.css>; rel=preload"));
+        assert_eq!(
+            serialized(response),
+            "HTTP/1.1 200 OK\r\nLink: </a.css>; rel=preload\r\nlink: </b.js>; rel=preload\r\n\
+             LINK: </c.png>; rel=preload\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn inserting_and_removing_replace_every_value() {
+        let mut response = HttpResponseBuilder::ok()
+            .append_header("Set-Cookie", "a=1")
+            .header("X-Kept", "yes")
+            .append_header("Set-Cookie", "b=2")
+            .build();
+
+        response.insert_header("set-cookie", "c=3");
+        assert_eq!(
+            response.header_values("Set-Cookie").collect::<Vec<_>>(),
+            ["c=3"]
+        );
+        assert_eq!(
+            serialized(
+                HttpResponseBuilder::ok()
+                    .append_header("Vary", "Origin")
+                    .header("X-Kept", "yes")
+                    .append_header("Vary", "Accept")
+                    .header("vary", "*")
+                    .build()
+            ),
+            "HTTP/1.1 200 OK\r\nVary: *\r\nX-Kept: yes\r\n\r\n"
+        );
+
+        response.append_header("Set-Cookie", "d=4");
+        assert_eq!(
+            response
+                .remove_header("SET-COOKIE")
+                .map(|value| value.as_ref().to_string()),
+            Some("c=3".to_string())
+        );
+        assert_eq!(response.header("Set-Cookie"), None);
+        assert_eq!(response.header("X-Kept"), Some("yes"));
+    }
+
+    #[test]
+    fn text_and_binary_responses() {
+        let response = "hi".into_response();
+        assert_eq!(response.status(), HttpStatus::Ok);
+        assert_eq!(response.body(), Some(&b"hi"[..]));
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+
+        let response = vec![0u8, 159, 146, 150].into_response();
+        assert_eq!(response.body(), Some(&[0u8, 159, 146, 150][..]));
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("application/octet-stream")
+        );
+
+        let response = ().into_response();
+        assert_eq!(response.status(), HttpStatus::Ok);
+        assert_eq!(response.body(), None);
+    }
+
+    #[test]
+    fn status_tuples_and_results() {
+        let response = (HttpStatus::NotFound, Html("<h1>gone</h1>")).into_response();
+        assert_eq!(response.status(), HttpStatus::NotFound);
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+
+        let result: Result<&'static str, (HttpStatus, String)> =
+            Err((HttpStatus::BadRequest, "nope".to_string()));
+        let response = result.into_response();
+        assert_eq!(response.status(), HttpStatus::BadRequest);
+        assert_eq!(response.body(), Some(&b"nope"[..]));
+    }
+
+    #[test]
+    fn json_response() {
+        let response = Json(vec![1, 2, 3]).into_response();
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert_eq!(response.body(), Some(&b"[1,2,3]"[..]));
     }
 }
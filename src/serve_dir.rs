@@ -0,0 +1,802 @@
+//! # Static Files
+//!
+//! [`ServeDir`] serves the files below a directory and [`ServeFile`] serves a single file. Both
+//! are [`Handler`]s, registered with
+//! [`HoochAppBuilder::add_handler`](crate::HoochAppBuilder::add_handler) like any other. A
+//! `ServeDir` is normally mounted on a route ending in a catch-all segment, whose value names the
+//! file relative to the directory.
+//!
+//! Files are served with:
+//!
+//! * a `Content-Type` guessed from the file extension,
+//! * `ETag` and `Last-Modified` validators, answering `If-None-Match` and `If-Modified-Since`
+//!   with `304 Not Modified`, and `If-Match` and `If-Unmodified-Since` with
+//!   `412 Precondition Failed`,
+//! * `Range` requests, honouring `If-Range`, answered with `206 Partial Content` (as
+//!   `multipart/byteranges` for several ranges) or `416 Range Not Satisfiable`,
+//! * optionally, precompressed `.br` and `.gz` sidecar files for clients accepting them.
+//!
+//! Requested paths are percent-decoded and may not leave the directory: `..` segments are
+//! rejected with `404 Not Found`, as are symbolic links pointing outside of it. Files are read
+//! from the local disk with blocking reads; small files are read at once and larger ones are
+//! streamed in chunks.
+//!
+//! Routes match a single method, so register the handler for `HEAD` as well to answer it.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hooch_http::{HoochAppBuilder, HttpMethod, ServeDir, ServeFile};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_handler("/favicon.ico", HttpMethod::GET, ServeFile::new("static/favicon.ico"));
+//! for method in [HttpMethod::GET, HttpMethod::HEAD] {
+//!     let assets = ServeDir::new("dist").precompressed_br().precompressed_gzip().spa_fallback();
+//!     app.add_handler("/{*path}", method, assets);
+//! }
+//! ```
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use futures::{
+    future::{self, BoxFuture},
+    stream, FutureExt, StreamExt,
+};
+
+use crate::{
+    body::{Body, BodyStream},
+    conditional::{evaluate_preconditions, ETag},
+    extract::percent_decode,
+    handler::Handler,
+    range::{self, requested_range, ByteRange, Multipart, RangeRequest},
+    request::{HttpRequest, Params},
+    response::{HttpResponse, HttpResponseBuilder},
+    shared::quality,
+    HttpMethod,
+};
+
+/// Files up to this size are read into memory at once; larger ones are streamed.
+const MAX_BUFFERED_FILE: u64 = 256 * 1024;
+
+/// Size of the chunks larger files are streamed in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Which precompressed sidecar files to look for.
+#[derive(Debug, Default, Copy, Clone)]
+struct Precompressed {
+    br: bool,
+    gzip: bool,
+}
+
+impl Precompressed {
+    fn any(self) -> bool {
+        self.br || self.gzip
+    }
+}
+
+/// Handler serving the files below a directory.
+///
+/// See the [module documentation](self) for how files are served.
+#[derive(Debug, Clone)]
+pub struct ServeDir {
+    root: PathBuf,
+    index: Option<String>,
+    spa_fallback: bool,
+    precompressed: Precompressed,
+}
+
+impl ServeDir {
+    /// Creates a handler serving the files below `root`, with `index.html` as the index file.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            index: Some("index.html".to_string()),
+            spa_fallback: false,
+            precompressed: Precompressed::default(),
+        }
+    }
+
+    /// Sets the file served for requests naming a directory.
+    pub fn index_file(mut self, name: impl Into<String>) -> Self {
+        self.index = Some(name.into());
+        self
+    }
+
+    /// Answers requests naming a directory with `404 Not Found` instead of an index file.
+    pub fn without_index(mut self) -> Self {
+        self.index = None;
+        self
+    }
+
+    /// Serves the index file at the root of the directory for paths that do not exist, so
+    /// client-side routes of a single-page application load the application.
+    pub fn spa_fallback(mut self) -> Self {
+        self.spa_fallback = true;
+        self
+    }
+
+    /// Serves `<file>.gz` in place of a file to clients accepting `gzip`, if it exists.
+    pub fn precompressed_gzip(mut self) -> Self {
+        self.precompressed.gzip = true;
+        self
+    }
+
+    /// Serves `<file>.br` in place of a file to clients accepting `br`, if it exists.
+    pub fn precompressed_br(mut self) -> Self {
+        self.precompressed.br = true;
+        self
+    }
+
+    /// Serves the file at `requested`, a percent-encoded path relative to the root.
+    fn serve(&self, req: &HttpRequest<'_>, requested: &str) -> HttpResponse {
+        let path = match self.resolve(requested) {
+            Some(path) => path,
+            None => return HttpResponseBuilder::not_found().build(),
+        };
+        let path = match self.index.as_ref() {
+            Some(index) if path.is_dir() => path.join(index),
+            _ => path,
+        };
+
+        match self.index.as_ref() {
+            Some(index) if self.spa_fallback && !path.is_file() => {
+                serve_file(req, &self.root.join(index), self.precompressed)
+            }
+            _ => serve_file(req, &path, self.precompressed),
+        }
+    }
+
+    /// Maps a requested path onto the file system, refusing paths that leave the root.
+    fn resolve(&self, requested: &str) -> Option<PathBuf> {
+        let requested = percent_decode(requested)?;
+        let mut path = self.root.clone();
+        for segment in requested.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return None,
+                segment if segment.contains(['\\', '\0', ':']) => return None,
+                segment => path.push(segment),
+            }
+        }
+
+        // Symbolic links may still point elsewhere.
+        if let Ok(canonical) = path.canonicalize() {
+            let root = self.root.canonicalize().ok()?;
+            if !canonical.starts_with(root) {
+                return None;
+            }
+        }
+        Some(path)
+    }
+}
+
+impl Handler<()> for ServeDir {
+    fn call(
+        &self,
+        req: HttpRequest<'static>,
+        params: Params<'static>,
+    ) -> BoxFuture<'static, HttpResponse> {
+        // The last path parameter, normally the catch-all segment, names the file.
+        let requested = params
+            .path_segment()
+            .pairs()
+            .last()
+            .and_then(|(_, value)| value)
+            .unwrap_or_default();
+        futures::future::ready(self.serve(&req, requested)).boxed()
+    }
+}
+
+/// Handler serving a single file.
+///
+/// See the [module documentation](self) for how the file is served.
+#[derive(Debug, Clone)]
+pub struct ServeFile {
+    path: PathBuf,
+    precompressed: Precompressed,
+}
+
+impl ServeFile {
+    /// Creates a handler serving the file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            precompressed: Precompressed::default(),
+        }
+    }
+
+    /// Serves `<file>.gz` in place of the file to clients accepting `gzip`, if it exists.
+    pub fn precompressed_gzip(mut self) -> Self {
+        self.precompressed.gzip = true;
+        self
+    }
+
+    /// Serves `<file>.br` in place of the file to clients accepting `br`, if it exists.
+    pub fn precompressed_br(mut self) -> Self {
+        self.precompressed.br = true;
+        self
+    }
+}
+
+impl Handler<()> for ServeFile {
+    fn call(
+        &self,
+        req: HttpRequest<'static>,
+        _params: Params<'static>,
+    ) -> BoxFuture<'static, HttpResponse> {
+        futures::future::ready(serve_file(&req, &self.path, self.precompressed)).boxed()
+    }
+}
+
+/// Serves a file, or a precompressed sidecar of it, answering conditional and range requests.
+fn serve_file(req: &HttpRequest<'_>, path: &Path, precompressed: Precompressed) -> HttpResponse {
+    let (file, metadata, encoding) = match open_variant(req, path, precompressed) {
+        Ok(opened) => opened,
+        Err(err) => return error_response(&err),
+    };
+    if !metadata.is_file() {
+        return HttpResponseBuilder::not_found().build();
+    }
+
+    let length = metadata.len();
+    // HTTP dates only carry whole seconds.
+    let modified = metadata.modified().ok().map(|modified| {
+        let secs = modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        UNIX_EPOCH + Duration::from_secs(secs)
+    });
+    let etag = match modified.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok()) {
+        Some(modified) => ETag::strong(format!("{:x}-{:x}", length, modified.as_secs())),
+        None => ETag::strong(format!("{:x}", length)),
+    };
+
+    let mut response = HttpResponseBuilder::ok()
+        .header("Content-Type", content_type(path))
+        .header("Accept-Ranges", "bytes")
+        .etag(etag.clone());
+    if let Some(modified) = modified {
+        response = response.last_modified(modified);
+    }
+    if let Some(encoding) = encoding {
+        response = response.header("Content-Encoding", encoding);
+    }
+    if precompressed.any() {
+        response = response.header("Vary", "Accept-Encoding");
+    }
+
+    if let Some(status) = evaluate_preconditions(req, Some(&etag), modified) {
+        let mut response = response.build();
+        response.set_status(status);
+        for header in ["Content-Type", "Accept-Ranges", "Content-Encoding"] {
+            response.remove_header(header);
+        }
+        return response;
+    }
+
+    let mut response = response.build();
+    let (ranges, multipart) = match requested_range(req, length, Some(&etag), modified) {
+        RangeRequest::Full => (
+            vec![ByteRange {
+                start: 0,
+                end: length,
+            }],
+            None,
+        ),
+        RangeRequest::Partial(ranges) => {
+            let multipart = range::into_partial(&mut response, &ranges, length);
+            (ranges, multipart)
+        }
+        RangeRequest::Unsatisfiable => return range::unsatisfiable(length),
+    };
+    with_body(req, response, file, ranges, multipart)
+}
+
+/// Opens the best precompressed variant of the file the client accepts, or the file itself.
+///
+/// Returns the file, its metadata and the content coding of the variant.
+fn open_variant(
+    req: &HttpRequest<'_>,
+    path: &Path,
+    precompressed: Precompressed,
+) -> io::Result<(File, fs::Metadata, Option<&'static str>)> {
+    let accept_encoding = req.headers().get("Accept-Encoding").unwrap_or_default();
+    let accepts = |coding| {
+        quality(accept_encoding, coding)
+            .or_else(|| quality(accept_encoding, "*"))
+            .unwrap_or(0)
+    };
+    let mut variants = Vec::new();
+    if precompressed.br && accepts("br") > 0 {
+        variants.push((accepts("br"), "br", "br"));
+    }
+    if precompressed.gzip && accepts("gzip") > 0 {
+        variants.push((accepts("gzip"), "gzip", "gz"));
+    }
+    // Prefer the higher quality, then brotli.
+    variants.sort_by_key(|(quality, _, _)| std::cmp::Reverse(*quality));
+
+    for (_, coding, extension) in variants {
+        let mut variant = path.as_os_str().to_owned();
+        variant.push(".");
+        variant.push(extension);
+        if let Ok(file) = File::open(&variant)
+            && let Ok(metadata) = file.metadata()
+            && metadata.is_file()
+        {
+            return Ok((file, metadata, Some(coding)));
+        }
+    }
+
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    Ok((file, metadata, None))
+}
+
+/// Attaches the bytes of the file within `ranges` to the response, framed as
+/// `multipart/byteranges` if `multipart` is given.
+///
+/// Responses to `HEAD` requests only get the matching `Content-Length`.
+fn with_body(
+    req: &HttpRequest<'_>,
+    mut response: HttpResponse,
+    mut file: File,
+    ranges: Vec<ByteRange>,
+    multipart: Option<Multipart>,
+) -> HttpResponse {
+    let length = match &multipart {
+        Some(multipart) => multipart.body_length(&ranges),
+        None => ranges.iter().map(ByteRange::len).sum(),
+    };
+    if req.method() == HttpMethod::HEAD {
+        response.insert_header("Content-Length", length.to_string());
+        return response;
+    }
+
+    if length <= MAX_BUFFERED_FILE {
+        let mut body = Vec::with_capacity(length as usize);
+        for range in &ranges {
+            if let Some(multipart) = &multipart {
+                body.extend_from_slice(&multipart.part_head(*range));
+            }
+            let read = file
+                .seek(SeekFrom::Start(range.start))
+                .and_then(|_| (&mut file).take(range.len()).read_to_end(&mut body));
+            if let Err(err) = read {
+                return error_response(&err);
+            }
+        }
+        if let Some(multipart) = &multipart {
+            body.extend_from_slice(&multipart.closing());
+        }
+        response.set_body(Body::Full(body));
+        return response;
+    }
+
+    let Some(multipart) = multipart else {
+        let range = ranges[0];
+        if let Err(err) = file.seek(SeekFrom::Start(range.start)) {
+            return error_response(&err);
+        }
+        response.set_body(Body::Stream(file_stream(file, range.len())));
+        return response;
+    };
+    // Each part reads from its own handle, positioned once the part is reached.
+    let closing = multipart.closing();
+    let parts = stream::iter(ranges).flat_map(move |range| {
+        let head = stream::once(future::ready(Ok(multipart.part_head(range))));
+        match file.try_clone() {
+            Ok(mut file) => match file.seek(SeekFrom::Start(range.start)) {
+                Ok(_) => head.chain(file_stream(file, range.len())).boxed(),
+                Err(err) => head.chain(stream::once(future::ready(Err(err)))).boxed(),
+            },
+            Err(err) => head.chain(stream::once(future::ready(Err(err)))).boxed(),
+        }
+    });
+    let body = parts.chain(stream::once(future::ready(Ok(closing))));
+    response.set_body(Body::Stream(body.boxed()));
+    response
+}
+
+/// Streams the next `length` bytes of the file in chunks.
+fn file_stream(file: File, length: u64) -> BodyStream {
+    stream::unfold((file, length), |(mut file, remaining)| async move {
+        if remaining == 0 {
+            return None;
+        }
+        let mut chunk = vec![0; remaining.min(CHUNK_SIZE as u64) as usize];
+        match file.read(&mut chunk) {
+            Ok(0) => Some((Err(io::ErrorKind::UnexpectedEof.into()), (file, 0))),
+            Ok(bytes_read) => {
+                chunk.truncate(bytes_read);
+                Some((Ok(chunk), (file, remaining - bytes_read as u64)))
+            }
+            Err(err) => Some((Err(err), (file, 0))),
+        }
+    })
+    .boxed()
+}
+
+/// The response to a file that could not be opened or read.
+fn error_response(err: &io::Error) -> HttpResponse {
+    match err.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => {
+            HttpResponseBuilder::not_found().build()
+        }
+        io::ErrorKind::PermissionDenied => HttpResponseBuilder::forbidden().build(),
+        _ => HttpResponseBuilder::internal_server_error().build(),
+    }
+}
+
+/// Guesses the `Content-Type` of a file from its extension.
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "txt" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, net::SocketAddr};
+
+    use super::*;
+    use crate::app::tests::{connect, dechunk, get, header, serve};
+
+    /// Creates a directory to serve, next to a file that must stay private.
+    fn fixture(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hooch-http-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let root = dir.join("public");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        fs::write(root.join("index.html"), "<h1>app</h1>").unwrap();
+        fs::write(root.join("sub/index.html"), "<h1>sub</h1>").unwrap();
+        fs::write(root.join("app.js"), "console.log('plain');").unwrap();
+        fs::write(root.join("app.js.gz"), "gzip bytes").unwrap();
+        fs::write(root.join("app.js.br"), "brotli bytes").unwrap();
+        fs::write(root.join("my file.txt"), "spaced").unwrap();
+        let big: Vec<u8> = (0..400 * 1024).map(|i| (i % 251) as u8).collect();
+        fs::write(root.join("big.bin"), big).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("secret.txt"), root.join("link.txt")).unwrap();
+        root
+    }
+
+    fn request(addr: SocketAddr, method: &str, path: &str, headers: &str) -> Vec<u8> {
+        let mut stream = connect(addr);
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+            method, path, headers
+        )
+        .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        response
+    }
+
+    fn serve_fixture(name: &str, serve_dir: impl Fn(ServeDir) -> ServeDir) -> SocketAddr {
+        let root = fixture(name);
+        let serve_dir = serve_dir(ServeDir::new(&root));
+        serve(move |app| {
+            app.add_handler("/{*path}", HttpMethod::GET, serve_dir.clone());
+            app.add_handler("/{*path}", HttpMethod::HEAD, serve_dir);
+        })
+    }
+
+    #[test]
+    fn serves_files_and_index() {
+        let addr = serve_fixture("files", |serve_dir| serve_dir);
+
+        let response = get(addr, "/app.js", "");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(
+            header(&response, "Content-Type"),
+            Some("text/javascript; charset=utf-8")
+        );
+        assert_eq!(header(&response, "Accept-Ranges"), Some("bytes"));
+        assert!(header(&response, "ETag").is_some());
+        assert!(header(&response, "Last-Modified").is_some());
+        assert!(response.ends_with("\r\n\r\nconsole.log('plain');"));
+
+        assert!(get(addr, "/", "").ends_with("<h1>app</h1>"));
+        assert!(get(addr, "/sub/", "").ends_with("<h1>sub</h1>"));
+        assert!(get(addr, "/sub", "").ends_with("<h1>sub</h1>"));
+        assert!(get(addr, "/my%20file.txt", "").ends_with("\r\n\r\nspaced"));
+        assert!(get(addr, "/missing.js", "").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let head = String::from_utf8(request(addr, "HEAD", "/app.js", "")).unwrap();
+        assert_eq!(header(&head, "Content-Length"), Some("21"));
+        assert!(head.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_root() {
+        let addr = serve_fixture("traversal", |serve_dir| serve_dir.spa_fallback());
+
+        for path in [
+            "/../secret.txt",
+            "/sub/../../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/..%5csecret.txt",
+        ] {
+            let response = get(addr, path, "");
+            assert!(
+                response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+                "{}",
+                path
+            );
+            assert!(!response.contains("secret"), "{}", path);
+        }
+        #[cfg(unix)]
+        assert!(get(addr, "/link.txt", "").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn spa_fallback_serves_the_root_index() {
+        let addr = serve_fixture("spa", |serve_dir| serve_dir.spa_fallback());
+        let response = get(addr, "/users/42/settings", "");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("<h1>app</h1>"));
+        assert!(get(addr, "/app.js", "").ends_with("console.log('plain');"));
+    }
+
+    #[test]
+    fn serve_file_serves_one_file() {
+        let file = ServeFile::new(fixture("single").join("sub/index.html"));
+        let addr = serve(move |app| app.add_handler("/page", HttpMethod::GET, file));
+        let response = get(addr, "/page", "");
+        assert_eq!(
+            header(&response, "Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert!(response.ends_with("<h1>sub</h1>"));
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let addr = serve_fixture("conditional", |serve_dir| serve_dir);
+        let response = get(addr, "/app.js", "");
+        let etag = header(&response, "ETag").unwrap();
+        let last_modified = header(&response, "Last-Modified").unwrap();
+
+        let response = get(
+            addr,
+            "/app.js",
+            &format!("If-None-Match: \"other\", {}\r\n", etag),
+        );
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert_eq!(header(&response, "ETag"), Some(etag));
+        assert!(header(&response, "Content-Length").is_none());
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = get(addr, "/app.js", &format!("If-None-Match: W/{}\r\n", etag));
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+
+        let response = get(
+            addr,
+            "/app.js",
+            &format!("If-Modified-Since: {}\r\n", last_modified),
+        );
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+
+        // `If-None-Match` takes precedence over `If-Modified-Since`.
+        let headers = format!(
+            "If-None-Match: \"other\"\r\nIf-Modified-Since: {}\r\n",
+            last_modified
+        );
+        assert!(get(addr, "/app.js", &headers).starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let response = get(addr, "/app.js", "If-Match: \"other\"\r\n");
+        assert!(response.starts_with("HTTP/1.1 412 Precondition Failed\r\n"));
+        let response = get(addr, "/app.js", &format!("If-Match: W/{}\r\n", etag));
+        assert!(response.starts_with("HTTP/1.1 412 Precondition Failed\r\n"));
+        let response = get(addr, "/app.js", &format!("If-Match: {}\r\n", etag));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let response = get(
+            addr,
+            "/app.js",
+            "If-Unmodified-Since: Sat, 01 Jan 2000 00:00:00 GMT\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 412 Precondition Failed\r\n"));
+    }
+
+    #[test]
+    fn range_requests() {
+        let addr = serve_fixture("range", |serve_dir| serve_dir);
+        let response = get(addr, "/app.js", "Range: bytes=0-6\r\n");
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 0-6/21"));
+        assert_eq!(header(&response, "Content-Length"), Some("7"));
+        assert!(response.ends_with("\r\n\r\nconsole"));
+
+        let response = get(addr, "/app.js", "Range: bytes=-3\r\n");
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 18-20/21"));
+        assert!(response.ends_with("\r\n\r\n');"));
+
+        let response = get(addr, "/app.js", "Range: bytes=15-100\r\n");
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 15-20/21"));
+
+        let response = get(addr, "/app.js", "Range: bytes=21-\r\n");
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert_eq!(header(&response, "Content-Range"), Some("bytes */21"));
+
+        // Malformed ranges and outdated `If-Range` validators get the whole file.
+        for headers in [
+            "Range: bytes=5-2\r\n",
+            "Range: items=0-1\r\n",
+            "Range: bytes=0-1\r\nIf-Range: \"outdated\"\r\n",
+        ] {
+            let response = get(addr, "/app.js", headers);
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", headers);
+        }
+        let etag = header(&get(addr, "/app.js", ""), "ETag")
+            .unwrap()
+            .to_string();
+        let response = get(
+            addr,
+            "/app.js",
+            &format!("Range: bytes=0-1\r\nIf-Range: {}\r\n", etag),
+        );
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+
+        // Large ranges are streamed.
+        let response = request(addr, "GET", "/big.bin", "Range: bytes=1000-\r\n");
+        let head_end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap()
+            + 4;
+        let head = String::from_utf8_lossy(&response[..head_end]);
+        assert_eq!(
+            header(&head, "Content-Range"),
+            Some("bytes 1000-409599/409600")
+        );
+        assert_eq!(header(&head, "Transfer-Encoding"), Some("chunked"));
+        let body = dechunk(&response[head_end..]);
+        let expected: Vec<u8> = (1000..400 * 1024).map(|i| (i % 251) as u8).collect();
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn multiple_ranges() {
+        let addr = serve_fixture("multirange", |serve_dir| serve_dir);
+        let response = get(addr, "/app.js", "Range: bytes=0-1, -3\r\n");
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert_eq!(header(&response, "Content-Range"), None);
+        let content_type = header(&response, "Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1;
+        assert_eq!(
+            body,
+            format!(
+                "\r\n--{b}\r\nContent-Type: text/javascript; charset=utf-8\r\n\
+                 Content-Range: bytes 0-1/21\r\n\r\nco\
+                 \r\n--{b}\r\nContent-Type: text/javascript; charset=utf-8\r\n\
+                 Content-Range: bytes 18-20/21\r\n\r\n');\
+                 \r\n--{b}--\r\n",
+                b = boundary
+            )
+        );
+        assert_eq!(
+            header(&response, "Content-Length"),
+            Some(body.len().to_string().as_str())
+        );
+
+        // Overlapping ranges are merged.
+        let response = get(addr, "/app.js", "Range: bytes=4-8, 0-5\r\n");
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 0-8/21"));
+        assert!(response.ends_with("\r\n\r\nconsole.l"));
+
+        // Large multipart bodies are streamed.
+        let response = request(addr, "GET", "/big.bin", "Range: bytes=10-19, 100000-\r\n");
+        let head_end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap()
+            + 4;
+        let head = String::from_utf8_lossy(&response[..head_end]);
+        assert_eq!(header(&head, "Transfer-Encoding"), Some("chunked"));
+        let boundary = header(&head, "Content-Type")
+            .unwrap()
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+        let mut expected = Vec::new();
+        for (start, end) in [(10, 20), (100000, 409600)] {
+            expected.extend_from_slice(
+                format!(
+                    "\r\n--{}\r\nContent-Type: application/octet-stream\r\n\
+                     Content-Range: bytes {}-{}/409600\r\n\r\n",
+                    boundary,
+                    start,
+                    end - 1
+                )
+                .as_bytes(),
+            );
+            expected.extend((start..end).map(|i| (i % 251) as u8));
+        }
+        expected.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+        assert_eq!(dechunk(&response[head_end..]), expected);
+    }
+
+    #[test]
+    fn precompressed_sidecars() {
+        let addr = serve_fixture("precompressed", |serve_dir| {
+            serve_dir.precompressed_br().precompressed_gzip()
+        });
+
+        let response = get(addr, "/app.js", "Accept-Encoding: gzip, br\r\n");
+        assert_eq!(header(&response, "Content-Encoding"), Some("br"));
+        assert_eq!(
+            header(&response, "Content-Type"),
+            Some("text/javascript; charset=utf-8")
+        );
+        assert_eq!(header(&response, "Vary"), Some("Accept-Encoding"));
+        assert!(response.ends_with("\r\n\r\nbrotli bytes"));
+
+        let response = get(addr, "/app.js", "Accept-Encoding: gzip, br;q=0.5\r\n");
+        assert_eq!(header(&response, "Content-Encoding"), Some("gzip"));
+        assert!(response.ends_with("\r\n\r\ngzip bytes"));
+
+        let response = get(addr, "/app.js", "Accept-Encoding: identity\r\n");
+        assert_eq!(header(&response, "Content-Encoding"), None);
+        assert_eq!(header(&response, "Vary"), Some("Accept-Encoding"));
+        assert!(response.ends_with("\r\n\r\nconsole.log('plain');"));
+
+        // Files without a sidecar are served as is.
+        let response = get(addr, "/", "Accept-Encoding: br\r\n");
+        assert_eq!(header(&response, "Content-Encoding"), None);
+        assert!(response.ends_with("<h1>app</h1>"));
+    }
+
+    #[test]
+    fn helpers() {
+        assert_eq!(content_type(Path::new("a/b.WOFF2")), "font/woff2");
+        assert_eq!(
+            content_type(Path::new("README")),
+            "application/octet-stream"
+        );
+    }
+}
@@ -0,0 +1,768 @@
+//! # Sessions
+//!
+//! The [`SessionLayer`] keeps server-side state per client. It reads the session id from a
+//! cookie, loads the session from a [`SessionStore`] and hands it to handlers as a [`Session`],
+//! available as an extractor or from the request's extensions. Values are stored as JSON, so any
+//! type implementing `serde`'s traits can be read and written.
+//!
+//! A session is saved, and its cookie set, only when a handler modified it; every save extends
+//! its lifetime by the configured time to live. New sessions get an id when they are first
+//! saved. Call [`Session::rotate_id`] whenever the privileges of a session change, such as on
+//! login, so that an id obtained earlier, possibly planted by an attacker, becomes worthless.
+//! [`Session::destroy`] removes the session and its cookie, as on logout.
+//!
+//! Two stores are included: [`MemoryStore`], which keeps sessions for the lifetime of the
+//! process, and [`FileStore`], which keeps one file per session in a directory and accesses it
+//! from a thread of its own, so the runtime is never blocked on the disk. Other backends
+//! implement [`SessionStore`].
+//!
+//! ## Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use hooch_http::{HoochAppBuilder, HttpMethod, MemoryStore, Session, SessionLayer};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_layer(SessionLayer::new(MemoryStore::new()).ttl(Duration::from_secs(3600)));
+//!
+//! app.add_handler("/login", HttpMethod::POST, |session: Session| async move {
+//!     session.rotate_id();
+//!     session.insert("user_id", 42u64).unwrap();
+//!     "logged in"
+//! });
+//! app.add_handler("/me", HttpMethod::GET, |session: Session| async move {
+//!     match session.get::<u64>("user_id") {
+//!         Some(user_id) => format!("user {}", user_id),
+//!         None => "anonymous".to_string(),
+//!     }
+//! });
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures::{
+    channel::oneshot,
+    future::{self, BoxFuture},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    cookie::{Cookie, SameSite},
+    extract::FromRequest,
+    layer::{Layer, Next},
+    request::{HttpRequest, Params},
+    response::{HttpResponse, HttpResponseBuilder},
+};
+
+/// Length of a session id: 32 random bytes, base64url encoded.
+const ID_LEN: usize = 43;
+
+/// A stored session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionRecord {
+    /// The values of the session.
+    pub data: Map<String, Value>,
+    /// When the session expires. Stores need not return expired sessions.
+    pub expires: SystemTime,
+}
+
+impl SessionRecord {
+    /// Returns `true` if the session has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires <= SystemTime::now()
+    }
+}
+
+/// Storage backend for sessions, keyed by session id.
+///
+/// Ids consist of the characters `A-Z`, `a-z`, `0-9`, `-` and `_` only.
+pub trait SessionStore: Send + Sync + 'static {
+    /// Loads a session, resolving to `None` if it does not exist.
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<Option<SessionRecord>>>;
+
+    /// Creates or replaces a session.
+    fn store<'a>(&'a self, id: &'a str, record: &'a SessionRecord)
+        -> BoxFuture<'a, io::Result<()>>;
+
+    /// Removes a session, succeeding if it does not exist.
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>>;
+}
+
+/// Session store keeping sessions in memory.
+///
+/// Clones share the same sessions. Expired sessions are dropped when loaded or by
+/// [`remove_expired`](Self::remove_expired).
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    sessions: Arc<Mutex<HashMap<String, SessionRecord>>>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of stored sessions, including expired ones not yet removed.
+    pub fn len(&self) -> usize {
+        self.sessions().len()
+    }
+
+    /// Returns `true` if no sessions are stored.
+    pub fn is_empty(&self) -> bool {
+        self.sessions().is_empty()
+    }
+
+    /// Removes all expired sessions.
+    pub fn remove_expired(&self) {
+        self.sessions().retain(|_, record| !record.is_expired());
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, HashMap<String, SessionRecord>> {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<Option<SessionRecord>>> {
+        let mut sessions = self.sessions();
+        let record = match sessions.get(id) {
+            Some(record) if record.is_expired() => {
+                sessions.remove(id);
+                None
+            }
+            record => record.cloned(),
+        };
+        Box::pin(future::ready(Ok(record)))
+    }
+
+    fn store<'a>(
+        &'a self,
+        id: &'a str,
+        record: &'a SessionRecord,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        self.sessions().insert(id.to_string(), record.clone());
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        self.sessions().remove(id);
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+/// Session store keeping each session in a JSON file named after its id.
+///
+/// The [`SessionStore`] methods read and write the files on a thread spawned for each call, so
+/// slow disks do not stall the runtime. Expired sessions are deleted when loaded or by
+/// [`remove_expired`](Self::remove_expired), which blocks the calling thread.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Creates a store in `dir`, creating the directory if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Deletes the files of all expired sessions, returning how many were deleted.
+    ///
+    /// This blocks until the whole directory has been checked; call it from a thread of its own
+    /// rather than from a handler.
+    pub fn remove_expired(&self) -> io::Result<usize> {
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let Some(id) = path
+                .file_name()
+                .and_then(|name| name.to_str()?.strip_suffix(".json"))
+            else {
+                continue;
+            };
+            if is_valid_id(id) && self.read(id)?.is_some_and(|record| record.is_expired()) {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        if !is_valid_id(id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid session id",
+            ));
+        }
+        Ok(self.dir.join(format!("{}.json", id)))
+    }
+
+    fn read(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        let contents = match fs::read(self.path(id)?) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid session file");
+        let mut file: Map<String, Value> =
+            serde_json::from_slice(&contents).map_err(|_| invalid())?;
+        let expires = file
+            .get("expires")
+            .and_then(Value::as_u64)
+            .ok_or_else(invalid)?;
+        let Some(Value::Object(data)) = file.remove("data") else {
+            return Err(invalid());
+        };
+        Ok(Some(SessionRecord {
+            data,
+            expires: UNIX_EPOCH + Duration::from_secs(expires),
+        }))
+    }
+
+    fn remove_file(&self, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(id)?) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn write(&self, id: &str, record: &SessionRecord) -> io::Result<()> {
+        let path = self.path(id)?;
+        let expires = record
+            .expires
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let contents = serde_json::json!({ "expires": expires, "data": record.data });
+        // Write a temporary file first, so readers never see a partial session.
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, contents.to_string())?;
+        fs::rename(&temporary, &path)
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<Option<SessionRecord>>> {
+        let (store, id) = (self.clone(), id.to_string());
+        blocking(move || {
+            store.read(&id).and_then(|record| match record {
+                Some(record) if record.is_expired() => store.remove_file(&id).map(|_| None),
+                record => Ok(record),
+            })
+        })
+    }
+
+    fn store<'a>(
+        &'a self,
+        id: &'a str,
+        record: &'a SessionRecord,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        let (store, id, record) = (self.clone(), id.to_string(), record.clone());
+        blocking(move || store.write(&id, &record))
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        let (store, id) = (self.clone(), id.to_string());
+        blocking(move || store.remove_file(&id))
+    }
+}
+
+/// Runs blocking file system work on a thread of its own and waits for its result.
+fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> BoxFuture<'static, io::Result<T>> {
+    let (result_tx, result_rx) = oneshot::channel();
+    let spawned = std::thread::Builder::new()
+        .name("hooch-http-session".to_string())
+        .spawn(move || {
+            let _ = result_tx.send(work());
+        });
+    Box::pin(async move {
+        spawned?;
+        result_rx
+            .await
+            .unwrap_or_else(|_| Err(io::Error::other("session file operation panicked")))
+    })
+}
+
+/// The session of the current request.
+///
+/// Clones refer to the same session. Changes are saved after the handler returns; see the
+/// [module documentation](self).
+#[derive(Clone)]
+pub struct Session {
+    inner: Arc<Mutex<SessionState>>,
+}
+
+/// What happened to a session while handling a request.
+#[derive(Debug, Default)]
+struct SessionState {
+    /// The id the session was loaded with, `None` for new sessions.
+    id: Option<String>,
+    data: Map<String, Value>,
+    modified: bool,
+    rotate: bool,
+    destroyed: bool,
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state();
+        f.debug_struct("Session")
+            .field("data", &state.data)
+            .field("modified", &state.modified)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Session {
+    fn new(id: Option<String>, data: Map<String, Value>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(SessionState {
+                id,
+                data,
+                ..SessionState::default()
+            })),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, SessionState> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Get a value, or `None` if it is missing or not a `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.state().data.get(key)?.clone();
+        serde_json::from_value(value).ok()
+    }
+
+    /// Set a value, replacing any previous value for `key`.
+    pub fn insert<T: Serialize>(&self, key: impl Into<String>, value: T) -> serde_json::Result<()> {
+        let value = serde_json::to_value(value)?;
+        let mut state = self.state();
+        state.data.insert(key.into(), value);
+        state.modified = true;
+        Ok(())
+    }
+
+    /// Remove a value, returning `true` if it was present.
+    pub fn remove(&self, key: &str) -> bool {
+        let mut state = self.state();
+        let removed = state.data.remove(key).is_some();
+        state.modified |= removed;
+        removed
+    }
+
+    /// Remove all values, keeping the session itself.
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.modified |= !state.data.is_empty();
+        state.data.clear();
+    }
+
+    /// Returns `true` if the session has no values.
+    pub fn is_empty(&self) -> bool {
+        self.state().data.is_empty()
+    }
+
+    /// Gives the session a new id when it is saved, removing the old one from the store.
+    pub fn rotate_id(&self) {
+        let mut state = self.state();
+        state.rotate = true;
+        state.modified = true;
+    }
+
+    /// Removes the session from the store and tells the client to delete its cookie.
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.data.clear();
+        state.destroyed = true;
+    }
+}
+
+impl FromRequest for Session {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        _params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        req.extensions().get::<Session>().cloned().ok_or_else(|| {
+            HttpResponseBuilder::internal_server_error()
+                .body("Sessions require the `SessionLayer`".into())
+                .build()
+        })
+    }
+}
+
+/// Layer loading and saving the [`Session`] of each request.
+///
+/// See the [module documentation](self) for how sessions are handled.
+#[derive(Clone)]
+pub struct SessionLayer {
+    store: Arc<dyn SessionStore>,
+    cookie_name: String,
+    ttl: Duration,
+    path: String,
+    domain: Option<String>,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl fmt::Debug for SessionLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionLayer")
+            .field("cookie_name", &self.cookie_name)
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionLayer {
+    /// Creates a layer keeping sessions in `store` for a day, with their id in an `HttpOnly`,
+    /// `SameSite=Lax` cookie named `session` sent for every path.
+    pub fn new(store: impl SessionStore) -> Self {
+        Self {
+            store: Arc::new(store),
+            cookie_name: "session".to_string(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            path: "/".to_string(),
+            domain: None,
+            secure: false,
+            same_site: SameSite::Lax,
+        }
+    }
+
+    /// Sets the name of the session cookie.
+    pub fn cookie_name(mut self, name: impl Into<String>) -> Self {
+        self.cookie_name = name.into();
+        self
+    }
+
+    /// Sets how long a session lives after it was last saved.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the path the session cookie is sent for.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Sets the domain the session cookie is sent to.
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Sets whether the session cookie is only sent over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Sets the `SameSite` attribute of the session cookie.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    fn cookie(&self, value: impl Into<String>) -> Cookie {
+        let cookie = Cookie::new(self.cookie_name.clone(), value)
+            .path(self.path.clone())
+            .secure(self.secure)
+            .http_only(true)
+            .same_site(self.same_site);
+        match &self.domain {
+            Some(domain) => cookie.domain(domain.clone()),
+            None => cookie,
+        }
+    }
+
+    /// Loads the session named by the request's cookie, or starts a new one.
+    async fn load(&self, req: &HttpRequest<'static>) -> io::Result<Session> {
+        let Some(id) = req
+            .cookies()
+            .get(&self.cookie_name)
+            .filter(|id| is_valid_id(id))
+        else {
+            return Ok(Session::new(None, Map::new()));
+        };
+        Ok(match self.store.load(id).await? {
+            Some(record) if !record.is_expired() => Session::new(Some(id.to_string()), record.data),
+            _ => Session::new(None, Map::new()),
+        })
+    }
+
+    /// Saves or removes the session as the handler left it, setting the cookie accordingly.
+    async fn save(&self, session: &Session, response: &mut HttpResponse) -> io::Result<()> {
+        let (old_id, new_id, record) = {
+            let mut state = session.state();
+            if state.destroyed {
+                (state.id.take(), None, None)
+            } else if !state.modified {
+                return Ok(());
+            } else {
+                let old_id = if state.rotate { state.id.take() } else { None };
+                let id = state.id.get_or_insert_with(generate_id).clone();
+                let record = SessionRecord {
+                    data: state.data.clone(),
+                    expires: SystemTime::now() + self.ttl,
+                };
+                (old_id, Some(id), Some(record))
+            }
+        };
+
+        if let Some(old_id) = &old_id {
+            self.store.remove(old_id).await?;
+        }
+        match (new_id, record) {
+            (Some(id), Some(record)) => {
+                self.store.store(&id, &record).await?;
+                response.add_cookie(self.cookie(id).max_age(self.ttl));
+            }
+            _ => {
+                let removal = self.cookie("").max_age(Duration::ZERO).expires(UNIX_EPOCH);
+                response.add_cookie(removal);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Layer for SessionLayer {
+    fn call<'a>(
+        &'a self,
+        mut req: HttpRequest<'static>,
+        _socket_addr: SocketAddr,
+        next: Next,
+    ) -> BoxFuture<'a, HttpResponse> {
+        Box::pin(async move {
+            let session = match self.load(&req).await {
+                Ok(session) => session,
+                Err(_err) => return session_error(_err),
+            };
+            req.extensions_mut().insert(session.clone());
+
+            let mut response = next.run(req).await;
+            match self.save(&session, &mut response).await {
+                Ok(()) => response,
+                Err(_err) => session_error(_err),
+            }
+        })
+    }
+}
+
+/// The response to a failing session store.
+fn session_error(_err: io::Error) -> HttpResponse {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(error = %_err, "session store failed");
+    HttpResponseBuilder::internal_server_error().build()
+}
+
+/// Creates a random session id.
+fn generate_id() -> String {
+    let mut bytes = [0; 32];
+    getrandom::fill(&mut bytes).expect("the operating system provides randomness");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn is_valid_id(id: &str) -> bool {
+    id.len() == ID_LEN
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::{
+        app::tests::{connect, read_response, serve},
+        HttpMethod,
+    };
+
+    fn session_cookie(response: &str) -> Option<&str> {
+        response
+            .lines()
+            .find_map(|line| line.strip_prefix("Set-Cookie: session="))
+    }
+
+    fn session_id(response: &str) -> &str {
+        session_cookie(response).unwrap().split(';').next().unwrap()
+    }
+
+    fn exchange(addr: SocketAddr, method: &str, path: &str, cookie: Option<&str>) -> String {
+        let mut stream = connect(addr);
+        let cookie = cookie.map_or(String::new(), |id| format!("Cookie: session={}\r\n", id));
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n{}\r\n",
+            method, path, cookie
+        )
+        .unwrap();
+        read_response(&mut stream)
+    }
+
+    fn serve_sessions(store: impl SessionStore) -> SocketAddr {
+        let layer = SessionLayer::new(store).ttl(Duration::from_secs(60));
+        serve(move |app| {
+            app.add_layer(layer);
+            app.add_handler("/login", HttpMethod::POST, |session: Session| async move {
+                session.rotate_id();
+                session.insert("user", "bart").unwrap();
+                "ok"
+            });
+            app.add_handler("/count", HttpMethod::POST, |session: Session| async move {
+                let count = session.get::<u32>("count").unwrap_or(0) + 1;
+                session.insert("count", count).unwrap();
+                count.to_string()
+            });
+            app.add_handler("/me", HttpMethod::GET, |session: Session| async move {
+                session
+                    .get::<String>("user")
+                    .unwrap_or_else(|| "anonymous".into())
+            });
+            app.add_handler("/logout", HttpMethod::POST, |session: Session| async move {
+                session.destroy();
+                "bye"
+            });
+        })
+    }
+
+    #[test]
+    fn sessions_are_saved_only_when_modified() {
+        let store = MemoryStore::new();
+        let addr = serve_sessions(store.clone());
+
+        let response = exchange(addr, "GET", "/me", None);
+        assert!(response.ends_with("anonymous"));
+        assert_eq!(session_cookie(&response), None);
+        assert!(store.is_empty());
+
+        let response = exchange(addr, "POST", "/count", None);
+        assert!(response.ends_with("\r\n\r\n1"));
+        assert_eq!(
+            session_cookie(&response)
+                .unwrap()
+                .split_once(';')
+                .unwrap()
+                .1,
+            " Path=/; Max-Age=60; HttpOnly; SameSite=Lax"
+        );
+        let id = session_id(&response).to_string();
+        assert_eq!(id.len(), ID_LEN);
+        assert_eq!(store.len(), 1);
+
+        let response = exchange(addr, "POST", "/count", Some(&id));
+        assert!(response.ends_with("\r\n\r\n2"));
+        assert_eq!(session_id(&response), id);
+
+        // Reading does not save, and unknown ids start a new session.
+        assert_eq!(
+            session_cookie(&exchange(addr, "GET", "/me", Some(&id))),
+            None
+        );
+        let unknown = "A".repeat(ID_LEN);
+        let response = exchange(addr, "POST", "/count", Some(&unknown));
+        assert!(response.ends_with("\r\n\r\n1"));
+        assert_ne!(session_id(&response), unknown);
+        assert!(exchange(addr, "POST", "/count", Some("../../etc/passwd")).ends_with("\r\n\r\n1"));
+    }
+
+    #[test]
+    fn login_rotates_and_logout_destroys() {
+        let store = MemoryStore::new();
+        let addr = serve_sessions(store.clone());
+
+        let before = session_id(&exchange(addr, "POST", "/count", None)).to_string();
+        let response = exchange(addr, "POST", "/login", Some(&before));
+        let after = session_id(&response).to_string();
+        assert_ne!(before, after);
+        assert_eq!(store.len(), 1);
+        assert!(exchange(addr, "GET", "/me", Some(&before)).ends_with("anonymous"));
+        assert!(exchange(addr, "GET", "/me", Some(&after)).ends_with("bart"));
+
+        let response = exchange(addr, "POST", "/logout", Some(&after));
+        assert_eq!(
+            session_cookie(&response),
+            Some("; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT; HttpOnly; SameSite=Lax")
+        );
+        assert!(store.is_empty());
+        assert!(exchange(addr, "GET", "/me", Some(&after)).ends_with("anonymous"));
+    }
+
+    #[test]
+    fn stores_expire_sessions() {
+        let dir = std::env::temp_dir().join(format!("hooch-http-sessions-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let file_store = FileStore::new(&dir).unwrap();
+        let stores: [&dyn SessionStore; 2] = [&MemoryStore::new(), &file_store];
+
+        let mut data = Map::new();
+        data.insert("user".into(), "bart".into());
+        // Files keep whole seconds.
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let live = SessionRecord {
+            data,
+            expires: UNIX_EPOCH + Duration::from_secs(now.as_secs() + 60),
+        };
+        let expired = SessionRecord {
+            data: Map::new(),
+            expires: UNIX_EPOCH + Duration::from_secs(1),
+        };
+        let (live_id, expired_id) = (generate_id(), generate_id());
+
+        for store in stores {
+            futures::executor::block_on(async {
+                store.store(&live_id, &live).await.unwrap();
+                store.store(&expired_id, &expired).await.unwrap();
+                assert_eq!(store.load(&live_id).await.unwrap(), Some(live.clone()));
+                assert_eq!(store.load(&expired_id).await.unwrap(), None);
+                assert_eq!(store.load(&generate_id()).await.unwrap(), None);
+                store.remove(&live_id).await.unwrap();
+                store.remove(&live_id).await.unwrap();
+                assert_eq!(store.load(&live_id).await.unwrap(), None);
+            });
+        }
+
+        futures::executor::block_on(file_store.store(&expired_id, &expired)).unwrap();
+        assert_eq!(file_store.remove_expired().unwrap(), 1);
+        assert!(futures::executor::block_on(file_store.load("../secret")).is_err());
+
+        let addr = serve_sessions(file_store);
+        let id = session_id(&exchange(addr, "POST", "/login", None)).to_string();
+        assert!(dir.join(format!("{}.json", id)).is_file());
+        assert!(exchange(addr, "GET", "/me", Some(&id)).ends_with("bart"));
+    }
+
+    #[test]
+    fn blocking_work_runs_on_another_thread() {
+        let caller = std::thread::current().id();
+        let worker = futures::executor::block_on(blocking(|| Ok(std::thread::current().id())));
+        assert_ne!(worker.unwrap(), caller);
+
+        let panicked = futures::executor::block_on(blocking::<()>(|| panic!("disk on fire")));
+        assert!(panicked.is_err());
+    }
+}
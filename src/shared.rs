@@ -1,24 +1,34 @@
 //! Defines the supported HTTP versions and conversion logic.
 //!
 //! This module provides the `HttpVersion` enum to represent HTTP protocol versions
-//! and implements conversions from raw bytes and to string representations.
+//! and implements conversions from raw bytes and to string representations, along with helpers
+//! for header values shared across the crate.
+
+use crate::{extract::percent_decode, request::ParseError};
 
 /// Represents the supported HTTP protocol versions.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum HttpVersion {
+    /// HTTP version 1.0
+    OneZero,
     /// HTTP version 1.1
     OnePointOne,
+    /// HTTP version 2
+    Two,
 }
 
-impl From<&[u8]> for HttpVersion {
-    /// Converts a byte slice into an `HttpVersion` enum.
+impl TryFrom<&[u8]> for HttpVersion {
+    type Error = ParseError;
+
+    /// Converts the version of an HTTP/1 request line into an `HttpVersion` enum.
     ///
-    /// # Panics
-    /// Panics if the provided byte slice does not correspond to a supported HTTP version.
-    fn from(value: &[u8]) -> Self {
+    /// Fails with [`ParseError::UnsupportedVersion`] for anything but `HTTP/1.0` and `HTTP/1.1`.
+    /// HTTP/2 has no request line; its requests are only ever built from HTTP/2 frames.
+    fn try_from(value: &[u8]) -> Result<Self, ParseError> {
         match value {
-            b"HTTP/1.1" => HttpVersion::OnePointOne,
-            _ => panic!("Unsupported version"),
+            b"HTTP/1.0" => Ok(HttpVersion::OneZero),
+            b"HTTP/1.1" => Ok(HttpVersion::OnePointOne),
+            _ => Err(ParseError::UnsupportedVersion),
         }
     }
 }
@@ -27,7 +37,9 @@ impl From<HttpVersion> for &'static str {
     /// Converts an `HttpVersion` enum into its corresponding string representation.
     fn from(value: HttpVersion) -> Self {
         match value {
+            HttpVersion::OneZero => "HTTP/1.0",
             HttpVersion::OnePointOne => "HTTP/1.1",
+            HttpVersion::Two => "HTTP/2",
         }
     }
 }
@@ -42,20 +54,162 @@ pub enum HttpMethod {
     PUT,
     PATCH,
     DELETE,
+    CONNECT,
 }
 
-impl From<&[u8]> for HttpMethod {
+impl TryFrom<&[u8]> for HttpMethod {
+    type Error = ParseError;
+
     /// Convert raw bytes (e.g., b"GET") to an `HttpMethod` enum variant.
-    fn from(value: &[u8]) -> Self {
+    ///
+    /// Fails with [`ParseError::UnknownMethod`] for any other method.
+    fn try_from(value: &[u8]) -> Result<Self, ParseError> {
+        match value {
+            b"GET" => Ok(HttpMethod::GET),
+            b"HEAD" => Ok(HttpMethod::HEAD),
+            b"OPTIONS" => Ok(HttpMethod::OPTIONS),
+            b"POST" => Ok(HttpMethod::POST),
+            b"PUT" => Ok(HttpMethod::PUT),
+            b"PATCH" => Ok(HttpMethod::PATCH),
+            b"DELETE" => Ok(HttpMethod::DELETE),
+            b"CONNECT" => Ok(HttpMethod::CONNECT),
+            _ => Err(ParseError::UnknownMethod),
+        }
+    }
+}
+
+impl From<HttpMethod> for &'static str {
+    /// Converts an `HttpMethod` enum into its corresponding string representation.
+    fn from(value: HttpMethod) -> Self {
         match value {
-            b"GET" => HttpMethod::GET,
-            b"HEAD" => HttpMethod::HEAD,
-            b"OPTIONS" => HttpMethod::OPTIONS,
-            b"POST" => HttpMethod::POST,
-            b"PUT" => HttpMethod::PUT,
-            b"PATCH" => HttpMethod::PATCH,
-            b"DELETE" => HttpMethod::DELETE,
-            _ => panic!("Unknown HTTP method"),
+            HttpMethod::GET => "GET",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::OPTIONS => "OPTIONS",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::CONNECT => "CONNECT",
         }
     }
 }
+
+/// Returns `true` if the request path `path` lies under `prefix`, matching whole path segments
+/// only.
+///
+/// `/api` covers `/api` and `/api/users` but not `/apiary`; a prefix ending in `/`, such as
+/// `/api/`, only covers paths below it. The path is compared in the form [`ServeDir`] and
+/// routes would resolve it to, so `/%61pi` and `//api/./users` are under `/api` as well. Paths
+/// that cannot be normalised, such as ones with `..` segments, are under every prefix, so that
+/// layers guarding a prefix fail closed.
+///
+/// [`ServeDir`]: crate::ServeDir
+pub(crate) fn has_path_prefix(path: &str, prefix: &str) -> bool {
+    let Some(path) = normalize_path(path) else {
+        return true;
+    };
+    match path.strip_prefix(prefix) {
+        Some(rest) => prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Percent-decodes `path` and drops its empty and `.` segments, keeping a trailing slash.
+///
+/// Returns `None` for malformed escapes and `..` segments.
+fn normalize_path(path: &str) -> Option<String> {
+    let decoded = percent_decode(path)?;
+    let mut normalized = String::with_capacity(decoded.len());
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            segment => {
+                normalized.push('/');
+                normalized.push_str(segment);
+            }
+        }
+    }
+    if normalized.is_empty() || decoded.ends_with('/') {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+/// Returns the quality value, in thousandths, that an `Accept-*` style header gives `token`.
+///
+/// Returns `None` if the token is not listed. Unparseable quality values count as zero.
+pub(crate) fn quality(header: &str, token: &str) -> Option<u16> {
+    header.split(',').find_map(|item| {
+        let mut params = item.split(';');
+        if !params.next()?.trim().eq_ignore_ascii_case(token) {
+            return None;
+        }
+        let quality = params
+            .find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                name.trim().eq_ignore_ascii_case("q").then(|| value.trim())
+            })
+            .map_or(1000, parse_quality);
+        Some(quality)
+    })
+}
+
+/// Parses a quality value such as `0.8` into thousandths.
+fn parse_quality(value: &str) -> u16 {
+    let (int, frac) = value.split_once('.').unwrap_or((value, ""));
+    if frac.len() > 3 || !frac.bytes().all(|byte| byte.is_ascii_digit()) {
+        return 0;
+    }
+    let frac = format!("{:0<3}", frac).parse::<u16>().unwrap_or(0);
+    match int {
+        "0" => frac,
+        "1" if frac == 0 => 1000,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_prefixes_match_whole_segments() {
+        assert!(has_path_prefix("/api", "/api"));
+        assert!(has_path_prefix("/api/users", "/api"));
+        assert!(!has_path_prefix("/apiary", "/api"));
+        assert!(!has_path_prefix("/ap", "/api"));
+        assert!(has_path_prefix("/api/users", "/api/"));
+        assert!(!has_path_prefix("/api", "/api/"));
+        assert!(has_path_prefix("/anything", "/"));
+        assert!(has_path_prefix("/api/", "/api/"));
+    }
+
+    #[test]
+    fn path_prefixes_match_normalized_paths() {
+        for path in [
+            "/%70rivate/secret",
+            "//private/secret",
+            "/./private",
+            "/private%2Fsecret",
+            "/public/../private",
+            "/private/%zz",
+        ] {
+            assert!(has_path_prefix(path, "/private"), "{}", path);
+        }
+        assert!(!has_path_prefix("/%70ublic/private", "/private"));
+        assert_eq!(normalize_path("//a/./b/").as_deref(), Some("/a/b/"));
+        assert_eq!(normalize_path("").as_deref(), Some("/"));
+        assert_eq!(normalize_path("/a/../b"), None);
+    }
+
+    #[test]
+    fn quality_values() {
+        assert_eq!(quality("gzip;q=0.25, br", "gzip"), Some(250));
+        assert_eq!(quality("gzip;q=0.25, br", "BR"), Some(1000));
+        assert_eq!(quality("gzip;q=0.25, br", "deflate"), None);
+        assert_eq!(quality("gzip; Q=1.000", "gzip"), Some(1000));
+        assert_eq!(quality("gzip;q=1.5", "gzip"), Some(0));
+        assert_eq!(quality("gzip;q=0.0001", "gzip"), Some(0));
+    }
+}
@@ -0,0 +1,228 @@
+//! # Server-Sent Events
+//!
+//! [`Sse`] turns a [`Stream`] of [`Event`]s into a `text/event-stream` response. Each event is
+//! written to the client as soon as the stream yields it, and while the stream is idle a
+//! keep-alive comment is sent periodically so that proxies do not time the connection out.
+//!
+//! The response ends when the event stream does. If the client disconnects first, the next write
+//! fails and the event stream is dropped, so producers can rely on `Drop` to clean up.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use futures::stream;
+//! use hooch_http::{Event, HoochAppBuilder, HttpMethod, Sse};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_route("/events", HttpMethod::GET, |_req, _params| async move {
+//!     let events = stream::iter((1..=3).map(|n| {
+//!         Event::default()
+//!             .id(n.to_string())
+//!             .event("tick")
+//!             .data(format!("tick {}", n))
+//!     }));
+//!     Sse::new(events).keep_alive(Duration::from_secs(5))
+//! });
+//! ```
+
+use std::{fmt::Write, pin::pin, time::Duration};
+
+use futures::{
+    future::{select, Either},
+    stream, Stream, StreamExt,
+};
+use hooch::time::sleep;
+use serde::Serialize;
+
+use crate::response::{HttpResponse, HttpResponseBuilder, IntoResponse};
+
+/// Interval between keep-alive comments unless configured otherwise.
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Frame sent while the event stream is idle.
+const KEEP_ALIVE_FRAME: &[u8] = b": keep-alive\n\n";
+
+/// A single server-sent event.
+///
+/// All fields are optional; an event with only a comment is ignored by clients but keeps the
+/// connection busy.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+    comment: Option<String>,
+}
+
+impl Event {
+    /// Set the event ID, which the client sends back in `Last-Event-ID` when reconnecting.
+    ///
+    /// Line breaks and NUL characters are removed, since they would end the field early or make
+    /// clients ignore the ID.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        let mut id = id.into();
+        id.retain(|c| !matches!(c, '\r' | '\n' | '\0'));
+        self.id = Some(id);
+        self
+    }
+
+    /// Set the event type, dispatched to listeners registered for that name.
+    ///
+    /// Line breaks are removed, since they would end the field early.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        let mut event = event.into();
+        event.retain(|c| !matches!(c, '\r' | '\n'));
+        self.event = Some(event);
+        self
+    }
+
+    /// Set the event data. Multi-line data is split across several `data` fields.
+    pub fn data(mut self, data: impl Into<String>) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// Set the event data to `value` serialized as JSON.
+    pub fn json_data<T: Serialize>(self, value: &T) -> Result<Self, serde_json::Error> {
+        Ok(self.data(serde_json::to_string(value)?))
+    }
+
+    /// Set how long the client should wait before reconnecting after losing the connection.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Attach a comment, which clients ignore. Multi-line comments are split across lines.
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Encode the event as a `text/event-stream` frame.
+    fn encode(&self) -> Vec<u8> {
+        let mut frame = String::new();
+        if let Some(comment) = &self.comment {
+            for line in lines(comment) {
+                let _ = writeln!(frame, ": {}", line);
+            }
+        }
+        if let Some(id) = &self.id {
+            let _ = writeln!(frame, "id: {}", id);
+        }
+        if let Some(event) = &self.event {
+            let _ = writeln!(frame, "event: {}", event);
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(frame, "retry: {}", retry.as_millis());
+        }
+        if let Some(data) = &self.data {
+            for line in lines(data) {
+                let _ = writeln!(frame, "data: {}", line);
+            }
+        }
+        frame.push('\n');
+        frame.into_bytes()
+    }
+}
+
+/// Splits `text` on any of the line breaks the event stream format recognises.
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split("\r\n").flat_map(|line| line.split(['\r', '\n']))
+}
+
+/// A `text/event-stream` response built from a stream of [`Event`]s.
+#[derive(Debug)]
+pub struct Sse<S> {
+    events: S,
+    keep_alive: Option<Duration>,
+}
+
+impl<S> Sse<S>
+where
+    S: Stream<Item = Event> + Send + 'static,
+{
+    /// Create a response sending every event `events` yields.
+    ///
+    /// Keep-alive comments are sent after 15 seconds without an event.
+    pub fn new(events: S) -> Self {
+        Self {
+            events,
+            keep_alive: Some(DEFAULT_KEEP_ALIVE),
+        }
+    }
+
+    /// Set how long the stream may stay idle before a keep-alive comment is sent.
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some(interval);
+        self
+    }
+
+    /// Disable keep-alive comments.
+    pub fn without_keep_alive(mut self) -> Self {
+        self.keep_alive = None;
+        self
+    }
+}
+
+impl<S> IntoResponse for Sse<S>
+where
+    S: Stream<Item = Event> + Send + 'static,
+{
+    fn into_response(self) -> HttpResponse {
+        let keep_alive = self.keep_alive;
+        let frames = stream::unfold(self.events.boxed(), move |mut events| async move {
+            let frame = match keep_alive {
+                None => events.next().await?.encode(),
+                Some(interval) => match select(events.next(), pin!(sleep(interval))).await {
+                    Either::Left((event, _)) => event?.encode(),
+                    Either::Right(_) => KEEP_ALIVE_FRAME.to_vec(),
+                },
+            };
+            Some((Ok(frame), events))
+        });
+
+        HttpResponseBuilder::ok()
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+            .body_stream(frames)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_encoding() {
+        let event = Event::default()
+            .comment("hello")
+            .id("42")
+            .event("update")
+            .retry(Duration::from_secs(3))
+            .data("line one\nline two\r\nline three");
+
+        assert_eq!(
+            String::from_utf8(event.encode()).unwrap(),
+            ": hello\nid: 42\nevent: update\nretry: 3000\ndata: line one\ndata: line two\ndata: line three\n\n"
+        );
+    }
+
+    #[test]
+    fn json_data_event() {
+        let event = Event::default().json_data(&vec![1, 2]).unwrap();
+        assert_eq!(event.encode(), b"data: [1,2]\n\n");
+    }
+
+    #[test]
+    fn id_and_event_line_breaks_are_removed() {
+        let event = Event::default()
+            .id("1\r\n\0data: injected")
+            .event("up\ndate");
+        assert_eq!(event.encode(), b"id: 1data: injected\nevent: update\n\n");
+    }
+}
@@ -0,0 +1,899 @@
+//! # WebSockets
+//!
+//! WebSocket connections start as an ordinary `GET` request carrying `Upgrade: websocket`.
+//! [`WebSocketUpgrade`] validates that request and completes the RFC 6455 opening handshake,
+//! answering `101 Switching Protocols`; once the response has been written the connection is
+//! handed to a callback as a [`WebSocket`].
+//!
+//! The simplest way to accept WebSockets is
+//! [`HoochAppBuilder::add_websocket_route`](crate::HoochAppBuilder::add_websocket_route). For more
+//! control, take [`WebSocketUpgrade`] as an extractor and call [`WebSocketUpgrade::on_upgrade`].
+//!
+//! [`WebSocket`] handles the protocol details: fragmented messages are reassembled, pings are
+//! answered automatically, and frames that violate the protocol (unmasked client frames, reserved
+//! bits, oversized or malformed control frames, invalid UTF-8 in text messages, messages larger
+//! than the configured maximum) close the connection with the matching close code.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hooch_http::{HoochAppBuilder, Message};
+//!
+//! let mut app = HoochAppBuilder::new("127.0.0.1:8080").unwrap();
+//! app.add_websocket_route("/echo", |mut socket, _req, _params| async move {
+//!     while let Some(Ok(message)) = socket.recv().await {
+//!         match message {
+//!             Message::Text(_) | Message::Binary(_) => {
+//!                 if socket.send(message).await.is_err() {
+//!                     break;
+//!                 }
+//!             }
+//!             Message::Close(_) => break,
+//!             Message::Ping(_) | Message::Pong(_) => {}
+//!         }
+//!     }
+//! });
+//! ```
+
+use std::{fmt::Display, future::Future, io};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hooch::net::HoochTcpStream;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    app::write_all,
+    extract::FromRequest,
+    request::{HttpRequest, Params},
+    response::{HttpResponse, HttpResponseBuilder},
+    HttpMethod,
+};
+
+/// GUID appended to the client key when computing `Sec-WebSocket-Accept`.
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest message accepted unless configured otherwise.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+/// Largest payload a control frame may carry.
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// Size by which the read buffer grows while waiting for more of a frame.
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Computes the `Sec-WebSocket-Accept` value for a client's `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(HANDSHAKE_GUID.as_bytes());
+    STANDARD.encode(hasher.finalize())
+}
+
+/// Returns `true` if the comma separated header value contains `token`.
+pub(crate) fn has_token(value: Option<&str>, token: &str) -> bool {
+    value.is_some_and(|value| {
+        value
+            .split(',')
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    })
+}
+
+/// Extractor for a WebSocket opening handshake.
+///
+/// Requests that are not valid handshakes are rejected with `426 Upgrade Required` if they do
+/// not ask for a WebSocket upgrade or use an unsupported version, and with `400 Bad Request`
+/// otherwise.
+#[derive(Debug)]
+pub struct WebSocketUpgrade {
+    accept: String,
+    max_message_size: usize,
+}
+
+impl WebSocketUpgrade {
+    /// Set the largest message, in bytes, the [`WebSocket`] accepts. Defaults to 1 MiB.
+    ///
+    /// Larger messages close the connection with [`CloseCode::TooBig`].
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Complete the handshake, running `callback` with the [`WebSocket`] once the
+    /// `101 Switching Protocols` response has been sent.
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> HttpResponse
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let max_message_size = self.max_message_size;
+        HttpResponseBuilder::switching_protocols()
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header("Sec-WebSocket-Accept", self.accept)
+            .on_upgrade(move |upgraded| {
+                callback(WebSocket::new(
+                    upgraded.stream,
+                    upgraded.buffered,
+                    max_message_size,
+                ))
+            })
+            .build()
+    }
+}
+
+impl FromRequest for WebSocketUpgrade {
+    fn from_request(
+        req: &HttpRequest<'static>,
+        _params: &Params<'static>,
+    ) -> Result<Self, HttpResponse> {
+        let headers = req.headers();
+        if req.method() != HttpMethod::GET
+            || !has_token(headers.get("Connection"), "upgrade")
+            || !has_token(headers.get("Upgrade"), "websocket")
+        {
+            return Err(HttpResponseBuilder::upgrade_required()
+                .header("Upgrade", "websocket")
+                .header("Connection", "Upgrade")
+                .body("Expected a WebSocket upgrade request".into())
+                .build());
+        }
+
+        if headers.get("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+            return Err(HttpResponseBuilder::upgrade_required()
+                .header("Sec-WebSocket-Version", "13")
+                .body("Unsupported WebSocket version".into())
+                .build());
+        }
+
+        let key = headers
+            .get("Sec-WebSocket-Key")
+            .map(str::trim)
+            .filter(|key| STANDARD.decode(key).is_ok_and(|nonce| nonce.len() == 16))
+            .ok_or_else(|| {
+                HttpResponseBuilder::bad_request()
+                    .body("Missing or invalid Sec-WebSocket-Key".into())
+                    .build()
+            })?;
+
+        Ok(Self {
+            accept: accept_key(key),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        })
+    }
+}
+
+/// A WebSocket message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// A UTF-8 text message.
+    Text(String),
+    /// A binary message.
+    Binary(Vec<u8>),
+    /// A ping. Received pings are answered automatically.
+    Ping(Vec<u8>),
+    /// A pong, usually in reply to a ping.
+    Pong(Vec<u8>),
+    /// A close frame, with the close code and reason if one was given.
+    Close(Option<CloseFrame>),
+}
+
+/// The code and reason carried by a close frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
+/// Status codes sent in close frames, as defined by RFC 6455.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CloseCode {
+    /// 1000: the purpose of the connection has been fulfilled.
+    Normal,
+    /// 1001: the endpoint is going away, such as a server shutting down.
+    Away,
+    /// 1002: the peer violated the protocol.
+    Protocol,
+    /// 1003: the peer sent a type of data that cannot be accepted.
+    Unsupported,
+    /// 1007: a message contained data inconsistent with its type, such as invalid UTF-8.
+    Invalid,
+    /// 1008: a message violated the endpoint's policy.
+    Policy,
+    /// 1009: a message was too big to process.
+    TooBig,
+    /// 1010: the client expected an extension the server did not negotiate.
+    Extension,
+    /// 1011: the server hit an unexpected condition.
+    Error,
+    /// Any other code, including application codes in the 3000-4999 range.
+    Other(u16),
+}
+
+impl From<CloseCode> for u16 {
+    fn from(code: CloseCode) -> u16 {
+        match code {
+            CloseCode::Normal => 1000,
+            CloseCode::Away => 1001,
+            CloseCode::Protocol => 1002,
+            CloseCode::Unsupported => 1003,
+            CloseCode::Invalid => 1007,
+            CloseCode::Policy => 1008,
+            CloseCode::TooBig => 1009,
+            CloseCode::Extension => 1010,
+            CloseCode::Error => 1011,
+            CloseCode::Other(code) => code,
+        }
+    }
+}
+
+impl From<u16> for CloseCode {
+    fn from(code: u16) -> Self {
+        match code {
+            1000 => CloseCode::Normal,
+            1001 => CloseCode::Away,
+            1002 => CloseCode::Protocol,
+            1003 => CloseCode::Unsupported,
+            1007 => CloseCode::Invalid,
+            1008 => CloseCode::Policy,
+            1009 => CloseCode::TooBig,
+            1010 => CloseCode::Extension,
+            1011 => CloseCode::Error,
+            code => CloseCode::Other(code),
+        }
+    }
+}
+
+impl CloseCode {
+    /// Returns `true` if the code may be sent in a close frame.
+    fn is_allowed(self) -> bool {
+        matches!(u16::from(self), 1000..=1003 | 1007..=1011 | 3000..=4999)
+    }
+}
+
+/// Errors produced by a [`WebSocket`].
+#[derive(Debug)]
+pub enum WebSocketError {
+    /// Reading from or writing to the connection failed.
+    Io(io::Error),
+    /// The peer violated the WebSocket protocol.
+    Protocol(&'static str),
+    /// A message exceeded the maximum message size.
+    MessageTooLarge,
+    /// A text message or close reason was not valid UTF-8.
+    InvalidUtf8,
+    /// The connection has already been closed.
+    ConnectionClosed,
+}
+
+impl Display for WebSocketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "WebSocket I/O error: {}", err),
+            Self::Protocol(reason) => write!(f, "WebSocket protocol error: {}", reason),
+            Self::MessageTooLarge => f.write_str("WebSocket message too large"),
+            Self::InvalidUtf8 => f.write_str("WebSocket text is not valid UTF-8"),
+            Self::ConnectionClosed => f.write_str("WebSocket connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for WebSocketError {}
+
+impl From<io::Error> for WebSocketError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Frame opcodes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum OpCode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl OpCode {
+    fn from_bits(bits: u8) -> Option<Self> {
+        Some(match bits {
+            0x0 => Self::Continuation,
+            0x1 => Self::Text,
+            0x2 => Self::Binary,
+            0x8 => Self::Close,
+            0x9 => Self::Ping,
+            0xA => Self::Pong,
+            _ => return None,
+        })
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Self::Continuation => 0x0,
+            Self::Text => 0x1,
+            Self::Binary => 0x2,
+            Self::Close => 0x8,
+            Self::Ping => 0x9,
+            Self::Pong => 0xA,
+        }
+    }
+
+    fn is_control(self) -> bool {
+        matches!(self, Self::Close | Self::Ping | Self::Pong)
+    }
+}
+
+/// A single frame read from the client, already unmasked.
+struct Frame {
+    fin: bool,
+    opcode: OpCode,
+    payload: Vec<u8>,
+}
+
+/// The server side of an established WebSocket connection.
+///
+/// [`recv`](WebSocket::recv) and [`send`](WebSocket::send) both need exclusive access, so a
+/// connection is driven by a single task alternating between the two.
+pub struct WebSocket {
+    stream: HoochTcpStream,
+    /// Bytes read from the connection but not yet parsed into frames.
+    buffer: Vec<u8>,
+    max_message_size: usize,
+    /// The opcode and data of a fragmented message still being received.
+    fragments: Option<(OpCode, Vec<u8>)>,
+    close_sent: bool,
+    /// Set once the closing handshake has finished or the connection failed.
+    closed: bool,
+}
+
+impl std::fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("max_message_size", &self.max_message_size)
+            .field("close_sent", &self.close_sent)
+            .field("closed", &self.closed)
+            .finish()
+    }
+}
+
+impl WebSocket {
+    fn new(stream: HoochTcpStream, buffered: Vec<u8>, max_message_size: usize) -> Self {
+        Self {
+            stream,
+            buffer: buffered,
+            max_message_size,
+            fragments: None,
+            close_sent: false,
+            closed: false,
+        }
+    }
+
+    /// Receive the next message.
+    ///
+    /// Returns `None` once the connection is closed. A received close frame is answered and
+    /// returned as [`Message::Close`]; the following call returns `None`. Protocol violations
+    /// close the connection with the matching close code and are returned as errors.
+    pub async fn recv(&mut self) -> Option<Result<Message, WebSocketError>> {
+        loop {
+            if self.closed {
+                return None;
+            }
+            let message = match self.read_frame().await {
+                Ok(frame) => self.handle_frame(frame).await,
+                Err(err) => Err(err),
+            };
+            match message {
+                Ok(Some(message)) => return Some(Ok(message)),
+                Ok(None) => continue,
+                Err(err) => return Some(Err(self.fail(err).await)),
+            }
+        }
+    }
+
+    /// Send a message.
+    ///
+    /// Sending a [`Message::Close`] starts the closing handshake; keep calling
+    /// [`recv`](WebSocket::recv) to receive the client's reply. Nothing can be sent after a
+    /// close frame.
+    pub async fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        if self.close_sent || self.closed {
+            return Err(WebSocketError::ConnectionClosed);
+        }
+        match message {
+            Message::Text(text) => self.write_frame(OpCode::Text, text.as_bytes()).await,
+            Message::Binary(data) => self.write_frame(OpCode::Binary, &data).await,
+            Message::Ping(data) | Message::Pong(data) if data.len() > MAX_CONTROL_PAYLOAD => Err(
+                WebSocketError::Protocol("control frame payload longer than 125 bytes"),
+            ),
+            Message::Ping(data) => self.write_frame(OpCode::Ping, &data).await,
+            Message::Pong(data) => self.write_frame(OpCode::Pong, &data).await,
+            Message::Close(frame) => self.write_close(frame).await,
+        }
+    }
+
+    /// Start the closing handshake with the given code and reason.
+    pub async fn close(
+        &mut self,
+        code: CloseCode,
+        reason: impl Into<String>,
+    ) -> Result<(), WebSocketError> {
+        self.send(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.into(),
+        })))
+        .await
+    }
+
+    /// Applies a frame to the connection state, returning a message if one is complete.
+    async fn handle_frame(&mut self, frame: Frame) -> Result<Option<Message>, WebSocketError> {
+        match frame.opcode {
+            OpCode::Continuation => {
+                let Some((_, data)) = self.fragments.as_mut() else {
+                    return Err(WebSocketError::Protocol("unexpected continuation frame"));
+                };
+                if data.len() + frame.payload.len() > self.max_message_size {
+                    return Err(WebSocketError::MessageTooLarge);
+                }
+                data.extend_from_slice(&frame.payload);
+                if !frame.fin {
+                    return Ok(None);
+                }
+                let (opcode, data) = self
+                    .fragments
+                    .take()
+                    .unwrap_or((OpCode::Binary, Vec::new()));
+                Self::message(opcode, data).map(Some)
+            }
+            OpCode::Text | OpCode::Binary => {
+                if self.fragments.is_some() {
+                    return Err(WebSocketError::Protocol("expected continuation frame"));
+                }
+                if !frame.fin {
+                    self.fragments = Some((frame.opcode, frame.payload));
+                    return Ok(None);
+                }
+                Self::message(frame.opcode, frame.payload).map(Some)
+            }
+            OpCode::Ping => {
+                if !self.close_sent {
+                    self.write_frame(OpCode::Pong, &frame.payload).await?;
+                }
+                Ok(Some(Message::Ping(frame.payload)))
+            }
+            OpCode::Pong => Ok(Some(Message::Pong(frame.payload))),
+            OpCode::Close => {
+                let close = Self::parse_close(&frame.payload)?;
+                if !self.close_sent {
+                    // Echo the code back to complete the closing handshake.
+                    let reply = close.as_ref().map(|close| CloseFrame {
+                        code: close.code,
+                        reason: String::new(),
+                    });
+                    let _ = self.write_close(reply).await;
+                }
+                self.closed = true;
+                Ok(Some(Message::Close(close)))
+            }
+        }
+    }
+
+    /// Builds a data message, validating text as UTF-8.
+    fn message(opcode: OpCode, data: Vec<u8>) -> Result<Message, WebSocketError> {
+        match opcode {
+            OpCode::Text => String::from_utf8(data)
+                .map(Message::Text)
+                .map_err(|_| WebSocketError::InvalidUtf8),
+            _ => Ok(Message::Binary(data)),
+        }
+    }
+
+    /// Parses the payload of a close frame.
+    fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>, WebSocketError> {
+        match payload {
+            [] => Ok(None),
+            [_] => Err(WebSocketError::Protocol(
+                "close frame with one byte payload",
+            )),
+            [high, low, reason @ ..] => {
+                let code = CloseCode::from(u16::from_be_bytes([*high, *low]));
+                if !code.is_allowed() {
+                    return Err(WebSocketError::Protocol("invalid close code"));
+                }
+                let reason = std::str::from_utf8(reason)
+                    .map_err(|_| WebSocketError::InvalidUtf8)?
+                    .to_string();
+                Ok(Some(CloseFrame { code, reason }))
+            }
+        }
+    }
+
+    /// Closes the connection after an error, telling the client why where possible.
+    async fn fail(&mut self, err: WebSocketError) -> WebSocketError {
+        let code = match &err {
+            WebSocketError::Protocol(_) => Some(CloseCode::Protocol),
+            WebSocketError::MessageTooLarge => Some(CloseCode::TooBig),
+            WebSocketError::InvalidUtf8 => Some(CloseCode::Invalid),
+            WebSocketError::Io(_) | WebSocketError::ConnectionClosed => None,
+        };
+        if let Some(code) = code
+            && !self.close_sent
+        {
+            let frame = CloseFrame {
+                code,
+                reason: String::new(),
+            };
+            let _ = self.write_close(Some(frame)).await;
+        }
+        self.closed = true;
+        err
+    }
+
+    /// Reads the next frame from the connection.
+    async fn read_frame(&mut self) -> Result<Frame, WebSocketError> {
+        self.fill(2).await?;
+        let (first, second) = (self.buffer[0], self.buffer[1]);
+
+        if first & 0x70 != 0 {
+            return Err(WebSocketError::Protocol("reserved bits set"));
+        }
+        let fin = first & 0x80 != 0;
+        let opcode =
+            OpCode::from_bits(first & 0x0F).ok_or(WebSocketError::Protocol("unknown opcode"))?;
+        if second & 0x80 == 0 {
+            return Err(WebSocketError::Protocol("client frames must be masked"));
+        }
+
+        let (length_size, length) = match second & 0x7F {
+            126 => {
+                self.fill(4).await?;
+                (
+                    2,
+                    u16::from_be_bytes([self.buffer[2], self.buffer[3]]) as u64,
+                )
+            }
+            127 => {
+                self.fill(10).await?;
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&self.buffer[2..10]);
+                (8, u64::from_be_bytes(bytes))
+            }
+            length => (0, length as u64),
+        };
+
+        if opcode.is_control() && (!fin || length > MAX_CONTROL_PAYLOAD as u64) {
+            return Err(WebSocketError::Protocol("invalid control frame"));
+        }
+        if length > self.max_message_size as u64 {
+            return Err(WebSocketError::MessageTooLarge);
+        }
+
+        let mask_start = 2 + length_size;
+        let payload_start = mask_start + 4;
+        let frame_end = payload_start + length as usize;
+        self.fill(frame_end).await?;
+
+        let mut mask = [0; 4];
+        mask.copy_from_slice(&self.buffer[mask_start..payload_start]);
+        let mut payload: Vec<u8> = self.buffer.drain(..frame_end).skip(payload_start).collect();
+        payload
+            .iter_mut()
+            .enumerate()
+            .for_each(|(idx, byte)| *byte ^= mask[idx % 4]);
+
+        Ok(Frame {
+            fin,
+            opcode,
+            payload,
+        })
+    }
+
+    /// Reads from the connection until at least `len` bytes are buffered.
+    ///
+    /// The buffer only grows as bytes arrive, so a frame header announcing a large payload does
+    /// not allocate it up front.
+    async fn fill(&mut self, len: usize) -> Result<(), WebSocketError> {
+        while self.buffer.len() < len {
+            let filled = self.buffer.len();
+            self.buffer.resize(filled + READ_CHUNK_SIZE, 0);
+            let read = self.stream.read(&mut self.buffer[filled..]).await;
+            let bytes_read = match read {
+                Ok(bytes_read) => bytes_read,
+                Err(err) => {
+                    self.buffer.truncate(filled);
+                    return Err(err.into());
+                }
+            };
+            self.buffer.truncate(filled + bytes_read);
+            if bytes_read == 0 {
+                return Err(WebSocketError::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a close frame and records that the closing handshake has started.
+    async fn write_close(&mut self, frame: Option<CloseFrame>) -> Result<(), WebSocketError> {
+        let mut payload = Vec::new();
+        if let Some(frame) = frame {
+            // Cut overlong reasons at a character boundary so they stay valid UTF-8.
+            let mut reason_end = frame.reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+            while !frame.reason.is_char_boundary(reason_end) {
+                reason_end -= 1;
+            }
+            payload.extend_from_slice(&u16::from(frame.code).to_be_bytes());
+            payload.extend_from_slice(&frame.reason.as_bytes()[..reason_end]);
+        }
+        self.close_sent = true;
+        self.write_frame(OpCode::Close, &payload).await
+    }
+
+    /// Writes a single, unfragmented and unmasked frame.
+    async fn write_frame(&mut self, opcode: OpCode, payload: &[u8]) -> Result<(), WebSocketError> {
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(0x80 | opcode.bits());
+        match payload.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        write_all(&mut self.stream, &frame).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpStream},
+    };
+
+    use super::*;
+    use crate::{
+        app::tests::{connect, read_response, serve},
+        HoochAppBuilder,
+    };
+
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+    /// Registers an echo endpoint at `/echo` and one limited to 16 byte messages at `/small`.
+    fn echo_app(app: &mut HoochAppBuilder) {
+        async fn echo(mut socket: WebSocket) {
+            while let Some(Ok(message)) = socket.recv().await {
+                match message {
+                    Message::Text(_) | Message::Binary(_) => {
+                        if socket.send(message).await.is_err() {
+                            break;
+                        }
+                    }
+                    Message::Close(_) => break,
+                    Message::Ping(_) | Message::Pong(_) => {}
+                }
+            }
+        }
+
+        app.add_websocket_route("/echo", |socket, _req, _params| echo(socket));
+        app.add_handler(
+            "/small",
+            HttpMethod::GET,
+            |ws: WebSocketUpgrade| async move { ws.max_message_size(16).on_upgrade(echo) },
+        );
+    }
+
+    /// Connects and completes the opening handshake.
+    fn handshake(addr: SocketAddr, path: &str) -> TcpStream {
+        let mut stream = connect(addr);
+        stream
+            .write_all(
+                format!(
+                    "GET {} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+                    path, KEY
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        stream
+    }
+
+    /// Writes a client frame, masked unless `masked` is false.
+    fn write_frame(stream: &mut TcpStream, fin: bool, opcode: u8, payload: &[u8], masked: bool) {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![(fin as u8) << 7 | opcode];
+        let mask_bit = if masked { 0x80 } else { 0 };
+        match payload.len() {
+            len @ 0..=125 => frame.push(mask_bit | len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(mask_bit | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(mask_bit | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        if masked {
+            frame.extend_from_slice(&mask);
+            frame.extend(
+                payload
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, byte)| byte ^ mask[idx % 4]),
+            );
+        } else {
+            frame.extend_from_slice(payload);
+        }
+        stream.write_all(&frame).unwrap();
+    }
+
+    /// Reads a server frame, returning its opcode and payload.
+    fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0; 2];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(header[0] & 0x80, 0x80, "server frames are never fragmented");
+        assert_eq!(header[1] & 0x80, 0, "server frames must not be masked");
+        let len = match header[1] & 0x7F {
+            126 => {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).unwrap();
+                u16::from_be_bytes(len) as usize
+            }
+            127 => {
+                let mut len = [0; 8];
+                stream.read_exact(&mut len).unwrap();
+                u64::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).unwrap();
+        (header[0] & 0x0F, payload)
+    }
+
+    /// Reads a close frame, returning its code.
+    fn read_close_code(stream: &mut TcpStream) -> u16 {
+        let (opcode, payload) = read_frame(stream);
+        assert_eq!(opcode, 0x8);
+        u16::from_be_bytes([payload[0], payload[1]])
+    }
+
+    #[test]
+    fn accept_key_matches_rfc_example() {
+        assert_eq!(accept_key(KEY), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn echoes_text_and_binary() {
+        let addr = serve(echo_app);
+        let mut stream = handshake(addr, "/echo");
+
+        write_frame(&mut stream, true, 0x1, "héllo".as_bytes(), true);
+        assert_eq!(read_frame(&mut stream), (0x1, "héllo".as_bytes().to_vec()));
+
+        let large = vec![7u8; 70_000];
+        write_frame(&mut stream, true, 0x2, &large, true);
+        assert_eq!(read_frame(&mut stream), (0x2, large));
+    }
+
+    #[test]
+    fn frames_sent_with_handshake_are_not_lost() {
+        let addr = serve(echo_app);
+        let mut stream = connect(addr);
+
+        let mut request = format!(
+            "GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            KEY
+        )
+        .into_bytes();
+        // Masked text frame "hi" with an all-zero mask.
+        request.extend_from_slice(&[0x81, 0x82, 0, 0, 0, 0, b'h', b'i']);
+        stream.write_all(&request).unwrap();
+
+        let mut response = Vec::new();
+        while !response.ends_with(b"\x81\x02hi") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        assert!(response.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+    }
+
+    #[test]
+    fn reassembles_fragments_and_answers_pings() {
+        let addr = serve(echo_app);
+        let mut stream = handshake(addr, "/echo");
+
+        write_frame(&mut stream, false, 0x1, b"frag", true);
+        write_frame(&mut stream, true, 0x9, b"are you there", true);
+        write_frame(&mut stream, false, 0x0, b"men", true);
+        write_frame(&mut stream, true, 0x0, b"ted", true);
+
+        assert_eq!(read_frame(&mut stream), (0xA, b"are you there".to_vec()));
+        assert_eq!(read_frame(&mut stream), (0x1, b"fragmented".to_vec()));
+    }
+
+    #[test]
+    fn closing_handshake_echoes_code() {
+        let addr = serve(echo_app);
+        let mut stream = handshake(addr, "/echo");
+
+        write_frame(
+            &mut stream,
+            true,
+            0x8,
+            &[0x03, 0xE8, b'b', b'y', b'e'],
+            true,
+        );
+        assert_eq!(read_close_code(&mut stream), 1000);
+        assert!(read_response(&mut stream).is_empty());
+    }
+
+    #[test]
+    fn protocol_violations_close_with_matching_code() {
+        let addr = serve(echo_app);
+
+        let mut stream = handshake(addr, "/echo");
+        write_frame(&mut stream, true, 0x1, b"unmasked", false);
+        assert_eq!(read_close_code(&mut stream), 1002);
+
+        let mut stream = handshake(addr, "/echo");
+        write_frame(&mut stream, true, 0x0, b"orphan", true);
+        assert_eq!(read_close_code(&mut stream), 1002);
+
+        let mut stream = handshake(addr, "/echo");
+        write_frame(&mut stream, false, 0x9, b"fragmented ping", true);
+        assert_eq!(read_close_code(&mut stream), 1002);
+
+        let mut stream = handshake(addr, "/echo");
+        write_frame(&mut stream, true, 0x1, &[0xC3, 0x28], true);
+        assert_eq!(read_close_code(&mut stream), 1007);
+
+        let mut stream = handshake(addr, "/small");
+        write_frame(&mut stream, false, 0x2, &[0; 10], true);
+        write_frame(&mut stream, true, 0x0, &[0; 10], true);
+        assert_eq!(read_close_code(&mut stream), 1009);
+    }
+
+    #[test]
+    fn rejects_invalid_handshakes() {
+        let addr = serve(echo_app);
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut stream).starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n")
+            .unwrap();
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(response.contains("\r\nSec-WebSocket-Version: 13\r\n"));
+
+        let mut stream = connect(addr);
+        stream
+            .write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: short\r\nSec-WebSocket-Version: 13\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut stream).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+}